@@ -7,7 +7,7 @@ use crate::google::{self, fetch_google_public_keys};
 use crate::{AtomaAuthConfig, Sui};
 use anyhow::anyhow;
 use atoma_state::{
-    types::{AtomaAtomaStateManagerEvent, TokenResponse, UserProfile},
+    types::{AtomaAtomaStateManagerEvent, CreateTokenRequest, TokenResponse, UserProfile},
     AtomaStateManagerError,
 };
 use atoma_utils::hashing::blake2b_hash;
@@ -47,6 +47,8 @@ use tracing::{error, instrument};
 
 /// The length of the API token
 const API_TOKEN_LENGTH: usize = 30;
+/// The length of the non-secret API token prefix, shown to users to identify their tokens
+const API_TOKEN_PREFIX_LENGTH: usize = 8;
 
 const SUI_BALANCE_RETRY_COUNT: usize = 5; // How many times to retry the Sui call for the balance
 const SUI_BALANCE_RETRY_PAUSE: u64 = 500; // In milliseconds
@@ -124,6 +126,8 @@ type Result<T> = std::result::Result<T, AuthError>;
 pub struct Auth {
     /// The secret key for JWT authentication.
     secret_key: String,
+    /// The key API tokens are hashed with.
+    api_token_hash_key: String,
     /// The access token lifetime in minutes.
     access_token_lifetime: usize,
     /// The refresh token lifetime in days.
@@ -161,6 +165,7 @@ impl Auth {
         let google_public_keys = fetch_google_public_keys().await?;
         Ok(Self {
             secret_key: config.secret_key,
+            api_token_hash_key: config.api_token_hash_key,
             access_token_lifetime: config.access_token_lifetime,
             refresh_token_lifetime: config.refresh_token_lifetime,
             state_manager_sender,
@@ -320,6 +325,23 @@ impl Auth {
         hex::encode(hash_result)
     }
 
+    /// Used for hashing API tokens before they are stored or looked up
+    /// This method will hash the API token keyed with the API token hash key, so a leaked
+    /// database alone is not enough to check guesses against the stored hashes. The key is
+    /// distinct from the JWT secret key, which can be rotated without invalidating API tokens
+    ///
+    /// # Arguments
+    ///
+    /// * `api_token` - The API token to be hashed
+    ///
+    /// # Returns
+    ///
+    /// * `String` - The hashed API token
+    #[must_use]
+    pub fn hash_api_token(&self, api_token: &str) -> String {
+        self.hash_string(&format!("{}:{api_token}", self.api_token_hash_key))
+    }
+
     /// Register user with email/password.
     /// This method will register a new user with a email and password
     /// The password is hashed and stored in the DB
@@ -435,13 +457,17 @@ impl Auth {
     /// # Arguments
     ///
     /// * `jwt` - The access token to be used to generate the API token
-    /// * `name` - The name of the API token
+    /// * `request` - The name, scopes, expiration and spending limit of the API token
     ///
     /// # Returns
     ///
     /// * `Result<String>` - The generated API token
     #[instrument(level = "info", skip(self))]
-    pub async fn generate_api_token(&self, jwt: &str, name: String) -> Result<String> {
+    pub async fn generate_api_token(
+        &self,
+        jwt: &str,
+        request: CreateTokenRequest,
+    ) -> Result<String> {
         let claims = self.get_claims_from_token(jwt).await?;
         let api_token: String = rand::thread_rng()
             .sample_iter(&rand::distributions::Alphanumeric)
@@ -451,8 +477,10 @@ impl Auth {
         self.state_manager_sender
             .send(AtomaAtomaStateManagerEvent::StoreNewApiToken {
                 user_id: claims.user_id,
-                api_token: api_token.clone(),
-                name,
+                api_token_hash: self.hash_api_token(&api_token),
+                api_token_prefix: api_token[..API_TOKEN_PREFIX_LENGTH].to_string(),
+                api_token_last_4: api_token[API_TOKEN_LENGTH - 4..].to_string(),
+                request,
             })?;
         Ok(api_token)
     }
@@ -944,14 +972,15 @@ impl Auth {
 mod test {
     use std::{path::PathBuf, sync::Arc};
 
-    use atoma_state::types::AtomaAtomaStateManagerEvent;
+    use atoma_state::types::{ApiTokenScopes, AtomaAtomaStateManagerEvent, CreateTokenRequest};
     use atoma_sui::config::Config;
+    use chrono::{Duration, Utc};
     use flume::Receiver;
-    use tokio::sync::RwLock;
+    use tokio::sync::{oneshot, RwLock};
 
     use crate::AtomaAuthConfig;
 
-    use super::Auth;
+    use super::{Auth, API_TOKEN_PREFIX_LENGTH};
     use std::env;
     use std::fs::File;
     use std::io::Write;
@@ -995,6 +1024,7 @@ service_bind_address = "0.0.0.0:8081"
 
 [atoma_auth]
 secret_key = "secret_key"
+api_token_hash_key = "api_token_hash_key"
 access_token_lifetime = 1
 refresh_token_lifetime = 1
         "#,
@@ -1041,6 +1071,7 @@ active_address: "0x939cfcc7fcbc71ce983203bcb36fa498901932ab9293dfa2b271203e71603
     async fn setup_test() -> (Auth, Receiver<AtomaAtomaStateManagerEvent>) {
         let config = AtomaAuthConfig::new(
             "secret".to_string(),
+            "api_token_hash_key".to_string(),
             1,
             1,
             #[cfg(feature = "google-oauth")]
@@ -1102,6 +1133,18 @@ active_address: "0x939cfcc7fcbc71ce983203bcb36fa498901932ab9293dfa2b271203e71603
         let password = "top_secret";
         let (auth, receiver) = setup_test().await;
         let hash_password = auth.hash_string(&format!("{salt}:{password}"));
+        let (api_token_sender, api_token_receiver) = oneshot::channel();
+        let api_token_request = CreateTokenRequest {
+            name: "test".to_string(),
+            scopes: ApiTokenScopes {
+                endpoints: vec!["/v1/embeddings".to_string()],
+                models: vec![],
+                confidential_only: false,
+            },
+            expires_at: Some(Utc::now() + Duration::days(1)),
+            spending_limit: Some(1_000_000),
+        };
+        let api_token_request_clone = api_token_request.clone();
         let mock_handle = tokio::task::spawn(async move {
             // First event is for the user to log in to get the tokens
             let event = receiver.recv_async().await.unwrap();
@@ -1155,11 +1198,16 @@ active_address: "0x939cfcc7fcbc71ce983203bcb36fa498901932ab9293dfa2b271203e71603
             match event {
                 AtomaAtomaStateManagerEvent::StoreNewApiToken {
                     user_id: event_user_id,
-                    api_token: _api_token,
-                    name: _name,
+                    api_token_hash,
+                    api_token_prefix,
+                    api_token_last_4,
+                    request,
                 } => {
+                    assert_eq!(request, api_token_request);
                     assert_eq!(event_user_id, user_id);
-                    // assert_eq!(event_api_token, api_token);
+                    api_token_sender
+                        .send((api_token_hash, api_token_prefix, api_token_last_4))
+                        .unwrap();
                 }
                 _ => panic!("Unexpected event"),
             }
@@ -1175,10 +1223,18 @@ active_address: "0x939cfcc7fcbc71ce983203bcb36fa498901932ab9293dfa2b271203e71603
         assert_eq!(claims.user_id, user_id);
         assert!(claims.refresh_token_hash.is_some());
         // Generate api token
-        let _api_token = auth
-            .generate_api_token(&access_token, "test".to_string())
+        let api_token = auth
+            .generate_api_token(&access_token, api_token_request_clone)
             .await
             .unwrap();
+        // Only the keyed hash, the prefix and the last 4 chars of the api token should be stored
+        let (api_token_hash, api_token_prefix, api_token_last_4) =
+            api_token_receiver.await.unwrap();
+        assert_eq!(api_token_hash, auth.hash_api_token(&api_token));
+        assert_ne!(api_token_hash, auth.hash_string(&api_token));
+        assert!(api_token.starts_with(&api_token_prefix));
+        assert_eq!(api_token_prefix.len(), API_TOKEN_PREFIX_LENGTH);
+        assert!(api_token.ends_with(&api_token_last_4));
         if tokio::time::timeout(std::time::Duration::from_secs(1), mock_handle)
             .await
             .is_err()
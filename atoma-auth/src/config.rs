@@ -7,6 +7,9 @@ use std::path::Path;
 pub struct AtomaAuthConfig {
     /// The secret key for JWT authentication.
     pub secret_key: String,
+    /// The key API tokens are hashed with before being stored, independent of the JWT secret
+    /// key so that rotating the latter does not invalidate the existing API tokens.
+    pub api_token_hash_key: String,
     /// The access token lifetime in minutes.
     pub access_token_lifetime: usize,
     /// The refresh token lifetime in days.
@@ -21,12 +24,14 @@ impl AtomaAuthConfig {
     #[must_use]
     pub const fn new(
         secret_key: String,
+        api_token_hash_key: String,
         access_token_lifetime: usize,
         refresh_token_lifetime: usize,
         #[cfg(feature = "google-oauth")] google_client_id: String,
     ) -> Self {
         Self {
             secret_key,
+            api_token_hash_key,
             access_token_lifetime,
             refresh_token_lifetime,
             #[cfg(feature = "google-oauth")]
@@ -49,6 +54,8 @@ impl AtomaAuthConfig {
     /// This method will panic if:
     /// - The configuration file cannot be read or parsed.
     /// - The "atoma-auth" section is missing from the configuration file.
+    /// - The `api_token_hash_key` field is missing, as in the configuration files written before
+    ///   API tokens were hashed.
     /// - The required fields are missing or have invalid types in the configuration file.
     ///
     /// # Examples
@@ -72,6 +79,12 @@ impl AtomaAuthConfig {
         let config = builder
             .build()
             .expect("Failed to generate atoma state configuration file");
+        assert!(
+            config.get_string("atoma_auth.api_token_hash_key").is_ok(),
+            "Missing `api_token_hash_key` in the `atoma_auth` section of the configuration file (or \
+             the `ATOMA_AUTH__API_TOKEN_HASH_KEY` environment variable): set it to a long random value, \
+             see the upgrade notes of the README"
+        );
         config
             .get::<Self>("atoma_auth")
             .expect("Failed to generate configuration instance")
@@ -29,6 +29,11 @@ use crate::{
         },
         subscriptions::{GetAllSubscriptionsOpenApi, SUBSCRIPTIONS_PATH},
         tasks::{GetAllTasksOpenApi, TASKS_PATH},
+        usage::{GetUsage, USAGE_PATH},
+        webhooks::{
+            CreateWebhook, DeleteWebhook, GetWebhooks, CREATE_WEBHOOK_PATH, DELETE_WEBHOOK_PATH,
+            GET_WEBHOOKS_PATH,
+        },
     },
     HealthOpenApi, HEALTH_PATH,
 };
@@ -61,6 +66,10 @@ pub fn openapi_router() -> Router {
             (path = GET_NODES_DISTRIBUTION_PATH, api = GetNodeDistribution, tags = ["Stats"]),
             (path = GET_GRAPHS_PATH, api = GetGraphs, tags = ["Stats"]),
             (path = GET_GRAPH_DATA_PATH, api = GetGraphData, tags = ["Stats"]),
+            (path = USAGE_PATH, api = GetUsage, tags = ["Usage"]),
+            (path = GET_WEBHOOKS_PATH, api = GetWebhooks, tags = ["Webhooks"]),
+            (path = CREATE_WEBHOOK_PATH, api = CreateWebhook, tags = ["Webhooks"]),
+            (path = DELETE_WEBHOOK_PATH, api = DeleteWebhook, tags = ["Webhooks"]),
         ),
         tags(
             (name = "Health", description = "Health check endpoints"),
@@ -69,6 +78,8 @@ pub fn openapi_router() -> Router {
             (name = "Subscriptions", description = "Node task subscriptions management"),
             (name = "Stacks", description = "Stacks management"),
             (name = "Stats", description = "Stats and metrics"),
+            (name = "Usage", description = "Per-request usage and billing records"),
+            (name = "Webhooks", description = "Billing and API token event notifications"),
         ),
         servers(
             (url = "http://localhost:8081", description = "Local server"),
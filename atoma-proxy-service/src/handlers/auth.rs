@@ -11,6 +11,7 @@ use axum::{
 };
 
 use base64::{prelude::BASE64_STANDARD, Engine};
+use chrono::Utc;
 use tracing::{error, instrument};
 use utoipa::OpenApi;
 
@@ -151,6 +152,7 @@ pub struct GenerateApiTokenOpenApi;
 ///
 /// * `proxy_service_state` - The shared state containing the state manager
 /// * `headers` - The headers of the request
+/// * `body` - The request body containing the name, scopes, expiration and spending limit of the token
 ///
 /// # Returns
 ///
@@ -161,8 +163,10 @@ pub struct GenerateApiTokenOpenApi;
     security(
         ("bearerAuth" = [])
     ),
+    request_body = CreateTokenRequest,
     responses(
         (status = OK, description = "Generates an API token for the user"),
+        (status = BAD_REQUEST, description = "Invalid expiration or spending limit"),
         (status = UNAUTHORIZED, description = "Unauthorized request"),
         (status = INTERNAL_SERVER_ERROR, description = "Failed to generate api token")
     )
@@ -175,10 +179,22 @@ pub async fn generate_api_token(
 ) -> Result<Json<String>> {
     let jwt = get_jwt_from_headers(&headers)?;
 
+    if body
+        .expires_at
+        .is_some_and(|expires_at| expires_at <= Utc::now())
+    {
+        error!("API token expiration is in the past");
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    if body.spending_limit.is_some_and(|limit| limit <= 0) {
+        error!("API token spending limit must be positive");
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
     Ok(Json(
         proxy_service_state
             .auth
-            .generate_api_token(jwt, body.name.clone())
+            .generate_api_token(jwt, body.0)
             .await
             .map_err(|e| {
                 error!("Failed to generate api token: {:?}", e);
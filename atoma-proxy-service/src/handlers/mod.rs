@@ -3,3 +3,5 @@ pub mod stacks;
 pub mod stats;
 pub mod subscriptions;
 pub mod tasks;
+pub mod usage;
+pub mod webhooks;
@@ -0,0 +1,148 @@
+use atoma_state::types::UsagePage;
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    routing::get,
+    Json, Router,
+};
+use tracing::{error, instrument};
+use utoipa::OpenApi;
+
+use crate::{query::UsageQuery, ProxyServiceState};
+
+type Result<T> = std::result::Result<T, StatusCode>;
+
+/// The path for the usage endpoint.
+pub const USAGE_PATH: &str = "/usage";
+
+/// The default number of usage records per page.
+const DEFAULT_USAGE_PAGE_SIZE: i64 = 100;
+
+/// The maximum number of usage records per page.
+const MAX_USAGE_PAGE_SIZE: i64 = 1_000;
+
+/// Returns a router with the usage endpoint.
+///
+/// # Returns
+/// * `Router<ProxyServiceState>` - A router with the usage endpoint
+pub fn usage_router() -> Router<ProxyServiceState> {
+    Router::new().route(USAGE_PATH, get(get_usage))
+}
+
+/// OpenAPI documentation for the get_usage endpoint.
+///
+/// This struct is used to generate OpenAPI documentation for the get_usage
+/// endpoint. It uses the `utoipa` crate's derive macro to automatically generate
+/// the OpenAPI specification from the code.
+#[derive(OpenApi)]
+#[openapi(paths(get_usage))]
+pub struct GetUsage;
+
+/// Retrieves a page of the usage ledger of the user, based on the access token.
+///
+/// Each usage record contains the billing details of a single request (api token, model,
+/// endpoint, stack, node, prompt and completion tokens, cost in USDC, latency and status),
+/// so that users can reconcile their invoices. Requests that failed once a node was selected
+/// for them are recorded too, with their status and no tokens, as they are not billed.
+/// Records are sorted by most recent first.
+///
+/// # Arguments
+/// * `proxy_service_state` - The shared state containing the state manager
+/// * `headers` - The headers of the request
+/// * `query` - The page to retrieve, and the optional time range of the usage records
+///
+/// # Returns
+/// * `Result<Json<UsagePage>>` - A JSON response containing a page of usage records
+///   - `Ok(Json<UsagePage>)` - Successfully retrieved the usage records
+///   - `Err(StatusCode::BAD_REQUEST)` - Invalid page or page size
+///   - `Err(StatusCode::UNAUTHORIZED)` - Missing or invalid access token
+///   - `Err(StatusCode::INTERNAL_SERVER_ERROR)` - Failed to retrieve usage records from state manager
+///
+/// # Example Response
+/// ```json
+/// {
+///     "records": [
+///         {
+///             "id": 1,
+///             "user_id": 1,
+///             "api_token_id": 1,
+///             "model": "meta-llama/Llama-3.3-70B-Instruct",
+///             "endpoint": "/v1/chat/completions",
+///             "stack_small_id": 1,
+///             "node_small_id": 1,
+///             "prompt_tokens": 1000,
+///             "completion_tokens": 500,
+///             "cost": 3,
+///             "latency_ms": 250,
+///             "status": 200,
+///             "created_at": "2025-04-17T12:00:00Z"
+///         }
+///     ],
+///     "page": 0,
+///     "page_size": 100,
+///     "total": 1
+/// }
+/// ```
+#[utoipa::path(
+    get,
+    path = "",
+    security(
+        ("bearerAuth" = [])
+    ),
+    params(
+        ("page" = Option<i64>, Query, description = "The index of the page, starting at 0"),
+        ("page_size" = Option<i64>, Query, description = "The number of usage records per page (at most 1000)"),
+        ("start" = Option<String>, Query, description = "Only return usage records created at or after this RFC 3339 timestamp"),
+        ("end" = Option<String>, Query, description = "Only return usage records created before this RFC 3339 timestamp"),
+    ),
+    responses(
+        (status = OK, description = "Retrieves a page of the usage ledger of the user"),
+        (status = BAD_REQUEST, description = "Invalid page or page size"),
+        (status = UNAUTHORIZED, description = "Missing or invalid access token"),
+        (status = INTERNAL_SERVER_ERROR, description = "Failed to get usage records")
+    )
+)]
+#[instrument(level = "trace", skip_all)]
+pub async fn get_usage(
+    State(proxy_service_state): State<ProxyServiceState>,
+    headers: HeaderMap,
+    Query(query): Query<UsageQuery>,
+) -> Result<Json<UsagePage>> {
+    let page = query.page.unwrap_or(0);
+    let page_size = query.page_size.unwrap_or(DEFAULT_USAGE_PAGE_SIZE);
+    // NOTE: The offset of the page must fit in an i64, not to overflow when computing it
+    if page < 0
+        || !(1..=MAX_USAGE_PAGE_SIZE).contains(&page_size)
+        || page.checked_mul(page_size).is_none()
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let auth_header = headers
+        .get("Authorization")
+        .ok_or(StatusCode::UNAUTHORIZED)?
+        .to_str()
+        .map_err(|_| StatusCode::UNAUTHORIZED)?;
+
+    let jwt = auth_header
+        .strip_prefix("Bearer ")
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+    let user_id = proxy_service_state
+        .auth
+        .get_user_id_from_token(jwt)
+        .await
+        .map_err(|_| {
+            error!("Failed to get user ID from token");
+            StatusCode::UNAUTHORIZED
+        })?;
+    Ok(Json(
+        proxy_service_state
+            .atoma_state
+            .get_usage_records_for_user(user_id, page, page_size, query.start, query.end)
+            .await
+            .map_err(|_| {
+                error!("Failed to get usage records");
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?,
+    ))
+}
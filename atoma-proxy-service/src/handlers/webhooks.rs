@@ -0,0 +1,263 @@
+use atoma_state::{
+    types::{CreateWebhookRequest, CreateWebhookResponse, DeleteWebhookRequest, Webhook},
+    webhooks::resolve_webhook_url,
+    AtomaStateManagerError,
+};
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    routing::{get, post},
+    Json, Router,
+};
+use rand::Rng;
+use tracing::{error, instrument};
+use utoipa::OpenApi;
+
+use crate::ProxyServiceState;
+
+type Result<T> = std::result::Result<T, StatusCode>;
+
+/// The path for the webhooks endpoint.
+pub const GET_WEBHOOKS_PATH: &str = "/webhooks";
+
+/// The path for the create_webhook endpoint.
+pub const CREATE_WEBHOOK_PATH: &str = "/create_webhook";
+
+/// The path for the delete_webhook endpoint.
+pub const DELETE_WEBHOOK_PATH: &str = "/delete_webhook";
+
+/// The maximum number of webhooks a user can register.
+const MAX_WEBHOOKS_PER_USER: usize = 10;
+
+/// The size, in bytes, of the key used to sign the webhook payloads.
+const WEBHOOK_SECRET_SIZE: usize = 32;
+
+/// Returns a router with the webhook endpoints.
+///
+/// # Returns
+/// * `Router<ProxyServiceState>` - A router with the webhook endpoints
+pub fn webhooks_router() -> Router<ProxyServiceState> {
+    Router::new()
+        .route(GET_WEBHOOKS_PATH, get(get_webhooks))
+        .route(CREATE_WEBHOOK_PATH, post(create_webhook))
+        .route(DELETE_WEBHOOK_PATH, post(delete_webhook))
+}
+
+/// Authenticates the request, and returns the id of the user.
+async fn get_user_id(proxy_service_state: &ProxyServiceState, headers: &HeaderMap) -> Result<i64> {
+    let jwt = headers
+        .get("Authorization")
+        .ok_or(StatusCode::UNAUTHORIZED)?
+        .to_str()
+        .map_err(|_| StatusCode::UNAUTHORIZED)?
+        .strip_prefix("Bearer ")
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+    proxy_service_state
+        .auth
+        .get_user_id_from_token(jwt)
+        .await
+        .map_err(|_| {
+            error!("Failed to get user ID from token");
+            StatusCode::UNAUTHORIZED
+        })
+}
+
+/// OpenAPI documentation for the get_webhooks endpoint.
+///
+/// This struct is used to generate OpenAPI documentation for the get_webhooks
+/// endpoint. It uses the `utoipa` crate's derive macro to automatically generate
+/// the OpenAPI specification from the code.
+#[derive(OpenApi)]
+#[openapi(paths(get_webhooks))]
+pub struct GetWebhooks;
+
+/// Retrieves the webhooks registered by the user, based on the access token.
+///
+/// # Arguments
+/// * `proxy_service_state` - The shared state containing the state manager
+/// * `headers` - The headers of the request
+///
+/// # Returns
+/// * `Result<Json<Vec<Webhook>>>` - A JSON response containing the webhooks of the user
+///   - `Ok(Json<Vec<Webhook>>)` - Successfully retrieved the webhooks
+///   - `Err(StatusCode::UNAUTHORIZED)` - Missing or invalid access token
+///   - `Err(StatusCode::INTERNAL_SERVER_ERROR)` - Failed to retrieve webhooks from state manager
+#[utoipa::path(
+    get,
+    path = "",
+    security(
+        ("bearerAuth" = [])
+    ),
+    responses(
+        (status = OK, description = "Retrieves the webhooks of the user"),
+        (status = UNAUTHORIZED, description = "Missing or invalid access token"),
+        (status = INTERNAL_SERVER_ERROR, description = "Failed to get webhooks")
+    )
+)]
+#[instrument(level = "trace", skip_all)]
+pub async fn get_webhooks(
+    State(proxy_service_state): State<ProxyServiceState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<Webhook>>> {
+    let user_id = get_user_id(&proxy_service_state, &headers).await?;
+    Ok(Json(
+        proxy_service_state
+            .atoma_state
+            .get_webhooks_for_user(user_id)
+            .await
+            .map_err(|_| {
+                error!("Failed to get webhooks");
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?,
+    ))
+}
+
+/// OpenAPI documentation for the create_webhook endpoint.
+///
+/// This struct is used to generate OpenAPI documentation for the create_webhook
+/// endpoint. It uses the `utoipa` crate's derive macro to automatically generate
+/// the OpenAPI specification from the code.
+#[derive(OpenApi)]
+#[openapi(paths(create_webhook))]
+pub struct CreateWebhook;
+
+/// Registers a webhook for the user, based on the access token.
+///
+/// The webhook receives signed JSON events when the USDC balance drops below
+/// `low_balance_threshold`, when the USDC spent in the current month reaches
+/// `monthly_spend_limit`, when a USDC payment is credited, and when an API token is revoked.
+/// The secret used to sign the payloads is only returned by this endpoint.
+///
+/// # Arguments
+/// * `proxy_service_state` - The shared state containing the state manager
+/// * `headers` - The headers of the request
+/// * `body` - The URL and thresholds of the webhook
+///
+/// # Returns
+/// * `Result<Json<CreateWebhookResponse>>` - A JSON response containing the webhook and its secret
+///   - `Ok(Json<CreateWebhookResponse>)` - Successfully registered the webhook
+///   - `Err(StatusCode::BAD_REQUEST)` - Invalid URL or thresholds, or too many webhooks. The URL
+///     must be http(s), and its host must only resolve to public IP addresses
+///   - `Err(StatusCode::UNAUTHORIZED)` - Missing or invalid access token
+///   - `Err(StatusCode::INTERNAL_SERVER_ERROR)` - Failed to register the webhook
+#[utoipa::path(
+    post,
+    path = "",
+    security(
+        ("bearerAuth" = [])
+    ),
+    request_body = CreateWebhookRequest,
+    responses(
+        (status = OK, description = "Registers a webhook for the user"),
+        (status = BAD_REQUEST, description = "Invalid URL or thresholds, or too many webhooks"),
+        (status = UNAUTHORIZED, description = "Missing or invalid access token"),
+        (status = INTERNAL_SERVER_ERROR, description = "Failed to register webhook")
+    )
+)]
+#[instrument(level = "info", skip_all)]
+pub async fn create_webhook(
+    State(proxy_service_state): State<ProxyServiceState>,
+    headers: HeaderMap,
+    body: Json<CreateWebhookRequest>,
+) -> Result<Json<CreateWebhookResponse>> {
+    // NOTE: Webhooks are delivered from the proxy, so they must not target its internal network
+    let is_valid_url = resolve_webhook_url(&body.url).await.is_ok();
+    let is_valid_threshold = |threshold: Option<i64>| threshold.is_none_or(|t| t >= 0);
+    if !is_valid_url
+        || !is_valid_threshold(body.low_balance_threshold)
+        || !is_valid_threshold(body.monthly_spend_limit)
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let user_id = get_user_id(&proxy_service_state, &headers).await?;
+    let num_webhooks = proxy_service_state
+        .atoma_state
+        .get_webhooks_for_user(user_id)
+        .await
+        .map_err(|_| {
+            error!("Failed to get webhooks");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .len();
+    if num_webhooks >= MAX_WEBHOOKS_PER_USER {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let secret = rand::thread_rng()
+        .gen::<[u8; WEBHOOK_SECRET_SIZE]>()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>();
+    let webhook = proxy_service_state
+        .atoma_state
+        .insert_webhook(
+            user_id,
+            &body.url,
+            &secret,
+            body.low_balance_threshold,
+            body.monthly_spend_limit,
+        )
+        .await
+        .map_err(|_| {
+            error!("Failed to register webhook");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(Json(CreateWebhookResponse { webhook, secret }))
+}
+
+/// OpenAPI documentation for the delete_webhook endpoint.
+///
+/// This struct is used to generate OpenAPI documentation for the delete_webhook
+/// endpoint. It uses the `utoipa` crate's derive macro to automatically generate
+/// the OpenAPI specification from the code.
+#[derive(OpenApi)]
+#[openapi(paths(delete_webhook))]
+pub struct DeleteWebhook;
+
+/// Deletes a webhook of the user, based on the access token.
+///
+/// # Arguments
+/// * `proxy_service_state` - The shared state containing the state manager
+/// * `headers` - The headers of the request
+/// * `body` - The request body containing the id of the webhook to delete
+///
+/// # Returns
+/// * `Result<Json<()>>` - A JSON response indicating the success of the operation
+///   - `Err(StatusCode::NOT_FOUND)` - The user has no webhook with this id
+///   - `Err(StatusCode::UNAUTHORIZED)` - Missing or invalid access token
+///   - `Err(StatusCode::INTERNAL_SERVER_ERROR)` - Failed to delete the webhook
+#[utoipa::path(
+    post,
+    path = "",
+    security(
+        ("bearerAuth" = [])
+    ),
+    request_body = DeleteWebhookRequest,
+    responses(
+        (status = OK, description = "Deletes a webhook of the user"),
+        (status = NOT_FOUND, description = "Webhook not found"),
+        (status = UNAUTHORIZED, description = "Missing or invalid access token"),
+        (status = INTERNAL_SERVER_ERROR, description = "Failed to delete webhook")
+    )
+)]
+#[instrument(level = "info", skip_all)]
+pub async fn delete_webhook(
+    State(proxy_service_state): State<ProxyServiceState>,
+    headers: HeaderMap,
+    body: Json<DeleteWebhookRequest>,
+) -> Result<Json<()>> {
+    let user_id = get_user_id(&proxy_service_state, &headers).await?;
+    proxy_service_state
+        .atoma_state
+        .delete_webhook(user_id, body.webhook_id)
+        .await
+        .map_err(|e| match e {
+            AtomaStateManagerError::WebhookNotFound => StatusCode::NOT_FOUND,
+            _ => {
+                error!("Failed to delete webhook: {e:?}");
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        })?;
+    Ok(Json(()))
+}
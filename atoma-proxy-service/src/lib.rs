@@ -20,4 +20,8 @@ pub enum ModelModality {
     #[serde(rename = "Images Generations")]
     ImagesGenerations,
     Embeddings,
+    #[serde(rename = "Audio Transcriptions")]
+    AudioTranscriptions,
+    #[serde(rename = "Audio Speech")]
+    AudioSpeech,
 }
@@ -17,7 +17,8 @@ use crate::{
     components::{grafana::Grafana, openapi::openapi_router},
     handlers::{
         auth::auth_router, stacks::stacks_router, stats::stats_router,
-        subscriptions::subscriptions_router, tasks::tasks_router,
+        subscriptions::subscriptions_router, tasks::tasks_router, usage::usage_router,
+        webhooks::webhooks_router,
     },
     ModelModality,
 };
@@ -160,6 +161,8 @@ pub fn create_proxy_service_router(proxy_service_state: ProxyServiceState) -> Ro
         .merge(subscriptions_router())
         .merge(tasks_router())
         .merge(stats_router())
+        .merge(usage_router())
+        .merge(webhooks_router())
         .layer(cors)
         .with_state(proxy_service_state)
         .route(HEALTH_PATH, get(health))
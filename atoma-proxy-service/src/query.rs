@@ -1,3 +1,4 @@
+use chrono::{DateTime, Utc};
 use serde::Deserialize;
 
 /// A query params for latency requests. Since the latencies are on hourly basis. It will return last `LatencyQuery::hours` hours of latencies.
@@ -17,3 +18,12 @@ pub struct ComputeUnitsProcessedQuery {
 pub struct StatsStackQuery {
     pub hours: usize,
 }
+
+/// A query params for usage requests. It will return the `UsageQuery::page` page of `UsageQuery::page_size` usage records, optionally restricted to the usage records created between `UsageQuery::start` and `UsageQuery::end`.
+#[derive(Deserialize)]
+pub struct UsageQuery {
+    pub page: Option<i64>,
+    pub page_size: Option<i64>,
+    pub start: Option<DateTime<Utc>>,
+    pub end: Option<DateTime<Utc>>,
+}
@@ -11,10 +11,7 @@ use atoma_state::{
 use atoma_sui::{config::Config as AtomaSuiConfig, subscriber::Subscriber};
 use atoma_utils::spawn_with_shutdown;
 use clap::Parser;
-use futures::future::try_join_all;
-use hf_hub::{api::sync::ApiBuilder, Repo, RepoType};
 use sui_keys::keystore::FileBasedKeystore;
-use tokenizers::Tokenizer;
 use tokio::{net::TcpListener, sync::watch, sync::RwLock, try_join};
 use tracing::{error, info, instrument};
 
@@ -81,7 +78,7 @@ async fn main() -> Result<()> {
     let args = Args::parse();
     tracing::info!("Loading configuration from: {}", args.config_path);
 
-    let config = Config::load(args.config_path);
+    let config = Config::load(args.config_path.clone());
     tracing::info!("Configuration loaded successfully");
 
     let (shutdown_sender, mut shutdown_receiver) = watch::channel(false);
@@ -110,17 +107,10 @@ async fn main() -> Result<()> {
         AtomaP2pNode::start(config.p2p, Arc::new(keystore), atoma_p2p_sender, true)?;
 
     let (metrics_collector_sender, metrics_collector_receiver) = flume::unbounded();
-    let (_request_best_available_models_sender, request_best_available_models_receiver) =
+    let (request_best_available_models_sender, request_best_available_models_receiver) =
         flume::unbounded();
     let node_metrics_collector = NodeMetricsCollector::new()?;
 
-    let tokenizers = initialize_tokenizers(
-        &config.service.models,
-        &config.service.revisions,
-        &config.service.hf_token,
-    )
-    .await?;
-
     let metrics_collector_handle = spawn_with_shutdown(
         trigger_new_metrics_collection_task(
             node_metrics_collector,
@@ -143,6 +133,15 @@ async fn main() -> Result<()> {
     )
     .await?;
 
+    // API tokens created before they were hashed at rest are still stored in plaintext
+    let hashed_api_tokens = state_manager
+        .state
+        .hash_plaintext_api_tokens(|api_token| auth.hash_api_token(api_token))
+        .await?;
+    if hashed_api_tokens > 0 {
+        info!("Hashed {hashed_api_tokens} plaintext API tokens");
+    }
+
     let state_manager_handle = spawn_with_shutdown(
         state_manager.run(shutdown_receiver.clone()),
         shutdown_sender.clone(),
@@ -165,8 +164,10 @@ async fn main() -> Result<()> {
         start_server(
             config.service,
             state_manager_sender,
+            request_best_available_models_sender,
             sui,
-            tokenizers,
+            auth.clone(),
+            args.config_path,
             shutdown_receiver.clone(),
         ),
         shutdown_sender.clone(),
@@ -247,75 +248,6 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-/// Initializes tokenizers for multiple models by fetching their configurations from HuggingFace.
-///
-/// This function concurrently fetches tokenizer configurations for multiple models from HuggingFace's
-/// repository and initializes them. Each tokenizer is wrapped in an Arc for safe sharing across threads.
-///
-/// # Arguments
-///
-/// * `models` - A slice of model names/paths on HuggingFace (e.g., ["facebook/opt-125m"])
-/// * `revisions` - A slice of revision/branch names corresponding to each model (e.g., ["main"])
-/// * `hf_token` - The HuggingFace API token to use for fetching the tokenizer configurations
-///
-/// # Returns
-///
-/// Returns a `Result<()>`, which is `Ok(())` if all tasks succeeded, or an error if:
-/// - Failed to fetch tokenizer configuration from HuggingFace
-/// - Failed to parse the tokenizer JSON
-/// - Any other network or parsing errors occur
-///
-/// # Examples
-///
-/// ```rust,ignore
-/// use anyhow::Result;
-///
-/// #[tokio::main]
-/// async fn example() -> Result<()> {
-///     let models = vec!["facebook/opt-125m".to_string()];
-///     let revisions = vec!["main".to_string()];
-///
-///     let tokenizers = initialize_tokenizers(&models, &revisions).await?;
-///     Ok(())
-/// }
-/// ```
-#[instrument(level = "info", skip(models, revisions))]
-async fn initialize_tokenizers(
-    models: &[String],
-    revisions: &[String],
-    hf_token: &String,
-) -> Result<Vec<Arc<Tokenizer>>> {
-    let api = ApiBuilder::new()
-        .with_progress(true)
-        .with_token(Some(hf_token.clone()))
-        .build()?;
-    let fetch_futures: Vec<_> = models
-        .iter()
-        .zip(revisions.iter())
-        .map(|(model, revision)| {
-            let api = api.clone();
-            async move {
-                let repo = api.repo(Repo::with_revision(
-                    model.clone(),
-                    RepoType::Model,
-                    revision.clone(),
-                ));
-                let tokenizer_filename = repo.get("tokenizer.json")?;
-                Tokenizer::from_file(tokenizer_filename)
-                    .map_err(|e| {
-                        anyhow::anyhow!(format!(
-                            "Failed to parse tokenizer for model {}, with error: {}",
-                            model, e
-                        ))
-                    })
-                    .map(Arc::new)
-            }
-        })
-        .collect();
-
-    try_join_all(fetch_futures).await
-}
-
 /// Handles the results of various tasks (subscriber, state manager, and server).
 ///
 /// This function checks the results of the subscriber, state manager, and server tasks.
@@ -0,0 +1,353 @@
+use std::time::Duration;
+
+use atoma_state::types::{
+    AtomaAtomaStateManagerEvent, Batch, BatchRequest, BatchRequestOutcome, BatchStatus, NewUserFile,
+};
+use futures::future::join_all;
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use sqlx::types::chrono::Utc;
+use tokio::sync::watch;
+use tracing::{error, info, instrument};
+
+use super::{
+    handlers::{files::BATCH_OUTPUT_PURPOSE, query_state_manager},
+    http_server::ProxyState,
+    loopback::{loopback_url, LOOPBACK_API_TOKEN_ID_HEADER},
+    Result,
+};
+
+/// Name of the batch worker, used as the endpoint of its errors.
+const BATCH_WORKER: &str = "batch_worker";
+
+/// Runs the batch worker, until the proxy shuts down.
+///
+/// The worker executes the requests of the batches that are not finalized yet, with at most
+/// `max_concurrency` requests of a batch in flight, and finalizes the batches that are completed,
+/// cancelled or expired, writing their output and error files.
+#[instrument(level = "info", skip_all)]
+pub async fn run_batch_worker(state: ProxyState, mut shutdown_receiver: watch::Receiver<bool>) {
+    let client = reqwest::Client::new();
+    // NOTE: Requests that were running when the proxy stopped are executed again
+    match query_state_manager(
+        &state.state_manager_sender,
+        |result_sender| AtomaAtomaStateManagerEvent::ReleaseRunningBatchRequests { result_sender },
+        BATCH_WORKER,
+    )
+    .await
+    {
+        Ok(0) => {}
+        Ok(num_released) => info!(
+            target = "atoma-service",
+            event = "batch-worker",
+            "Released {num_released} batch requests interrupted by the last shutdown"
+        ),
+        Err(e) => error!(
+            target = "atoma-service",
+            event = "batch-worker",
+            "Failed to release running batch requests: {e}"
+        ),
+    }
+
+    let poll_interval = Duration::from_secs(state.batches.poll_interval_secs);
+    loop {
+        let is_busy = process_active_batches(&state, &client)
+            .await
+            .unwrap_or_else(|e| {
+                error!(
+                    target = "atoma-service",
+                    event = "batch-worker",
+                    "Failed to process batches: {e}"
+                );
+                false
+            });
+        if *shutdown_receiver.borrow() {
+            break;
+        }
+        if is_busy {
+            continue;
+        }
+        tokio::select! {
+            () = tokio::time::sleep(poll_interval) => {}
+            _ = shutdown_receiver.changed() => break,
+        }
+    }
+}
+
+/// Executes the next requests of every active batch, and finalizes the batches that are done.
+///
+/// Returns whether any request was executed, in which case the worker looks for more requests
+/// right away, instead of waiting for the poll interval.
+async fn process_active_batches(state: &ProxyState, client: &reqwest::Client) -> Result<bool> {
+    let batches = query_state_manager(
+        &state.state_manager_sender,
+        |result_sender| AtomaAtomaStateManagerEvent::GetActiveBatches { result_sender },
+        BATCH_WORKER,
+    )
+    .await?;
+
+    let mut is_busy = false;
+    for batch in batches {
+        if batch.status == BatchStatus::Cancelling.as_str() {
+            finalize_batch(state, &batch, BatchStatus::Cancelled).await?;
+            continue;
+        }
+        if batch.expires_at <= Utc::now() {
+            finalize_batch(state, &batch, BatchStatus::Expired).await?;
+            continue;
+        }
+        if batch.completed_requests + batch.failed_requests >= batch.total_requests {
+            finalize_batch(state, &batch, BatchStatus::Completed).await?;
+            continue;
+        }
+        let requests = query_state_manager(
+            &state.state_manager_sender,
+            |result_sender| AtomaAtomaStateManagerEvent::ClaimBatchRequests {
+                batch_id: batch.id.clone(),
+                limit: i64::try_from(state.batches.max_concurrency).unwrap_or(i64::MAX),
+                result_sender,
+            },
+            BATCH_WORKER,
+        )
+        .await?;
+        let outcomes = join_all(
+            requests
+                .into_iter()
+                .map(|request| execute_batch_request(state, client, &batch, request)),
+        )
+        .await;
+        is_busy |= outcomes.into_iter().any(|is_completed| is_completed);
+    }
+    Ok(is_busy)
+}
+
+/// Executes a request of a batch, by sending it to the proxy itself, and records its outcome.
+///
+/// Requests that are rate limited, or for which no stack is available yet, are released to be
+/// executed again later, and do not count as failed.
+///
+/// Returns whether the outcome of the request was recorded.
+#[instrument(level = "debug", skip_all, fields(batch_id = %batch.id, line_index = request.line_index))]
+async fn execute_batch_request(
+    state: &ProxyState,
+    client: &reqwest::Client,
+    batch: &Batch,
+    request: BatchRequest,
+) -> bool {
+    let outcome = match serde_json::from_str::<Value>(&request.body) {
+        Ok(mut body) => {
+            // NOTE: The responses of batch requests are stored whole, so they are never streamed
+            if let Some(body) = body.as_object_mut() {
+                body.remove("stream");
+                body.remove("stream_options");
+            }
+            let response = client
+                .post(loopback_url(state, &batch.endpoint))
+                .bearer_auth(state.loopback_secret.as_ref())
+                .header(LOOPBACK_API_TOKEN_ID_HEADER, batch.api_token_id)
+                .json(&body)
+                .send()
+                .await;
+            match response {
+                Ok(response)
+                    if [
+                        StatusCode::TOO_MANY_REQUESTS,
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        StatusCode::TOO_EARLY,
+                    ]
+                    .contains(&response.status()) =>
+                {
+                    None
+                }
+                Ok(response) => {
+                    let status = response.status();
+                    match response.text().await {
+                        Ok(response_body) => Some(BatchRequestOutcome {
+                            succeeded: status.is_success(),
+                            response_status: Some(i32::from(status.as_u16())),
+                            response_body: Some(response_body),
+                            error: None,
+                        }),
+                        Err(e) => Some(request_failed(&format!("Failed to read response: {e}"))),
+                    }
+                }
+                Err(e) => Some(request_failed(&format!("Failed to send request: {e}"))),
+            }
+        }
+        Err(e) => Some(request_failed(&format!("Invalid request body: {e}"))),
+    };
+
+    let event = match outcome {
+        Some(outcome) => AtomaAtomaStateManagerEvent::CompleteBatchRequest {
+            batch_id: batch.id.clone(),
+            line_index: request.line_index,
+            outcome,
+        },
+        None => AtomaAtomaStateManagerEvent::ReleaseBatchRequest {
+            batch_id: batch.id.clone(),
+            line_index: request.line_index,
+        },
+    };
+    let is_completed = matches!(
+        event,
+        AtomaAtomaStateManagerEvent::CompleteBatchRequest { .. }
+    );
+    if let Err(e) = state.state_manager_sender.send(event) {
+        error!(
+            target = "atoma-service",
+            event = "batch-worker",
+            "Failed to record the outcome of batch request: {e}"
+        );
+        return false;
+    }
+    is_completed
+}
+
+/// The outcome of a request of a batch that could not be executed.
+fn request_failed(message: &str) -> BatchRequestOutcome {
+    BatchRequestOutcome {
+        succeeded: false,
+        response_status: None,
+        response_body: None,
+        error: Some(json!({ "code": "request_failed", "message": message }).to_string()),
+    }
+}
+
+/// Finalizes a batch, writing the responses of its successful requests to its output file,
+/// and the responses of its failed (or never executed) requests to its error file.
+#[instrument(level = "info", skip_all, fields(batch_id = %batch.id, status = status.as_str()))]
+async fn finalize_batch(state: &ProxyState, batch: &Batch, status: BatchStatus) -> Result<()> {
+    let requests = query_state_manager(
+        &state.state_manager_sender,
+        |result_sender| AtomaAtomaStateManagerEvent::GetBatchRequests {
+            batch_id: batch.id.clone(),
+            result_sender,
+        },
+        BATCH_WORKER,
+    )
+    .await?;
+
+    let mut output = Vec::new();
+    let mut errors = Vec::new();
+    for request in requests {
+        let (lines, line) = match request.status.as_str() {
+            "completed" => (&mut output, batch_output_line(&request)),
+            "failed" => (&mut errors, batch_output_line(&request)),
+            _ => {
+                let (code, message) = if status == BatchStatus::Expired {
+                    (
+                        "batch_expired",
+                        "The batch expired before the request was executed",
+                    )
+                } else {
+                    (
+                        "batch_cancelled",
+                        "The batch was cancelled before the request was executed",
+                    )
+                };
+                let line = json!({
+                    "id": format!("batch_req_{}", uuid::Uuid::new_v4().simple()),
+                    "custom_id": request.custom_id,
+                    "response": null,
+                    "error": { "code": code, "message": message },
+                });
+                (&mut errors, line)
+            }
+        };
+        lines.extend_from_slice(line.to_string().as_bytes());
+        lines.push(b'\n');
+    }
+
+    let batch_file = |suffix: &str, content: Vec<u8>| {
+        (!content.is_empty()).then(|| NewUserFile {
+            id: format!("file-{}", uuid::Uuid::new_v4().simple()),
+            user_id: batch.user_id,
+            purpose: BATCH_OUTPUT_PURPOSE.to_string(),
+            filename: format!("{}_{suffix}.jsonl", batch.id),
+            content,
+        })
+    };
+    let output_file = batch_file("output", output);
+    let error_file = batch_file("error", errors);
+    query_state_manager(
+        &state.state_manager_sender,
+        |result_sender| AtomaAtomaStateManagerEvent::FinalizeBatch {
+            batch_id: batch.id.clone(),
+            status,
+            output_file,
+            error_file,
+            result_sender,
+        },
+        BATCH_WORKER,
+    )
+    .await?;
+    info!(
+        target = "atoma-service",
+        event = "batch-worker",
+        "Finalized batch {} as {}",
+        batch.id,
+        status.as_str()
+    );
+    Ok(())
+}
+
+/// Formats the outcome of an executed request of a batch, as a line of its output or error file.
+fn batch_output_line(request: &BatchRequest) -> Value {
+    let parse = |value: &Option<String>| {
+        value.as_ref().map(|value| {
+            serde_json::from_str::<Value>(value).unwrap_or_else(|_| Value::String(value.clone()))
+        })
+    };
+    let response = request.response_status.map(|status_code| {
+        json!({
+            "status_code": status_code,
+            "body": parse(&request.response_body),
+        })
+    });
+    json!({
+        "id": format!("batch_req_{}", uuid::Uuid::new_v4().simple()),
+        "custom_id": request.custom_id,
+        "response": response,
+        "error": parse(&request.error),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn batch_request(status: &str) -> BatchRequest {
+        BatchRequest {
+            batch_id: "batch_1".to_string(),
+            line_index: 0,
+            custom_id: "request-1".to_string(),
+            body: "{}".to_string(),
+            status: status.to_string(),
+            response_status: None,
+            response_body: None,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_batch_output_line() {
+        let request = BatchRequest {
+            response_status: Some(200),
+            response_body: Some(r#"{"object": "chat.completion"}"#.to_string()),
+            ..batch_request("completed")
+        };
+        let line = batch_output_line(&request);
+        assert_eq!(line["custom_id"], "request-1");
+        assert_eq!(line["response"]["status_code"], 200);
+        assert_eq!(line["response"]["body"]["object"], "chat.completion");
+        assert_eq!(line["error"], Value::Null);
+
+        let request = BatchRequest {
+            error: request_failed("Failed to send request").error,
+            ..batch_request("failed")
+        };
+        let line = batch_output_line(&request);
+        assert_eq!(line["response"], Value::Null);
+        assert_eq!(line["error"]["code"], "request_failed");
+    }
+}
@@ -13,14 +13,23 @@ use crate::server::handlers::image_generations::{
     ConfidentialImageGenerationsOpenApi, CONFIDENTIAL_IMAGE_GENERATIONS_PATH,
 };
 use crate::server::handlers::{
+    audio::{
+        AudioSpeechOpenApi, AudioTranscriptionsOpenApi, AUDIO_SPEECH_PATH,
+        AUDIO_TRANSCRIPTIONS_PATH,
+    },
+    batches::{BatchesOpenApi, BATCHES_PATH},
     chat_completions::{ChatCompletionsOpenApi, CompletionsOpenApi},
     chat_completions::{CHAT_COMPLETIONS_PATH, COMPLETIONS_PATH},
     embeddings::EmbeddingsOpenApi,
     embeddings::EMBEDDINGS_PATH,
+    files::{FilesOpenApi, FILES_PATH},
     image_generations::ImageGenerationsOpenApi,
     image_generations::IMAGE_GENERATIONS_PATH,
+    messages::{MessagesOpenApi, MESSAGES_PATH},
     models::{ModelsOpenApi, OpenRouterModelsListApi, MODELS_PATH, OPEN_ROUTER_MODELS_PATH},
     nodes::NodesOpenApi,
+    responses::{ResponsesOpenApi, RESPONSES_PATH},
+    verify::{VerifyOpenApi, VERIFY_PATH},
 };
 use crate::server::handlers::{
     chat_completions::{ConfidentialChatCompletionsOpenApi, CONFIDENTIAL_CHAT_COMPLETIONS_PATH},
@@ -34,28 +43,41 @@ pub fn openapi_routes() -> Router {
     #[openapi(
         modifiers(&SpeakeasyExtension, &SecurityAddon),
         nest(
+            (path = AUDIO_SPEECH_PATH, api = AudioSpeechOpenApi, tags = ["Audio"]),
+            (path = AUDIO_TRANSCRIPTIONS_PATH, api = AudioTranscriptionsOpenApi, tags = ["Audio"]),
+            (path = BATCHES_PATH, api = BatchesOpenApi, tags = ["Batches"]),
             (path = COMPLETIONS_PATH, api = CompletionsOpenApi, tags = ["Chat"]),
             (path = CHAT_COMPLETIONS_PATH, api = ChatCompletionsOpenApi, tags = ["Chat"]),
             (path = CONFIDENTIAL_CHAT_COMPLETIONS_PATH, api = ConfidentialChatCompletionsOpenApi, tags = ["Confidential Chat"]),
             (path = CONFIDENTIAL_EMBEDDINGS_PATH, api = ConfidentialEmbeddingsOpenApi, tags = ["Confidential Embeddings"]),
             (path = CONFIDENTIAL_IMAGE_GENERATIONS_PATH, api = ConfidentialImageGenerationsOpenApi, tags = ["Confidential Images"]),
             (path = EMBEDDINGS_PATH, api = EmbeddingsOpenApi, tags = ["Embeddings"]),
+            (path = FILES_PATH, api = FilesOpenApi, tags = ["Files"]),
             (path = HEALTH_PATH, api = HealthOpenApi, tags = ["Health"]),
             (path = IMAGE_GENERATIONS_PATH, api = ImageGenerationsOpenApi, tags = ["Images"]),
+            (path = MESSAGES_PATH, api = MessagesOpenApi, tags = ["Messages"]),
             (path = MODELS_PATH, api = ModelsOpenApi, tags = ["Models"]),
             (path = OPEN_ROUTER_MODELS_PATH, api = OpenRouterModelsListApi, tags = ["Models"]),
             (path = NODES_PATH, api = NodesOpenApi, tags = ["Nodes"]),
+            (path = RESPONSES_PATH, api = ResponsesOpenApi, tags = ["Responses"]),
+            (path = VERIFY_PATH, api = VerifyOpenApi, tags = ["Verify"]),
         ),
         tags(
+            (name = "Audio", description = "OpenAI's API audio v1 endpoints"),
+            (name = "Batches", description = "OpenAI's API batches v1 endpoints"),
             (name = "Chat", description = "OpenAI's API chat completions v1 endpoint"),
             (name = "Confidential Chat", description = "Atoma's API confidential chat completions v1 endpoint"),
             (name = "Confidential Embeddings", description = "Atoma's API confidential embeddings v1 endpoint"),
             (name = "Confidential Images", description = "Atoma's API confidential images v1 endpoint"),
             (name = "Embeddings", description = "OpenAI's API embeddings v1 endpoint"),
+            (name = "Files", description = "OpenAI's API files v1 endpoints"),
             (name = "Health", description = "Health check"),
             (name = "Images", description = "OpenAI's API images v1 endpoint"),
+            (name = "Messages", description = "Anthropic's API messages v1 endpoint"),
             (name = "Models", description = "OpenAI's API models v1 endpoint"),
             (name = "Nodes", description = "Nodes Management"),
+            (name = "Responses", description = "OpenAI's API responses v1 endpoint"),
+            (name = "Verify", description = "Verification of the responses signed by the nodes"),
             (name = "Node Public Key Selection", description = "Node public key selection")
         ),
         servers(
@@ -1,8 +1,9 @@
-use std::path::Path;
+use std::{collections::HashMap, path::Path};
 
 use atoma_proxy_service::ModelModality;
 use serde::Deserialize;
 
+use anyhow::Context;
 use config::{Config, File};
 
 /// Configuration for the Atoma Service.
@@ -40,8 +41,600 @@ pub struct AtomaServiceConfig {
     /// requests to the Hugging Face API.
     pub hf_token: String,
 
-    /// Path to open router json.
-    pub open_router_models_file: String,
+    /// Path to the JSON file of per-model overrides of the OpenRouter models feed.
+    ///
+    /// Entries of the file, in the OpenRouter format, are merged into the entries generated
+    /// for the models of the same id.
+    #[serde(default)]
+    pub open_router_models_file: Option<String>,
+
+    /// Time, in seconds, between two refreshes of the OpenRouter models feed.
+    #[serde(default = "default_open_router_refresh_interval_secs")]
+    pub open_router_refresh_interval_secs: u64,
+
+    /// Node selection policy used when the proxy acquires a new stack.
+    ///
+    /// When this section is missing from the configuration file, the proxy
+    /// always buys stacks from the cheapest node.
+    #[serde(default)]
+    pub node_selection: NodeSelectionConfig,
+
+    /// Rate limits applied to authenticated requests, per user and per API token.
+    ///
+    /// When this section is missing from the configuration file, requests are not rate limited.
+    #[serde(default)]
+    pub rate_limits: Option<RateLimitConfig>,
+
+    /// Circuit breaker thresholds used to eject unhealthy nodes from node and stack selection.
+    ///
+    /// When this section is missing from the configuration file, the default thresholds are used.
+    #[serde(default)]
+    pub node_health: NodeHealthConfig,
+
+    /// API key required to access the admin endpoints, as a bearer token.
+    ///
+    /// When this field is missing from the configuration file, the admin endpoints are disabled.
+    #[serde(default)]
+    pub admin_api_key: Option<String>,
+
+    /// How users are charged for the stacks bought on their behalf.
+    ///
+    /// When this field is missing from the configuration file, users are charged for whole stacks.
+    #[serde(default)]
+    pub billing_mode: BillingMode,
+
+    /// How image content parts of chat completion requests are converted to input tokens,
+    /// and which images are accepted, per model.
+    ///
+    /// When this section is missing from the configuration file, the default policy is used for all models.
+    #[serde(default)]
+    pub image_tokens: ImageTokensConfig,
+
+    /// Context window of the models, used to reject the requests that do not fit in it
+    /// before compute units are locked or a stack is bought.
+    ///
+    /// When this section is missing from the configuration file, requests are only rejected by the nodes.
+    #[serde(default)]
+    pub model_limits: ModelLimitsConfig,
+
+    /// Limits of the batch API, and concurrency of the batch worker.
+    ///
+    /// When this section is missing from the configuration file, the default limits are used.
+    #[serde(default)]
+    pub batches: BatchConfig,
+
+    /// Exact-match cache of the responses of embeddings and deterministic chat completion requests.
+    ///
+    /// When this section is missing from the configuration file, responses are not cached.
+    #[serde(default)]
+    pub response_cache: Option<ResponseCacheConfig>,
+
+    /// Replay buffers of the chat completion streams, for clients to resume a stream once their
+    /// connection dropped, with the `Last-Event-ID` header.
+    ///
+    /// When this section is missing from the configuration file, streams cannot be resumed, and
+    /// the generation is stopped as soon as the client disconnects.
+    #[serde(default)]
+    pub resumable_streams: Option<ResumableStreamsConfig>,
+
+    /// Aliases of the models, and fallback models used when no node can serve a model.
+    ///
+    /// When this section is missing from the configuration file, clients must request the exact
+    /// models listed in `models`, and requests are never served by another model.
+    #[serde(default)]
+    pub model_routing: ModelRoutingConfig,
+
+    /// Sources of the tokenizers of the models, used to estimate the compute units of requests.
+    ///
+    /// When this section is missing from the configuration file, tokenizers are downloaded
+    /// from the Hugging Face hub.
+    #[serde(default)]
+    pub tokenizers: TokenizersConfig,
+}
+
+/// Default time, in seconds, between two refreshes of the OpenRouter models feed.
+const fn default_open_router_refresh_interval_secs() -> u64 {
+    60
+}
+
+/// Configuration of the sources of the tokenizers.
+///
+/// A model's tokenizer is read from the file configured for it, else from the tokenizers
+/// directory, else downloaded from the Hugging Face hub (unless `offline` is set). Files with
+/// the `.tiktoken` extension are read as tiktoken BPE ranks, other files as Hugging Face tokenizers.
+#[derive(Clone, Debug, Deserialize)]
+pub struct TokenizersConfig {
+    /// Directory holding the tokenizers, as `<directory>/<model>/tokenizer.json`
+    /// or `<directory>/<model>/tokenizer.tiktoken`.
+    #[serde(default)]
+    pub directory: Option<String>,
+
+    /// Tokenizer files, indexed by model.
+    #[serde(default)]
+    pub files: HashMap<String, String>,
+
+    /// Whether tokenizers are only read from local files, and never downloaded.
+    #[serde(default)]
+    pub offline: bool,
+
+    /// Time, in seconds, between two attempts to load the tokenizers that failed to load.
+    #[serde(default = "default_tokenizers_retry_interval_secs")]
+    pub retry_interval_secs: u64,
+}
+
+impl Default for TokenizersConfig {
+    fn default() -> Self {
+        Self {
+            directory: None,
+            files: HashMap::new(),
+            offline: false,
+            retry_interval_secs: default_tokenizers_retry_interval_secs(),
+        }
+    }
+}
+
+/// Default time, in seconds, between two attempts to load the tokenizers.
+const fn default_tokenizers_retry_interval_secs() -> u64 {
+    30
+}
+
+/// Configuration of the model aliases and fallback models.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ModelRoutingConfig {
+    /// Models, indexed by their short alias (e.g. `llama-3.3-70b`).
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+
+    /// Models (or aliases) tried in order, indexed by model (or alias), when no node is available
+    /// for the model, or all its nodes failed to process the request.
+    #[serde(default)]
+    pub fallbacks: HashMap<String, Vec<String>>,
+}
+
+/// Configuration of the exact-match response cache.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ResponseCacheConfig {
+    /// Time, in seconds, a response stays cached.
+    #[serde(default = "default_response_cache_ttl_secs")]
+    pub ttl_secs: u64,
+
+    /// Maximum total size, in bytes, of the responses cached in memory.
+    ///
+    /// The oldest responses are evicted first, once the limit is reached.
+    #[serde(default = "default_response_cache_max_memory_bytes")]
+    pub max_memory_bytes: usize,
+
+    /// Whether responses are also cached in Postgres, to be shared by all the proxy instances
+    /// and to survive restarts.
+    #[serde(default)]
+    pub postgres: bool,
+
+    /// How users are charged for the requests served from the cache.
+    #[serde(default)]
+    pub hit_billing: CacheHitBilling,
+}
+
+/// Default time, in seconds, a response stays cached.
+const fn default_response_cache_ttl_secs() -> u64 {
+    3600
+}
+
+/// Default maximum total size of the responses cached in memory, 64MB.
+const fn default_response_cache_max_memory_bytes() -> usize {
+    64 * 1024 * 1024
+}
+
+/// Configuration of the resumable chat completion streams.
+///
+/// Streams that end while the client is disconnected, within the resume window, are billed in
+/// full, as the client can still resume them to receive the remaining chunks.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ResumableStreamsConfig {
+    /// Time, in seconds, the generation goes on after the client disconnected, waiting for it
+    /// to resume the stream. Once elapsed, the generation is stopped.
+    #[serde(default = "default_resumable_streams_resume_window_secs")]
+    pub resume_window_secs: u64,
+
+    /// Time, in seconds, a stream can still be resumed after the generation ended.
+    #[serde(default = "default_resumable_streams_retention_secs")]
+    pub retention_secs: u64,
+
+    /// Maximum number of chunks already delivered to the client retained per stream, to be
+    /// replayed. Chunks not yet delivered are always retained.
+    #[serde(default = "default_resumable_streams_max_events")]
+    pub max_events: usize,
+}
+
+/// Default time, in seconds, the generation goes on after the client disconnected.
+const fn default_resumable_streams_resume_window_secs() -> u64 {
+    30
+}
+
+/// Default time, in seconds, a stream can be resumed after the generation ended.
+const fn default_resumable_streams_retention_secs() -> u64 {
+    60
+}
+
+/// Default maximum number of delivered chunks retained per stream.
+const fn default_resumable_streams_max_events() -> usize {
+    4096
+}
+
+/// How users are charged for the requests served from the response cache.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheHitBilling {
+    /// Cache hits are free, they are only recorded in the usage ledger.
+    #[default]
+    Free,
+
+    /// Cache hits are charged the compute units reported in the usage of the cached response,
+    /// at the price of the cheapest node serving the model, directly to the balance of the user.
+    Full,
+}
+
+/// Configuration of the batch API and of the background worker executing the batches.
+#[derive(Clone, Debug, Deserialize)]
+pub struct BatchConfig {
+    /// Maximum number of requests of a batch executed concurrently.
+    #[serde(default = "default_batch_max_concurrency")]
+    pub max_concurrency: usize,
+
+    /// Time, in seconds, the batch worker waits before looking for new batches, when it is idle.
+    #[serde(default = "default_batch_poll_interval_secs")]
+    pub poll_interval_secs: u64,
+
+    /// Maximum size of an uploaded batch input file, in bytes.
+    #[serde(default = "default_batch_max_file_size_bytes")]
+    pub max_file_size_bytes: usize,
+
+    /// Maximum number of requests of a batch.
+    #[serde(default = "default_batch_max_requests")]
+    pub max_requests: usize,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrency: default_batch_max_concurrency(),
+            poll_interval_secs: default_batch_poll_interval_secs(),
+            max_file_size_bytes: default_batch_max_file_size_bytes(),
+            max_requests: default_batch_max_requests(),
+        }
+    }
+}
+
+/// Default maximum number of requests of a batch executed concurrently.
+const fn default_batch_max_concurrency() -> usize {
+    8
+}
+
+/// Default time, in seconds, the batch worker waits before looking for new batches.
+const fn default_batch_poll_interval_secs() -> u64 {
+    5
+}
+
+/// Default maximum size of an uploaded batch input file, 50MB.
+const fn default_batch_max_file_size_bytes() -> usize {
+    50 * 1024 * 1024
+}
+
+/// Default maximum number of requests of a batch.
+const fn default_batch_max_requests() -> usize {
+    50_000
+}
+
+/// How users are charged, from their prepaid USDC balance, for the stacks bought on their behalf.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BillingMode {
+    /// Charge the price of the whole stack when it is bought.
+    #[default]
+    Stack,
+
+    /// Reserve the price of the estimated compute units of each request, and settle it
+    /// to the actual compute units used once the request completes.
+    Metered,
+}
+
+/// Strategy used to select the node on which the proxy buys a new stack.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeSelectionStrategy {
+    /// Select the node with the cheapest price per one million compute units.
+    #[default]
+    Cheapest,
+
+    /// Select the best performing node, as ranked by the metrics collection task,
+    /// falling back to the cheapest node when no fresh ranking is available.
+    BestPerformance,
+}
+
+/// Configuration for the node selection policy.
+#[derive(Clone, Debug, Deserialize)]
+pub struct NodeSelectionConfig {
+    /// The node selection strategy.
+    #[serde(default)]
+    pub strategy: NodeSelectionStrategy,
+
+    /// Maximum price per one million compute units that the proxy is willing to pay
+    /// for a stack on a ranked node. Ranked nodes above this price are skipped.
+    #[serde(default)]
+    pub max_price_per_one_million_compute_units: Option<i64>,
+
+    /// Maximum age, in seconds, of a node ranking before it is considered stale
+    /// and the proxy falls back to the cheapest node.
+    #[serde(default = "default_max_metrics_age_secs")]
+    pub max_metrics_age_secs: u64,
+}
+
+impl Default for NodeSelectionConfig {
+    fn default() -> Self {
+        Self {
+            strategy: NodeSelectionStrategy::default(),
+            max_price_per_one_million_compute_units: None,
+            max_metrics_age_secs: default_max_metrics_age_secs(),
+        }
+    }
+}
+
+/// Default maximum age of a node ranking, in seconds.
+///
+/// This is twice the period of the metrics collection task, so that a single
+/// failed collection does not immediately disable ranked node selection.
+const fn default_max_metrics_age_secs() -> u64 {
+    6 * 60
+}
+
+/// Configuration for the circuit breakers of the nodes.
+#[derive(Clone, Debug, Deserialize)]
+pub struct NodeHealthConfig {
+    /// Number of consecutive failures after which a node is ejected.
+    #[serde(default = "default_consecutive_failures_threshold")]
+    pub consecutive_failures_threshold: u32,
+
+    /// Error rate, between 0 and 1, over the most recent requests after which a node is ejected.
+    #[serde(default = "default_error_rate_threshold")]
+    pub error_rate_threshold: f64,
+
+    /// Number of most recent requests the error rate is computed over. The error rate
+    /// is only taken into account once a node served that many requests.
+    #[serde(default = "default_window_size")]
+    pub window_size: usize,
+
+    /// Time, in seconds, during which an ejected node is excluded from node and stack
+    /// selection, before it receives requests again.
+    #[serde(default = "default_cooldown_secs")]
+    pub cooldown_secs: u64,
+}
+
+impl Default for NodeHealthConfig {
+    fn default() -> Self {
+        Self {
+            consecutive_failures_threshold: default_consecutive_failures_threshold(),
+            error_rate_threshold: default_error_rate_threshold(),
+            window_size: default_window_size(),
+            cooldown_secs: default_cooldown_secs(),
+        }
+    }
+}
+
+/// Default number of consecutive failures after which a node is ejected.
+const fn default_consecutive_failures_threshold() -> u32 {
+    5
+}
+
+/// Default error rate after which a node is ejected.
+const fn default_error_rate_threshold() -> f64 {
+    0.5
+}
+
+/// Default number of requests the error rate of a node is computed over.
+const fn default_window_size() -> usize {
+    20
+}
+
+/// Default time, in seconds, during which an ejected node is excluded from selection.
+const fn default_cooldown_secs() -> u64 {
+    30
+}
+
+/// Configuration for the rate limits of the proxy.
+#[derive(Clone, Debug, Deserialize)]
+pub struct RateLimitConfig {
+    /// Name of the tier applied to users that are not listed in any tier.
+    pub default_tier: String,
+
+    /// Rate limit tiers, indexed by name.
+    pub tiers: HashMap<String, RateLimitTier>,
+}
+
+impl RateLimitConfig {
+    /// Returns the rate limit tier of a user, falling back to the default tier.
+    ///
+    /// Returns `None` if the user is not listed in any tier and the default tier does not exist.
+    #[must_use]
+    pub fn tier_for_user(&self, user_id: i64) -> Option<&RateLimitTier> {
+        self.tiers
+            .values()
+            .find(|tier| tier.user_ids.contains(&user_id))
+            .or_else(|| self.tiers.get(&self.default_tier))
+    }
+}
+
+/// A rate limit tier.
+#[derive(Clone, Debug, Deserialize)]
+pub struct RateLimitTier {
+    /// Users assigned to this tier.
+    #[serde(default)]
+    pub user_ids: Vec<i64>,
+
+    /// Limits shared by all the API tokens of a user.
+    pub user: RateLimit,
+
+    /// Limits applied to each API token of a user, on top of the user limits.
+    #[serde(default)]
+    pub api_token: Option<RateLimit>,
+}
+
+/// Limits of a single token bucket pair.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Maximum number of requests per minute.
+    pub requests_per_minute: u64,
+
+    /// Maximum number of (estimated) compute units per minute.
+    pub tokens_per_minute: u64,
+}
+
+/// Configuration of the image token estimation, per model.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ImageTokensConfig {
+    /// Policy applied to models that are not listed in `models`.
+    #[serde(default)]
+    pub default: ImageTokensPolicy,
+
+    /// Policies, indexed by model name.
+    #[serde(default)]
+    pub models: HashMap<String, ImageTokensPolicy>,
+}
+
+impl ImageTokensConfig {
+    /// Returns the image token policy of a model, falling back to the default policy.
+    #[must_use]
+    pub fn policy_for_model(&self, model: &str) -> &ImageTokensPolicy {
+        self.models.get(model).unwrap_or(&self.default)
+    }
+}
+
+/// Configuration of the context window of the models.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ModelLimitsConfig {
+    /// Whether the `max_completion_tokens` of requests that exceed the remaining context window
+    /// are lowered to fit in it, instead of rejecting the requests.
+    #[serde(default)]
+    pub clamp_max_completion_tokens: bool,
+
+    /// Limits, indexed by model name. Requests for models that are not listed are not checked.
+    #[serde(default)]
+    pub models: HashMap<String, ModelLimits>,
+}
+
+impl ModelLimitsConfig {
+    /// Returns the limits of a model, if any.
+    #[must_use]
+    pub fn limits_for_model(&self, model: &str) -> Option<&ModelLimits> {
+        self.models.get(model)
+    }
+}
+
+/// Context window of a model.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+pub struct ModelLimits {
+    /// Maximum number of tokens of a request, input and output tokens included.
+    #[serde(default)]
+    pub context_length: Option<u64>,
+
+    /// Maximum number of output tokens of a request.
+    #[serde(default)]
+    pub max_output_tokens: Option<u64>,
+}
+
+/// How the images sent to a model are converted to input tokens, and which images are accepted.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct ImageTokensPolicy {
+    /// Formula used to compute the number of input tokens of an image.
+    #[serde(default)]
+    pub formula: ImageTokensFormula,
+
+    /// Maximum size, in bytes, of a decoded image.
+    #[serde(default = "default_max_image_size_bytes")]
+    pub max_image_size_bytes: usize,
+
+    /// Image formats accepted by the model.
+    #[serde(default = "default_allowed_image_formats")]
+    pub allowed_formats: Vec<ImageFormat>,
+}
+
+impl Default for ImageTokensPolicy {
+    fn default() -> Self {
+        Self {
+            formula: ImageTokensFormula::default(),
+            max_image_size_bytes: default_max_image_size_bytes(),
+            allowed_formats: default_allowed_image_formats(),
+        }
+    }
+}
+
+/// Formula used to compute the number of input tokens of an image.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ImageTokensFormula {
+    /// Every image costs the same number of tokens, whatever its dimensions.
+    Fixed {
+        /// Number of tokens of an image.
+        tokens_per_image: u64,
+    },
+
+    /// The image is split into square tiles, each costing the same number of tokens.
+    Tiles {
+        /// Side, in pixels, of a tile.
+        tile_size: u32,
+
+        /// Number of tokens of a tile.
+        tokens_per_tile: u64,
+
+        /// Number of tokens added to every image, on top of its tiles.
+        #[serde(default)]
+        base_tokens: u64,
+
+        /// Maximum number of tiles of an image. Larger images are downscaled by the model.
+        max_tiles: u64,
+    },
+}
+
+impl Default for ImageTokensFormula {
+    /// The tile-based formula of OpenAI's high detail images.
+    fn default() -> Self {
+        Self::Tiles {
+            tile_size: 512,
+            tokens_per_tile: 170,
+            base_tokens: 85,
+            max_tiles: 16,
+        }
+    }
+}
+
+/// Image formats whose dimensions can be read by the proxy.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageFormat {
+    /// PNG image.
+    Png,
+
+    /// JPEG image.
+    Jpeg,
+
+    /// GIF image, whose first frame dimensions are used.
+    Gif,
+
+    /// WebP image, either lossy, lossless or extended.
+    Webp,
+}
+
+/// Default maximum size of a decoded image, in bytes.
+const fn default_max_image_size_bytes() -> usize {
+    20 * 1024 * 1024
+}
+
+/// Default image formats accepted by a model.
+fn default_allowed_image_formats() -> Vec<ImageFormat> {
+    vec![
+        ImageFormat::Png,
+        ImageFormat::Jpeg,
+        ImageFormat::Gif,
+        ImageFormat::Webp,
+    ]
 }
 
 impl AtomaServiceConfig {
@@ -64,8 +657,24 @@ impl AtomaServiceConfig {
     /// * The "atoma-service" section is missing from the configuration
     /// * The configuration format doesn't match the expected structure
     pub fn from_file_path<P: AsRef<Path>>(config_file_path: P) -> Self {
+        Self::try_from_file_path(config_file_path)
+            .expect("Failed to generate atoma-service configuration instance")
+    }
+
+    /// Creates a new `AtomaServiceConfig` instance from a configuration file, like
+    /// `from_file_path`, without panicking (e.g. to reload the configuration of a running proxy).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the configuration file cannot be read or parsed, or if the
+    /// "atoma-service" section is missing or invalid.
+    pub fn try_from_file_path<P: AsRef<Path>>(config_file_path: P) -> anyhow::Result<Self> {
+        let config_file_path = config_file_path
+            .as_ref()
+            .to_str()
+            .ok_or_else(|| anyhow::anyhow!("Invalid configuration file path"))?;
         let builder = Config::builder()
-            .add_source(File::with_name(config_file_path.as_ref().to_str().unwrap()))
+            .add_source(File::with_name(config_file_path))
             .add_source(
                 config::Environment::with_prefix("ATOMA_SERVICE")
                     .keep_prefix(true)
@@ -73,9 +682,9 @@ impl AtomaServiceConfig {
             );
         let config = builder
             .build()
-            .expect("Failed to generate atoma-service configuration file");
+            .context("Failed to generate atoma-service configuration file")?;
         config
             .get::<Self>("atoma_service")
-            .expect("Failed to generate configuration instance")
+            .context("Failed to generate configuration instance")
     }
 }
@@ -6,6 +6,8 @@ use axum::{
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use super::rate_limiter::RateLimitExceeded;
+
 /// Response structure for API errors
 ///
 /// This struct is used to provide a consistent error response format across the API.
@@ -74,6 +76,15 @@ pub enum AtomaProxyError {
         endpoint: String,
     },
 
+    /// Error returned when the authenticated API token is not allowed to perform the request
+    #[error("Forbidden: {message}")]
+    Forbidden {
+        /// Description of why the request is not allowed
+        message: String,
+        /// The endpoint that the error occurred on
+        endpoint: String,
+    },
+
     /// Error returned when a resource is not found
     #[error("Resource not found: {message}")]
     NotFound {
@@ -92,6 +103,17 @@ pub enum AtomaProxyError {
         endpoint: String,
     },
 
+    /// Error returned when the selected node could not be reached or failed to process the request
+    #[error("Node error: {message}")]
+    NodeError {
+        /// Description of the node error
+        message: String,
+        /// Optional message to return to the client
+        client_message: Option<String>,
+        /// The endpoint that the error occurred on
+        endpoint: String,
+    },
+
     /// Error returned when a service is unavailable
     #[error("Service unavailable: {message}")]
     ServiceUnavailable {
@@ -123,6 +145,26 @@ pub enum AtomaProxyError {
     TooManyRequests {
         /// Description of the too many requests
         message: String,
+        /// The rate limits of the proxy the request exceeded, reported in the response headers
+        rate_limit: Option<RateLimitExceeded>,
+        /// The endpoint that the error occurred on
+        endpoint: String,
+    },
+
+    /// Error returned when no node is available to serve the requested model
+    #[error("No available node: {message}")]
+    NoAvailableNode {
+        /// Description of the unavailable model
+        message: String,
+        /// The endpoint that the error occurred on
+        endpoint: String,
+    },
+
+    /// Error returned when the request does not fit in the context window of the requested model
+    #[error("Context length exceeded: {message}")]
+    ContextLengthExceeded {
+        /// Description of the exceeded limit, in the format of the OpenAI API
+        message: String,
         /// The endpoint that the error occurred on
         endpoint: String,
     },
@@ -148,7 +190,9 @@ impl AtomaProxyError {
         match self {
             Self::RequestError { .. } => "REQUEST_ERROR",
             Self::AuthError { .. } => "AUTH_ERROR",
+            Self::Forbidden { .. } => "FORBIDDEN",
             Self::InternalError { .. } => "INTERNAL_ERROR",
+            Self::NodeError { .. } => "NODE_ERROR",
             Self::NotFound { .. } => "NOT_FOUND",
             Self::NotImplemented { .. } => "NOT_IMPLEMENTED",
             Self::ServiceUnavailable { .. } => "SERVICE_UNAVAILABLE",
@@ -156,6 +200,9 @@ impl AtomaProxyError {
             Self::Locked { .. } => "LOCKED",
             Self::UnavailableStack { .. } => "UNAVAILABLE_STACK",
             Self::TooManyRequests { .. } => "TOO_MANY_REQUESTS",
+            Self::NoAvailableNode { .. } => "NO_AVAILABLE_NODE",
+            // NOTE: Same code as the OpenAI API, which clients may already handle
+            Self::ContextLengthExceeded { .. } => "context_length_exceeded",
         }
     }
 
@@ -178,9 +225,13 @@ impl AtomaProxyError {
         match self {
             Self::RequestError { message, .. } => format!("Request error: {message}"),
             Self::AuthError { .. } => "Authentication failed".to_string(),
+            Self::Forbidden { message, .. } => format!("Forbidden: {message}"),
             Self::InternalError { client_message, .. } => client_message
                 .clone()
                 .unwrap_or_else(|| "Internal server error occurred".to_string()),
+            Self::NodeError { client_message, .. } => client_message
+                .clone()
+                .unwrap_or_else(|| "Inference node failed to process the request".to_string()),
             Self::NotFound { .. } => "Resource not found".to_string(),
             Self::NotImplemented { .. } => "Endpoint not implemented".to_string(),
             Self::ServiceUnavailable { .. } => "Service unavailable".to_string(),
@@ -188,6 +239,8 @@ impl AtomaProxyError {
             Self::Locked { .. } => "Locked".to_string(),
             Self::UnavailableStack { .. } => "Stack unavailable".to_string(),
             Self::TooManyRequests { .. } => "Too many requests".to_string(),
+            Self::NoAvailableNode { message, .. } => format!("No available node: {message}"),
+            Self::ContextLengthExceeded { message, .. } => message.clone(),
         }
     }
 
@@ -196,7 +249,9 @@ impl AtomaProxyError {
     /// Maps each error variant to an appropriate HTTP status code:
     /// - `400 Bad Request` for invalid inputs (missing/invalid headers, invalid body, model errors)
     /// - `401 Unauthorized` for authentication failures
+    /// - `403 Forbidden` for requests not allowed by the API token scopes
     /// - `500 Internal Server Error` for unexpected server errors
+    /// - `502 Bad Gateway` for errors of the node processing the request
     ///
     /// # Returns
     ///
@@ -205,7 +260,9 @@ impl AtomaProxyError {
         match self {
             Self::RequestError { .. } => StatusCode::BAD_REQUEST,
             Self::AuthError { .. } => StatusCode::UNAUTHORIZED,
+            Self::Forbidden { .. } => StatusCode::FORBIDDEN,
             Self::InternalError { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::NodeError { .. } => StatusCode::BAD_GATEWAY,
             Self::NotFound { .. } => StatusCode::NOT_FOUND,
             Self::NotImplemented { .. } => StatusCode::NOT_IMPLEMENTED,
             Self::ServiceUnavailable { .. } => StatusCode::SERVICE_UNAVAILABLE,
@@ -213,6 +270,9 @@ impl AtomaProxyError {
             Self::Locked { .. } => StatusCode::LOCKED,
             Self::UnavailableStack { .. } => StatusCode::TOO_EARLY,
             Self::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
+            Self::NoAvailableNode { .. } | Self::ContextLengthExceeded { .. } => {
+                StatusCode::BAD_REQUEST
+            }
         }
     }
 
@@ -228,14 +288,18 @@ impl AtomaProxyError {
         match self {
             Self::RequestError { endpoint, .. }
             | Self::AuthError { endpoint, .. }
+            | Self::Forbidden { endpoint, .. }
             | Self::InternalError { endpoint, .. }
+            | Self::NodeError { endpoint, .. }
             | Self::NotFound { endpoint, .. }
             | Self::NotImplemented { endpoint, .. }
             | Self::ServiceUnavailable { endpoint, .. }
             | Self::BalanceError { endpoint, .. }
             | Self::Locked { endpoint, .. }
             | Self::UnavailableStack { endpoint, .. }
-            | Self::TooManyRequests { endpoint, .. } => endpoint.clone(),
+            | Self::TooManyRequests { endpoint, .. }
+            | Self::NoAvailableNode { endpoint, .. }
+            | Self::ContextLengthExceeded { endpoint, .. } => endpoint.clone(),
         }
     }
 
@@ -258,7 +322,9 @@ impl AtomaProxyError {
         match self {
             Self::RequestError { message, .. } => format!("Request error: {message}"),
             Self::AuthError { auth_error, .. } => format!("Authentication error: {auth_error}"),
+            Self::Forbidden { message, .. } => format!("Forbidden: {message}"),
             Self::InternalError { message, .. } => format!("Internal server error: {message}"),
+            Self::NodeError { message, .. } => format!("Node error: {message}"),
             Self::NotFound { .. } => "Resource not found".to_string(),
             Self::NotImplemented { .. } => "Endpoint not implemented".to_string(),
             Self::ServiceUnavailable { message, .. } => format!("Service unavailable: {message}"),
@@ -266,6 +332,10 @@ impl AtomaProxyError {
             Self::Locked { message, .. } => format!("Locked: {message}"),
             Self::UnavailableStack { message, .. } => format!("Stack unavailable: {message}"),
             Self::TooManyRequests { message, .. } => format!("Too many requests: {message}"),
+            Self::NoAvailableNode { message, .. } => format!("No available node: {message}"),
+            Self::ContextLengthExceeded { message, .. } => {
+                format!("Context length exceeded: {message}")
+            }
         }
     }
 }
@@ -284,6 +354,14 @@ impl IntoResponse for AtomaProxyError {
                 message: self.client_message(),
             },
         };
-        (self.status_code(), Json(error_response)).into_response()
+        let mut response = (self.status_code(), Json(error_response)).into_response();
+        if let Self::TooManyRequests {
+            rate_limit: Some(rate_limit),
+            ..
+        } = self
+        {
+            rate_limit.insert_headers(response.headers_mut());
+        }
+        response
     }
 }
@@ -0,0 +1,99 @@
+use axum::{extract::State, Json};
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::server::{
+    error::AtomaProxyError, http_server::ProxyState, model_registry::ReloadSummary,
+    node_health::NodeHealthStatus,
+};
+
+/// Path for the node health admin endpoint.
+///
+/// This endpoint reports the circuit breaker state of every node the proxy forwarded requests to.
+pub const ADMIN_NODES_HEALTH_PATH: &str = "/admin/nodes/health";
+
+/// Path for the reload admin endpoint.
+///
+/// This endpoint reloads the served models from the configuration file, like a `SIGHUP`.
+pub const ADMIN_RELOAD_PATH: &str = "/admin/reload";
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(admin_nodes_health, admin_reload),
+    components(schemas(NodesHealthResponse, NodeHealthStatus, ReloadSummary))
+)]
+/// OpenAPI documentation for the admin endpoints.
+///
+/// The admin endpoints are only served when an admin API key is configured, and
+/// require that key as a bearer token.
+pub struct AdminOpenApi;
+
+/// Response of the node health admin endpoint.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct NodesHealthResponse {
+    /// Health of the nodes, sorted by node small id
+    pub nodes: Vec<NodeHealthStatus>,
+}
+
+/// Node health
+///
+/// Returns the health of the nodes the proxy forwarded requests to, including
+/// the nodes currently ejected from node and stack selection.
+#[utoipa::path(
+    get,
+    path = "",
+    security(
+        ("bearerAuth" = [])
+    ),
+    responses(
+        (status = OK, description = "Health of the nodes", body = NodesHealthResponse),
+        (status = UNAUTHORIZED, description = "Invalid or missing admin API key")
+    )
+)]
+#[instrument(level = "trace", skip_all)]
+pub async fn admin_nodes_health(
+    State(state): State<ProxyState>,
+) -> std::result::Result<Json<NodesHealthResponse>, AtomaProxyError> {
+    Ok(Json(NodesHealthResponse {
+        nodes: state.node_health.statuses(),
+    }))
+}
+
+/// Reload
+///
+/// Reloads the models, revisions, modalities, aliases and fallback models from the
+/// configuration file, loading the tokenizers of the new models, without restarting the proxy.
+/// Requests in flight are not interrupted.
+///
+/// ## Errors
+///   - `INTERNAL_SERVER_ERROR` - The configuration is invalid or a tokenizer cannot be loaded,
+///     in which case the served models are left unchanged
+#[utoipa::path(
+    post,
+    path = "",
+    security(
+        ("bearerAuth" = [])
+    ),
+    responses(
+        (status = OK, description = "The served models were reloaded", body = ReloadSummary),
+        (status = UNAUTHORIZED, description = "Invalid or missing admin API key"),
+        (status = INTERNAL_SERVER_ERROR, description = "Failed to reload the served models")
+    )
+)]
+#[instrument(level = "info", skip_all)]
+pub async fn admin_reload(
+    State(state): State<ProxyState>,
+) -> std::result::Result<Json<ReloadSummary>, AtomaProxyError> {
+    let summary =
+        state
+            .model_registry
+            .reload()
+            .await
+            .map_err(|e| AtomaProxyError::InternalError {
+                message: format!("Failed to reload the served models: {e:#}"),
+                client_message: Some(format!("Failed to reload the served models: {e:#}")),
+                endpoint: ADMIN_RELOAD_PATH.to_string(),
+            })?;
+    Ok(Json(summary))
+}
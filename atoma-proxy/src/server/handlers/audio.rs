@@ -0,0 +1,879 @@
+use std::time::Instant;
+
+use atoma_state::types::AtomaAtomaStateManagerEvent;
+use axum::{
+    body::{Body, Bytes},
+    extract::State,
+    http::{header::CONTENT_TYPE, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use base64::engine::{general_purpose::STANDARD, Engine};
+use opentelemetry::KeyValue;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use sqlx::types::chrono::{DateTime, Utc};
+use tracing::instrument;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::server::{
+    error::AtomaProxyError, http_server::ProxyState, middleware::RequestMetadataExtension,
+    multipart::parse_multipart_form, tokenizer::TokenCounter, Result, MODEL,
+};
+
+use super::{
+    handle_status_code_error,
+    metrics::{
+        AUDIO_LATENCY_METRICS, AUDIO_NUM_REQUESTS, TOTAL_COMPLETED_REQUESTS,
+        TOTAL_FAILED_AUDIO_REQUESTS, TOTAL_FAILED_REQUESTS,
+    },
+    record_request_usage,
+    request_model::{ComputeUnitsEstimate, RequestModel},
+    update_api_token_spend, update_state_manager,
+    verify::insert_response_proof_headers,
+    verify_response_hash_and_signature, RESPONSE_HASH_KEY,
+};
+
+/// Path for the audio transcriptions endpoint.
+///
+/// This endpoint follows the OpenAI API format for audio transcriptions. The audio file
+/// is uploaded as `multipart/form-data`, and billed by its duration.
+pub const AUDIO_TRANSCRIPTIONS_PATH: &str = "/v1/audio/transcriptions";
+
+/// Path for the audio speech endpoint.
+///
+/// This endpoint follows the OpenAI API format for text to speech, and is billed by
+/// the number of characters of the input text.
+pub const AUDIO_SPEECH_PATH: &str = "/v1/audio/speech";
+
+/// Maximum size of an uploaded audio file, in bytes.
+pub const MAX_AUDIO_FILE_SIZE: usize = 25 * 1024 * 1024;
+
+/// Maximum size of the body of an audio transcription request, in bytes.
+///
+/// Multipart uploads are converted to JSON bodies, with the base64 encoded audio file,
+/// so this leaves room for the base64 overhead and the other form fields.
+pub const MAX_AUDIO_TRANSCRIPTION_BODY_SIZE: usize = MAX_AUDIO_FILE_SIZE / 3 * 4 + 1024 * 1024;
+
+/// Number of compute units charged per second of transcribed audio.
+///
+/// This matches the rate of the audio frames processed by Whisper's encoder.
+const COMPUTE_UNITS_PER_AUDIO_SECOND: u64 = 50;
+
+/// Lowest bitrate, in bits per second, assumed for compressed audio files whose duration
+/// can't be read from their header, so that their duration is never underestimated.
+const MIN_COMPRESSED_AUDIO_BITRATE: u64 = 32_000;
+
+/// Highest sample rate, in Hz, of the WAV files whose duration is read from their header.
+const MAX_WAV_SAMPLE_RATE: u32 = 384_000;
+
+/// Highest number of channels of the WAV files whose duration is read from their header.
+const MAX_WAV_CHANNELS: u16 = 8;
+
+/// Maximum number of characters of the input of a speech request.
+const MAX_SPEECH_INPUT_CHARACTERS: usize = 4096;
+
+/// The file field in the request payload.
+const FILE: &str = "file";
+
+/// The filename field in the request payload.
+const FILENAME: &str = "filename";
+
+/// The input field in the request payload.
+const INPUT: &str = "input";
+
+/// The response format field in the request payload.
+const RESPONSE_FORMAT: &str = "response_format";
+
+/// The temperature field in the request payload.
+const TEMPERATURE: &str = "temperature";
+
+/// The timestamp granularities field in the request payload.
+const TIMESTAMP_GRANULARITIES: &str = "timestamp_granularities";
+
+/// A model representing an audio transcription request payload.
+#[derive(Debug, Clone)]
+pub struct RequestModelAudioTranscriptions {
+    /// The identifier of the speech recognition model to use
+    model: String,
+    /// Duration of the uploaded audio, in seconds
+    duration_secs: f64,
+}
+
+impl RequestModel for RequestModelAudioTranscriptions {
+    fn new(request: &Value) -> Result<Self> {
+        let request_error = |message: &str| AtomaProxyError::RequestError {
+            message: message.to_string(),
+            endpoint: AUDIO_TRANSCRIPTIONS_PATH.to_string(),
+        };
+        let model = request
+            .get(MODEL)
+            .and_then(Value::as_str)
+            .ok_or_else(|| request_error("Model field is required"))?;
+        // NOTE: Plain text and subtitles responses can't carry the response hash and signature of the node
+        if let Some(response_format) = request.get(RESPONSE_FORMAT).and_then(Value::as_str) {
+            if !["json", "verbose_json"].contains(&response_format) {
+                return Err(request_error(
+                    "Only the json and verbose_json response formats are supported",
+                ));
+            }
+        }
+        let file = request
+            .get(FILE)
+            .and_then(Value::as_str)
+            .ok_or_else(|| request_error("File field is required"))?;
+        let audio = STANDARD
+            .decode(file)
+            .map_err(|_| request_error("File field is not base64 encoded"))?;
+        if audio.len() > MAX_AUDIO_FILE_SIZE {
+            return Err(request_error(&format!(
+                "File is larger than the maximum of {MAX_AUDIO_FILE_SIZE} bytes"
+            )));
+        }
+
+        Ok(Self {
+            model: model.to_string(),
+            duration_secs: audio_duration_secs(&audio),
+        })
+    }
+
+    fn get_model(&self) -> String {
+        self.model.clone()
+    }
+
+    fn get_compute_units_estimate(
+        &self,
+        _tokenizer: Option<&dyn TokenCounter>,
+    ) -> Result<ComputeUnitsEstimate> {
+        let num_compute_units = audio_compute_units(self.duration_secs);
+        Ok(ComputeUnitsEstimate {
+            num_input_compute_units: num_compute_units,
+            max_total_compute_units: num_compute_units,
+        })
+    }
+}
+
+/// A model representing a speech request payload.
+#[derive(Debug, Clone)]
+pub struct RequestModelAudioSpeech {
+    /// The identifier of the text to speech model to use
+    model: String,
+    /// Number of characters of the text to generate audio for
+    num_input_characters: u64,
+}
+
+impl RequestModel for RequestModelAudioSpeech {
+    fn new(request: &Value) -> Result<Self> {
+        let model = request.get(MODEL).and_then(Value::as_str).ok_or_else(|| {
+            AtomaProxyError::RequestError {
+                message: "Model field is required".to_string(),
+                endpoint: AUDIO_SPEECH_PATH.to_string(),
+            }
+        })?;
+        let input = request.get(INPUT).and_then(Value::as_str).ok_or_else(|| {
+            AtomaProxyError::RequestError {
+                message: "Input field is required".to_string(),
+                endpoint: AUDIO_SPEECH_PATH.to_string(),
+            }
+        })?;
+        let num_input_characters = input.chars().count();
+        if num_input_characters > MAX_SPEECH_INPUT_CHARACTERS {
+            return Err(AtomaProxyError::RequestError {
+                message: format!(
+                    "Input is longer than the maximum of {MAX_SPEECH_INPUT_CHARACTERS} characters"
+                ),
+                endpoint: AUDIO_SPEECH_PATH.to_string(),
+            });
+        }
+
+        Ok(Self {
+            model: model.to_string(),
+            num_input_characters: num_input_characters as u64,
+        })
+    }
+
+    fn get_model(&self) -> String {
+        self.model.clone()
+    }
+
+    fn get_compute_units_estimate(
+        &self,
+        _tokenizer: Option<&dyn TokenCounter>,
+    ) -> Result<ComputeUnitsEstimate> {
+        Ok(ComputeUnitsEstimate {
+            num_input_compute_units: self.num_input_characters,
+            max_total_compute_units: self.num_input_characters,
+        })
+    }
+}
+
+/// Converts a duration of audio, in seconds, to compute units.
+///
+/// Every started second is charged, with a minimum of one second.
+#[allow(clippy::cast_possible_truncation)]
+fn audio_compute_units(duration_secs: f64) -> u64 {
+    (duration_secs.ceil() as u64).max(1) * COMPUTE_UNITS_PER_AUDIO_SECOND
+}
+
+/// Returns the duration, in seconds, of an audio file.
+///
+/// The exact duration is read from the header of WAV and FLAC files. For other (compressed)
+/// formats, the duration is bounded from the file size, assuming the lowest common bitrate.
+#[allow(clippy::cast_precision_loss)]
+#[must_use]
+pub fn audio_duration_secs(audio: &[u8]) -> f64 {
+    wav_duration_secs(audio)
+        .or_else(|| flac_duration_secs(audio))
+        .unwrap_or_else(|| (audio.len() as u64 * 8) as f64 / MIN_COMPRESSED_AUDIO_BITRATE as f64)
+}
+
+/// Reads the duration of a WAV file, from the format of its `fmt ` chunk and the size of its `data` chunk.
+///
+/// The byte rate is computed from the sample rate, number of channels and bits per sample, which
+/// decoders play the file at. Headers whose declared byte rate does not match it, or with an
+/// implausible format, are ignored, so that the duration is bounded from the file size instead.
+#[allow(clippy::cast_precision_loss)]
+fn wav_duration_secs(audio: &[u8]) -> Option<f64> {
+    if !audio.starts_with(b"RIFF") || audio.get(8..12)? != b"WAVE" {
+        return None;
+    }
+    let u16_le = |at: usize| Some(u16::from_le_bytes(audio.get(at..at + 2)?.try_into().ok()?));
+    let u32_le = |at: usize| Some(u32::from_le_bytes(audio.get(at..at + 4)?.try_into().ok()?));
+    let wav_byte_rate = |fmt: usize| {
+        let channels = u16_le(fmt + 10)?;
+        let sample_rate = u32_le(fmt + 12)?;
+        let declared_byte_rate = u32_le(fmt + 16)?;
+        let bits_per_sample = u16_le(fmt + 22)?;
+        if !(1..=MAX_WAV_CHANNELS).contains(&channels)
+            || !(1..=MAX_WAV_SAMPLE_RATE).contains(&sample_rate)
+            || !matches!(bits_per_sample, 8 | 16 | 24 | 32 | 64)
+        {
+            return None;
+        }
+        let byte_rate = sample_rate * u32::from(channels) * u32::from(bits_per_sample) / 8;
+        (byte_rate == declared_byte_rate).then_some(byte_rate)
+    };
+    let mut byte_rate = None;
+    let mut position = 12;
+    while let (Some(chunk_id), Some(chunk_size)) =
+        (audio.get(position..position + 4), u32_le(position + 4))
+    {
+        match chunk_id {
+            b"fmt " => byte_rate = wav_byte_rate(position),
+            // NOTE: Streamed files may declare a larger data chunk than the bytes actually uploaded
+            b"data" => {
+                let data_size = (chunk_size as usize).min(audio.len() - position - 8);
+                return Some(data_size as f64 / f64::from(byte_rate.filter(|rate| *rate > 0)?));
+            }
+            _ => {}
+        }
+        // Chunks are padded to an even size
+        position += 8 + chunk_size as usize + (chunk_size as usize % 2);
+    }
+    None
+}
+
+/// Reads the duration of a FLAC file, from the sample rate and total number of samples of its `STREAMINFO` block.
+///
+/// FLAC never encodes audio in (much) more bytes than its PCM samples, so headers declaring less PCM audio
+/// than the size of the file are ignored, so that the duration is bounded from the file size instead.
+#[allow(clippy::cast_precision_loss)]
+fn flac_duration_secs(audio: &[u8]) -> Option<f64> {
+    if !audio.starts_with(b"fLaC") {
+        return None;
+    }
+    // The STREAMINFO block always comes first, after its 4 bytes header
+    let stream_info = audio.get(8..26)?;
+    let sample_rate = (u32::from(stream_info[10]) << 12)
+        | (u32::from(stream_info[11]) << 4)
+        | (u32::from(stream_info[12]) >> 4);
+    let channels = u64::from((stream_info[12] >> 1) & 0x07) + 1;
+    let bits_per_sample =
+        ((u64::from(stream_info[12] & 0x01) << 4) | u64::from(stream_info[13] >> 4)) + 1;
+    let total_samples = (u64::from(stream_info[13] & 0x0f) << 32)
+        | u64::from(u32::from_be_bytes(stream_info[14..18].try_into().ok()?));
+    // NOTE: The total number of samples is unknown (zero) for some streamed encodings
+    if sample_rate == 0 || total_samples == 0 {
+        return None;
+    }
+    let pcm_size = total_samples * channels * bits_per_sample.div_ceil(8);
+    if pcm_size < audio.len() as u64 {
+        return None;
+    }
+    Some(total_samples as f64 / f64::from(sample_rate))
+}
+
+/// Converts the `multipart/form-data` body of an audio transcription request to a JSON body.
+///
+/// The audio file is base64 encoded in the `file` field, and its name is kept in the
+/// `filename` field. The other form fields are kept as strings, except for the temperature
+/// (a number) and the timestamp granularities (an array). The request is then processed,
+/// signed and forwarded to the node as a JSON request, like on the other endpoints.
+///
+/// # Errors
+///
+/// Returns a `RequestError` if the content type has no boundary, or the body is not a valid
+/// multipart form.
+pub fn transcription_multipart_to_json(content_type: &str, body: &[u8]) -> Result<Value> {
+    let mut fields = Map::new();
+    for field in parse_multipart_form(content_type, body, AUDIO_TRANSCRIPTIONS_PATH)? {
+        if field.name == FILE {
+            fields.insert(FILE.to_string(), json!(STANDARD.encode(field.content)));
+            if let Some(filename) = field.filename {
+                fields.insert(FILENAME.to_string(), json!(filename));
+            }
+            continue;
+        }
+        let value = field.text(AUDIO_TRANSCRIPTIONS_PATH)?;
+        if field.name == TEMPERATURE {
+            let temperature = value
+                .parse::<f64>()
+                .map_err(|_| AtomaProxyError::RequestError {
+                    message: "Invalid temperature".to_string(),
+                    endpoint: AUDIO_TRANSCRIPTIONS_PATH.to_string(),
+                })?;
+            fields.insert(field.name, json!(temperature));
+        } else if field.name == format!("{TIMESTAMP_GRANULARITIES}[]") {
+            if let Value::Array(granularities) = fields
+                .entry(TIMESTAMP_GRANULARITIES)
+                .or_insert_with(|| json!([]))
+            {
+                granularities.push(json!(value));
+            }
+        } else {
+            fields.insert(field.name, json!(value));
+        }
+    }
+    Ok(Value::Object(fields))
+}
+
+/// OpenAPI documentation for the audio transcriptions endpoint.
+#[derive(OpenApi)]
+#[openapi(
+    paths(audio_transcriptions_create),
+    components(schemas(CreateTranscriptionRequest, CreateTranscriptionResponse))
+)]
+pub struct AudioTranscriptionsOpenApi;
+
+/// OpenAPI documentation for the audio speech endpoint.
+#[derive(OpenApi)]
+#[openapi(paths(audio_speech_create), components(schemas(CreateSpeechRequest)))]
+pub struct AudioSpeechOpenApi;
+
+/// Create transcription
+///
+/// This endpoint transcribes an audio file into text, following the OpenAI API format.
+/// The multipart upload has already been converted to a JSON body, and the request metadata
+/// and compute units have already been validated by middleware before reaching this handler.
+///
+/// ## Errors
+/// * Returns various status codes based on the underlying `handle_audio_response`:
+///   - `INTERNAL_SERVER_ERROR` - If there's an error communicating with the AI node
+#[utoipa::path(
+    post,
+    path = "",
+    request_body(content = CreateTranscriptionRequest, content_type = "multipart/form-data"),
+    security(
+        ("bearerAuth" = [])
+    ),
+    responses(
+        (status = OK, description = "Audio transcription", body = CreateTranscriptionResponse),
+        (status = BAD_REQUEST, description = "Bad request"),
+        (status = UNAUTHORIZED, description = "Unauthorized"),
+        (status = INTERNAL_SERVER_ERROR, description = "Internal server error")
+    )
+)]
+#[instrument(
+    level = "info",
+    skip_all,
+    fields(endpoint = metadata.endpoint)
+)]
+pub async fn audio_transcriptions_create(
+    Extension(metadata): Extension<RequestMetadataExtension>,
+    State(state): State<ProxyState>,
+    headers: HeaderMap,
+    Json(payload): Json<Value>,
+) -> Result<Response<Body>> {
+    audio_create(metadata, state, headers, payload).await
+}
+
+/// Create speech
+///
+/// This endpoint generates audio from the input text, following the OpenAI API format.
+/// The request metadata and compute units have already been validated by middleware
+/// before reaching this handler.
+///
+/// ## Errors
+/// * Returns various status codes based on the underlying `handle_audio_response`:
+///   - `INTERNAL_SERVER_ERROR` - If there's an error communicating with the AI node
+#[utoipa::path(
+    post,
+    path = "",
+    request_body = CreateSpeechRequest,
+    security(
+        ("bearerAuth" = [])
+    ),
+    responses(
+        (status = OK, description = "The generated audio", content_type = "application/octet-stream"),
+        (status = BAD_REQUEST, description = "Bad request"),
+        (status = UNAUTHORIZED, description = "Unauthorized"),
+        (status = INTERNAL_SERVER_ERROR, description = "Internal server error")
+    )
+)]
+#[instrument(
+    level = "info",
+    skip_all,
+    fields(endpoint = metadata.endpoint)
+)]
+pub async fn audio_speech_create(
+    Extension(metadata): Extension<RequestMetadataExtension>,
+    State(state): State<ProxyState>,
+    headers: HeaderMap,
+    Json(payload): Json<Value>,
+) -> Result<Response<Body>> {
+    audio_create(metadata, state, headers, payload).await
+}
+
+/// Forwards an audio request to its node, and records its usage and spend.
+///
+/// On failure, the compute units locked for the request are released.
+async fn audio_create(
+    metadata: RequestMetadataExtension,
+    state: ProxyState,
+    headers: HeaderMap,
+    payload: Value,
+) -> Result<Response<Body>> {
+    let endpoint = metadata.endpoint.clone();
+    tokio::spawn(async move {
+        let start = Instant::now();
+        let estimated_compute_units = metadata.max_total_num_compute_units as i64;
+        // TODO: We should allow cancelling the request if the client disconnects
+        match handle_audio_response(
+            &state,
+            metadata.node_address.clone(),
+            headers,
+            payload,
+            &metadata.endpoint,
+            metadata.model_name.clone(),
+            metadata.node_id,
+            metadata.selected_stack_small_id,
+        )
+        .await
+        {
+            Ok((response, actual_compute_units)) => {
+                let num_compute_units =
+                    actual_compute_units.map_or(estimated_compute_units, |actual| actual as i64);
+                if num_compute_units != estimated_compute_units {
+                    update_state_manager(
+                        &state.state_manager_sender,
+                        metadata.selected_stack_small_id,
+                        estimated_compute_units,
+                        num_compute_units,
+                        num_compute_units,
+                        &metadata.endpoint,
+                    )?;
+                }
+                update_api_token_spend(
+                    &state.state_manager_sender,
+                    metadata.api_token_id,
+                    metadata.selected_stack_small_id,
+                    num_compute_units,
+                    &metadata.endpoint,
+                )?;
+                record_request_usage(
+                    &state.state_manager_sender,
+                    &metadata,
+                    num_compute_units,
+                    0,
+                    start,
+                    StatusCode::OK,
+                )?;
+                TOTAL_COMPLETED_REQUESTS.add(1, &[KeyValue::new("model", metadata.model_name)]);
+                Ok(response)
+            }
+            Err(e) => {
+                let model_label: String = metadata.model_name.clone();
+                TOTAL_FAILED_AUDIO_REQUESTS.add(1, &[KeyValue::new("model", model_label.clone())]);
+
+                // Record the failed request in the total failed requests metric
+                TOTAL_FAILED_REQUESTS.add(1, &[KeyValue::new("model", model_label)]);
+
+                update_state_manager(
+                    &state.state_manager_sender,
+                    metadata.selected_stack_small_id,
+                    estimated_compute_units,
+                    0,
+                    0,
+                    &metadata.endpoint,
+                )?;
+                Err(e)
+            }
+        }
+    })
+    .await
+    .map_err(|e| AtomaProxyError::InternalError {
+        message: format!("Failed to spawn audio task: {e:?}"),
+        client_message: None,
+        endpoint,
+    })?
+}
+
+/// Handles the response processing for audio requests.
+///
+/// Transcriptions are JSON responses, whose hash and signature are verified and whose hash
+/// is added to the total hash of the stack, like for the other endpoints. When the node reports
+/// the duration of the transcribed audio, the request is billed by that duration instead of the estimate.
+///
+/// Speech responses are the raw generated audio, and are forwarded as is, with the content
+/// type set by the node.
+///
+/// # Returns
+/// * The response to send back to the client, and the actual number of compute units of the
+///   request, if known
+///
+/// # Errors
+/// * Returns `NodeError` if the node can't be reached, or answers with an error status
+/// * Returns `InternalError` if the response of the node can't be read or parsed
+#[instrument(level = "info", skip_all, fields(path = endpoint, stack_small_id))]
+async fn handle_audio_response(
+    state: &ProxyState,
+    node_address: String,
+    headers: HeaderMap,
+    payload: Value,
+    endpoint: &str,
+    model_name: String,
+    node_small_id: i64,
+    stack_small_id: i64,
+) -> Result<(Response<Body>, Option<u64>)> {
+    let model_label: String = model_name.clone();
+    AUDIO_NUM_REQUESTS.add(
+        1,
+        &[
+            KeyValue::new("model", model_label.clone()),
+            KeyValue::new("endpoint", endpoint.to_string()),
+        ],
+    );
+
+    let client = reqwest::Client::new();
+    let time = Instant::now();
+    let response = client
+        .post(format!("{node_address}{endpoint}"))
+        .headers(headers)
+        .json(&payload)
+        .send()
+        .await
+        .map_err(|err| AtomaProxyError::NodeError {
+            message: format!("Failed to send audio request: {err:?}"),
+            client_message: Some("Failed to connect to the node".to_string()),
+            endpoint: endpoint.to_string(),
+        })?;
+
+    if !response.status().is_success() {
+        let error = response
+            .status()
+            .canonical_reason()
+            .unwrap_or("Unknown error");
+        handle_status_code_error(response.status(), endpoint, error)?;
+    }
+
+    if endpoint == AUDIO_SPEECH_PATH {
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .cloned()
+            .unwrap_or_else(|| HeaderValue::from_static("application/octet-stream"));
+        let audio: Bytes =
+            response
+                .bytes()
+                .await
+                .map_err(|err| AtomaProxyError::InternalError {
+                    message: format!("Failed to read speech response: {err:?}"),
+                    client_message: Some("Failed to read node's response".to_string()),
+                    endpoint: endpoint.to_string(),
+                })?;
+        AUDIO_LATENCY_METRICS.record(
+            time.elapsed().as_secs_f64(),
+            &[
+                KeyValue::new("model", model_label),
+                KeyValue::new("endpoint", endpoint.to_string()),
+            ],
+        );
+        let mut response = Body::from(audio).into_response();
+        response.headers_mut().insert(CONTENT_TYPE, content_type);
+        return Ok((response, None));
+    }
+
+    let response =
+        response
+            .json::<Value>()
+            .await
+            .map_err(|err| AtomaProxyError::InternalError {
+                message: format!("Failed to parse audio transcription response: {err:?}"),
+                client_message: Some("Failed to parse node's response".to_string()),
+                endpoint: endpoint.to_string(),
+            })?;
+    verify_response_hash_and_signature(&response, true)?;
+
+    // `verbose_json` responses carry the duration, and `json` responses their usage in seconds
+    let actual_compute_units = response
+        .get("duration")
+        .or_else(|| response.get("usage").and_then(|usage| usage.get("seconds")))
+        .and_then(Value::as_f64)
+        .map(audio_compute_units);
+
+    state
+        .state_manager_sender
+        .send(
+            AtomaAtomaStateManagerEvent::UpdateNodeThroughputPerformance {
+                timestamp: DateTime::<Utc>::from(std::time::SystemTime::now()),
+                model_name,
+                input_tokens: 0,
+                output_tokens: actual_compute_units.unwrap_or_default() as i64,
+                time: time.elapsed().as_secs_f64(),
+            },
+        )
+        .map_err(|err| AtomaProxyError::InternalError {
+            message: format!("Failed to update node throughput performance: {err:?}"),
+            client_message: None,
+            endpoint: endpoint.to_string(),
+        })?;
+
+    let total_hash = response
+        .get(RESPONSE_HASH_KEY)
+        .and_then(|hash| hash.as_str())
+        .map(|hash| STANDARD.decode(hash).unwrap_or_default())
+        .unwrap_or_default()
+        .try_into()
+        .map_err(|e: Vec<u8>| AtomaProxyError::InternalError {
+            message: format!(
+                "Error converting response hash to array, received array of length {}",
+                e.len()
+            ),
+            client_message: None,
+            endpoint: endpoint.to_string(),
+        })?;
+
+    state
+        .state_manager_sender
+        .send(AtomaAtomaStateManagerEvent::UpdateStackTotalHash {
+            stack_small_id,
+            total_hash,
+        })
+        .map_err(|err| AtomaProxyError::InternalError {
+            message: format!("Error updating stack total hash: {err:?}"),
+            client_message: None,
+            endpoint: endpoint.to_string(),
+        })?;
+
+    AUDIO_LATENCY_METRICS.record(
+        time.elapsed().as_secs_f64(),
+        &[
+            KeyValue::new("model", model_label),
+            KeyValue::new("endpoint", endpoint.to_string()),
+        ],
+    );
+
+    let mut http_response = Json(&response).into_response();
+    insert_response_proof_headers(
+        http_response.headers_mut(),
+        &response,
+        node_small_id,
+        stack_small_id,
+    );
+    Ok((http_response, actual_compute_units))
+}
+
+/// Request body for audio transcription
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CreateTranscriptionRequest {
+    /// The audio file to transcribe, in one of these formats: flac, mp3, mp4, mpeg, mpga, m4a, ogg, wav, or webm.
+    #[schema(value_type = String, format = Binary)]
+    pub file: String,
+
+    /// The model to use for audio transcription.
+    #[schema(example = "openai/whisper-large-v3")]
+    pub model: String,
+
+    /// The language of the input audio, in ISO-639-1 format.
+    #[schema(example = "en")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+
+    /// An optional text to guide the model's style or continue a previous audio segment.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+
+    /// The format of the transcript output, either `json` or `verbose_json`.
+    #[schema(example = "json")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<String>,
+
+    /// The sampling temperature, between 0 and 1.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+}
+
+/// Response of an audio transcription
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CreateTranscriptionResponse {
+    /// The transcribed text.
+    #[schema(example = "Hello from the other side of Mars")]
+    pub text: String,
+}
+
+/// Request body for speech generation
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CreateSpeechRequest {
+    /// The model to use for speech generation.
+    #[schema(example = "hexgrad/Kokoro-82M")]
+    pub model: String,
+
+    /// The text to generate audio for. The maximum length is 4096 characters.
+    #[schema(example = "Hello from the other side of Mars")]
+    pub input: String,
+
+    /// The voice to use when generating the audio.
+    #[schema(example = "alloy")]
+    pub voice: String,
+
+    /// The format of the generated audio: mp3, opus, aac, flac, wav or pcm.
+    #[schema(example = "mp3")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<String>,
+
+    /// The speed of the generated audio, between 0.25 and 4.0.
+    #[schema(example = 1.0)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed: Option<f32>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wav(sample_rate: u32, num_samples: u32) -> Vec<u8> {
+        let data_size = num_samples * 2;
+        let mut audio = b"RIFF".to_vec();
+        audio.extend_from_slice(&(36 + data_size).to_le_bytes());
+        audio.extend_from_slice(b"WAVEfmt ");
+        audio.extend_from_slice(&16u32.to_le_bytes());
+        // PCM, mono
+        audio.extend_from_slice(&[1, 0, 1, 0]);
+        audio.extend_from_slice(&sample_rate.to_le_bytes());
+        audio.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+        // Block align and bits per sample
+        audio.extend_from_slice(&[2, 0, 16, 0]);
+        audio.extend_from_slice(b"data");
+        audio.extend_from_slice(&data_size.to_le_bytes());
+        audio.resize(audio.len() + data_size as usize, 0);
+        audio
+    }
+
+    #[test]
+    fn test_audio_duration_secs() {
+        assert!((audio_duration_secs(&wav(16_000, 40_000)) - 2.5).abs() < f64::EPSILON);
+
+        // STREAMINFO of 44.1kHz, 16 bits, stereo audio with 441_000 samples
+        let mut flac = b"fLaC\x00\x00\x00\x22".to_vec();
+        flac.extend_from_slice(&[0x10, 0x00, 0x10, 0x00, 0, 0, 0, 0, 0, 0]);
+        flac.extend_from_slice(&[0x0a, 0xc4, 0x42, 0xf0]);
+        flac.extend_from_slice(&441_000u32.to_be_bytes());
+        assert!((audio_duration_secs(&flac) - 10.0).abs() < f64::EPSILON);
+
+        // 40_000 bytes of unknown audio, at 32kbps
+        assert!((audio_duration_secs(&[0; 40_000]) - 10.0).abs() < f64::EPSILON);
+
+        // WAV files declaring a byte rate that does not match their format are bounded from
+        // their size, at 32kbps
+        let mut audio = wav(16_000, 20_000);
+        audio[28..32].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!((audio_duration_secs(&audio) - 10.011).abs() < f64::EPSILON);
+
+        // As are WAV files with an implausible sample rate
+        let audio = wav(4_000_000, 20_000);
+        assert!((audio_duration_secs(&audio) - 10.011).abs() < f64::EPSILON);
+
+        // And FLAC files declaring less PCM audio (1_764_000 bytes) than their size
+        flac.resize(4_000_000, 0);
+        assert!((audio_duration_secs(&flac) - 1_000.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_audio_compute_units() {
+        assert_eq!(audio_compute_units(0.0), COMPUTE_UNITS_PER_AUDIO_SECOND);
+        assert_eq!(audio_compute_units(2.5), 3 * COMPUTE_UNITS_PER_AUDIO_SECOND);
+        assert_eq!(
+            audio_compute_units(10.0),
+            10 * COMPUTE_UNITS_PER_AUDIO_SECOND
+        );
+    }
+
+    #[test]
+    fn test_transcription_multipart_to_json() {
+        let body = b"--boundary\r\n\
+            Content-Disposition: form-data; name=\"model\"\r\n\r\n\
+            openai/whisper-large-v3\r\n\
+            --boundary\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"speech.wav\"\r\n\
+            Content-Type: audio/wav\r\n\r\n\
+            RIFF\x00\r\n\
+            --boundary\r\n\
+            Content-Disposition: form-data; name=\"temperature\"\r\n\r\n\
+            0.2\r\n\
+            --boundary\r\n\
+            Content-Disposition: form-data; name=\"timestamp_granularities[]\"\r\n\r\n\
+            word\r\n\
+            --boundary\r\n\
+            Content-Disposition: form-data; name=\"timestamp_granularities[]\"\r\n\r\n\
+            segment\r\n\
+            --boundary--\r\n";
+        let request =
+            transcription_multipart_to_json("multipart/form-data; boundary=boundary", body)
+                .unwrap();
+        assert_eq!(
+            request,
+            json!({
+                "model": "openai/whisper-large-v3",
+                "file": STANDARD.encode(b"RIFF\x00"),
+                "filename": "speech.wav",
+                "temperature": 0.2,
+                "timestamp_granularities": ["word", "segment"],
+            })
+        );
+
+        let request_model = RequestModelAudioTranscriptions::new(&request).unwrap();
+        assert_eq!(request_model.get_model(), "openai/whisper-large-v3");
+
+        assert!(transcription_multipart_to_json("multipart/form-data", body).is_err());
+    }
+
+    #[test]
+    fn test_request_model_audio_transcriptions_rejects_text_formats() {
+        let request = json!({
+            "model": "openai/whisper-large-v3",
+            "file": STANDARD.encode(wav(16_000, 16_000)),
+            "response_format": "srt",
+        });
+        assert!(RequestModelAudioTranscriptions::new(&request).is_err());
+    }
+
+    #[test]
+    fn test_request_model_audio_speech() {
+        let request = json!({
+            "model": "hexgrad/Kokoro-82M",
+            "input": "Hello, wörld",
+            "voice": "alloy",
+        });
+        let request_model = RequestModelAudioSpeech::new(&request).unwrap();
+        let estimate = request_model.get_compute_units_estimate(None).unwrap();
+        assert_eq!(estimate.num_input_compute_units, 12);
+        assert_eq!(estimate.max_total_compute_units, 12);
+
+        let request = json!({
+            "model": "hexgrad/Kokoro-82M",
+            "input": "a".repeat(MAX_SPEECH_INPUT_CHARACTERS + 1),
+            "voice": "alloy",
+        });
+        assert!(RequestModelAudioSpeech::new(&request).is_err());
+    }
+}
@@ -0,0 +1,560 @@
+use std::collections::{HashMap, HashSet};
+
+use atoma_state::types::{AtomaAtomaStateManagerEvent, Batch, NewBatch, NewBatchRequest};
+use axum::{
+    extract::{Path, Query, State},
+    http::HeaderMap,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::types::chrono::{Duration, Utc};
+use tracing::instrument;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+use crate::server::{check_auth, error::AtomaProxyError, http_server::ProxyState, Result, MODEL};
+
+use super::{
+    chat_completions::CHAT_COMPLETIONS_PATH,
+    embeddings::EMBEDDINGS_PATH,
+    files::{FileObject, BATCH_PURPOSE},
+    query_state_manager,
+};
+
+/// Path for the batches endpoint.
+///
+/// This endpoint follows the OpenAI API format for batches. The requests of a batch are read
+/// from a JSONL file uploaded to the files endpoint, and executed in the background by the batch worker.
+pub const BATCHES_PATH: &str = "/v1/batches";
+
+/// Path for retrieving a batch.
+pub const BATCH_PATH: &str = "/v1/batches/{batch_id}";
+
+/// Path for cancelling a batch.
+pub const BATCH_CANCEL_PATH: &str = "/v1/batches/{batch_id}/cancel";
+
+/// The endpoints the requests of a batch can be sent to.
+pub const BATCH_ENDPOINTS: [&str; 2] = [CHAT_COMPLETIONS_PATH, EMBEDDINGS_PATH];
+
+/// The only supported completion window of a batch.
+pub const BATCH_COMPLETION_WINDOW: &str = "24h";
+
+/// The duration of the completion window of a batch, in hours.
+const BATCH_COMPLETION_WINDOW_HOURS: i64 = 24;
+
+/// The default number of batches returned when listing batches.
+const DEFAULT_LIST_LIMIT: i64 = 20;
+
+/// The maximum number of batches returned when listing batches.
+const MAX_LIST_LIMIT: i64 = 100;
+
+/// A request of a batch, from one line of its input file.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct BatchInputLine {
+    /// The id set by the user to match the request with its response
+    pub custom_id: String,
+    /// The HTTP method of the request, only `POST` is supported
+    pub method: String,
+    /// The endpoint the request is sent to
+    pub url: String,
+    /// The body of the request
+    pub body: Value,
+}
+
+/// Parses and validates the lines of a batch input file.
+///
+/// Every non-empty line must be a `POST` request to one of the batch endpoints, with a
+/// unique `custom_id` and a body naming the model to use.
+///
+/// # Errors
+///
+/// Returns a `RequestError` naming the first invalid line, or if the file is empty or has
+/// more than `max_requests` lines.
+pub fn parse_batch_input(
+    content: &[u8],
+    max_requests: usize,
+    endpoint: &str,
+) -> Result<Vec<BatchInputLine>> {
+    let request_error = |message: String| AtomaProxyError::RequestError {
+        message,
+        endpoint: endpoint.to_string(),
+    };
+    let content = std::str::from_utf8(content)
+        .map_err(|_| request_error("Batch input file is not valid UTF-8".to_string()))?;
+
+    let mut custom_ids = HashSet::new();
+    let mut lines = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let line_number = index + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if lines.len() == max_requests {
+            return Err(request_error(format!(
+                "Batch input file has more than the maximum of {max_requests} requests"
+            )));
+        }
+        let line = serde_json::from_str::<BatchInputLine>(line).map_err(|e| {
+            request_error(format!("Line {line_number} is not a valid request: {e}"))
+        })?;
+        if line.method != "POST" {
+            return Err(request_error(format!(
+                "Line {line_number}: only the POST method is supported"
+            )));
+        }
+        if !BATCH_ENDPOINTS.contains(&line.url.as_str()) {
+            return Err(request_error(format!(
+                "Line {line_number}: unsupported url {}, expected one of {BATCH_ENDPOINTS:?}",
+                line.url
+            )));
+        }
+        if line.body.get(MODEL).and_then(Value::as_str).is_none() {
+            return Err(request_error(format!(
+                "Line {line_number}: the body must set the model"
+            )));
+        }
+        if !custom_ids.insert(line.custom_id.clone()) {
+            return Err(request_error(format!(
+                "Line {line_number}: duplicate custom_id {}",
+                line.custom_id
+            )));
+        }
+        lines.push(line);
+    }
+    if lines.is_empty() {
+        return Err(request_error(
+            "Batch input file has no requests".to_string(),
+        ));
+    }
+    Ok(lines)
+}
+
+/// OpenAPI documentation for the batches endpoints.
+#[derive(OpenApi)]
+#[openapi(
+    paths(batches_create, batches_list, batches_retrieve, batches_cancel),
+    components(schemas(CreateBatchRequest, BatchObject, BatchRequestCounts, BatchList))
+)]
+pub struct BatchesOpenApi;
+
+/// Request body for creating a batch
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CreateBatchRequest {
+    /// The id of an uploaded file, with the `batch` purpose, containing the requests of the batch.
+    #[schema(example = "file-abc123")]
+    pub input_file_id: String,
+
+    /// The endpoint the requests of the batch are sent to.
+    #[schema(example = "/v1/chat/completions")]
+    pub endpoint: String,
+
+    /// The time frame within which the batch should be processed. Only `24h` is supported.
+    #[schema(example = "24h")]
+    pub completion_window: String,
+
+    /// Key-value pairs attached to the batch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<HashMap<String, String>>,
+}
+
+/// The request counts of a batch
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct BatchRequestCounts {
+    /// The number of requests of the batch
+    pub total: i64,
+    /// The number of requests that completed successfully
+    pub completed: i64,
+    /// The number of requests that failed
+    pub failed: i64,
+}
+
+/// A batch of requests
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct BatchObject {
+    /// The id of the batch
+    pub id: String,
+    /// The object type, always `batch`
+    pub object: String,
+    /// The endpoint the requests of the batch are sent to
+    pub endpoint: String,
+    /// The id of the input file of the batch
+    pub input_file_id: String,
+    /// The time frame within which the batch should be processed
+    pub completion_window: String,
+    /// The status of the batch: `validating`, `in_progress`, `cancelling`, `completed`, `expired` or `cancelled`
+    pub status: String,
+    /// The id of the file with the responses of the successful requests
+    pub output_file_id: Option<String>,
+    /// The id of the file with the responses of the failed requests
+    pub error_file_id: Option<String>,
+    /// The Unix timestamp (in seconds) of the batch creation
+    pub created_at: i64,
+    /// The Unix timestamp (in seconds) at which the batch started being processed
+    pub in_progress_at: Option<i64>,
+    /// The Unix timestamp (in seconds) at which the batch expires
+    pub expires_at: i64,
+    /// The Unix timestamp (in seconds) at which the batch was cancelled
+    pub cancelling_at: Option<i64>,
+    /// The Unix timestamp (in seconds) at which the batch was completed
+    pub completed_at: Option<i64>,
+    /// The Unix timestamp (in seconds) at which the batch expired
+    pub expired_at: Option<i64>,
+    /// The Unix timestamp (in seconds) at which the cancellation of the batch was finalized
+    pub cancelled_at: Option<i64>,
+    /// The request counts of the batch
+    pub request_counts: BatchRequestCounts,
+    /// Key-value pairs attached to the batch
+    pub metadata: Option<HashMap<String, String>>,
+}
+
+impl From<Batch> for BatchObject {
+    fn from(batch: Batch) -> Self {
+        Self {
+            id: batch.id,
+            object: "batch".to_string(),
+            endpoint: batch.endpoint,
+            input_file_id: batch.input_file_id,
+            completion_window: batch.completion_window,
+            status: batch.status,
+            output_file_id: batch.output_file_id,
+            error_file_id: batch.error_file_id,
+            created_at: batch.created_at.timestamp(),
+            in_progress_at: batch.in_progress_at.map(|t| t.timestamp()),
+            expires_at: batch.expires_at.timestamp(),
+            cancelling_at: batch.cancelling_at.map(|t| t.timestamp()),
+            completed_at: batch.completed_at.map(|t| t.timestamp()),
+            expired_at: batch.expired_at.map(|t| t.timestamp()),
+            cancelled_at: batch.cancelled_at.map(|t| t.timestamp()),
+            request_counts: BatchRequestCounts {
+                total: batch.total_requests,
+                completed: batch.completed_requests,
+                failed: batch.failed_requests,
+            },
+            metadata: batch
+                .metadata
+                .and_then(|metadata| serde_json::from_str(&metadata).ok()),
+        }
+    }
+}
+
+/// A page of the batches of a user, most recent first
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct BatchList {
+    /// The object type, always `list`
+    pub object: String,
+    /// The batches of the page
+    pub data: Vec<BatchObject>,
+    /// The id of the first batch of the page
+    pub first_id: Option<String>,
+    /// The id of the last batch of the page, to request the next page
+    pub last_id: Option<String>,
+    /// Whether there are more batches after this page
+    pub has_more: bool,
+}
+
+/// Query parameters for listing batches
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct ListBatchesQuery {
+    /// Only return the batches created before the batch with this id
+    pub after: Option<String>,
+    /// The maximum number of batches to return, between 1 and 100, defaults to 20
+    pub limit: Option<i64>,
+}
+
+/// Create batch
+///
+/// Creates a batch from an uploaded JSONL file of requests. The requests are executed in the
+/// background, through the same stack and node selection as regular requests, and their responses
+/// are written to the output and error files of the batch once it is finalized.
+///
+/// ## Errors
+///   - `BAD_REQUEST` - The endpoint or completion window is not supported, or the input file is invalid
+///   - `NOT_FOUND` - The input file does not exist
+#[utoipa::path(
+    post,
+    path = "",
+    request_body = CreateBatchRequest,
+    security(
+        ("bearerAuth" = [])
+    ),
+    responses(
+        (status = OK, description = "The created batch", body = BatchObject),
+        (status = BAD_REQUEST, description = "Bad request"),
+        (status = UNAUTHORIZED, description = "Unauthorized"),
+        (status = NOT_FOUND, description = "Input file not found"),
+        (status = INTERNAL_SERVER_ERROR, description = "Internal server error")
+    )
+)]
+#[instrument(level = "info", skip_all, fields(endpoint = BATCHES_PATH))]
+pub async fn batches_create(
+    State(state): State<ProxyState>,
+    headers: HeaderMap,
+    Json(payload): Json<CreateBatchRequest>,
+) -> Result<Json<BatchObject>> {
+    let api_token = check_auth(&state, &headers, BATCHES_PATH, None).await?;
+    if payload.completion_window != BATCH_COMPLETION_WINDOW {
+        return Err(AtomaProxyError::RequestError {
+            message: format!("Only the {BATCH_COMPLETION_WINDOW} completion window is supported"),
+            endpoint: BATCHES_PATH.to_string(),
+        });
+    }
+    if !BATCH_ENDPOINTS.contains(&payload.endpoint.as_str()) {
+        return Err(AtomaProxyError::RequestError {
+            message: format!(
+                "Unsupported endpoint {}, expected one of {BATCH_ENDPOINTS:?}",
+                payload.endpoint
+            ),
+            endpoint: BATCHES_PATH.to_string(),
+        });
+    }
+
+    let input_file = query_state_manager(
+        &state.state_manager_sender,
+        |result_sender| AtomaAtomaStateManagerEvent::GetUserFile {
+            user_id: api_token.user_id,
+            file_id: payload.input_file_id.clone(),
+            result_sender,
+        },
+        BATCHES_PATH,
+    )
+    .await?
+    .map(FileObject::from)
+    .filter(|file| file.purpose == BATCH_PURPOSE)
+    .ok_or_else(|| AtomaProxyError::NotFound {
+        message: format!("No batch input file with id {}", payload.input_file_id),
+        endpoint: BATCHES_PATH.to_string(),
+    })?;
+    let content = query_state_manager(
+        &state.state_manager_sender,
+        |result_sender| AtomaAtomaStateManagerEvent::GetUserFileContent {
+            user_id: api_token.user_id,
+            file_id: input_file.id.clone(),
+            result_sender,
+        },
+        BATCHES_PATH,
+    )
+    .await?
+    .unwrap_or_default();
+
+    let lines = parse_batch_input(&content, state.batches.max_requests, BATCHES_PATH)?;
+    let mut requests = Vec::with_capacity(lines.len());
+    for (line_index, line) in lines.into_iter().enumerate() {
+        if line.url != payload.endpoint {
+            return Err(AtomaProxyError::RequestError {
+                message: format!(
+                    "Request {} is sent to {}, but the batch endpoint is {}",
+                    line.custom_id, line.url, payload.endpoint
+                ),
+                endpoint: BATCHES_PATH.to_string(),
+            });
+        }
+        requests.push(NewBatchRequest {
+            line_index: line_index as i64,
+            custom_id: line.custom_id,
+            body: line.body.to_string(),
+        });
+    }
+
+    let batch = NewBatch {
+        id: format!("batch_{}", uuid::Uuid::new_v4().simple()),
+        user_id: api_token.user_id,
+        api_token_id: api_token.id,
+        endpoint: payload.endpoint,
+        input_file_id: input_file.id,
+        completion_window: payload.completion_window,
+        metadata: payload
+            .metadata
+            .map(|metadata| serde_json::to_string(&metadata).unwrap_or_default()),
+        expires_at: Utc::now() + Duration::hours(BATCH_COMPLETION_WINDOW_HOURS),
+    };
+    let batch = query_state_manager(
+        &state.state_manager_sender,
+        |result_sender| AtomaAtomaStateManagerEvent::CreateBatch {
+            batch,
+            requests,
+            result_sender,
+        },
+        BATCHES_PATH,
+    )
+    .await?;
+    Ok(Json(batch.into()))
+}
+
+/// List batches
+///
+/// Lists the batches of the user, most recent first.
+#[utoipa::path(
+    get,
+    path = "",
+    params(ListBatchesQuery),
+    security(
+        ("bearerAuth" = [])
+    ),
+    responses(
+        (status = OK, description = "The batches of the user", body = BatchList),
+        (status = UNAUTHORIZED, description = "Unauthorized"),
+        (status = INTERNAL_SERVER_ERROR, description = "Internal server error")
+    )
+)]
+#[instrument(level = "info", skip_all, fields(endpoint = BATCHES_PATH))]
+pub async fn batches_list(
+    State(state): State<ProxyState>,
+    headers: HeaderMap,
+    Query(query): Query<ListBatchesQuery>,
+) -> Result<Json<BatchList>> {
+    let api_token = check_auth(&state, &headers, BATCHES_PATH, None).await?;
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_LIST_LIMIT)
+        .clamp(1, MAX_LIST_LIMIT);
+    // NOTE: Fetch one more batch than requested, to know whether there are more batches
+    let mut batches = query_state_manager(
+        &state.state_manager_sender,
+        |result_sender| AtomaAtomaStateManagerEvent::ListBatches {
+            user_id: api_token.user_id,
+            after: query.after,
+            limit: limit + 1,
+            result_sender,
+        },
+        BATCHES_PATH,
+    )
+    .await?;
+    let has_more = batches.len() as i64 > limit;
+    batches.truncate(limit as usize);
+    let data = batches
+        .into_iter()
+        .map(BatchObject::from)
+        .collect::<Vec<_>>();
+    Ok(Json(BatchList {
+        object: "list".to_string(),
+        first_id: data.first().map(|batch| batch.id.clone()),
+        last_id: data.last().map(|batch| batch.id.clone()),
+        data,
+        has_more,
+    }))
+}
+
+/// Retrieve batch
+///
+/// Retrieves a batch of the user, with its status and request counts.
+#[utoipa::path(
+    get,
+    path = "/{batch_id}",
+    params(("batch_id" = String, Path, description = "The id of the batch")),
+    security(
+        ("bearerAuth" = [])
+    ),
+    responses(
+        (status = OK, description = "The batch", body = BatchObject),
+        (status = UNAUTHORIZED, description = "Unauthorized"),
+        (status = NOT_FOUND, description = "Batch not found"),
+        (status = INTERNAL_SERVER_ERROR, description = "Internal server error")
+    )
+)]
+#[instrument(level = "info", skip_all, fields(endpoint = BATCH_PATH))]
+pub async fn batches_retrieve(
+    State(state): State<ProxyState>,
+    headers: HeaderMap,
+    Path(batch_id): Path<String>,
+) -> Result<Json<BatchObject>> {
+    let api_token = check_auth(&state, &headers, BATCHES_PATH, None).await?;
+    let batch = query_state_manager(
+        &state.state_manager_sender,
+        |result_sender| AtomaAtomaStateManagerEvent::GetBatch {
+            user_id: api_token.user_id,
+            batch_id: batch_id.clone(),
+            result_sender,
+        },
+        BATCH_PATH,
+    )
+    .await?
+    .ok_or_else(|| AtomaProxyError::NotFound {
+        message: format!("No batch with id {batch_id}"),
+        endpoint: BATCH_PATH.to_string(),
+    })?;
+    Ok(Json(batch.into()))
+}
+
+/// Cancel batch
+///
+/// Cancels a batch of the user. The requests being executed complete, the remaining requests
+/// are not executed, and the batch is finalized with the responses received so far.
+#[utoipa::path(
+    post,
+    path = "/{batch_id}/cancel",
+    params(("batch_id" = String, Path, description = "The id of the batch")),
+    security(
+        ("bearerAuth" = [])
+    ),
+    responses(
+        (status = OK, description = "The cancelling batch", body = BatchObject),
+        (status = UNAUTHORIZED, description = "Unauthorized"),
+        (status = NOT_FOUND, description = "Batch not found"),
+        (status = INTERNAL_SERVER_ERROR, description = "Internal server error")
+    )
+)]
+#[instrument(level = "info", skip_all, fields(endpoint = BATCH_CANCEL_PATH))]
+pub async fn batches_cancel(
+    State(state): State<ProxyState>,
+    headers: HeaderMap,
+    Path(batch_id): Path<String>,
+) -> Result<Json<BatchObject>> {
+    let api_token = check_auth(&state, &headers, BATCHES_PATH, None).await?;
+    let batch = query_state_manager(
+        &state.state_manager_sender,
+        |result_sender| AtomaAtomaStateManagerEvent::CancelBatch {
+            user_id: api_token.user_id,
+            batch_id: batch_id.clone(),
+            result_sender,
+        },
+        BATCH_CANCEL_PATH,
+    )
+    .await?
+    .ok_or_else(|| AtomaProxyError::NotFound {
+        message: format!("No batch with id {batch_id}"),
+        endpoint: BATCH_CANCEL_PATH.to_string(),
+    })?;
+    Ok(Json(batch.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_batch_input() {
+        let content = br#"{"custom_id": "request-1", "method": "POST", "url": "/v1/chat/completions", "body": {"model": "llama", "messages": []}}
+
+{"custom_id": "request-2", "method": "POST", "url": "/v1/embeddings", "body": {"model": "bge", "input": "hello"}}
+"#;
+        let lines = parse_batch_input(content, 10, BATCHES_PATH).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].custom_id, "request-1");
+        assert_eq!(lines[1].url, EMBEDDINGS_PATH);
+
+        assert!(parse_batch_input(content, 1, BATCHES_PATH).is_err());
+        assert!(parse_batch_input(b"\n\n", 10, BATCHES_PATH).is_err());
+        assert!(parse_batch_input(b"not json", 10, BATCHES_PATH).is_err());
+        assert!(parse_batch_input(
+            br#"{"custom_id": "1", "method": "GET", "url": "/v1/chat/completions", "body": {"model": "llama"}}"#,
+            10,
+            BATCHES_PATH
+        )
+        .is_err());
+        assert!(parse_batch_input(
+            br#"{"custom_id": "1", "method": "POST", "url": "/v1/images/generations", "body": {"model": "flux"}}"#,
+            10,
+            BATCHES_PATH
+        )
+        .is_err());
+        assert!(parse_batch_input(
+            br#"{"custom_id": "1", "method": "POST", "url": "/v1/chat/completions", "body": {}}"#,
+            10,
+            BATCHES_PATH
+        )
+        .is_err());
+        let duplicate = br#"{"custom_id": "1", "method": "POST", "url": "/v1/embeddings", "body": {"model": "bge"}}
+{"custom_id": "1", "method": "POST", "url": "/v1/embeddings", "body": {"model": "bge"}}"#;
+        assert!(parse_batch_input(duplicate, 10, BATCHES_PATH).is_err());
+    }
+}
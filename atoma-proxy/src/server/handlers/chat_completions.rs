@@ -1,6 +1,9 @@
+use std::convert::Infallible;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use crate::server::streamer::ClientStreamer;
+use crate::server::stream_replay::{ClientSignal, StreamReplay, LAST_EVENT_ID, X_ATOMA_STREAM_ID};
+use crate::server::tokenizer::TokenCounter;
 use crate::server::types::{ConfidentialComputeResponse, ConfidentialComputeStreamResponse};
 use crate::server::{
     error::AtomaProxyError, http_server::ProxyState, middleware::RequestMetadataExtension,
@@ -9,12 +12,13 @@ use crate::server::{
 use atoma_state::types::AtomaAtomaStateManagerEvent;
 use atoma_utils::constants::REQUEST_ID;
 use axum::body::Body;
-use axum::http::HeaderValue;
-use axum::response::{IntoResponse, Response, Sse};
+use axum::extract::Path;
+use axum::http::{HeaderValue, StatusCode};
+use axum::response::{sse::Event, IntoResponse, Response, Sse};
 use axum::Extension;
 use axum::{extract::State, http::HeaderMap, Json};
 use base64::engine::{general_purpose::STANDARD, Engine};
-use futures::StreamExt;
+use futures::{Stream, StreamExt};
 use openai_api::message::Role;
 use openai_api::tools::ToolFunction;
 use openai_api::{
@@ -39,7 +43,6 @@ use opentelemetry::KeyValue;
 use serde::Deserialize;
 use serde_json::{json, Value};
 use sqlx::types::chrono::{DateTime, Utc};
-use tokenizers::Tokenizer;
 use tracing::instrument;
 use utoipa::OpenApi;
 
@@ -52,11 +55,18 @@ use super::metrics::{
     UNSUCCESSFUL_CHAT_COMPLETION_REQUESTS_PER_USER,
 };
 use super::request_model::{ComputeUnitsEstimate, RequestModel};
+use super::verify::{
+    insert_response_proof_headers, wants_stream_proof, PROOF_EVENT, X_ATOMA_NODE_SMALL_ID,
+    X_ATOMA_STACK_SMALL_ID,
+};
 use super::{
-    handle_status_code_error, update_state_manager, verify_response_hash_and_signature,
-    RESPONSE_HASH_KEY,
+    cache_response, handle_status_code_error, record_request_usage, update_api_token_spend,
+    update_state_manager, verify_response_hash_and_signature, RESPONSE_HASH_KEY,
+};
+use crate::server::{
+    check_auth, ImageTokensPolicy, Result, DEFAULT_MAX_TOKENS, MAX_COMPLETION_TOKENS, MAX_TOKENS,
+    MODEL,
 };
-use crate::server::{Result, DEFAULT_MAX_TOKENS, MAX_COMPLETION_TOKENS, MAX_TOKENS, MODEL};
 
 /// Path for the confidential chat completions endpoint.
 ///
@@ -91,12 +101,23 @@ const MESSAGES: &str = "messages";
 /// The stream field in the request payload.
 const STREAM: &str = "stream";
 
+/// Path for the endpoint resuming a chat completion stream, once the connection dropped.
+pub const CHAT_COMPLETIONS_STREAM_PATH: &str = "/v1/chat/completions/streams/{stream_id}";
+
 /// The path for the stop streamer endpoint.
 const STOP_STREAMER_PATH: &str = "/v1/stop-streamer";
 
+/// The time the node is given to end a stream, with its usage, once its client disconnected.
+/// After that time, the request to the node is aborted.
+const STOP_STREAMER_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
 #[derive(OpenApi)]
 #[openapi(
-    paths(chat_completions_create, chat_completions_create_stream),
+    paths(
+        chat_completions_create,
+        chat_completions_create_stream,
+        chat_completions_resume_stream
+    ),
     components(schemas(
         ChatCompletionRequest,
         ChatCompletionMessage,
@@ -196,6 +217,7 @@ pub async fn chat_completions_create(
                     metadata.selected_stack_small_id,
                     metadata.max_total_num_compute_units as i64,
                     0,
+                    0,
                     &metadata.endpoint,
                 )?;
                 Err(e)
@@ -450,28 +472,19 @@ async fn handle_chat_completions_request(
             state,
             &metadata.node_address,
             metadata.user_id,
+            metadata.api_token_id,
             headers,
             &payload,
             metadata.num_input_tokens.map(|v| v as i64),
             metadata.max_total_num_compute_units as i64,
             metadata.selected_stack_small_id,
+            metadata.node_id,
             metadata.endpoint.clone(),
             metadata.model_name.clone(),
         )
         .await
     } else {
-        handle_non_streaming_response(
-            state,
-            &metadata.node_address,
-            metadata.user_id,
-            headers,
-            &payload,
-            metadata.max_total_num_compute_units as i64,
-            metadata.selected_stack_small_id,
-            metadata.endpoint.clone(),
-            metadata.model_name.clone(),
-        )
-        .await
+        handle_non_streaming_response(state, metadata, headers, &payload).await
     }
 }
 
@@ -506,6 +519,76 @@ pub fn chat_completions_create_stream(
     })
 }
 
+/// Resume a chat completion stream
+///
+/// Resumes a streaming chat completion whose connection dropped, from the `x-atoma-stream-id`
+/// header of its response. The chunks following the `Last-Event-ID` header (or all the chunks,
+/// without it) are replayed, followed by the remaining chunks of the generation. The generation
+/// is neither run nor billed again.
+#[utoipa::path(
+    get,
+    path = "/streams/{stream_id}",
+    params(
+        ("stream_id" = String, Path, description = "The identifier of the stream, from the x-atoma-stream-id header"),
+        ("Last-Event-ID" = Option<u64>, Header, description = "The identifier of the last chunk received")
+    ),
+    security(
+        ("bearerAuth" = [])
+    ),
+    responses(
+        (status = OK, description = "Chat completions", content(
+            (openai_api::ChatCompletionStreamResponse = "text/event-stream")
+        )),
+        (status = BAD_REQUEST, description = "Invalid Last-Event-ID header, or the chunks following it are no longer available"),
+        (status = UNAUTHORIZED, description = "Unauthorized"),
+        (status = NOT_FOUND, description = "Stream not found")
+    )
+)]
+#[instrument(level = "info", skip_all, fields(%stream_id))]
+pub async fn chat_completions_resume_stream(
+    State(state): State<ProxyState>,
+    headers: HeaderMap,
+    Path(stream_id): Path<String>,
+) -> Result<Response<Body>> {
+    let replay = state
+        .resumable_streams
+        .as_ref()
+        .and_then(|registry| registry.get(&stream_id));
+    let endpoint = replay
+        .as_ref()
+        .map_or(CHAT_COMPLETIONS_STREAM_PATH, |replay| replay.endpoint());
+    let api_token = check_auth(&state, &headers, endpoint, None).await?;
+    // NOTE: Streams of other users are reported as not found, not to leak their existence
+    let replay = replay
+        .filter(|replay| replay.user_id() == api_token.user_id)
+        .ok_or_else(|| AtomaProxyError::NotFound {
+            message: format!("Stream {stream_id} not found"),
+            endpoint: CHAT_COMPLETIONS_STREAM_PATH.to_string(),
+        })?;
+    let last_event_id = headers
+        .get(LAST_EVENT_ID)
+        .map(|value| {
+            value
+                .to_str()
+                .ok()
+                .and_then(|value| value.trim().parse::<u64>().ok())
+                .ok_or_else(|| AtomaProxyError::RequestError {
+                    message: format!("Invalid {LAST_EVENT_ID} header"),
+                    endpoint: CHAT_COMPLETIONS_STREAM_PATH.to_string(),
+                })
+        })
+        .transpose()?;
+    let events = replay
+        .subscribe(last_event_id)
+        .ok_or_else(|| AtomaProxyError::RequestError {
+            message: format!(
+                "The chunks of stream {stream_id} following the {LAST_EVENT_ID} header are no longer available"
+            ),
+            endpoint: CHAT_COMPLETIONS_STREAM_PATH.to_string(),
+        })?;
+    Ok(sse_response(events, Some(&stream_id)))
+}
+
 /// OpenAPI documentation structure for confidential chat completions endpoint.
 ///
 /// This structure defines the OpenAPI (Swagger) documentation for the confidential chat completions
@@ -623,6 +706,7 @@ pub async fn confidential_chat_completions_create(
                     metadata.selected_stack_small_id,
                     metadata.max_total_num_compute_units as i64,
                     0,
+                    0,
                     &metadata.endpoint,
                 )?;
                 Err(e)
@@ -678,12 +762,10 @@ pub fn confidential_chat_completions_create_stream(
 /// # Arguments
 ///
 /// * `state` - Application state containing service configuration
-/// * `node_address` - The address of the inference node to send the request to
-/// * `signature` - Authentication signature for the request
-/// * `selected_stack_small_id` - Unique identifier for the selected stack
+/// * `metadata` - The request metadata set by the middleware (node address, selected stack and
+///   node, estimated total tokens, cache key, ...)
 /// * `headers` - HTTP headers to forward with the request
 /// * `payload` - The JSON payload containing the chat completion request
-/// * `estimated_total_tokens` - Estimated token count for the request
 ///
 /// # Returns
 ///
@@ -712,25 +794,27 @@ pub fn confidential_chat_completions_create_stream(
     level = "info",
     skip_all,
     fields(
-        path = endpoint,
+        path = metadata.endpoint,
         completion_type = "non-streaming",
         stack_small_id,
         estimated_total_tokens,
         payload_hash
     )
 )]
-#[allow(clippy::too_many_arguments)]
 async fn handle_non_streaming_response(
     state: &ProxyState,
-    node_address: &String,
-    user_id: i64,
+    metadata: &RequestMetadataExtension,
     headers: HeaderMap,
     payload: &Value,
-    estimated_total_tokens: i64,
-    selected_stack_small_id: i64,
-    endpoint: String,
-    model_name: String,
 ) -> Result<Response<Body>> {
+    let node_address = &metadata.node_address;
+    let user_id = metadata.user_id;
+    let api_token_id = metadata.api_token_id;
+    let estimated_total_tokens = metadata.max_total_num_compute_units as i64;
+    let selected_stack_small_id = metadata.selected_stack_small_id;
+    let selected_node_small_id = metadata.node_id;
+    let endpoint = metadata.endpoint.clone();
+    let model_name = metadata.model_name.clone();
     let client = reqwest::Client::new();
     let time = Instant::now();
 
@@ -742,7 +826,7 @@ async fn handle_non_streaming_response(
         .json(&payload)
         .send()
         .await
-        .map_err(|err| AtomaProxyError::InternalError {
+        .map_err(|err| AtomaProxyError::NodeError {
             message: format!("Failed to send OpenAI API request: {err:?}"),
             client_message: Some("Failed to connect to the node.".to_string()),
             endpoint: endpoint.to_string(),
@@ -775,13 +859,13 @@ async fn handle_non_streaming_response(
 
     let input_tokens = response
         .get("usage")
-        .and_then(|usage| usage.get("completion_tokens"))
+        .and_then(|usage| usage.get("prompt_tokens"))
         .and_then(serde_json::Value::as_u64)
         .map_or(0, |n| n as i64);
 
     let output_tokens = response
         .get("usage")
-        .and_then(|usage| usage.get("prompt_tokens"))
+        .and_then(|usage| usage.get("completion_tokens"))
         .and_then(serde_json::Value::as_u64)
         .map_or(0, |n| n as i64);
 
@@ -863,6 +947,7 @@ async fn handle_non_streaming_response(
         selected_stack_small_id,
         estimated_total_tokens,
         total_tokens,
+        total_tokens,
         &endpoint,
     ) {
         return Err(AtomaProxyError::InternalError {
@@ -871,13 +956,45 @@ async fn handle_non_streaming_response(
             endpoint: endpoint.to_string(),
         });
     }
+    update_api_token_spend(
+        &state.state_manager_sender,
+        api_token_id,
+        selected_stack_small_id,
+        total_tokens,
+        &endpoint,
+    )?;
+    record_request_usage(
+        &state.state_manager_sender,
+        metadata,
+        input_tokens,
+        output_tokens,
+        time,
+        StatusCode::OK,
+    )?;
 
     CHAT_COMPLETIONS_LATENCY_METRICS.record(
         time.elapsed().as_secs_f64(),
-        &[KeyValue::new("model", model_label)],
+        &[KeyValue::new("model", model_label.clone())],
     );
 
-    Ok(response.into_response())
+    let mut http_response = Json(&response.0).into_response();
+    insert_response_proof_headers(
+        http_response.headers_mut(),
+        &response.0,
+        selected_node_small_id,
+        selected_stack_small_id,
+    );
+    if let Some(cache_key) = metadata.cache_key.clone() {
+        cache_response(
+            state,
+            cache_key,
+            &model_label,
+            &endpoint,
+            &response.0,
+            &mut http_response,
+        );
+    }
+    Ok(http_response)
 }
 
 /// Handles streaming chat completion requests by establishing a Server-Sent Events (SSE) connection.
@@ -893,6 +1010,7 @@ async fn handle_non_streaming_response(
 /// * `state` - Application state containing service configuration and connections
 /// * `payload` - The JSON payload containing the chat completion request
 /// * `stack_small_id` - Unique identifier for the stack making the request
+/// * `selected_node_small_id` - Unique identifier for the node processing the request
 /// * `estimated_total_tokens` - Estimated token count for the request
 /// * `payload_hash` - BLAKE2b hash of the original request payload
 ///
@@ -930,14 +1048,17 @@ async fn handle_streaming_response(
     state: &ProxyState,
     node_address: &String,
     user_id: i64,
+    api_token_id: i64,
     mut headers: HeaderMap,
     payload: &Value,
     num_input_tokens: Option<i64>,
     estimated_total_tokens: i64,
     selected_stack_small_id: i64,
+    selected_node_small_id: i64,
     endpoint: String,
     model_name: String,
 ) -> Result<Response<Body>> {
+    let stream_proof = wants_stream_proof(&headers);
     let client = reqwest::Client::new();
     let start = Instant::now();
 
@@ -949,7 +1070,7 @@ async fn handle_streaming_response(
         .json(&payload)
         .send()
         .await
-        .map_err(|e| AtomaProxyError::InternalError {
+        .map_err(|e| AtomaProxyError::NodeError {
             message: format!("Error sending request to inference service: {e:?}"),
             client_message: Some("Failed to connect to the node.".to_string()),
             endpoint: endpoint.to_string(),
@@ -969,12 +1090,40 @@ async fn handle_streaming_response(
     );
     let stream = response.bytes_stream();
 
-    let (event_sender, event_receiver) = flume::unbounded();
-    let (kill_signal_sender, kill_signal_receiver) = flume::unbounded();
+    let (client_signal_sender, client_signal_receiver) = flume::unbounded();
+    let resumable_streams = state.resumable_streams.clone();
+    let replay = match &resumable_streams {
+        Some(registry) => registry.register(
+            request_id.clone(),
+            user_id,
+            endpoint.clone(),
+            client_signal_sender,
+        ),
+        None => Arc::new(StreamReplay::new(
+            user_id,
+            endpoint.clone(),
+            0,
+            client_signal_sender,
+        )),
+    };
+    let events = replay
+        .subscribe(None)
+        .ok_or_else(|| AtomaProxyError::InternalError {
+            message: "Failed to subscribe to a new stream".to_string(),
+            client_message: None,
+            endpoint: endpoint.clone(),
+        })?;
     let state_manager_sender = state.state_manager_sender.clone();
+    // NOTE: Generated tokens can only be counted by the proxy when the chunks are not encrypted
+    let tokenizer = if endpoint == CHAT_COMPLETIONS_PATH {
+        state.model_registry.current().tokenizer(&model_name)
+    } else {
+        None
+    };
     let node_address_clone = node_address.clone();
     let request_id_clone = request_id.clone();
     let client_clone = client.clone();
+    let stream_id = resumable_streams.is_some().then(|| request_id.clone());
     tokio::spawn(async move {
         tracing::info!(
             target = "atoma-service-chat-completions",
@@ -984,28 +1133,31 @@ async fn handle_streaming_response(
             stream,
             state_manager_sender,
             selected_stack_small_id,
+            selected_node_small_id,
             num_input_tokens.unwrap_or(0),
             estimated_total_tokens,
             start,
             user_id,
+            api_token_id,
             model_name,
             endpoint,
+            tokenizer,
         );
+        let resume_window = resumable_streams
+            .as_ref()
+            .map_or(Duration::ZERO, |registry| registry.resume_window());
+        // NOTE: Set once the client disconnected, the generation is stopped when it is reached,
+        // unless the client resumed the stream
+        let mut resume_deadline: Option<tokio::time::Instant> = None;
+        // NOTE: Set once the generation is stopped, the request to the node is aborted when it is reached
+        let mut stop_deadline: Option<tokio::time::Instant> = None;
         loop {
             tokio::select! {
                 event = streamer.next() => {
                     match event {
                         Some(Ok(maybe_chunk)) => {
-                            tracing::info!(target = "atoma-service-chat-completions", "Sending chunk to event sender");
-                            if let Err(e) = event_sender.send(maybe_chunk) {
-                                tracing::error!(
-                                    target = "atoma-service-chat-completions",
-                                    level = "error",
-                                    "Error sending chunk: {e}"
-                                );
-                                // We continue the loop, to allow the streamer to finish with updated usage from the node
-                                continue;
-                            }
+                            tracing::info!(target = "atoma-service-chat-completions", "Sending chunk to the stream replay");
+                            replay.push(maybe_chunk);
                         }
                         Some(Err(e)) => {
                             tracing::error!(
@@ -1020,8 +1172,27 @@ async fn handle_streaming_response(
                         }
                     }
                 }
-                _ = kill_signal_receiver.recv_async() => {
-                    tracing::info!(target = "atoma-service-streamer", "Received kill signal, stopping streamer");
+                Ok(signal) = client_signal_receiver.recv_async(), if stop_deadline.is_none() => {
+                    match signal {
+                        ClientSignal::Disconnected => {
+                            tracing::info!(target = "atoma-service-streamer", "Client disconnected, waiting for it to resume the stream");
+                            streamer.client_detached();
+                            resume_deadline = Some(tokio::time::Instant::now() + resume_window);
+                        }
+                        ClientSignal::Reconnected => {
+                            streamer.client_resumed();
+                            resume_deadline = None;
+                        }
+                    }
+                }
+                () = tokio::time::sleep_until(resume_deadline.unwrap_or_else(tokio::time::Instant::now)), if resume_deadline.is_some() => {
+                    tracing::info!(target = "atoma-service-streamer", "Client did not resume the stream, stopping streamer");
+                    streamer.client_disconnected();
+                    resume_deadline = None;
+                    stop_deadline = Some(tokio::time::Instant::now() + STOP_STREAMER_GRACE_PERIOD);
+                    if let Some(registry) = &resumable_streams {
+                        registry.remove(&request_id_clone);
+                    }
                     let stop_response = client_clone
                         .post(format!("{node_address_clone}{STOP_STREAMER_PATH}"))
                         .header("X-Request-ID", request_id_clone.clone())
@@ -1039,12 +1210,37 @@ async fn handle_streaming_response(
                     // We continue the loop, to allow the streamer to finish with updated usage from the node
                     continue;
                 }
+                () = tokio::time::sleep_until(stop_deadline.unwrap_or_else(tokio::time::Instant::now)), if stop_deadline.is_some() => {
+                    tracing::info!(
+                        target = "atoma-service-streamer",
+                        "Node did not end the stream in time after the client disconnected, aborting the request"
+                    );
+                    break;
+                }
             }
         }
+        if stream_proof {
+            if let Some(proof) = streamer.response_proof() {
+                match Event::default().event(PROOF_EVENT).json_data(proof) {
+                    Ok(event) => replay.push(event),
+                    Err(e) => tracing::error!(
+                        target = "atoma-service-chat-completions",
+                        level = "error",
+                        "Error serializing the proof of the stream: {e}"
+                    ),
+                }
+            }
+        }
+        replay.finish();
+        drop(streamer);
         tracing::info!(
             target = "atoma-service-chat-completions",
             "Streamer finished for request id: {request_id}"
         );
+        if let Some(registry) = resumable_streams {
+            tokio::time::sleep(registry.retention()).await;
+            registry.remove(&request_id);
+        }
     });
 
     // Create the SSE stream
@@ -1056,13 +1252,36 @@ async fn handle_streaming_response(
     // 1. The node is running in confidential compute mode, so we can trust the real usage information by node
     //    (say when claiming stacks from the blockchain).
     // 2. It only affects requests whose connection is dropped before the final chunk is processed, by the client.
-    let stream = Sse::new(ClientStreamer::new(event_receiver, kill_signal_sender)).keep_alive(
-        axum::response::sse::KeepAlive::new()
-            .interval(Duration::from_millis(STREAM_KEEP_ALIVE_INTERVAL_IN_SECONDS))
-            .text("keep-alive"),
+    let mut response = sse_response(events, stream_id.as_deref());
+    let response_headers = response.headers_mut();
+    response_headers.insert(
+        X_ATOMA_NODE_SMALL_ID,
+        HeaderValue::from(selected_node_small_id),
+    );
+    response_headers.insert(
+        X_ATOMA_STACK_SMALL_ID,
+        HeaderValue::from(selected_stack_small_id),
     );
+    Ok(response)
+}
 
-    Ok(stream.into_response())
+/// Builds the SSE response of a stream, with keep-alive messages, and the identifier of the
+/// stream to resume it, if it is resumable.
+fn sse_response(
+    events: impl Stream<Item = std::result::Result<Event, Infallible>> + Send + 'static,
+    stream_id: Option<&str>,
+) -> Response<Body> {
+    let mut response = Sse::new(events)
+        .keep_alive(
+            axum::response::sse::KeepAlive::new()
+                .interval(Duration::from_millis(STREAM_KEEP_ALIVE_INTERVAL_IN_SECONDS))
+                .text("keep-alive"),
+        )
+        .into_response();
+    if let Some(stream_id) = stream_id.and_then(|stream_id| HeaderValue::from_str(stream_id).ok()) {
+        response.headers_mut().insert(X_ATOMA_STREAM_ID, stream_id);
+    }
+    response
 }
 
 /// Represents a chat completion request model following the OpenAI API format
@@ -1077,9 +1296,22 @@ pub struct RequestModelChatCompletions {
     /// The content can be a string or an array of content parts.
     messages: Vec<Value>,
 
-    /// The maximum number of tokens to generate in the completion
+    /// The maximum number of tokens to generate in the completion, if set by the request
     /// This limits the length of the model's response
-    max_completion_tokens: u64,
+    max_completion_tokens: Option<u64>,
+
+    /// How the image content parts are converted to input tokens, and which images are accepted
+    image_tokens_policy: ImageTokensPolicy,
+}
+
+impl RequestModelChatCompletions {
+    /// Sets the image token policy of the requested model, used to estimate the
+    /// input tokens of the image content parts.
+    #[must_use]
+    pub fn with_image_tokens_policy(mut self, image_tokens_policy: ImageTokensPolicy) -> Self {
+        self.image_tokens_policy = image_tokens_policy;
+        self
+    }
 }
 
 impl RequestModel for RequestModelChatCompletions {
@@ -1102,13 +1334,13 @@ impl RequestModel for RequestModelChatCompletions {
         let max_completion_tokens = request
             .get(MAX_COMPLETION_TOKENS)
             .or_else(|| request.get(MAX_TOKENS))
-            .and_then(serde_json::Value::as_u64)
-            .unwrap_or(DEFAULT_MAX_TOKENS);
+            .and_then(serde_json::Value::as_u64);
 
         Ok(Self {
             model: model.to_string(),
             messages: messages.clone(),
             max_completion_tokens,
+            image_tokens_policy: ImageTokensPolicy::default(),
         })
     }
 
@@ -1116,6 +1348,10 @@ impl RequestModel for RequestModelChatCompletions {
         self.model.clone()
     }
 
+    fn get_max_completion_tokens(&self) -> Option<u64> {
+        self.max_completion_tokens
+    }
+
     /// Computes the total number of tokens for the chat completion request.
     ///
     /// This is used to estimate the cost of the chat completion request, on the proxy side.
@@ -1124,7 +1360,7 @@ impl RequestModel for RequestModelChatCompletions {
     /// are to be concatenated and treated as a single message, by the model and from the estimate point of view.
     fn get_compute_units_estimate(
         &self,
-        tokenizer: Option<&Tokenizer>,
+        tokenizer: Option<&dyn TokenCounter>,
     ) -> Result<ComputeUnitsEstimate> {
         // In order to account for the possibility of not taking into account possible additional special tokens,
         // which might not be considered by the tokenizer, we add a small overhead to the total number of tokens, per message.
@@ -1138,17 +1374,15 @@ impl RequestModel for RequestModelChatCompletions {
         };
         // Helper function to count tokens for a text string
         let count_text_tokens = |text: &str| -> Result<u64> {
-            Ok(tokenizer
-                .encode(text, true)
+            tokenizer
+                .count_tokens(text)
                 .map_err(|err| AtomaProxyError::InternalError {
                     message: format!("Failed to encode message: {err:?}"),
                     client_message: Some(
                         "Failed to encode message using the model's tokenizer".to_string(),
                     ),
                     endpoint: CHAT_COMPLETIONS_PATH.to_string(),
-                })?
-                .get_ids()
-                .len() as u64)
+                })
         };
 
         let mut total_num_tokens = 0;
@@ -1183,10 +1417,15 @@ impl RequestModel for RequestModelChatCompletions {
                                 let num_tokens = count_text_tokens(&text)?;
                                 total_num_tokens += num_tokens + MESSAGE_OVERHEAD_TOKENS;
                             }
-                            MessageContentPart::Image { .. } => {
-                                // TODO: Ensure that for image content parts, we have a way to estimate the number of tokens,
-                                // which can depend on the size of the image and the output description.
-                                continue;
+                            MessageContentPart::Image { image_url, .. } => {
+                                let num_tokens = self
+                                    .image_tokens_policy
+                                    .estimate_tokens(&image_url.url)
+                                    .map_err(|err| AtomaProxyError::RequestError {
+                                        message: format!("Invalid image content: {err}"),
+                                        endpoint: CHAT_COMPLETIONS_PATH.to_string(),
+                                    })?;
+                                total_num_tokens += num_tokens + MESSAGE_OVERHEAD_TOKENS;
                             }
                         }
                     }
@@ -1196,7 +1435,8 @@ impl RequestModel for RequestModelChatCompletions {
         // add the max completion tokens, to account for the response
         Ok(ComputeUnitsEstimate {
             num_input_compute_units: total_num_tokens,
-            max_total_compute_units: total_num_tokens + self.max_completion_tokens,
+            max_total_compute_units: total_num_tokens
+                + self.max_completion_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
         })
     }
 }
@@ -1936,9 +2176,9 @@ pub mod openai_api {
         #[serde(rename(serialize = "image_url", deserialize = "image_url"))]
         pub struct MessageContentPartImageUrl {
             /// Either a URL of the image or the base64 encoded image data.
-            url: String,
+            pub url: String,
             /// Specifies the detail level of the image.
-            detail: Option<String>,
+            pub detail: Option<String>,
         }
 
         /// Implementing Display for MessageContentPartImageUrl
@@ -2296,9 +2536,9 @@ pub mod openai_api {
 mod tests {
     use super::*;
 
+    use crate::server::ImageTokensFormula;
     use serde_json::json;
     use std::str::FromStr;
-    use tokenizers::Tokenizer;
 
     const MODEL: &str = "TinyLlama/TinyLlama-1.1B-Chat-v1.0";
 
@@ -2318,7 +2558,8 @@ mod tests {
                 "role": "user",
                 "content": "Hello from the other side of Mars"
             })],
-            max_completion_tokens: 10,
+            max_completion_tokens: Some(10),
+            image_tokens_policy: ImageTokensPolicy::default(),
         };
         let tokenizer = load_tokenizer().await;
         let result = request.get_compute_units_estimate(Some(&tokenizer));
@@ -2340,7 +2581,8 @@ mod tests {
                     "content": "Hello from the other side of Mars"
                 }),
             ],
-            max_completion_tokens: 10,
+            max_completion_tokens: Some(10),
+            image_tokens_policy: ImageTokensPolicy::default(),
         };
         let tokenizer = load_tokenizer().await;
         let result = request.get_compute_units_estimate(Some(&tokenizer));
@@ -2365,7 +2607,8 @@ mod tests {
                     }
                 ]
             })],
-            max_completion_tokens: 10,
+            max_completion_tokens: Some(10),
+            image_tokens_policy: ImageTokensPolicy::default(),
         };
 
         let tokenizer = load_tokenizer().await;
@@ -2382,7 +2625,8 @@ mod tests {
                 "role": "user",
                 "content": ""
             })],
-            max_completion_tokens: 10,
+            max_completion_tokens: Some(10),
+            image_tokens_policy: ImageTokensPolicy::default(),
         };
         let tokenizer = load_tokenizer().await;
         let result = request.get_compute_units_estimate(Some(&tokenizer));
@@ -2419,15 +2663,21 @@ mod tests {
                     ]
                 }),
             ],
-            max_completion_tokens: 15,
+            max_completion_tokens: Some(15),
+            image_tokens_policy: ImageTokensPolicy {
+                formula: ImageTokensFormula::Fixed {
+                    tokens_per_image: 100,
+                },
+                ..ImageTokensPolicy::default()
+            },
         };
         let tokenizer = load_tokenizer().await;
         let result = request.get_compute_units_estimate(Some(&tokenizer));
         assert!(result.is_ok());
         // System message: tokens + 15 completion
-        // User message array: (2 text parts tokens) + (15 * 2 for text completion for parts)
+        // User message array: (2 text parts tokens) + (15 * 2 for text completion for parts) + image tokens
         let tokens = result.unwrap();
-        assert_eq!(tokens.max_total_compute_units, 48); // 3 * 8 + 3 * 3 overhead + 15
+        assert_eq!(tokens.max_total_compute_units, 151); // 3 * 8 + 100 + 4 * 3 overhead + 15
     }
 
     #[tokio::test]
@@ -2438,7 +2688,8 @@ mod tests {
                 "role": "user",
                 // Missing "content" field
             })],
-            max_completion_tokens: 10,
+            max_completion_tokens: Some(10),
+            image_tokens_policy: ImageTokensPolicy::default(),
         };
         let tokenizer = load_tokenizer().await;
         let result = request.get_compute_units_estimate(Some(&tokenizer));
@@ -2457,7 +2708,8 @@ mod tests {
                 "role": "user",
                 "content": []
             })],
-            max_completion_tokens: 10,
+            max_completion_tokens: Some(10),
+            image_tokens_policy: ImageTokensPolicy::default(),
         };
         let tokenizer = load_tokenizer().await;
         let result = request.get_compute_units_estimate(Some(&tokenizer));
@@ -2476,7 +2728,8 @@ mod tests {
                 "role": "user",
                 "content": "Hello! 👋 🌍 \n\t Special chars: &*#@"
             })],
-            max_completion_tokens: 10,
+            max_completion_tokens: Some(10),
+            image_tokens_policy: ImageTokensPolicy::default(),
         };
         let tokenizer = load_tokenizer().await;
         let result = request.get_compute_units_estimate(Some(&tokenizer));
@@ -4,7 +4,7 @@ use atoma_state::types::AtomaAtomaStateManagerEvent;
 use axum::{
     body::Body,
     extract::State,
-    http::HeaderMap,
+    http::{HeaderMap, StatusCode},
     response::{IntoResponse, Response},
     Extension, Json,
 };
@@ -13,7 +13,6 @@ use opentelemetry::KeyValue;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use sqlx::types::chrono::{DateTime, Utc};
-use tokenizers::Tokenizer;
 use tracing::instrument;
 use utoipa::{OpenApi, ToSchema};
 
@@ -26,17 +25,20 @@ use crate::server::{
 };
 
 use super::{
-    handle_status_code_error,
+    cache_response, handle_status_code_error,
     metrics::{
         EMBEDDING_TOTAL_TOKENS_PER_USER, SUCCESSFUL_TEXT_EMBEDDING_REQUESTS_PER_USER,
         TEXT_EMBEDDINGS_LATENCY_METRICS, TEXT_EMBEDDINGS_NUM_REQUESTS, TOTAL_COMPLETED_REQUESTS,
         TOTAL_FAILED_REQUESTS, TOTAL_FAILED_TEXT_EMBEDDING_REQUESTS,
         UNSUCCESSFUL_TEXT_EMBEDDING_REQUESTS_PER_USER,
     },
+    record_request_usage,
     request_model::{ComputeUnitsEstimate, RequestModel},
-    update_state_manager, verify_response_hash_and_signature, RESPONSE_HASH_KEY,
+    update_api_token_spend, update_state_manager,
+    verify::insert_response_proof_headers,
+    verify_response_hash_and_signature, RESPONSE_HASH_KEY,
 };
-use crate::server::Result;
+use crate::server::{tokenizer::TokenCounter, Result};
 
 /// Path for the confidential embeddings endpoint.
 ///
@@ -105,7 +107,7 @@ impl RequestModel for RequestModelEmbeddings {
 
     fn get_compute_units_estimate(
         &self,
-        tokenizer: Option<&Tokenizer>,
+        tokenizer: Option<&dyn TokenCounter>,
     ) -> Result<ComputeUnitsEstimate> {
         let Some(tokenizer) = tokenizer else {
             return Err(AtomaProxyError::InternalError {
@@ -114,17 +116,15 @@ impl RequestModel for RequestModelEmbeddings {
                 endpoint: EMBEDDINGS_PATH.to_string(),
             });
         };
-        let num_tokens = tokenizer
-            .encode(self.input.as_str(), true)
-            .map_err(|err| AtomaProxyError::InternalError {
+        let num_tokens = tokenizer.count_tokens(self.input.as_str()).map_err(|err| {
+            AtomaProxyError::InternalError {
                 message: format!("Failed to encode input: {err:?}"),
                 client_message: Some(
                     "Failed to encode message using the model's tokenizer".to_string(),
                 ),
                 endpoint: EMBEDDINGS_PATH.to_string(),
-            })?
-            .get_ids()
-            .len() as u64;
+            }
+        })?;
         Ok(ComputeUnitsEstimate {
             num_input_compute_units: num_tokens,
             max_total_compute_units: num_tokens,
@@ -171,19 +171,22 @@ pub async fn embeddings_create(
 ) -> Result<Response<Body>> {
     let endpoint = metadata.endpoint.clone();
     tokio::spawn(async move {
+        let start = Instant::now();
         // TODO: We should allow cancelling the request if the client disconnects
         let RequestMetadataExtension {
             node_address,
             max_total_num_compute_units: num_input_compute_units,
+            cache_key,
             ..
-        } = metadata;
+        } = &metadata;
+        let num_input_compute_units = *num_input_compute_units;
         EMBEDDING_TOTAL_TOKENS_PER_USER.add(
             num_input_compute_units,
             &[KeyValue::new("user_id", metadata.user_id)],
         );
         match handle_embeddings_response(
             &state,
-            node_address,
+            node_address.clone(),
             headers,
             payload,
             num_input_compute_units as i64,
@@ -194,10 +197,42 @@ pub async fn embeddings_create(
         .await
         {
             Ok(response) => {
+                update_api_token_spend(
+                    &state.state_manager_sender,
+                    metadata.api_token_id,
+                    metadata.selected_stack_small_id,
+                    num_input_compute_units as i64,
+                    &metadata.endpoint,
+                )?;
+                record_request_usage(
+                    &state.state_manager_sender,
+                    &metadata,
+                    num_input_compute_units as i64,
+                    0,
+                    start,
+                    StatusCode::OK,
+                )?;
+                let mut http_response = Json(&response).into_response();
+                insert_response_proof_headers(
+                    http_response.headers_mut(),
+                    &response,
+                    metadata.node_id,
+                    metadata.selected_stack_small_id,
+                );
+                if let Some(key) = cache_key {
+                    cache_response(
+                        &state,
+                        key,
+                        &metadata.model_name,
+                        &metadata.endpoint,
+                        &response,
+                        &mut http_response,
+                    );
+                }
                 TOTAL_COMPLETED_REQUESTS.add(1, &[KeyValue::new("model", metadata.model_name)]);
                 SUCCESSFUL_TEXT_EMBEDDING_REQUESTS_PER_USER
                     .add(1, &[KeyValue::new("user_id", metadata.user_id)]);
-                Ok(Json(response).into_response())
+                Ok(http_response)
             }
             Err(e) => {
                 let model_label: String = metadata.model_name.clone();
@@ -210,6 +245,7 @@ pub async fn embeddings_create(
                     metadata.selected_stack_small_id,
                     num_input_compute_units as i64,
                     0,
+                    0,
                     &metadata.endpoint,
                 )?;
                 Err(e)
@@ -272,19 +308,21 @@ pub async fn confidential_embeddings_create(
 ) -> Result<Response<Body>> {
     let endpoint = metadata.endpoint.clone();
     tokio::spawn(async move {
+        let start = Instant::now();
         // TODO: We should allow cancelling the request if the client disconnects
         let RequestMetadataExtension {
             node_address,
             max_total_num_compute_units: num_input_compute_units,
             ..
-        } = metadata;
+        } = &metadata;
+        let num_input_compute_units = *num_input_compute_units;
         EMBEDDING_TOTAL_TOKENS_PER_USER.add(
             num_input_compute_units,
             &[KeyValue::new("user_id", metadata.user_id)],
         );
         match handle_embeddings_response(
             &state,
-            node_address,
+            node_address.clone(),
             headers,
             payload,
             num_input_compute_units as i64,
@@ -308,12 +346,35 @@ pub async fn confidential_embeddings_create(
                     metadata.selected_stack_small_id,
                     num_input_compute_units as i64,
                     total_tokens,
+                    total_tokens,
+                    &metadata.endpoint,
+                )?;
+                update_api_token_spend(
+                    &state.state_manager_sender,
+                    metadata.api_token_id,
+                    metadata.selected_stack_small_id,
+                    total_tokens,
                     &metadata.endpoint,
                 )?;
+                record_request_usage(
+                    &state.state_manager_sender,
+                    &metadata,
+                    total_tokens,
+                    0,
+                    start,
+                    StatusCode::OK,
+                )?;
                 TOTAL_COMPLETED_REQUESTS.add(1, &[KeyValue::new("model", metadata.model_name)]);
                 SUCCESSFUL_TEXT_EMBEDDING_REQUESTS_PER_USER
                     .add(1, &[KeyValue::new("user_id", metadata.user_id)]);
-                Ok(Json(response).into_response())
+                let mut http_response = Json(&response).into_response();
+                insert_response_proof_headers(
+                    http_response.headers_mut(),
+                    &response,
+                    metadata.node_id,
+                    metadata.selected_stack_small_id,
+                );
+                Ok(http_response)
             }
             Err(e) => {
                 let model_label: String = metadata.model_name.clone();
@@ -327,6 +388,7 @@ pub async fn confidential_embeddings_create(
                     metadata.selected_stack_small_id,
                     num_input_compute_units as i64,
                     0,
+                    0,
                     &metadata.endpoint,
                 )?;
                 Err(e)
@@ -402,7 +464,7 @@ async fn handle_embeddings_response(
         .json(&payload)
         .send()
         .await
-        .map_err(|err| AtomaProxyError::InternalError {
+        .map_err(|err| AtomaProxyError::NodeError {
             message: format!("Failed to send embeddings request: {err:?}"),
             client_message: Some("Failed to connect to the node".to_string()),
             endpoint: endpoint.to_string(),
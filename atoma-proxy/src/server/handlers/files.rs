@@ -0,0 +1,251 @@
+use atoma_state::types::{AtomaAtomaStateManagerEvent, NewUserFile, UserFile};
+use axum::{
+    body::{Body, Bytes},
+    extract::{Path, State},
+    http::{header::CONTENT_TYPE, HeaderMap, HeaderValue},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::server::{
+    check_auth, error::AtomaProxyError, http_server::ProxyState, multipart::parse_multipart_form,
+    Result,
+};
+
+use super::{batches::parse_batch_input, query_state_manager};
+
+/// Path for the files endpoint.
+///
+/// This endpoint follows the OpenAI API format for files. Only batch input files can be uploaded.
+pub const FILES_PATH: &str = "/v1/files";
+
+/// Path for retrieving a file.
+pub const FILE_PATH: &str = "/v1/files/{file_id}";
+
+/// Path for downloading the content of a file.
+pub const FILE_CONTENT_PATH: &str = "/v1/files/{file_id}/content";
+
+/// The purpose of batch input files.
+pub const BATCH_PURPOSE: &str = "batch";
+
+/// The purpose of the output and error files of batches.
+pub const BATCH_OUTPUT_PURPOSE: &str = "batch_output";
+
+/// OpenAPI documentation for the files endpoints.
+#[derive(OpenApi)]
+#[openapi(
+    paths(files_create, files_retrieve, files_content),
+    components(schemas(UploadFileRequest, FileObject))
+)]
+pub struct FilesOpenApi;
+
+/// Request body for uploading a file
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct UploadFileRequest {
+    /// The JSONL file of requests to upload.
+    #[schema(value_type = String, format = Binary)]
+    pub file: String,
+
+    /// The purpose of the file. Only `batch` is supported.
+    #[schema(example = "batch")]
+    pub purpose: String,
+}
+
+/// A file stored by the proxy
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct FileObject {
+    /// The id of the file
+    pub id: String,
+    /// The object type, always `file`
+    pub object: String,
+    /// The size of the file, in bytes
+    pub bytes: i64,
+    /// The Unix timestamp (in seconds) of the file creation
+    pub created_at: i64,
+    /// The name of the file
+    pub filename: String,
+    /// The purpose of the file, `batch` for batch inputs and `batch_output` for batch outputs and errors
+    pub purpose: String,
+}
+
+impl From<UserFile> for FileObject {
+    fn from(file: UserFile) -> Self {
+        Self {
+            id: file.id,
+            object: "file".to_string(),
+            bytes: file.bytes,
+            created_at: file.created_at.timestamp(),
+            filename: file.filename,
+            purpose: file.purpose,
+        }
+    }
+}
+
+/// Upload file
+///
+/// Uploads a JSONL file of requests, to be used as the input of a batch. The requests of
+/// the file are validated on upload.
+///
+/// ## Errors
+///   - `BAD_REQUEST` - The body is not a multipart form, the purpose is not `batch`, or the file is invalid
+#[utoipa::path(
+    post,
+    path = "",
+    request_body(content = UploadFileRequest, content_type = "multipart/form-data"),
+    security(
+        ("bearerAuth" = [])
+    ),
+    responses(
+        (status = OK, description = "The uploaded file", body = FileObject),
+        (status = BAD_REQUEST, description = "Bad request"),
+        (status = UNAUTHORIZED, description = "Unauthorized"),
+        (status = INTERNAL_SERVER_ERROR, description = "Internal server error")
+    )
+)]
+#[instrument(level = "info", skip_all, fields(endpoint = FILES_PATH))]
+pub async fn files_create(
+    State(state): State<ProxyState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<FileObject>> {
+    let api_token = check_auth(&state, &headers, FILES_PATH, None).await?;
+    let request_error = |message: &str| AtomaProxyError::RequestError {
+        message: message.to_string(),
+        endpoint: FILES_PATH.to_string(),
+    };
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| value.starts_with("multipart/form-data"))
+        .ok_or_else(|| request_error("Files must be uploaded as multipart/form-data"))?;
+    let fields = parse_multipart_form(content_type, &body, FILES_PATH)?;
+    let purpose = fields
+        .iter()
+        .find(|field| field.name == "purpose")
+        .ok_or_else(|| request_error("Purpose field is required"))?
+        .text(FILES_PATH)?;
+    if purpose != BATCH_PURPOSE {
+        return Err(request_error("Only the batch purpose is supported"));
+    }
+    let file = fields
+        .iter()
+        .find(|field| field.name == "file")
+        .ok_or_else(|| request_error("File field is required"))?;
+    if file.content.len() > state.batches.max_file_size_bytes {
+        return Err(request_error(&format!(
+            "File is larger than the maximum of {} bytes",
+            state.batches.max_file_size_bytes
+        )));
+    }
+    parse_batch_input(file.content, state.batches.max_requests, FILES_PATH)?;
+
+    let file = NewUserFile {
+        id: format!("file-{}", uuid::Uuid::new_v4().simple()),
+        user_id: api_token.user_id,
+        purpose,
+        filename: file
+            .filename
+            .clone()
+            .unwrap_or_else(|| "batch.jsonl".to_string()),
+        content: file.content.to_vec(),
+    };
+    let file = query_state_manager(
+        &state.state_manager_sender,
+        |result_sender| AtomaAtomaStateManagerEvent::InsertUserFile {
+            file,
+            result_sender,
+        },
+        FILES_PATH,
+    )
+    .await?;
+    Ok(Json(file.into()))
+}
+
+/// Retrieve file
+///
+/// Retrieves the metadata of a file of the user.
+#[utoipa::path(
+    get,
+    path = "/{file_id}",
+    params(("file_id" = String, Path, description = "The id of the file")),
+    security(
+        ("bearerAuth" = [])
+    ),
+    responses(
+        (status = OK, description = "The file", body = FileObject),
+        (status = UNAUTHORIZED, description = "Unauthorized"),
+        (status = NOT_FOUND, description = "File not found"),
+        (status = INTERNAL_SERVER_ERROR, description = "Internal server error")
+    )
+)]
+#[instrument(level = "info", skip_all, fields(endpoint = FILE_PATH))]
+pub async fn files_retrieve(
+    State(state): State<ProxyState>,
+    headers: HeaderMap,
+    Path(file_id): Path<String>,
+) -> Result<Json<FileObject>> {
+    let api_token = check_auth(&state, &headers, FILES_PATH, None).await?;
+    let file = query_state_manager(
+        &state.state_manager_sender,
+        |result_sender| AtomaAtomaStateManagerEvent::GetUserFile {
+            user_id: api_token.user_id,
+            file_id: file_id.clone(),
+            result_sender,
+        },
+        FILE_PATH,
+    )
+    .await?
+    .ok_or_else(|| AtomaProxyError::NotFound {
+        message: format!("No file with id {file_id}"),
+        endpoint: FILE_PATH.to_string(),
+    })?;
+    Ok(Json(file.into()))
+}
+
+/// Retrieve file content
+///
+/// Downloads the content of a file of the user, e.g. the output file of a batch.
+#[utoipa::path(
+    get,
+    path = "/{file_id}/content",
+    params(("file_id" = String, Path, description = "The id of the file")),
+    security(
+        ("bearerAuth" = [])
+    ),
+    responses(
+        (status = OK, description = "The content of the file", content_type = "application/jsonl"),
+        (status = UNAUTHORIZED, description = "Unauthorized"),
+        (status = NOT_FOUND, description = "File not found"),
+        (status = INTERNAL_SERVER_ERROR, description = "Internal server error")
+    )
+)]
+#[instrument(level = "info", skip_all, fields(endpoint = FILE_CONTENT_PATH))]
+pub async fn files_content(
+    State(state): State<ProxyState>,
+    headers: HeaderMap,
+    Path(file_id): Path<String>,
+) -> Result<Response<Body>> {
+    let api_token = check_auth(&state, &headers, FILES_PATH, None).await?;
+    let content = query_state_manager(
+        &state.state_manager_sender,
+        |result_sender| AtomaAtomaStateManagerEvent::GetUserFileContent {
+            user_id: api_token.user_id,
+            file_id: file_id.clone(),
+            result_sender,
+        },
+        FILE_CONTENT_PATH,
+    )
+    .await?
+    .ok_or_else(|| AtomaProxyError::NotFound {
+        message: format!("No file with id {file_id}"),
+        endpoint: FILE_CONTENT_PATH.to_string(),
+    })?;
+    let mut response = Body::from(content).into_response();
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/jsonl"));
+    Ok(response)
+}
@@ -4,13 +4,16 @@ use atoma_state::types::AtomaAtomaStateManagerEvent;
 use axum::body::Body;
 use axum::response::{IntoResponse, Response};
 use axum::Extension;
-use axum::{extract::State, http::HeaderMap, Json};
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    Json,
+};
 use base64::engine::{general_purpose::STANDARD, Engine};
 use opentelemetry::KeyValue;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use sqlx::types::chrono::{DateTime, Utc};
-use tokenizers::Tokenizer;
 use tracing::instrument;
 use utoipa::{OpenApi, ToSchema};
 
@@ -25,9 +28,13 @@ use super::metrics::{
     UNSUCCESSFUL_IMAGE_GENERATION_REQUESTS_PER_USER,
 };
 use super::request_model::ComputeUnitsEstimate;
+use super::verify::insert_response_proof_headers;
 use super::{handle_status_code_error, verify_response_hash_and_signature};
-use super::{request_model::RequestModel, update_state_manager, RESPONSE_HASH_KEY};
-use crate::server::{Result, MODEL};
+use super::{
+    record_request_usage, request_model::RequestModel, update_api_token_spend,
+    update_state_manager, RESPONSE_HASH_KEY,
+};
+use crate::server::{tokenizer::TokenCounter, Result, MODEL};
 
 /// Path for the confidential image generations endpoint.
 ///
@@ -104,7 +111,7 @@ impl RequestModel for RequestModelImageGenerations {
 
     fn get_compute_units_estimate(
         &self,
-        _tokenizer: Option<&Tokenizer>,
+        _tokenizer: Option<&dyn TokenCounter>,
     ) -> Result<ComputeUnitsEstimate> {
         // Parse dimensions from size string (e.g., "1024x1024")
         let dimensions: Vec<u64> = self
@@ -167,6 +174,7 @@ pub async fn image_generations_create(
 ) -> Result<Response<Body>> {
     let endpoint = metadata.endpoint.clone();
     tokio::spawn(async move {
+        let start = Instant::now();
         IMAGE_GENERATION_TOTAL_TOKENS_PER_USER.add(
             metadata.max_total_num_compute_units,
             &[KeyValue::new("user_id", metadata.user_id)],
@@ -174,17 +182,33 @@ pub async fn image_generations_create(
         // TODO: We should allow cancelling the request if the client disconnects
         match handle_image_generation_response(
             &state,
-            metadata.node_address,
+            metadata.node_address.clone(),
             headers,
             payload,
             metadata.max_total_num_compute_units as i64,
             metadata.endpoint.clone(),
             metadata.model_name.clone(),
+            metadata.node_id,
             metadata.selected_stack_small_id,
         )
         .await
         {
             Ok(response) => {
+                update_api_token_spend(
+                    &state.state_manager_sender,
+                    metadata.api_token_id,
+                    metadata.selected_stack_small_id,
+                    metadata.max_total_num_compute_units as i64,
+                    &metadata.endpoint,
+                )?;
+                record_request_usage(
+                    &state.state_manager_sender,
+                    &metadata,
+                    metadata.max_total_num_compute_units as i64,
+                    0,
+                    start,
+                    StatusCode::OK,
+                )?;
                 TOTAL_COMPLETED_REQUESTS.add(1, &[KeyValue::new("model", metadata.model_name)]);
                 Ok(response.into_response())
             }
@@ -202,6 +226,7 @@ pub async fn image_generations_create(
                     metadata.selected_stack_small_id,
                     metadata.max_total_num_compute_units as i64,
                     0,
+                    0,
                     &metadata.endpoint,
                 )?;
                 Err(e)
@@ -257,6 +282,7 @@ pub async fn confidential_image_generations_create(
 ) -> Result<Response<Body>> {
     let endpoint = metadata.endpoint.clone();
     tokio::spawn(async move {
+        let start = Instant::now();
         // TODO: We should allow cancelling the request if the client disconnects
         let payload =
             serde_json::to_value(payload).map_err(|e| AtomaProxyError::InternalError {
@@ -270,12 +296,13 @@ pub async fn confidential_image_generations_create(
         );
         match handle_image_generation_response(
             &state,
-            metadata.node_address,
+            metadata.node_address.clone(),
             headers,
             payload,
             metadata.max_total_num_compute_units as i64,
             metadata.endpoint.clone(),
             metadata.model_name.clone(),
+            metadata.node_id,
             metadata.selected_stack_small_id,
         )
         .await
@@ -283,6 +310,21 @@ pub async fn confidential_image_generations_create(
             Ok(response) => {
                 // NOTE: At this point, we do not need to update the stack num compute units,
                 // because the image generation response was correctly generated by a TEE node.
+                update_api_token_spend(
+                    &state.state_manager_sender,
+                    metadata.api_token_id,
+                    metadata.selected_stack_small_id,
+                    metadata.max_total_num_compute_units as i64,
+                    &metadata.endpoint,
+                )?;
+                record_request_usage(
+                    &state.state_manager_sender,
+                    &metadata,
+                    metadata.max_total_num_compute_units as i64,
+                    0,
+                    start,
+                    StatusCode::OK,
+                )?;
                 TOTAL_COMPLETED_REQUESTS.add(1, &[KeyValue::new("model", metadata.model_name)]);
                 SUCCESSFUL_IMAGE_GENERATION_REQUESTS_PER_USER
                     .add(1, &[KeyValue::new("user_id", metadata.user_id)]);
@@ -302,6 +344,7 @@ pub async fn confidential_image_generations_create(
                     metadata.selected_stack_small_id,
                     metadata.max_total_num_compute_units as i64,
                     0,
+                    0,
                     &metadata.endpoint,
                 )?;
                 Err(e)
@@ -363,6 +406,7 @@ async fn handle_image_generation_response(
     total_tokens: i64,
     endpoint: String,
     model_name: String,
+    node_small_id: i64,
     stack_small_id: i64,
 ) -> Result<Response<Body>> {
     // Record the request in the image generations num requests metric
@@ -378,7 +422,7 @@ async fn handle_image_generation_response(
         .json(&payload)
         .send()
         .await
-        .map_err(|err| AtomaProxyError::InternalError {
+        .map_err(|err| AtomaProxyError::NodeError {
             message: format!("Failed to send image generation request: {err:?}"),
             client_message: Some("Failed to connect to the node".to_string()),
             endpoint: endpoint.to_string(),
@@ -455,7 +499,14 @@ async fn handle_image_generation_response(
         &[KeyValue::new("model", model_label)],
     );
 
-    Ok(response.into_response())
+    let mut http_response = response.into_response();
+    insert_response_proof_headers(
+        http_response.headers_mut(),
+        &response.0,
+        node_small_id,
+        stack_small_id,
+    );
+    Ok(http_response)
 }
 
 /// Request body for image generation
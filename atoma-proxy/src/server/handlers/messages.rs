@@ -0,0 +1,895 @@
+use std::convert::Infallible;
+
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    extract::{rejection::JsonRejection, State},
+    http::{header::AUTHORIZATION, HeaderMap, HeaderValue, StatusCode},
+    response::{sse::Event, IntoResponse, Response, Sse},
+    Json,
+};
+use futures::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use tracing::{error, instrument};
+use utoipa::{OpenApi, ToSchema};
+
+use crate::server::{
+    error::AtomaProxyError,
+    handlers::{
+        authenticate_forwarded_request, chat_completions::CHAT_COMPLETIONS_PATH,
+        responses::drain_chat_completion_chunks,
+    },
+    http_server::ProxyState,
+    loopback::loopback_url,
+    Result, MAX_COMPLETION_TOKENS, MODEL,
+};
+
+/// Path for the messages endpoint.
+///
+/// This endpoint follows the Anthropic API format for the Messages API. Requests are
+/// translated to chat completions, and forwarded to the chat completions endpoint.
+pub const MESSAGES_PATH: &str = "/v1/messages";
+
+/// Header used by Anthropic clients to send their API key.
+const ANTHROPIC_API_KEY_HEADER: &str = "x-api-key";
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(messages_create),
+    components(schemas(CreateMessageRequest, MessageObject, MessageContentBlock, MessageUsage))
+)]
+pub struct MessagesOpenApi;
+
+/// Request payload of the Messages API.
+///
+/// Only text generation is supported: tools and extended thinking are rejected.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CreateMessageRequest {
+    /// ID of the model to use
+    pub model: String,
+
+    /// Input messages, with `text` and `image` content blocks
+    #[schema(value_type = Vec<Object>)]
+    pub messages: Vec<Value>,
+
+    /// Maximum number of tokens to generate before stopping
+    pub max_tokens: u32,
+
+    /// System prompt, either a string or a list of `text` content blocks
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub system: Option<Value>,
+
+    /// Custom sequences that cause the model to stop generating
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_sequences: Option<Vec<String>>,
+
+    /// Sampling temperature, between 0 and 1
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+
+    /// Nucleus sampling probability mass
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+
+    /// Whether to stream the message as server-sent events
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+
+    /// Request metadata, whose `user_id` is forwarded as the end-user identifier
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub metadata: Option<Value>,
+}
+
+/// A message of the Messages API.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct MessageObject {
+    /// Unique identifier of the message
+    pub id: String,
+    /// The object type, always `message`
+    #[serde(rename = "type")]
+    pub r#type: String,
+    /// The role of the message, always `assistant`
+    pub role: String,
+    /// The generated content blocks
+    pub content: Vec<MessageContentBlock>,
+    /// The model used to generate the message
+    pub model: String,
+    /// Why generation stopped: `end_turn` or `max_tokens`
+    pub stop_reason: Option<String>,
+    /// The stop sequence that was generated, always `null`
+    pub stop_sequence: Option<String>,
+    /// Token usage of the message
+    pub usage: MessageUsage,
+}
+
+/// A text content block of a message.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct MessageContentBlock {
+    /// The content block type, always `text`
+    #[serde(rename = "type")]
+    pub r#type: String,
+    /// The generated text
+    pub text: String,
+}
+
+/// Token usage of a message.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct MessageUsage {
+    /// Number of input tokens
+    pub input_tokens: u64,
+    /// Number of output tokens
+    pub output_tokens: u64,
+}
+
+impl MessageUsage {
+    /// Converts the `usage` of a chat completion.
+    fn from_chat_completion_usage(usage: &Value) -> Option<Self> {
+        Some(Self {
+            input_tokens: usage.get("prompt_tokens")?.as_u64()?,
+            output_tokens: usage.get("completion_tokens")?.as_u64()?,
+        })
+    }
+}
+
+/// Create message
+///
+/// This function processes Anthropic Messages API requests by translating them to chat completion
+/// requests, forwarding them to the chat completions endpoint (which handles compute units
+/// estimation, node selection and billing), and translating the chat completion, or its stream
+/// of chunks, back to a message or a stream of message events. The API token of the client is
+/// authenticated, and its scopes enforced, for the messages endpoint before forwarding.
+///
+/// Anthropic clients authenticate with the `x-api-key` header, which is used as a bearer
+/// token when no `Authorization` header is set.
+///
+/// ## Returns
+///
+/// Returns a Response containing either:
+/// - A streaming SSE connection of message events
+/// - A single JSON message object
+/// - An error, in the error format of the Messages API
+///
+/// ## Errors
+///
+/// Returns an error status code if:
+/// - The request uses unsupported features of the Messages API
+/// - The API token is invalid, or not allowed to use the messages endpoint or the model
+/// - The chat completions endpoint can't be reached
+#[utoipa::path(
+    post,
+    path = "",
+    security(
+        ("bearerAuth" = [])
+    ),
+    request_body = CreateMessageRequest,
+    responses(
+        (status = OK, description = "Messages", content(
+            (MessageObject = "application/json"),
+        )),
+        (status = BAD_REQUEST, description = "Bad request"),
+        (status = UNAUTHORIZED, description = "Unauthorized"),
+        (status = INTERNAL_SERVER_ERROR, description = "Internal server error")
+    )
+)]
+#[instrument(
+    level = "info",
+    skip_all,
+    fields(
+        path = MESSAGES_PATH,
+    )
+)]
+pub async fn messages_create(
+    State(state): State<ProxyState>,
+    headers: HeaderMap,
+    payload: std::result::Result<Json<Value>, JsonRejection>,
+) -> Response<Body> {
+    let result = match payload {
+        Ok(Json(payload)) => create_message(&state, headers, payload).await,
+        Err(rejection) => Err(AtomaProxyError::RequestError {
+            message: rejection.body_text(),
+            endpoint: MESSAGES_PATH.to_string(),
+        }),
+    };
+    match result {
+        Ok(response) => response,
+        Err(e) => {
+            // NOTE: The error response keeps the status and headers (e.g. the rate limits) of the proxy error
+            let (parts, body) = e.into_response().into_parts();
+            let body = to_bytes(body, usize::MAX).await.unwrap_or_default();
+            let body = anthropic_error(parts.status, &body);
+            Response::from_parts(parts, Body::from(body))
+        }
+    }
+}
+
+/// Translates a Messages API request to a chat completion request, forwards it to the chat
+/// completions endpoint, and translates its response back to a message (see `messages_create`).
+async fn create_message(
+    state: &ProxyState,
+    mut headers: HeaderMap,
+    payload: Value,
+) -> Result<Response<Body>> {
+    let chat_completion_request = messages_request_to_chat_completion_request(&payload)?;
+    let stop_sequences = stop_sequences(&payload);
+    let is_streaming = chat_completion_request
+        .get("stream")
+        .and_then(Value::as_bool)
+        .unwrap_or_default();
+
+    if !headers.contains_key(AUTHORIZATION) {
+        if let Some(api_key) = headers
+            .remove(ANTHROPIC_API_KEY_HEADER)
+            .and_then(|api_key| {
+                HeaderValue::from_str(&format!("Bearer {}", api_key.to_str().ok()?)).ok()
+            })
+        {
+            headers.insert(AUTHORIZATION, api_key);
+        }
+    }
+    let headers = authenticate_forwarded_request(
+        state,
+        &headers,
+        MESSAGES_PATH,
+        chat_completion_request.get(MODEL).and_then(Value::as_str),
+    )
+    .await?;
+
+    // Forward the translated payload to /v1/chat/completions
+    let chat_completions_endpoint = loopback_url(state, CHAT_COMPLETIONS_PATH);
+    let client = reqwest::Client::new();
+
+    let response = client
+        .post(chat_completions_endpoint)
+        .headers(headers)
+        .json(&chat_completion_request)
+        .send()
+        .await
+        .map_err(|err| AtomaProxyError::InternalError {
+            message: format!("Failed to forward request to chat completions endpoint: {err:?}"),
+            client_message: Some("Failed to connect to the chat completions endpoint.".to_string()),
+            endpoint: MESSAGES_PATH.to_string(),
+        })?;
+    let status = response.status();
+
+    if is_streaming && status.is_success() {
+        let translator = MessageStreamTranslator::new(
+            payload
+                .get(MODEL)
+                .and_then(Value::as_str)
+                .unwrap_or_default(),
+            stop_sequences,
+        );
+        let events = stream::unfold(
+            Some((response.bytes_stream().boxed(), translator)),
+            |state| async move {
+                let (mut chunks, mut translator) = state?;
+                match chunks.next().await {
+                    Some(Ok(bytes)) => {
+                        Some((translator.push_bytes(&bytes), Some((chunks, translator))))
+                    }
+                    Some(Err(e)) => {
+                        error!(
+                            target = "atoma-service",
+                            level = "error",
+                            "Error reading chat completions stream: {e:?}"
+                        );
+                        Some((translator.fail(), None))
+                    }
+                    None => Some((translator.finish(), None)),
+                }
+            },
+        )
+        .flat_map(stream::iter);
+        return Ok(Sse::new(events).into_response());
+    }
+
+    let body_bytes = response
+        .bytes()
+        .await
+        .map_err(|err| AtomaProxyError::InternalError {
+            message: format!("Failed to read response from chat completions endpoint: {err:?}"),
+            client_message: None,
+            endpoint: MESSAGES_PATH.to_string(),
+        })?;
+    let body = if status.is_success() {
+        let chat_completion = serde_json::from_slice::<Value>(&body_bytes).map_err(|err| {
+            AtomaProxyError::InternalError {
+                message: format!("Failed to parse chat completion: {err:?}"),
+                client_message: None,
+                endpoint: MESSAGES_PATH.to_string(),
+            }
+        })?;
+        let message = chat_completion_to_message(&chat_completion, &stop_sequences)?;
+        Body::from(
+            serde_json::to_vec(&message).map_err(|err| AtomaProxyError::InternalError {
+                message: format!("Failed to serialize message: {err:?}"),
+                client_message: None,
+                endpoint: MESSAGES_PATH.to_string(),
+            })?,
+        )
+    } else {
+        Body::from(anthropic_error(status, &body_bytes))
+    };
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(body)
+        .map_err(|err| AtomaProxyError::InternalError {
+            message: format!("Failed to create response: {err:?}"),
+            client_message: None,
+            endpoint: MESSAGES_PATH.to_string(),
+        })
+}
+
+/// Translates a Messages API request to a chat completion request.
+///
+/// # Errors
+///
+/// Returns a `RequestError` if the request is malformed, or uses features of the
+/// Messages API that can't be expressed as a chat completion.
+fn messages_request_to_chat_completion_request(payload: &Value) -> Result<Value> {
+    let request_error = |message: &str| AtomaProxyError::RequestError {
+        message: message.to_string(),
+        endpoint: MESSAGES_PATH.to_string(),
+    };
+    let payload = payload
+        .as_object()
+        .ok_or_else(|| request_error("Invalid payload"))?;
+    for unsupported in ["tools", "tool_choice", "thinking"] {
+        if payload
+            .get(unsupported)
+            .is_some_and(|value| !value.is_null())
+        {
+            return Err(request_error(&format!("'{unsupported}' is not supported")));
+        }
+    }
+    let model = payload
+        .get(MODEL)
+        .and_then(Value::as_str)
+        .ok_or_else(|| request_error("Missing 'model' field"))?;
+    let max_tokens = payload
+        .get("max_tokens")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| request_error("Missing 'max_tokens' field"))?;
+
+    let mut messages = Vec::new();
+    match payload.get("system") {
+        None | Some(Value::Null) => {}
+        Some(Value::String(system)) => {
+            messages.push(json!({ "role": "system", "content": system }))
+        }
+        Some(Value::Array(blocks)) => {
+            let system = blocks
+                .iter()
+                .map(|block| block.get("text").and_then(Value::as_str))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| request_error("Invalid 'system' field"))?;
+            messages.push(json!({ "role": "system", "content": system.join("\n") }));
+        }
+        Some(_) => return Err(request_error("Invalid 'system' field")),
+    }
+    for message in payload
+        .get("messages")
+        .and_then(Value::as_array)
+        .ok_or_else(|| request_error("Missing 'messages' field"))?
+    {
+        messages.push(
+            message_to_chat_message(message).ok_or_else(|| {
+                request_error("Invalid message, only text and images are supported")
+            })?,
+        );
+    }
+
+    let mut chat_completion_request = Map::new();
+    chat_completion_request.insert(MODEL.to_string(), json!(model));
+    chat_completion_request.insert("messages".to_string(), Value::Array(messages));
+    chat_completion_request.insert(MAX_COMPLETION_TOKENS.to_string(), json!(max_tokens));
+    for key in ["temperature", "top_p"] {
+        if let Some(value) = payload.get(key).filter(|value| !value.is_null()) {
+            chat_completion_request.insert(key.to_string(), value.clone());
+        }
+    }
+    if let Some(stop_sequences) = payload
+        .get("stop_sequences")
+        .filter(|value| !value.is_null())
+    {
+        chat_completion_request.insert("stop".to_string(), stop_sequences.clone());
+    }
+    if let Some(user_id) = payload
+        .get("metadata")
+        .and_then(|metadata| metadata.get("user_id"))
+        .filter(|value| !value.is_null())
+    {
+        chat_completion_request.insert("user".to_string(), user_id.clone());
+    }
+    if payload
+        .get("stream")
+        .and_then(Value::as_bool)
+        .unwrap_or_default()
+    {
+        chat_completion_request.insert("stream".to_string(), Value::Bool(true));
+        chat_completion_request.insert(
+            "stream_options".to_string(),
+            json!({ "include_usage": true }),
+        );
+    }
+    Ok(Value::Object(chat_completion_request))
+}
+
+/// Translates a message of the Messages API to a chat completion message.
+///
+/// Returns `None` if the message has an invalid role, or an unsupported content block.
+fn message_to_chat_message(message: &Value) -> Option<Value> {
+    let role = match message.get("role")?.as_str()? {
+        role @ ("user" | "assistant") => role,
+        _ => return None,
+    };
+    let content = match message.get("content")? {
+        Value::String(content) => json!(content),
+        Value::Array(blocks) => Value::Array(
+            blocks
+                .iter()
+                .map(|block| match block.get("type")?.as_str()? {
+                    "text" => Some(json!({ "type": "text", "text": block.get("text")?.as_str()? })),
+                    "image" => {
+                        let source = block.get("source")?;
+                        let url = match source.get("type")?.as_str()? {
+                            "base64" => format!(
+                                "data:{};base64,{}",
+                                source.get("media_type")?.as_str()?,
+                                source.get("data")?.as_str()?
+                            ),
+                            "url" => source.get("url")?.as_str()?.to_string(),
+                            _ => return None,
+                        };
+                        Some(json!({ "type": "image_url", "image_url": { "url": url } }))
+                    }
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()?,
+        ),
+        _ => return None,
+    };
+    Some(json!({ "role": role, "content": content }))
+}
+
+/// Translates an error response of the proxy, or of the chat completions endpoint, to the error
+/// format of the Messages API: `{"type": "error", "error": {"type": ..., "message": ...}}`.
+fn anthropic_error(status: StatusCode, body: &[u8]) -> Vec<u8> {
+    let message = serde_json::from_slice::<Value>(body)
+        .ok()
+        .and_then(|body| Some(body.get("error")?.get("message")?.as_str()?.to_string()))
+        .unwrap_or_else(|| String::from_utf8_lossy(body).into_owned());
+    let error_type = match status {
+        StatusCode::UNAUTHORIZED => "authentication_error",
+        StatusCode::PAYMENT_REQUIRED => "billing_error",
+        StatusCode::FORBIDDEN => "permission_error",
+        StatusCode::NOT_FOUND => "not_found_error",
+        StatusCode::PAYLOAD_TOO_LARGE => "request_too_large",
+        StatusCode::TOO_MANY_REQUESTS => "rate_limit_error",
+        StatusCode::SERVICE_UNAVAILABLE => "overloaded_error",
+        status if status.is_client_error() => "invalid_request_error",
+        _ => "api_error",
+    };
+    json!({
+        "type": "error",
+        "error": { "type": error_type, "message": message },
+    })
+    .to_string()
+    .into_bytes()
+}
+
+/// Returns the stop sequences of a Messages API request.
+fn stop_sequences(payload: &Value) -> Vec<String> {
+    payload
+        .get("stop_sequences")
+        .and_then(Value::as_array)
+        .map(|stop_sequences| {
+            stop_sequences
+                .iter()
+                .filter_map(|stop_sequence| Some(stop_sequence.as_str()?.to_string()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Returns the stop reason and stop sequence of a message, given a choice of the chat completion.
+///
+/// The stop sequence that ended the output is read from the `stop_reason` field of the choice, which
+/// inference engines like vLLM set. When it is not reported, the output is reported as ended by the
+/// end of the turn, as the stop sequence is not part of the output.
+fn stop_reason(choice: &Value, stop_sequences: &[String]) -> (&'static str, Option<String>) {
+    match choice.get("finish_reason").and_then(Value::as_str) {
+        Some("length") => ("max_tokens", None),
+        Some("stop") => choice
+            .get("stop_reason")
+            .and_then(Value::as_str)
+            .filter(|stop_sequence| stop_sequences.iter().any(|s| s == stop_sequence))
+            .map_or(("end_turn", None), |stop_sequence| {
+                ("stop_sequence", Some(stop_sequence.to_string()))
+            }),
+        _ => ("end_turn", None),
+    }
+}
+
+/// Translates a chat completion to a message of the Messages API.
+///
+/// # Errors
+///
+/// Returns an `InternalError` if the chat completion has no choices.
+fn chat_completion_to_message(
+    chat_completion: &Value,
+    stop_sequences: &[String],
+) -> Result<MessageObject> {
+    let choice = chat_completion
+        .get("choices")
+        .and_then(|choices| choices.get(0))
+        .ok_or_else(|| AtomaProxyError::InternalError {
+            message: "Chat completion has no choices".to_string(),
+            client_message: None,
+            endpoint: MESSAGES_PATH.to_string(),
+        })?;
+    let id = chat_completion
+        .get("id")
+        .and_then(Value::as_str)
+        .map_or_else(
+            || uuid::Uuid::new_v4().simple().to_string(),
+            ToString::to_string,
+        );
+    let text = choice
+        .get("message")
+        .and_then(|message| message.get("content"))
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    let (stop_reason, stop_sequence) = stop_reason(choice, stop_sequences);
+    Ok(MessageObject {
+        id: format!("msg_{id}"),
+        r#type: "message".to_string(),
+        role: "assistant".to_string(),
+        content: vec![MessageContentBlock {
+            r#type: "text".to_string(),
+            text: text.to_string(),
+        }],
+        model: chat_completion
+            .get(MODEL)
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string(),
+        stop_reason: Some(stop_reason.to_string()),
+        stop_sequence,
+        usage: chat_completion
+            .get("usage")
+            .and_then(MessageUsage::from_chat_completion_usage)
+            .unwrap_or_default(),
+    })
+}
+
+/// Translates the stream of chat completion chunks returned by the chat completions
+/// endpoint to a stream of Messages API events.
+///
+/// The whole message is streamed as a single text content block: `message_start`,
+/// `content_block_start`, a `content_block_delta` per chunk, then `content_block_stop`,
+/// `message_delta` with the stop reason and the token usage, and `message_stop`.
+struct MessageStreamTranslator {
+    /// The message being streamed
+    message: MessageObject,
+    /// Bytes of the incomplete server-sent event received so far
+    buffer: Vec<u8>,
+    /// The stop sequences of the request
+    stop_sequences: Vec<String>,
+    /// The choice of the chunk that finished the chat completion
+    finish_choice: Option<Value>,
+    /// Whether the content block was opened
+    is_started: bool,
+}
+
+impl MessageStreamTranslator {
+    fn new(model: &str, stop_sequences: Vec<String>) -> Self {
+        Self {
+            message: MessageObject {
+                id: format!("msg_{}", uuid::Uuid::new_v4().simple()),
+                r#type: "message".to_string(),
+                role: "assistant".to_string(),
+                content: vec![],
+                model: model.to_string(),
+                stop_reason: None,
+                stop_sequence: None,
+                usage: MessageUsage::default(),
+            },
+            buffer: Vec::new(),
+            stop_sequences,
+            finish_choice: None,
+            is_started: false,
+        }
+    }
+
+    /// Builds a server-sent event of the given type.
+    fn event(event_type: &str, mut data: Value) -> std::result::Result<Event, Infallible> {
+        if let Some(data) = data.as_object_mut() {
+            data.insert("type".to_string(), json!(event_type));
+        }
+        Ok(Event::default().event(event_type).data(data.to_string()))
+    }
+
+    /// Opens the message and its content block, if they are not open yet.
+    fn start(&mut self) -> Vec<std::result::Result<Event, Infallible>> {
+        if self.is_started {
+            return vec![];
+        }
+        self.is_started = true;
+        let message = json!(self.message);
+        self.message.content.push(MessageContentBlock {
+            r#type: "text".to_string(),
+            text: String::new(),
+        });
+        vec![
+            Self::event("message_start", json!({ "message": message })),
+            Self::event(
+                "content_block_start",
+                json!({ "index": 0, "content_block": { "type": "text", "text": "" } }),
+            ),
+        ]
+    }
+
+    /// Consumes bytes of the chat completions stream, returning the translated events.
+    fn push_bytes(&mut self, bytes: &Bytes) -> Vec<std::result::Result<Event, Infallible>> {
+        let mut events = self.start();
+        for chunk in drain_chat_completion_chunks(&mut self.buffer, bytes) {
+            events.extend(self.push_chunk(&chunk));
+        }
+        events
+    }
+
+    /// Consumes a chat completion chunk, returning the translated events.
+    fn push_chunk(&mut self, chunk: &Value) -> Vec<std::result::Result<Event, Infallible>> {
+        if let Some(usage) = chunk
+            .get("usage")
+            .and_then(MessageUsage::from_chat_completion_usage)
+        {
+            self.message.usage = usage;
+        }
+        let Some(choice) = chunk.get("choices").and_then(|choices| choices.get(0)) else {
+            return vec![];
+        };
+        if choice
+            .get("finish_reason")
+            .is_some_and(|value| !value.is_null())
+        {
+            self.finish_choice = Some(choice.clone());
+        }
+        let Some(delta) = choice
+            .get("delta")
+            .and_then(|delta| delta.get("content"))
+            .and_then(Value::as_str)
+            .filter(|delta| !delta.is_empty())
+        else {
+            return vec![];
+        };
+        self.message.content[0].text.push_str(delta);
+        vec![Self::event(
+            "content_block_delta",
+            json!({ "index": 0, "delta": { "type": "text_delta", "text": delta } }),
+        )]
+    }
+
+    /// Completes the message, returning the final events.
+    fn finish(&mut self) -> Vec<std::result::Result<Event, Infallible>> {
+        let mut events = self.start();
+        let (stop_reason, stop_sequence) = stop_reason(
+            self.finish_choice.as_ref().unwrap_or(&Value::Null),
+            &self.stop_sequences,
+        );
+        self.message.stop_reason = Some(stop_reason.to_string());
+        self.message.stop_sequence.clone_from(&stop_sequence);
+        events.extend([
+            Self::event("content_block_stop", json!({ "index": 0 })),
+            Self::event(
+                "message_delta",
+                json!({
+                    "delta": { "stop_reason": stop_reason, "stop_sequence": stop_sequence },
+                    "usage": self.message.usage,
+                }),
+            ),
+            Self::event("message_stop", json!({})),
+        ]);
+        events
+    }
+
+    /// Fails the message, returning the final event.
+    fn fail(&mut self) -> Vec<std::result::Result<Event, Infallible>> {
+        let mut events = self.start();
+        events.push(Self::event(
+            "error",
+            json!({
+                "error": {
+                    "type": "api_error",
+                    "message": "Failed to read the stream from the node",
+                },
+            }),
+        ));
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_translation() {
+        let chat_completion_request = messages_request_to_chat_completion_request(&json!({
+            "model": "llama",
+            "system": [{ "type": "text", "text": "Be concise" }],
+            "messages": [
+                { "role": "user", "content": "Hello" },
+                { "role": "assistant", "content": "Hi" },
+                {
+                    "role": "user",
+                    "content": [
+                        { "type": "text", "text": "What is in this image?" },
+                        {
+                            "type": "image",
+                            "source": { "type": "base64", "media_type": "image/png", "data": "AAAA" },
+                        },
+                    ],
+                },
+            ],
+            "max_tokens": 100,
+            "stop_sequences": ["\n\nHuman:"],
+            "metadata": { "user_id": "user-1" },
+            "stream": true,
+        }))
+        .unwrap();
+        assert_eq!(
+            chat_completion_request,
+            json!({
+                "model": "llama",
+                "messages": [
+                    { "role": "system", "content": "Be concise" },
+                    { "role": "user", "content": "Hello" },
+                    { "role": "assistant", "content": "Hi" },
+                    {
+                        "role": "user",
+                        "content": [
+                            { "type": "text", "text": "What is in this image?" },
+                            { "type": "image_url", "image_url": { "url": "data:image/png;base64,AAAA" } },
+                        ],
+                    },
+                ],
+                "max_completion_tokens": 100,
+                "stop": ["\n\nHuman:"],
+                "user": "user-1",
+                "stream": true,
+                "stream_options": { "include_usage": true },
+            })
+        );
+
+        assert!(messages_request_to_chat_completion_request(&json!({
+            "model": "llama",
+            "messages": [{ "role": "user", "content": "Hello" }],
+        }))
+        .is_err());
+        assert!(messages_request_to_chat_completion_request(&json!({
+            "model": "llama",
+            "max_tokens": 100,
+            "messages": [{
+                "role": "user",
+                "content": [{ "type": "tool_result", "tool_use_id": "1", "content": "{}" }],
+            }],
+        }))
+        .is_err());
+    }
+
+    #[test]
+    fn test_chat_completion_translation() {
+        let message = chat_completion_to_message(
+            &json!({
+                "id": "chatcmpl-1",
+                "model": "llama",
+                "choices": [{
+                    "index": 0,
+                    "message": { "role": "assistant", "content": "Hello" },
+                    "finish_reason": "length",
+                }],
+                "usage": { "prompt_tokens": 3, "completion_tokens": 2, "total_tokens": 5 },
+            }),
+            &[],
+        )
+        .unwrap();
+        assert_eq!(message.id, "msg_chatcmpl-1");
+        assert_eq!(message.stop_reason.as_deref(), Some("max_tokens"));
+        assert_eq!(message.content[0].text, "Hello");
+        assert_eq!(
+            message.usage,
+            MessageUsage {
+                input_tokens: 3,
+                output_tokens: 2,
+            }
+        );
+
+        let stop_sequences = ["\n\nHuman:".to_string()];
+        let chat_completion = |stop_reason: Value| {
+            json!({
+                "choices": [{
+                    "index": 0,
+                    "message": { "role": "assistant", "content": "Hello" },
+                    "finish_reason": "stop",
+                    "stop_reason": stop_reason,
+                }],
+            })
+        };
+        let message =
+            chat_completion_to_message(&chat_completion(json!("\n\nHuman:")), &stop_sequences)
+                .unwrap();
+        assert_eq!(message.stop_reason.as_deref(), Some("stop_sequence"));
+        assert_eq!(message.stop_sequence.as_deref(), Some("\n\nHuman:"));
+        // The end of sequence token of the model is not a stop sequence of the request
+        let message =
+            chat_completion_to_message(&chat_completion(json!(128_009)), &stop_sequences).unwrap();
+        assert_eq!(message.stop_reason.as_deref(), Some("end_turn"));
+        assert_eq!(message.stop_sequence, None);
+    }
+
+    #[test]
+    fn test_anthropic_error() {
+        let error: Value = serde_json::from_slice(&anthropic_error(
+            StatusCode::TOO_MANY_REQUESTS,
+            br#"{"error":{"code":"TOO_MANY_REQUESTS","message":"Too many requests"}}"#,
+        ))
+        .unwrap();
+        assert_eq!(
+            error,
+            json!({
+                "type": "error",
+                "error": { "type": "rate_limit_error", "message": "Too many requests" },
+            })
+        );
+        let error: Value =
+            serde_json::from_slice(&anthropic_error(StatusCode::BAD_GATEWAY, b"Bad gateway"))
+                .unwrap();
+        assert_eq!(error["error"]["type"], "api_error");
+        assert_eq!(error["error"]["message"], "Bad gateway");
+    }
+
+    #[test]
+    fn test_stream_translation() {
+        let mut translator = MessageStreamTranslator::new("llama", vec![]);
+        let chunk = |delta: &str, finish_reason: Option<&str>| {
+            format!(
+                "data: {}\n\n",
+                json!({
+                    "choices": [{
+                        "index": 0,
+                        "delta": { "content": delta },
+                        "finish_reason": finish_reason,
+                    }],
+                })
+            )
+        };
+        assert_eq!(
+            translator
+                .push_bytes(&Bytes::from(chunk("Hel", None)))
+                .len(),
+            3
+        );
+        let usage = json!({
+            "choices": [],
+            "usage": { "prompt_tokens": 3, "completion_tokens": 2, "total_tokens": 5 },
+        });
+        assert_eq!(
+            translator
+                .push_bytes(&Bytes::from(format!(
+                    "{}data: {usage}\n\ndata: [DONE]\n\n",
+                    chunk("lo", Some("stop"))
+                )))
+                .len(),
+            1
+        );
+        assert_eq!(translator.finish().len(), 3);
+        assert_eq!(translator.message.stop_reason.as_deref(), Some("end_turn"));
+        assert_eq!(translator.message.content[0].text, "Hello");
+        assert_eq!(translator.message.usage.output_tokens, 2);
+    }
+}
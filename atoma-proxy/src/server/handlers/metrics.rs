@@ -49,6 +49,24 @@ pub static IMAGE_GEN_NUM_REQUESTS: Lazy<Counter<u64>> = Lazy::new(|| {
         .build()
 });
 
+/// Counter metric that tracks the total number of audio requests.
+///
+/// This metric counts the number of incoming requests for audio transcription
+/// and speech generation, broken down by model type and endpoint.
+///
+/// # Metric Details
+/// - Name: `atoma_audio_num_requests`
+/// - Type: Counter
+/// - Labels: `model`, `endpoint`
+/// - Unit: requests (count)
+pub static AUDIO_NUM_REQUESTS: Lazy<Counter<u64>> = Lazy::new(|| {
+    GLOBAL_METER
+        .u64_counter("atoma_audio_num_requests")
+        .with_description("The number of incoming requests for audio tasks")
+        .with_unit("requests")
+        .build()
+});
+
 /// Counter metric that tracks the total number of text embedding requests.
 ///
 /// This metric counts the number of incoming requests for text embeddings,
@@ -130,6 +148,26 @@ pub static IMAGE_GEN_LATENCY_METRICS: Lazy<Histogram<f64>> = Lazy::new(|| {
         .build()
 });
 
+/// Histogram metric that tracks the latency of audio requests.
+///
+/// This metric measures the time taken to transcribe or generate audio, broken down by model type
+/// and endpoint.
+///
+/// # Metric Details
+/// - Name: `atoma_audio_latency`
+/// - Type: Histogram
+/// - Labels: `model`, `endpoint`
+/// - Unit: seconds
+/// - Buckets: [0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0]
+pub static AUDIO_LATENCY_METRICS: Lazy<Histogram<f64>> = Lazy::new(|| {
+    GLOBAL_METER
+        .f64_histogram("atoma_audio_latency")
+        .with_description("The latency of audio requests in seconds")
+        .with_unit("s")
+        .with_boundaries(LATENCY_HISTOGRAM_BUCKETS.to_vec())
+        .build()
+});
+
 /// Histogram metric that tracks the latency of text embedding requests.
 ///
 /// This metric measures the time taken to generate text embeddings, broken down by model type.
@@ -326,6 +364,21 @@ pub static TOTAL_FAILED_TEXT_EMBEDDING_REQUESTS: Lazy<Counter<u64>> = Lazy::new(
         .build()
 });
 
+/// Counter metric that tracks the total number of failed audio requests.
+///
+/// # Metric Details
+/// - Name: `atoma_total_failed_audio_requests`
+/// - Type: Counter
+/// - Labels: `model`
+/// - Unit: requests (count)
+pub static TOTAL_FAILED_AUDIO_REQUESTS: Lazy<Counter<u64>> = Lazy::new(|| {
+    GLOBAL_METER
+        .u64_counter("atoma_total_failed_audio_requests")
+        .with_description("Total number of failed audio requests")
+        .with_unit("requests")
+        .build()
+});
+
 /// Counter metric that tracks the total number of stack unavailable errors.
 ///
 /// # Metric Details
@@ -585,3 +638,99 @@ pub static LOCKED_STACK_COUNTER_PER_USER: Lazy<Counter<u64>> = Lazy::new(|| {
         .with_unit("requests")
         .build()
 });
+
+/// Counter metric that tracks the total number of requests rejected by the rate limiter per user_id.
+///
+/// # Metric Details
+/// - Name: `atoma_rate_limited_requests_per_user`
+/// - Type: Counter
+/// - Labels: `user_id`
+/// - Unit: requests (count)
+pub static RATE_LIMITED_REQUESTS_PER_USER: Lazy<Counter<u64>> = Lazy::new(|| {
+    GLOBAL_METER
+        .u64_counter("atoma_rate_limited_requests_per_user")
+        .with_description("Total number of requests rejected by the rate limiter per user_id")
+        .with_unit("requests")
+        .build()
+});
+
+/// Counter metric that tracks the total number of requests rerouted to another node, after a node failure.
+///
+/// # Metric Details
+/// - Name: `atoma_node_failover_counter`
+/// - Type: Counter
+/// - Labels: `model`
+/// - Unit: requests (count)
+pub static NODE_FAILOVER_COUNTER: Lazy<Counter<u64>> = Lazy::new(|| {
+    GLOBAL_METER
+        .u64_counter("atoma_node_failover_counter")
+        .with_description("Total number of requests rerouted to another node after a node failure")
+        .with_unit("requests")
+        .build()
+});
+
+/// Counter metric that tracks the total number of requests served from the response cache.
+///
+/// # Metric Details
+/// - Name: `atoma_response_cache_hits`
+/// - Type: Counter
+/// - Labels: `model`, `endpoint`
+/// - Unit: requests (count)
+pub static RESPONSE_CACHE_HITS: Lazy<Counter<u64>> = Lazy::new(|| {
+    GLOBAL_METER
+        .u64_counter("atoma_response_cache_hits")
+        .with_description("Total number of requests served from the response cache")
+        .with_unit("requests")
+        .build()
+});
+
+/// Counter metric that tracks the total number of cacheable requests not found in the response cache.
+///
+/// # Metric Details
+/// - Name: `atoma_response_cache_misses`
+/// - Type: Counter
+/// - Labels: `model`, `endpoint`
+/// - Unit: requests (count)
+pub static RESPONSE_CACHE_MISSES: Lazy<Counter<u64>> = Lazy::new(|| {
+    GLOBAL_METER
+        .u64_counter("atoma_response_cache_misses")
+        .with_description("Total number of cacheable requests not found in the response cache")
+        .with_unit("requests")
+        .build()
+});
+
+/// Counter metric that tracks the difference between the completion tokens reported by the nodes
+/// and the completion tokens counted by the proxy, for streamed chat completions.
+///
+/// # Metric Details
+/// - Name: `atoma_chat_completions_usage_discrepancy_tokens`
+/// - Type: Counter
+/// - Labels: `model`
+/// - Unit: tokens (count)
+pub static CHAT_COMPLETIONS_USAGE_DISCREPANCY_TOKENS: Lazy<Counter<u64>> = Lazy::new(|| {
+    GLOBAL_METER
+        .u64_counter("atoma_chat_completions_usage_discrepancy_tokens")
+        .with_description(
+            "Absolute difference between the completion tokens reported by the nodes and counted by the proxy",
+        )
+        .with_unit("tokens")
+        .build()
+});
+
+/// Counter metric that tracks the number of streamed chat completions settled with the completion
+/// tokens counted by the proxy, as the node did not report the usage of the request.
+///
+/// # Metric Details
+/// - Name: `atoma_chat_completions_proxy_counted_usage`
+/// - Type: Counter
+/// - Labels: `model`
+/// - Unit: requests (count)
+pub static CHAT_COMPLETIONS_PROXY_COUNTED_USAGE: Lazy<Counter<u64>> = Lazy::new(|| {
+    GLOBAL_METER
+        .u64_counter("atoma_chat_completions_proxy_counted_usage")
+        .with_description(
+            "Number of streamed chat completions settled with the tokens counted by the proxy",
+        )
+        .with_unit("requests")
+        .build()
+});
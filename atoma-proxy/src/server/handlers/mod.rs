@@ -1,6 +1,18 @@
-use std::str::FromStr;
+use std::{str::FromStr, time::Instant};
 
-use atoma_state::types::AtomaAtomaStateManagerEvent;
+use atoma_state::{
+    types::{AtomaAtomaStateManagerEvent, NewUsageRecord},
+    AtomaStateManagerError,
+};
+use axum::{
+    body::Body,
+    http::{
+        header::{AUTHORIZATION, CONTENT_LENGTH},
+        HeaderMap, HeaderName, HeaderValue,
+    },
+    response::{IntoResponse, Response},
+    Json,
+};
 use base64::engine::{general_purpose::STANDARD, Engine};
 use blake2::Digest;
 use fastcrypto::{
@@ -10,20 +22,37 @@ use fastcrypto::{
     traits::{ToFromBytes, VerifyingKey},
 };
 use flume::Sender;
+use opentelemetry::KeyValue;
 use reqwest::StatusCode;
+use serde_json::Value;
 use sui_sdk::types::crypto::{PublicKey, Signature, SignatureScheme, SuiSignature};
+use tokio::sync::oneshot;
 use tracing::instrument;
 
+use self::metrics::{RESPONSE_CACHE_HITS, RESPONSE_CACHE_MISSES};
+use super::check_auth;
 use super::error::AtomaProxyError;
+use super::http_server::ProxyState;
+use super::loopback::{LOOPBACK_API_TOKEN_ID_HEADER, LOOPBACK_SCOPE_ENDPOINT_HEADER};
+use super::middleware::RequestMetadataExtension;
+use super::response_cache::{CacheLookup, ResponseCache, CACHE_HIT, CACHE_MISS, X_ATOMA_CACHE};
+use super::{CacheHitBilling, ONE_MILLION};
 use crate::server::Result;
 
+pub mod admin;
+pub mod audio;
+pub mod batches;
 pub mod chat_completions;
 pub mod embeddings;
+pub mod files;
 pub mod image_generations;
+pub mod messages;
 pub mod metrics;
 pub mod models;
 pub mod nodes;
 pub mod request_model;
+pub mod responses;
+pub mod verify;
 
 /// Key for the response hash in the payload
 pub const RESPONSE_HASH_KEY: &str = "response_hash";
@@ -31,6 +60,55 @@ pub const RESPONSE_HASH_KEY: &str = "response_hash";
 /// Key for the signature in the payload
 pub const SIGNATURE_KEY: &str = "signature";
 
+/// Authenticates a request translated to a chat completion, and returns the headers to forward
+/// it to the chat completions endpoint with.
+///
+/// The forwarded request is authenticated with the loopback secret, on behalf of the
+/// API token of the client, so that the scopes of the token are enforced for the endpoint the
+/// client requested rather than for the chat completions endpoint.
+///
+/// # Arguments
+///
+/// * `state` - The proxy state
+/// * `headers` - The headers of the client request
+/// * `endpoint` - The endpoint the client requested
+/// * `model` - The model the client requested
+///
+/// # Errors
+///
+/// Returns an error if the API token of the client is invalid, or not allowed to use the
+/// endpoint or the model.
+pub async fn authenticate_forwarded_request(
+    state: &ProxyState,
+    headers: &HeaderMap,
+    endpoint: &str,
+    model: Option<&str>,
+) -> Result<HeaderMap> {
+    let api_token = check_auth(state, headers, endpoint, model).await?;
+    let mut forwarded_headers = headers.clone();
+    forwarded_headers.remove(CONTENT_LENGTH);
+    let internal_headers = [
+        (AUTHORIZATION, format!("Bearer {}", state.loopback_secret)),
+        (
+            HeaderName::from_static(LOOPBACK_API_TOKEN_ID_HEADER),
+            api_token.id.to_string(),
+        ),
+        (
+            HeaderName::from_static(LOOPBACK_SCOPE_ENDPOINT_HEADER),
+            endpoint.to_string(),
+        ),
+    ];
+    for (name, value) in internal_headers {
+        let value = HeaderValue::from_str(&value).map_err(|e| AtomaProxyError::InternalError {
+            message: format!("Failed to create header {name}: {e}"),
+            client_message: None,
+            endpoint: endpoint.to_string(),
+        })?;
+        forwarded_headers.insert(name, value);
+    }
+    Ok(forwarded_headers)
+}
+
 /// Updates the state manager with token usage and hash information for a stack.
 ///
 /// This function performs two main operations:
@@ -42,9 +120,10 @@ pub const SIGNATURE_KEY: &str = "signature";
 /// * `state` - Reference to the application state containing the state manager sender
 /// * `stack_small_id` - Unique identifier for the stack
 /// * `estimated_total_tokens` - The estimated number of tokens before processing
-/// * `total_tokens` - The actual number of tokens used
-/// * `payload_hash` - Hash of the request payload
-/// * `response_hash` - Hash of the response data
+/// * `total_tokens` - The actual number of tokens used, as reported by the node
+/// * `billed_total_tokens` - The number of tokens billed to the user, which is lower than
+///   `total_tokens` when the client disconnected before receiving the whole output
+/// * `endpoint` - The endpoint that processed the request
 ///
 /// # Returns
 ///
@@ -58,13 +137,20 @@ pub const SIGNATURE_KEY: &str = "signature";
 #[instrument(
     level = "info",
     skip_all,
-    fields(stack_small_id, estimated_total_tokens, total_tokens, endpoint)
+    fields(
+        stack_small_id,
+        estimated_total_tokens,
+        total_tokens,
+        billed_total_tokens,
+        endpoint
+    )
 )]
 pub fn update_state_manager(
     state_manager_sender: &Sender<AtomaAtomaStateManagerEvent>,
     stack_small_id: i64,
     estimated_total_tokens: i64,
     total_tokens: i64,
+    billed_total_tokens: i64,
     endpoint: &str,
 ) -> Result<()> {
     // Update stack num tokens
@@ -73,6 +159,7 @@ pub fn update_state_manager(
             stack_small_id,
             estimated_total_tokens,
             total_tokens,
+            billed_total_tokens,
         })
         .map_err(|e| AtomaProxyError::InternalError {
             message: format!("Error updating stack num tokens: {e}"),
@@ -82,6 +169,376 @@ pub fn update_state_manager(
     Ok(())
 }
 
+/// Records the spend of a request against the API token that authenticated it
+///
+/// # Arguments
+///
+/// * `state_manager_sender` - The sender channel for the state manager
+/// * `api_token_id` - The id of the API token used for the request
+/// * `stack_small_id` - Unique identifier for the stack that processed the request
+/// * `num_compute_units` - The actual number of compute units processed
+/// * `endpoint` - The endpoint that processed the request
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the update was sent, or a `AtomaProxyError::InternalError` otherwise.
+///
+/// # Errors
+///
+/// This function will return an error if the state manager channel is closed
+#[instrument(
+    level = "info",
+    skip_all,
+    fields(api_token_id, stack_small_id, num_compute_units, endpoint)
+)]
+pub fn update_api_token_spend(
+    state_manager_sender: &Sender<AtomaAtomaStateManagerEvent>,
+    api_token_id: i64,
+    stack_small_id: i64,
+    num_compute_units: i64,
+    endpoint: &str,
+) -> Result<()> {
+    state_manager_sender
+        .send(AtomaAtomaStateManagerEvent::UpdateApiTokenSpend {
+            api_token_id,
+            stack_small_id,
+            num_compute_units,
+        })
+        .map_err(|e| AtomaProxyError::InternalError {
+            message: format!("Error updating api token spend: {e}"),
+            client_message: None,
+            endpoint: endpoint.to_string(),
+        })?;
+    Ok(())
+}
+
+/// Records a billed request in the usage ledger
+///
+/// # Arguments
+///
+/// * `state_manager_sender` - The sender for the state manager
+/// * `usage_record` - The billing details of the request
+/// * `endpoint` - The endpoint of the request
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the usage record was sent, or a `AtomaProxyError::InternalError` otherwise.
+///
+/// # Errors
+///
+/// This function will return an error if the state manager channel is closed
+#[instrument(
+    level = "info",
+    skip_all,
+    fields(
+        user_id = usage_record.user_id,
+        stack_small_id = usage_record.stack_small_id,
+        endpoint
+    )
+)]
+pub fn record_usage(
+    state_manager_sender: &Sender<AtomaAtomaStateManagerEvent>,
+    usage_record: NewUsageRecord,
+    endpoint: &str,
+) -> Result<()> {
+    state_manager_sender
+        .send(AtomaAtomaStateManagerEvent::RecordUsage { usage_record })
+        .map_err(|e| AtomaProxyError::InternalError {
+            message: format!("Error recording usage: {e}"),
+            client_message: None,
+            endpoint: endpoint.to_string(),
+        })?;
+    Ok(())
+}
+
+/// Records a request in the usage ledger, for the user, API token, model, stack and node of its metadata
+///
+/// # Arguments
+///
+/// * `state_manager_sender` - The sender for the state manager
+/// * `metadata` - The metadata of the request
+/// * `prompt_tokens` - The number of prompt (input) tokens billed
+/// * `completion_tokens` - The number of completion (output) tokens billed
+/// * `start` - The time the request started being processed, its latency is measured from
+/// * `status` - The status code of the response
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the usage record was sent, or a `AtomaProxyError::InternalError` otherwise.
+///
+/// # Errors
+///
+/// This function will return an error if the state manager channel is closed
+pub fn record_request_usage(
+    state_manager_sender: &Sender<AtomaAtomaStateManagerEvent>,
+    metadata: &RequestMetadataExtension,
+    prompt_tokens: i64,
+    completion_tokens: i64,
+    start: Instant,
+    status: StatusCode,
+) -> Result<()> {
+    record_usage(
+        state_manager_sender,
+        NewUsageRecord {
+            user_id: metadata.user_id,
+            api_token_id: metadata.api_token_id,
+            model: metadata.model_name.clone(),
+            endpoint: metadata.endpoint.clone(),
+            stack_small_id: metadata.selected_stack_small_id,
+            node_small_id: metadata.node_id,
+            prompt_tokens,
+            completion_tokens,
+            latency_ms: i64::try_from(start.elapsed().as_millis()).unwrap_or(i64::MAX),
+            status: status.as_u16().into(),
+        },
+        &metadata.endpoint,
+    )
+}
+
+/// Looks up the response of a request in the response cache.
+///
+/// # Arguments
+///
+/// * `state` - The proxy state, holding the response cache
+/// * `user_id` - The user that made the request, cached responses are never shared between users
+/// * `model` - The model of the request
+/// * `endpoint` - The endpoint of the request
+/// * `payload` - The body of the request
+///
+/// # Returns
+///
+/// Returns the cached response of the request, or the key under which its response is cached once processed,
+/// or `CacheLookup::Uncacheable` if the request is not cacheable or the response cache is disabled.
+#[instrument(level = "debug", skip(state, payload))]
+pub async fn lookup_response_cache(
+    state: &ProxyState,
+    user_id: i64,
+    model: &str,
+    endpoint: &str,
+    payload: &Value,
+) -> CacheLookup {
+    let Some(response_cache) = &state.response_cache else {
+        return CacheLookup::Uncacheable;
+    };
+    let Some(key) = ResponseCache::cache_key(endpoint, user_id, payload) else {
+        return CacheLookup::Uncacheable;
+    };
+    let labels = [
+        KeyValue::new("model", model.to_string()),
+        KeyValue::new("endpoint", endpoint.to_string()),
+    ];
+    match response_cache
+        .get(&state.state_manager_sender, &key, endpoint)
+        .await
+    {
+        Some(response) => {
+            RESPONSE_CACHE_HITS.add(1, &labels);
+            CacheLookup::Hit(response)
+        }
+        None => {
+            RESPONSE_CACHE_MISSES.add(1, &labels);
+            CacheLookup::Miss(key)
+        }
+    }
+}
+
+/// Caches the response of a request processed by a node, and marks it as a cache miss.
+///
+/// # Arguments
+///
+/// * `state` - The proxy state, holding the response cache
+/// * `key` - The cache key of the request
+/// * `model` - The model of the request
+/// * `endpoint` - The endpoint of the request
+/// * `response` - The response of the node, as sent to the client
+/// * `http_response` - The HTTP response sent to the client
+pub fn cache_response(
+    state: &ProxyState,
+    key: String,
+    model: &str,
+    endpoint: &str,
+    response: &Value,
+    http_response: &mut Response<Body>,
+) {
+    if let Some(response_cache) = &state.response_cache {
+        response_cache.insert(&state.state_manager_sender, key, model, endpoint, response);
+    }
+    http_response
+        .headers_mut()
+        .insert(X_ATOMA_CACHE, HeaderValue::from_static(CACHE_MISS));
+}
+
+/// Serves a request from the response cache.
+///
+/// Cached responses are not processed by any node, so no compute units are locked, nor any stack bought,
+/// for them. The user is charged according to the cache hit billing policy: nothing for free hits, or the
+/// usage reported in the cached response for full hits, at the price of the cheapest node serving the model.
+/// The charge is deducted from the balance of the user, and recorded in the usage ledger.
+///
+/// # Arguments
+///
+/// * `state` - The proxy state, holding the response cache
+/// * `user_id` - The user that made the request
+/// * `api_token_id` - The API token that authenticated the request
+/// * `model` - The model of the request
+/// * `endpoint` - The endpoint of the request
+/// * `response` - The cached response
+///
+/// # Returns
+///
+/// Returns the cached response, marked as a cache hit.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// - The balance of the user does not cover the cost of the request (`BalanceError`)
+/// - No node serves the model, to price a full hit (`NoAvailableNode`)
+/// - The state manager channel is closed, or the state manager request failed
+#[instrument(level = "info", skip(state, response))]
+pub async fn serve_cached_response(
+    state: &ProxyState,
+    user_id: i64,
+    api_token_id: i64,
+    model: &str,
+    endpoint: &str,
+    response: Value,
+) -> Result<Response<Body>> {
+    let hit_billing = state
+        .response_cache
+        .as_ref()
+        .map_or(CacheHitBilling::Free, |response_cache| {
+            response_cache.hit_billing()
+        });
+    let (prompt_tokens, completion_tokens, cost) = match hit_billing {
+        CacheHitBilling::Free => (0, 0, 0),
+        CacheHitBilling::Full => {
+            let usage = response.get("usage");
+            let usage_tokens = |field: &str| {
+                usage
+                    .and_then(|usage| usage.get(field))
+                    .and_then(Value::as_i64)
+                    .unwrap_or_default()
+            };
+            let prompt_tokens = usage_tokens("prompt_tokens");
+            let completion_tokens = usage_tokens("completion_tokens");
+            let cheapest_node = query_state_manager(
+                &state.state_manager_sender,
+                |result_sender| AtomaAtomaStateManagerEvent::GetCheapestNodeForModel {
+                    model: model.to_string(),
+                    is_confidential: false,
+                    excluded_node_small_ids: state.node_health.ejected_node_small_ids(),
+                    result_sender,
+                },
+                endpoint,
+            )
+            .await?
+            .ok_or_else(|| AtomaProxyError::NoAvailableNode {
+                message: format!("No node available for model {model}"),
+                endpoint: endpoint.to_string(),
+            })?;
+            // NOTE: Rounded up the same way as the cost of the requests billed on a stack
+            let cost = (prompt_tokens + completion_tokens)
+                .saturating_mul(cheapest_node.price_per_one_million_compute_units)
+                .saturating_add(ONE_MILLION as i64 - 1)
+                / ONE_MILLION as i64;
+            (prompt_tokens, completion_tokens, cost)
+        }
+    };
+    let (result_sender, result_receiver) = oneshot::channel();
+    state
+        .state_manager_sender
+        .send(AtomaAtomaStateManagerEvent::ChargeCachedResponse {
+            usage_record: NewUsageRecord {
+                user_id,
+                api_token_id,
+                model: model.to_string(),
+                endpoint: endpoint.to_string(),
+                stack_small_id: 0,
+                node_small_id: 0,
+                prompt_tokens,
+                completion_tokens,
+                latency_ms: 0,
+                status: StatusCode::OK.as_u16().into(),
+            },
+            cost,
+            result_sender,
+        })
+        .map_err(|e| AtomaProxyError::InternalError {
+            message: format!("Failed to send ChargeCachedResponse event: {e}"),
+            client_message: None,
+            endpoint: endpoint.to_string(),
+        })?;
+    result_receiver
+        .await
+        .map_err(|e| AtomaProxyError::InternalError {
+            message: format!("Failed to receive ChargeCachedResponse result: {e}"),
+            client_message: None,
+            endpoint: endpoint.to_string(),
+        })?
+        .map_err(|e| match e {
+            AtomaStateManagerError::InsufficientBalance => AtomaProxyError::BalanceError {
+                message: format!("The balance does not cover the cached response: {e}"),
+                endpoint: endpoint.to_string(),
+            },
+            e => AtomaProxyError::InternalError {
+                message: format!("Failed to charge the cached response: {e}"),
+                client_message: None,
+                endpoint: endpoint.to_string(),
+            },
+        })?;
+    let mut response = Json(response).into_response();
+    response
+        .headers_mut()
+        .insert(X_ATOMA_CACHE, HeaderValue::from_static(CACHE_HIT));
+    Ok(response)
+}
+
+/// Sends a request to the state manager, and waits for its result.
+///
+/// # Arguments
+///
+/// * `state_manager_sender` - The state manager sender
+/// * `event` - Builds the state manager event, from the channel to send back the result on
+/// * `endpoint` - The endpoint of the request
+///
+/// # Returns
+///
+/// Returns the result of the state manager request.
+///
+/// # Errors
+///
+/// This function will return an `InternalError` if the state manager channel is closed,
+/// or the state manager request failed
+#[instrument(level = "debug", skip_all, fields(endpoint))]
+pub async fn query_state_manager<T>(
+    state_manager_sender: &Sender<AtomaAtomaStateManagerEvent>,
+    event: impl FnOnce(
+        oneshot::Sender<std::result::Result<T, AtomaStateManagerError>>,
+    ) -> AtomaAtomaStateManagerEvent,
+    endpoint: &str,
+) -> Result<T> {
+    let (result_sender, result_receiver) = oneshot::channel();
+    state_manager_sender
+        .send(event(result_sender))
+        .map_err(|e| AtomaProxyError::InternalError {
+            message: format!("Failed to send state manager event: {e}"),
+            client_message: None,
+            endpoint: endpoint.to_string(),
+        })?;
+    result_receiver
+        .await
+        .map_err(|e| AtomaProxyError::InternalError {
+            message: format!("Failed to receive state manager result: {e}"),
+            client_message: None,
+            endpoint: endpoint.to_string(),
+        })?
+        .map_err(|e| AtomaProxyError::InternalError {
+            message: format!("State manager request failed: {e}"),
+            client_message: None,
+            endpoint: endpoint.to_string(),
+        })
+}
+
 /// Verifies a Sui signature from a handler response
 ///
 /// # Arguments
@@ -113,7 +570,14 @@ pub fn verify_response_hash_and_signature(
                 client_message: Some("Invalid response from inference service".to_string()),
                 endpoint: "verify_signature".to_string(),
             })?;
-    let response_hash = STANDARD.decode(response_hash).unwrap();
+    let response_hash =
+        STANDARD
+            .decode(response_hash)
+            .map_err(|e| AtomaProxyError::InternalError {
+                message: format!("Failed to decode response_hash: {e}"),
+                client_message: Some("Invalid response from inference service".to_string()),
+                endpoint: "verify_signature".to_string(),
+            })?;
 
     if verify_hash {
         verify_response_hash(payload, &response_hash)?;
@@ -305,7 +769,7 @@ pub fn handle_status_code_error(
             auth_error: error.to_string(), // The message coming here is from node in the format format!("Unauthorized response from inference service: {error}"),
             endpoint: endpoint.to_string(),
         }),
-        StatusCode::INTERNAL_SERVER_ERROR => Err(AtomaProxyError::InternalError {
+        StatusCode::INTERNAL_SERVER_ERROR => Err(AtomaProxyError::NodeError {
             message: format!("Inference service returned internal server error: {error}"),
             client_message: Some(format!(
                 "Inference service returned status code error {status_code}"
@@ -327,6 +791,14 @@ pub fn handle_status_code_error(
         }),
         StatusCode::TOO_MANY_REQUESTS => Err(AtomaProxyError::TooManyRequests {
             message: format!("Inference service returned too many requests error: {error}"),
+            rate_limit: None,
+            endpoint: endpoint.to_string(),
+        }),
+        _ if status_code.is_server_error() => Err(AtomaProxyError::NodeError {
+            message: format!("Inference service returned server error: {error}"),
+            client_message: Some(format!(
+                "Inference service returned status code error {status_code}"
+            )),
             endpoint: endpoint.to_string(),
         }),
         _ => Err(AtomaProxyError::InternalError {
@@ -1,12 +1,20 @@
-use axum::{extract::State, Json};
+use atoma_proxy_service::ModelModality;
+use atoma_state::types::AtomaAtomaStateManagerEvent;
+use axum::{
+    extract::{Path, State},
+    http::HeaderMap,
+    Json,
+};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use tracing::instrument;
 use utoipa::{OpenApi, ToSchema};
 
+use crate::server::check_auth;
 use crate::server::error::AtomaProxyError;
 use crate::server::http_server::ProxyState;
-use tokio::fs;
+
+use super::query_state_manager;
 
 /// Path for the models listing endpoint.
 ///
@@ -17,13 +25,22 @@ pub const MODELS_PATH: &str = "/v1/models";
 /// Path for the OpenRouter models listing endpoint.
 pub const OPEN_ROUTER_MODELS_PATH: &str = "/v1/open_router/models";
 
+/// Path for retrieving a model.
+///
+/// Model names contain slashes (e.g. `meta-llama/Llama-3.3-70B-Instruct`), so the model
+/// is matched by the rest of the path.
+pub const MODEL_PATH: &str = "/v1/models/{*model}";
+
 /// OpenAPI documentation for the models listing endpoint.
 ///
 /// This struct is used to generate OpenAPI documentation for the models listing
 /// endpoint. It uses the `utoipa` crate's derive macro to automatically generate
 /// the OpenAPI specification from the code.
 #[derive(OpenApi)]
-#[openapi(paths(models_list), components(schemas(ModelList, Model)))]
+#[openapi(
+    paths(models_list, models_retrieve),
+    components(schemas(ModelList, Model, ModelPricing))
+)]
 pub struct ModelsOpenApi;
 
 /// List models
@@ -31,6 +48,9 @@ pub struct ModelsOpenApi;
 /// This endpoint mimics the OpenAI models endpoint format, returning a list of
 /// available models with their associated metadata. Each model includes standard
 /// OpenAI-compatible fields to ensure compatibility with existing OpenAI client libraries.
+///
+/// Only the models served by the proxy with at least one node currently subscribed
+/// to them are listed, along with their pricing and the number of nodes serving them.
 #[utoipa::path(
     get,
     path = "",
@@ -42,19 +62,11 @@ pub struct ModelsOpenApi;
         (status = INTERNAL_SERVER_ERROR, description = "Failed to retrieve list of available models")
     )
 )]
+#[instrument(level = "trace", skip_all)]
 pub async fn models_list(
     State(state): State<ProxyState>,
 ) -> std::result::Result<Json<ModelList>, AtomaProxyError> {
-    let models = state
-        .models
-        .iter()
-        .map(|model| Model {
-            id: model.to_string(),
-            object: "model".to_string(),
-            created: 1_686_935_002,
-            owned_by: "atoma".to_string(),
-        })
-        .collect();
+    let models = get_live_models(&state, MODELS_PATH).await?;
 
     Ok(Json(ModelList {
         object: "list".to_string(),
@@ -62,6 +74,94 @@ pub async fn models_list(
     }))
 }
 
+/// Retrieve model
+///
+/// Retrieves a model by name or alias, if it is served by the proxy and at least one node
+/// is currently subscribed to it.
+#[utoipa::path(
+    get,
+    path = "/{model}",
+    params(("model" = String, Path, description = "The name or alias of the model")),
+    security(
+        ("bearerAuth" = [])
+    ),
+    responses(
+        (status = OK, description = "The model", body = Model),
+        (status = UNAUTHORIZED, description = "Unauthorized"),
+        (status = NOT_FOUND, description = "Model not found"),
+        (status = INTERNAL_SERVER_ERROR, description = "Failed to retrieve the model")
+    )
+)]
+#[instrument(level = "trace", skip_all, fields(%model))]
+pub async fn models_retrieve(
+    State(state): State<ProxyState>,
+    headers: HeaderMap,
+    Path(model): Path<String>,
+) -> std::result::Result<Json<Model>, AtomaProxyError> {
+    check_auth(&state, &headers, MODELS_PATH, None).await?;
+    let served_models = state.model_registry.current();
+    let resolved_model = served_models.model_router.resolve(&model);
+    get_live_models(&state, MODEL_PATH)
+        .await?
+        .into_iter()
+        .find(|live_model| live_model.id == resolved_model)
+        .map(Json)
+        .ok_or_else(|| AtomaProxyError::NotFound {
+            message: format!("Model {model} is not available"),
+            endpoint: MODEL_PATH.to_string(),
+        })
+}
+
+/// Retrieves the models served by the proxy that have live node subscriptions, from the
+/// catalog of the state manager.
+///
+/// # Errors
+///
+/// Returns an error if the catalog cannot be retrieved from the state manager.
+pub async fn get_live_models(
+    state: &ProxyState,
+    endpoint: &str,
+) -> std::result::Result<Vec<Model>, AtomaProxyError> {
+    let catalog = query_state_manager(
+        &state.state_manager_sender,
+        |result_sender| AtomaAtomaStateManagerEvent::GetModelCatalog { result_sender },
+        endpoint,
+    )
+    .await?;
+    let served_models = state.model_registry.current();
+    Ok(catalog
+        .into_iter()
+        .filter(|entry| served_models.serves(&entry.model))
+        .map(|entry| Model {
+            modalities: served_models
+                .modalities
+                .get(&entry.model)
+                .cloned()
+                .unwrap_or_default(),
+            object: "model".to_string(),
+            created: entry.created_at.timestamp(),
+            owned_by: "atoma".to_string(),
+            // NOTE: The configured context window of the model, if any, is what requests are checked against
+            context_length: state
+                .model_limits
+                .limits_for_model(&entry.model)
+                .and_then(|limits| limits.context_length)
+                .map_or(entry.max_num_compute_units, |context_length| {
+                    context_length as i64
+                }),
+            pricing: ModelPricing {
+                min_price_per_one_million_compute_units: entry
+                    .min_price_per_one_million_compute_units,
+                median_price_per_one_million_compute_units: entry
+                    .median_price_per_one_million_compute_units,
+            },
+            num_nodes: entry.num_nodes,
+            confidential_compute: entry.is_confidential_available,
+            id: entry.model,
+        })
+        .collect())
+}
+
 /// Response object for the models listing endpoint
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct ModelList {
@@ -82,6 +182,27 @@ pub struct Model {
     pub created: i64,
     /// Organization that owns the model
     pub owned_by: String,
+    /// The modalities of the model (e.g. "Chat Completions", "Embeddings")
+    #[schema(value_type = Vec<String>)]
+    pub modalities: Vec<ModelModality>,
+    /// The context window of the model, as configured on the proxy, or else the largest number of
+    /// compute units (input and output tokens) a node accepts for a request
+    pub context_length: i64,
+    /// The prices offered by the nodes serving the model
+    pub pricing: ModelPricing,
+    /// The number of nodes serving the model
+    pub num_nodes: i64,
+    /// Whether the model can be used with confidential compute
+    pub confidential_compute: bool,
+}
+
+/// Prices offered by the nodes serving a model
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ModelPricing {
+    /// The cheapest price per one million compute units
+    pub min_price_per_one_million_compute_units: i64,
+    /// The median price per one million compute units
+    pub median_price_per_one_million_compute_units: i64,
 }
 
 /// OpenAPI documentation for the open router models listing endpoint.
@@ -95,9 +216,10 @@ pub struct OpenRouterModelsListApi;
 
 /// OpenRouter models listing endpoint
 ///
-/// This endpoint returns a list of available models from the OpenRouter
-/// models file. The file is expected to be in JSON format and contains
-/// information about the models, including their IDs and other metadata.
+/// This endpoint returns the models served by the proxy in the OpenRouter format,
+/// priced from the cheapest node subscribed to each model. The feed is cached and
+/// refreshed periodically, and per-model overrides (e.g. display names) are read from
+/// the OpenRouter models file, when configured.
 #[utoipa::path(
     get,
     path = "",
@@ -110,20 +232,6 @@ pub struct OpenRouterModelsListApi;
 pub async fn open_router_models_list(
     State(state): State<ProxyState>,
 ) -> std::result::Result<Json<Value>, AtomaProxyError> {
-    let file_content = fs::read_to_string(state.open_router_models_file)
-        .await
-        .map_err(|err| AtomaProxyError::InternalError {
-            message: format!("Failed to read OpenRouter models file: {err}"),
-            client_message: None,
-            endpoint: OPEN_ROUTER_MODELS_PATH.to_string(),
-        })?;
-
-    let json_data: Value =
-        serde_json::from_str(&file_content).map_err(|err| AtomaProxyError::InternalError {
-            message: format!("Failed to parse OpenRouter models file: {err}"),
-            client_message: None,
-            endpoint: OPEN_ROUTER_MODELS_PATH.to_string(),
-        })?;
-
-    Ok(Json(json_data))
+    let feed = state.open_router_feed.get(&state).await?;
+    Ok(Json(feed.as_ref().clone()))
 }
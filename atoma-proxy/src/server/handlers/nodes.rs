@@ -285,11 +285,13 @@ pub async fn nodes_create_lock(
 ) -> Result<Json<NodesCreateLockResponse>, AtomaProxyError> {
     let (sender, receiver) = oneshot::channel();
     let user_id = check_auth(
-        &state.state_manager_sender,
+        &state,
         &headers,
         NODES_CREATE_LOCK_PATH,
+        Some(&payload.model),
     )
-    .await?;
+    .await?
+    .user_id;
 
     let max_num_tokens = payload
         .max_num_tokens
@@ -348,6 +350,7 @@ pub async fn nodes_create_lock(
                 .send(AtomaAtomaStateManagerEvent::GetCheapestNodeForModel {
                     model: payload.model.clone(),
                     is_confidential: true, // NOTE: This endpoint is only required for confidential compute
+                    excluded_node_small_ids: state.node_health.ejected_node_small_ids(),
                     result_sender: sender,
                 })
                 .map_err(|e| AtomaProxyError::InternalError {
@@ -387,6 +390,7 @@ pub async fn nodes_create_lock(
                         MAX_NUM_TOKENS_FOR_CONFIDENTIAL_COMPUTE as u64,
                         sui,
                         node,
+                        state.billing_mode,
                     )
                     .await?
                     // NOTE: The `acquire_new_stack` method will emit a stack creation event, and it will stored it
@@ -521,6 +525,7 @@ fn prune_compute_units_in_memory(state: &ProxyState) -> Result<(), AtomaProxyErr
                 stack_small_id,
                 lock.max_num_tokens,
                 0,
+                0,
                 NODES_CREATE_LOCK_PATH,
             )?;
             details.pop();
@@ -1,7 +1,5 @@
+use crate::server::{error::AtomaProxyError, tokenizer::TokenCounter, ModelLimits, Result};
 use serde_json::Value;
-use tokenizers::Tokenizer;
-
-use crate::server::Result;
 
 /// A struct that contains the estimated compute units needed for a request.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -12,6 +10,86 @@ pub struct ComputeUnitsEstimate {
     pub max_total_compute_units: u64,
 }
 
+impl ComputeUnitsEstimate {
+    /// Fits the estimate in the context window of a model, before any compute units are locked for the request.
+    ///
+    /// The output tokens estimated for requests that do not set their maximum number of output tokens are
+    /// always lowered to the remaining context window, as the node stops generating when the window is full.
+    /// Requests that set it too high are rejected, unless `clamp` is set, in which case it is lowered.
+    ///
+    /// # Arguments
+    /// * `limits` - The context window of the requested model
+    /// * `max_completion_tokens` - The maximum number of output tokens set by the request, if any
+    /// * `clamp` - Whether to lower the maximum number of output tokens of the request, instead of rejecting it
+    /// * `endpoint` - The endpoint of the request
+    ///
+    /// # Returns
+    /// * `Ok((Self, Option<u64>))` - The fitted estimate, and the lowered maximum number of output tokens,
+    ///   if the request has to be updated with it
+    /// * `Err(AtomaProxyError::ContextLengthExceeded)` - If the request does not fit in the context window
+    pub fn fit_context_window(
+        self,
+        limits: &ModelLimits,
+        max_completion_tokens: Option<u64>,
+        clamp: bool,
+        endpoint: &str,
+    ) -> Result<(Self, Option<u64>)> {
+        let num_input_tokens = self.num_input_compute_units;
+        let num_output_tokens = self
+            .max_total_compute_units
+            .saturating_sub(num_input_tokens);
+        if let Some(context_length) = limits.context_length {
+            if num_input_tokens >= context_length {
+                return Err(AtomaProxyError::ContextLengthExceeded {
+                    message: format!(
+                        "This model's maximum context length is {context_length} tokens. However, your messages resulted in {num_input_tokens} tokens. Please reduce the length of the messages."
+                    ),
+                    endpoint: endpoint.to_string(),
+                });
+            }
+        }
+        let remaining_tokens = [
+            limits
+                .context_length
+                .map(|context_length| context_length - num_input_tokens),
+            limits.max_output_tokens,
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+        let Some(remaining_tokens) = remaining_tokens.filter(|&r| num_output_tokens > r) else {
+            return Ok((self, None));
+        };
+        let clamped_max_completion_tokens = match max_completion_tokens {
+            None => None,
+            Some(_) if clamp => Some(remaining_tokens),
+            Some(max_completion_tokens) => {
+                let message = match limits.max_output_tokens {
+                    Some(max_output_tokens) if max_completion_tokens > max_output_tokens => format!(
+                        "max_completion_tokens is too large: {max_completion_tokens}. This model supports at most {max_output_tokens} completion tokens."
+                    ),
+                    _ => format!(
+                        "This model's maximum context length is {} tokens. However, you requested {} tokens ({num_input_tokens} in the messages, {max_completion_tokens} in the completion). Please reduce the length of the messages or completion.",
+                        limits.context_length.unwrap_or_default(),
+                        num_input_tokens + max_completion_tokens,
+                    ),
+                };
+                return Err(AtomaProxyError::ContextLengthExceeded {
+                    message,
+                    endpoint: endpoint.to_string(),
+                });
+            }
+        };
+        Ok((
+            Self {
+                num_input_compute_units: num_input_tokens,
+                max_total_compute_units: num_input_tokens + remaining_tokens,
+            },
+            clamped_max_completion_tokens,
+        ))
+    }
+}
+
 /// A trait for parsing and handling AI model requests across different endpoints (chat, embeddings, images).
 /// This trait provides a common interface for processing various types of AI model requests
 /// and estimating their computational costs.
@@ -34,6 +112,15 @@ pub trait RequestModel: Clone {
     /// * `String` - The name/identifier of the AI model to be used
     fn get_model(&self) -> String;
 
+    /// Retrieves the maximum number of output tokens set by the request, if any.
+    ///
+    /// # Returns
+    /// * `Option<u64>` - The maximum number of output tokens, or `None` for requests that do not
+    ///   set it, or do not generate tokens
+    fn get_max_completion_tokens(&self) -> Option<u64> {
+        None
+    }
+
     /// Calculates the estimated computational resources required for this request.
     ///
     /// # Arguments
@@ -48,6 +135,82 @@ pub trait RequestModel: Clone {
     /// the associated model, as obtained by calling `get_model` on `Self`.
     fn get_compute_units_estimate(
         &self,
-        tokenizer: Option<&Tokenizer>,
+        tokenizer: Option<&dyn TokenCounter>,
     ) -> Result<ComputeUnitsEstimate>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENDPOINT: &str = "/v1/chat/completions";
+
+    const LIMITS: ModelLimits = ModelLimits {
+        context_length: Some(1_000),
+        max_output_tokens: Some(300),
+    };
+
+    const fn estimate(
+        num_input_compute_units: u64,
+        max_total_compute_units: u64,
+    ) -> ComputeUnitsEstimate {
+        ComputeUnitsEstimate {
+            num_input_compute_units,
+            max_total_compute_units,
+        }
+    }
+
+    #[test]
+    fn test_fit_context_window_within_limits() {
+        let (fitted, clamped) = estimate(500, 700)
+            .fit_context_window(&LIMITS, Some(200), false, ENDPOINT)
+            .unwrap();
+        assert_eq!(fitted, estimate(500, 700));
+        assert_eq!(clamped, None);
+
+        // Models without limits are not checked
+        let (fitted, clamped) = estimate(5_000, 20_000)
+            .fit_context_window(&ModelLimits::default(), Some(15_000), false, ENDPOINT)
+            .unwrap();
+        assert_eq!(fitted, estimate(5_000, 20_000));
+        assert_eq!(clamped, None);
+    }
+
+    #[test]
+    fn test_fit_context_window_rejects_oversized_requests() {
+        let error = estimate(1_000, 1_010)
+            .fit_context_window(&LIMITS, None, true, ENDPOINT)
+            .unwrap_err();
+        assert_eq!(error.error_code(), "context_length_exceeded");
+        assert!(matches!(
+            estimate(800, 1_050).fit_context_window(&LIMITS, Some(250), false, ENDPOINT),
+            Err(AtomaProxyError::ContextLengthExceeded { .. })
+        ));
+        assert!(matches!(
+            estimate(100, 500).fit_context_window(&LIMITS, Some(400), false, ENDPOINT),
+            Err(AtomaProxyError::ContextLengthExceeded { .. })
+        ));
+    }
+
+    #[test]
+    fn test_fit_context_window_lowers_output_tokens() {
+        // The default output tokens of requests that do not set them are lowered, without updating the request
+        let (fitted, clamped) = estimate(800, 8_992)
+            .fit_context_window(&LIMITS, None, false, ENDPOINT)
+            .unwrap();
+        assert_eq!(fitted, estimate(800, 1_000));
+        assert_eq!(clamped, None);
+
+        let (fitted, clamped) = estimate(800, 1_050)
+            .fit_context_window(&LIMITS, Some(250), true, ENDPOINT)
+            .unwrap();
+        assert_eq!(fitted, estimate(800, 1_000));
+        assert_eq!(clamped, Some(200));
+
+        let (fitted, clamped) = estimate(100, 500)
+            .fit_context_window(&LIMITS, Some(400), true, ENDPOINT)
+            .unwrap();
+        assert_eq!(fitted, estimate(100, 400));
+        assert_eq!(clamped, Some(300));
+    }
+}
@@ -0,0 +1,892 @@
+use std::convert::Infallible;
+
+use axum::{
+    body::{Body, Bytes},
+    extract::State,
+    http::HeaderMap,
+    response::{sse::Event, IntoResponse, Response, Sse},
+    Json,
+};
+use futures::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use sqlx::types::chrono::Utc;
+use tracing::{error, instrument};
+use utoipa::{OpenApi, ToSchema};
+
+use crate::server::{
+    error::AtomaProxyError,
+    handlers::{authenticate_forwarded_request, chat_completions::CHAT_COMPLETIONS_PATH},
+    http_server::ProxyState,
+    loopback::loopback_url,
+    Result, MAX_COMPLETION_TOKENS, MODEL,
+};
+
+/// Path for the responses endpoint.
+///
+/// This endpoint follows the OpenAI API format for the Responses API. Requests are
+/// translated to chat completions, and forwarded to the chat completions endpoint.
+pub const RESPONSES_PATH: &str = "/v1/responses";
+
+/// Separator between two server-sent events.
+const SSE_EVENT_SEPARATOR: &[u8] = b"\n\n";
+
+/// Prefix of the data lines of a server-sent event.
+const SSE_DATA_PREFIX: &str = "data: ";
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(responses_create),
+    components(schemas(
+        CreateResponseRequest,
+        ResponseObject,
+        ResponseOutputItem,
+        ResponseOutputContent,
+        ResponseUsage,
+        ResponseIncompleteDetails,
+    ))
+)]
+pub struct ResponsesOpenApi;
+
+/// Request payload of the Responses API.
+///
+/// Only text generation is supported: tools, stored conversations
+/// (`previous_response_id`) and background responses are rejected.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CreateResponseRequest {
+    /// ID of the model to use
+    pub model: String,
+
+    /// Text input, or a list of input messages, with `input_text` and `input_image` content parts
+    #[schema(value_type = Object)]
+    pub input: Value,
+
+    /// System (or developer) message inserted at the start of the conversation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instructions: Option<String>,
+
+    /// Upper bound on the number of tokens that can be generated for the response
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_output_tokens: Option<u32>,
+
+    /// Sampling temperature, between 0 and 2
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+
+    /// Nucleus sampling probability mass
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+
+    /// Whether to stream the response as server-sent events
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+
+    /// Output format, either `{"format": {"type": "text"}}`, `{"format": {"type": "json_object"}}`
+    /// or `{"format": {"type": "json_schema", "name": ..., "schema": ..., "strict": ...}}`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub text: Option<Value>,
+
+    /// A unique identifier representing the end-user
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+}
+
+/// A response of the Responses API.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ResponseObject {
+    /// Unique identifier of the response
+    pub id: String,
+    /// The object type, always `response`
+    pub object: String,
+    /// Unix timestamp, in seconds, of the creation of the response
+    pub created_at: i64,
+    /// Status of the response: `in_progress`, `completed`, `incomplete` or `failed`
+    pub status: String,
+    /// Why the response is incomplete, if it is
+    pub incomplete_details: Option<ResponseIncompleteDetails>,
+    /// The model used to generate the response
+    pub model: String,
+    /// The generated output items
+    pub output: Vec<ResponseOutputItem>,
+    /// Token usage of the response, once completed
+    pub usage: Option<ResponseUsage>,
+}
+
+/// Why a response is incomplete.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ResponseIncompleteDetails {
+    /// The reason, e.g. `max_output_tokens`
+    pub reason: String,
+}
+
+/// An output message of a response.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ResponseOutputItem {
+    /// The item type, always `message`
+    #[serde(rename = "type")]
+    pub r#type: String,
+    /// Unique identifier of the output message
+    pub id: String,
+    /// Status of the output message: `in_progress`, `completed` or `incomplete`
+    pub status: String,
+    /// The role of the output message, always `assistant`
+    pub role: String,
+    /// The content parts of the output message
+    pub content: Vec<ResponseOutputContent>,
+}
+
+/// A text content part of an output message.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ResponseOutputContent {
+    /// The content type, always `output_text`
+    #[serde(rename = "type")]
+    pub r#type: String,
+    /// The generated text
+    pub text: String,
+    /// Annotations of the text, always empty
+    #[schema(value_type = Vec<Object>)]
+    pub annotations: Vec<Value>,
+}
+
+/// Token usage of a response.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ResponseUsage {
+    /// Number of input tokens
+    pub input_tokens: u64,
+    /// Number of output tokens
+    pub output_tokens: u64,
+    /// Total number of tokens
+    pub total_tokens: u64,
+}
+
+impl ResponseUsage {
+    /// Converts the `usage` of a chat completion.
+    fn from_chat_completion_usage(usage: &Value) -> Option<Self> {
+        let input_tokens = usage.get("prompt_tokens")?.as_u64()?;
+        let output_tokens = usage.get("completion_tokens")?.as_u64()?;
+        Some(Self {
+            input_tokens,
+            output_tokens,
+            total_tokens: usage
+                .get("total_tokens")
+                .and_then(Value::as_u64)
+                .unwrap_or(input_tokens + output_tokens),
+        })
+    }
+}
+
+/// Create response
+///
+/// This function processes Responses API requests by translating them to chat completion
+/// requests, forwarding them to the chat completions endpoint (which handles compute units
+/// estimation, node selection and billing), and translating the chat completion, or its stream
+/// of chunks, back to a response or a stream of response events. The API token of the client
+/// is authenticated, and its scopes enforced, for the responses endpoint before forwarding.
+///
+/// ## Returns
+///
+/// Returns a Response containing either:
+/// - A streaming SSE connection of response events
+/// - A single JSON response object
+///
+/// ## Errors
+///
+/// Returns an error status code if:
+/// - The request uses unsupported features of the Responses API
+/// - The API token is invalid, or not allowed to use the responses endpoint or the model
+/// - The chat completions endpoint can't be reached
+#[utoipa::path(
+    post,
+    path = "",
+    security(
+        ("bearerAuth" = [])
+    ),
+    request_body = CreateResponseRequest,
+    responses(
+        (status = OK, description = "Responses", content(
+            (ResponseObject = "application/json"),
+        )),
+        (status = BAD_REQUEST, description = "Bad request"),
+        (status = UNAUTHORIZED, description = "Unauthorized"),
+        (status = INTERNAL_SERVER_ERROR, description = "Internal server error")
+    )
+)]
+#[instrument(
+    level = "info",
+    skip_all,
+    fields(
+        path = RESPONSES_PATH,
+    )
+)]
+pub async fn responses_create(
+    State(state): State<ProxyState>,
+    headers: HeaderMap,
+    Json(payload): Json<Value>,
+) -> Result<Response<Body>> {
+    let chat_completion_request = responses_request_to_chat_completion_request(&payload)?;
+    let is_streaming = chat_completion_request
+        .get("stream")
+        .and_then(Value::as_bool)
+        .unwrap_or_default();
+    let headers = authenticate_forwarded_request(
+        &state,
+        &headers,
+        RESPONSES_PATH,
+        chat_completion_request.get(MODEL).and_then(Value::as_str),
+    )
+    .await?;
+
+    // Forward the translated payload to /v1/chat/completions
+    let chat_completions_endpoint = loopback_url(&state, CHAT_COMPLETIONS_PATH);
+    let client = reqwest::Client::new();
+
+    let response = client
+        .post(chat_completions_endpoint)
+        .headers(headers)
+        .json(&chat_completion_request)
+        .send()
+        .await
+        .map_err(|err| AtomaProxyError::InternalError {
+            message: format!("Failed to forward request to chat completions endpoint: {err:?}"),
+            client_message: Some("Failed to connect to the chat completions endpoint.".to_string()),
+            endpoint: RESPONSES_PATH.to_string(),
+        })?;
+    let status = response.status();
+
+    if is_streaming && status.is_success() {
+        let translator = ResponseStreamTranslator::new(
+            payload
+                .get(MODEL)
+                .and_then(Value::as_str)
+                .unwrap_or_default(),
+        );
+        let events = stream::unfold(
+            Some((response.bytes_stream().boxed(), translator)),
+            |state| async move {
+                let (mut chunks, mut translator) = state?;
+                match chunks.next().await {
+                    Some(Ok(bytes)) => {
+                        Some((translator.push_bytes(&bytes), Some((chunks, translator))))
+                    }
+                    Some(Err(e)) => {
+                        error!(
+                            target = "atoma-service",
+                            level = "error",
+                            "Error reading chat completions stream: {e:?}"
+                        );
+                        Some((translator.fail(), None))
+                    }
+                    None => Some((translator.finish(), None)),
+                }
+            },
+        )
+        .flat_map(stream::iter);
+        return Ok(Sse::new(events).into_response());
+    }
+
+    let body_bytes = response
+        .bytes()
+        .await
+        .map_err(|err| AtomaProxyError::InternalError {
+            message: format!("Failed to read response from chat completions endpoint: {err:?}"),
+            client_message: None,
+            endpoint: RESPONSES_PATH.to_string(),
+        })?;
+    let body = if status.is_success() {
+        let chat_completion = serde_json::from_slice::<Value>(&body_bytes).map_err(|err| {
+            AtomaProxyError::InternalError {
+                message: format!("Failed to parse chat completion: {err:?}"),
+                client_message: None,
+                endpoint: RESPONSES_PATH.to_string(),
+            }
+        })?;
+        let response = chat_completion_to_response(&chat_completion)?;
+        Body::from(
+            serde_json::to_vec(&response).map_err(|err| AtomaProxyError::InternalError {
+                message: format!("Failed to serialize response: {err:?}"),
+                client_message: None,
+                endpoint: RESPONSES_PATH.to_string(),
+            })?,
+        )
+    } else {
+        // NOTE: Errors are forwarded as returned by the chat completions endpoint
+        Body::from(body_bytes)
+    };
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(body)
+        .map_err(|err| AtomaProxyError::InternalError {
+            message: format!("Failed to create response: {err:?}"),
+            client_message: None,
+            endpoint: RESPONSES_PATH.to_string(),
+        })
+}
+
+/// Translates a Responses API request to a chat completion request.
+///
+/// # Errors
+///
+/// Returns a `RequestError` if the request is malformed, or uses features of the
+/// Responses API that can't be expressed as a chat completion.
+fn responses_request_to_chat_completion_request(payload: &Value) -> Result<Value> {
+    let request_error = |message: &str| AtomaProxyError::RequestError {
+        message: message.to_string(),
+        endpoint: RESPONSES_PATH.to_string(),
+    };
+    let payload = payload
+        .as_object()
+        .ok_or_else(|| request_error("Invalid payload"))?;
+    for unsupported in ["tools", "previous_response_id", "background"] {
+        if payload
+            .get(unsupported)
+            .is_some_and(|value| !value.is_null())
+        {
+            return Err(request_error(&format!("'{unsupported}' is not supported")));
+        }
+    }
+    let model = payload
+        .get(MODEL)
+        .and_then(Value::as_str)
+        .ok_or_else(|| request_error("Missing 'model' field"))?;
+
+    let mut messages = Vec::new();
+    if let Some(instructions) = payload.get("instructions").and_then(Value::as_str) {
+        messages.push(json!({ "role": "system", "content": instructions }));
+    }
+    match payload.get("input") {
+        Some(Value::String(input)) => messages.push(json!({ "role": "user", "content": input })),
+        Some(Value::Array(items)) => {
+            for item in items {
+                messages.push(input_item_to_chat_message(item).ok_or_else(|| {
+                    request_error("Invalid 'input' item, only messages are supported")
+                })?);
+            }
+        }
+        _ => return Err(request_error("Missing 'input' field")),
+    }
+
+    let mut chat_completion_request = Map::new();
+    chat_completion_request.insert(MODEL.to_string(), json!(model));
+    chat_completion_request.insert("messages".to_string(), Value::Array(messages));
+    if let Some(max_output_tokens) = payload.get("max_output_tokens") {
+        chat_completion_request
+            .insert(MAX_COMPLETION_TOKENS.to_string(), max_output_tokens.clone());
+    }
+    for key in ["temperature", "top_p", "user"] {
+        if let Some(value) = payload.get(key).filter(|value| !value.is_null()) {
+            chat_completion_request.insert(key.to_string(), value.clone());
+        }
+    }
+    if let Some(format) = payload.get("text").and_then(|text| text.get("format")) {
+        match format.get("type").and_then(Value::as_str) {
+            Some("text") | None => {}
+            Some("json_object") => {
+                chat_completion_request.insert(
+                    "response_format".to_string(),
+                    json!({ "type": "json_object" }),
+                );
+            }
+            Some("json_schema") => {
+                let mut json_schema = format.clone();
+                if let Some(json_schema) = json_schema.as_object_mut() {
+                    json_schema.remove("type");
+                }
+                chat_completion_request.insert(
+                    "response_format".to_string(),
+                    json!({ "type": "json_schema", "json_schema": json_schema }),
+                );
+            }
+            Some(_) => return Err(request_error("Invalid 'text.format' type")),
+        }
+    }
+    if payload
+        .get("stream")
+        .and_then(Value::as_bool)
+        .unwrap_or_default()
+    {
+        chat_completion_request.insert("stream".to_string(), Value::Bool(true));
+        chat_completion_request.insert(
+            "stream_options".to_string(),
+            json!({ "include_usage": true }),
+        );
+    }
+    Ok(Value::Object(chat_completion_request))
+}
+
+/// Translates an input message of the Responses API to a chat completion message.
+///
+/// Returns `None` if the item is not a message, or has an invalid content.
+fn input_item_to_chat_message(item: &Value) -> Option<Value> {
+    if item
+        .get("type")
+        .and_then(Value::as_str)
+        .unwrap_or("message")
+        != "message"
+    {
+        return None;
+    }
+    let role = match item.get("role")?.as_str()? {
+        // NOTE: Chat completions models don't all support the developer role
+        "developer" => "system",
+        role @ ("system" | "user" | "assistant") => role,
+        _ => return None,
+    };
+    let content = match item.get("content")? {
+        Value::String(content) => json!(content),
+        Value::Array(parts) => Value::Array(
+            parts
+                .iter()
+                .map(|part| match part.get("type")?.as_str()? {
+                    "input_text" | "output_text" => {
+                        Some(json!({ "type": "text", "text": part.get("text")?.as_str()? }))
+                    }
+                    "input_image" => Some(json!({
+                        "type": "image_url",
+                        "image_url": { "url": part.get("image_url")?.as_str()? },
+                    })),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()?,
+        ),
+        _ => return None,
+    };
+    Some(json!({ "role": role, "content": content }))
+}
+
+/// Returns the status of a response, and why it is incomplete, given the finish reason of the chat completion.
+fn response_status(
+    finish_reason: Option<&str>,
+) -> (&'static str, Option<ResponseIncompleteDetails>) {
+    match finish_reason {
+        Some("length") => (
+            "incomplete",
+            Some(ResponseIncompleteDetails {
+                reason: "max_output_tokens".to_string(),
+            }),
+        ),
+        Some("content_filter") => (
+            "incomplete",
+            Some(ResponseIncompleteDetails {
+                reason: "content_filter".to_string(),
+            }),
+        ),
+        _ => ("completed", None),
+    }
+}
+
+/// Translates a chat completion to a response of the Responses API.
+///
+/// # Errors
+///
+/// Returns an `InternalError` if the chat completion has no choices.
+fn chat_completion_to_response(chat_completion: &Value) -> Result<ResponseObject> {
+    let choice = chat_completion
+        .get("choices")
+        .and_then(|choices| choices.get(0))
+        .ok_or_else(|| AtomaProxyError::InternalError {
+            message: "Chat completion has no choices".to_string(),
+            client_message: None,
+            endpoint: RESPONSES_PATH.to_string(),
+        })?;
+    let id = chat_completion
+        .get("id")
+        .and_then(Value::as_str)
+        .map_or_else(
+            || uuid::Uuid::new_v4().simple().to_string(),
+            ToString::to_string,
+        );
+    let text = choice
+        .get("message")
+        .and_then(|message| message.get("content"))
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    let (status, incomplete_details) =
+        response_status(choice.get("finish_reason").and_then(Value::as_str));
+    Ok(ResponseObject {
+        id: format!("resp_{id}"),
+        object: "response".to_string(),
+        created_at: chat_completion
+            .get("created")
+            .and_then(Value::as_i64)
+            .unwrap_or_else(|| Utc::now().timestamp()),
+        status: status.to_string(),
+        incomplete_details,
+        model: chat_completion
+            .get(MODEL)
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string(),
+        output: vec![ResponseOutputItem {
+            r#type: "message".to_string(),
+            id: format!("msg_{id}"),
+            status: status.to_string(),
+            role: "assistant".to_string(),
+            content: vec![ResponseOutputContent {
+                r#type: "output_text".to_string(),
+                text: text.to_string(),
+                annotations: vec![],
+            }],
+        }],
+        usage: chat_completion
+            .get("usage")
+            .and_then(ResponseUsage::from_chat_completion_usage),
+    })
+}
+
+/// Appends bytes of a chat completions stream to `buffer`, and returns the chat
+/// completion chunks of all the server-sent events it now holds in full.
+///
+/// Bytes of a trailing incomplete event are kept in `buffer` for the next call.
+pub(crate) fn drain_chat_completion_chunks(buffer: &mut Vec<u8>, bytes: &Bytes) -> Vec<Value> {
+    buffer.extend_from_slice(bytes);
+    let mut chunks = Vec::new();
+    while let Some(position) = buffer
+        .windows(SSE_EVENT_SEPARATOR.len())
+        .position(|window| window == SSE_EVENT_SEPARATOR)
+    {
+        let sse_event = buffer
+            .drain(..position + SSE_EVENT_SEPARATOR.len())
+            .collect::<Vec<_>>();
+        let sse_event = String::from_utf8_lossy(&sse_event);
+        // NOTE: Keep-alive comments and the `[DONE]` message are not JSON, and are skipped
+        chunks.extend(sse_event.lines().filter_map(|line| {
+            line.strip_prefix(SSE_DATA_PREFIX)
+                .and_then(|data| serde_json::from_str::<Value>(data).ok())
+        }));
+    }
+    chunks
+}
+
+/// Translates the stream of chat completion chunks returned by the chat completions
+/// endpoint to a stream of Responses API events.
+///
+/// The whole response is streamed as a single output message, with a single text
+/// content part: `response.created`, `response.output_item.added`, `response.content_part.added`,
+/// a `response.output_text.delta` per chunk, then the matching `.done` events and
+/// `response.completed` (or `response.incomplete`) with the token usage.
+struct ResponseStreamTranslator {
+    /// The response being streamed
+    response: ResponseObject,
+    /// Bytes of the incomplete server-sent event received so far
+    buffer: Vec<u8>,
+    /// The finish reason of the chat completion
+    finish_reason: Option<String>,
+    /// Whether the output message was opened
+    is_started: bool,
+    /// Sequence number of the next event
+    sequence_number: u64,
+}
+
+impl ResponseStreamTranslator {
+    fn new(model: &str) -> Self {
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        Self {
+            response: ResponseObject {
+                id: format!("resp_{id}"),
+                object: "response".to_string(),
+                created_at: Utc::now().timestamp(),
+                status: "in_progress".to_string(),
+                incomplete_details: None,
+                model: model.to_string(),
+                output: vec![ResponseOutputItem {
+                    r#type: "message".to_string(),
+                    id: format!("msg_{id}"),
+                    status: "in_progress".to_string(),
+                    role: "assistant".to_string(),
+                    content: vec![],
+                }],
+                usage: None,
+            },
+            buffer: Vec::new(),
+            finish_reason: None,
+            is_started: false,
+            sequence_number: 0,
+        }
+    }
+
+    /// Builds a server-sent event of the given type.
+    fn event(
+        &mut self,
+        event_type: &str,
+        mut data: Value,
+    ) -> std::result::Result<Event, Infallible> {
+        if let Some(data) = data.as_object_mut() {
+            data.insert("type".to_string(), json!(event_type));
+            data.insert("sequence_number".to_string(), json!(self.sequence_number));
+        }
+        self.sequence_number += 1;
+        Ok(Event::default().event(event_type).data(data.to_string()))
+    }
+
+    /// The response without its output, as sent when the output message is opened.
+    fn empty_response(&self) -> Value {
+        let mut response = self.response.clone();
+        response.output.clear();
+        json!(response)
+    }
+
+    /// Returns the item id and the text generated so far.
+    fn output_text(&self) -> (String, String) {
+        let item = &self.response.output[0];
+        let text = item
+            .content
+            .first()
+            .map(|content| content.text.clone())
+            .unwrap_or_default();
+        (item.id.clone(), text)
+    }
+
+    /// Opens the output message, if it is not open yet.
+    fn start(&mut self) -> Vec<std::result::Result<Event, Infallible>> {
+        if self.is_started {
+            return vec![];
+        }
+        self.is_started = true;
+        let response = self.empty_response();
+        let mut item = self.response.output[0].clone();
+        item.content.clear();
+        let item_id = item.id.clone();
+        self.response.output[0].content.push(ResponseOutputContent {
+            r#type: "output_text".to_string(),
+            text: String::new(),
+            annotations: vec![],
+        });
+        vec![
+            self.event("response.created", json!({ "response": response })),
+            self.event(
+                "response.output_item.added",
+                json!({ "output_index": 0, "item": item }),
+            ),
+            self.event(
+                "response.content_part.added",
+                json!({
+                    "item_id": item_id,
+                    "output_index": 0,
+                    "content_index": 0,
+                    "part": { "type": "output_text", "text": "", "annotations": [] },
+                }),
+            ),
+        ]
+    }
+
+    /// Consumes bytes of the chat completions stream, returning the translated events.
+    fn push_bytes(&mut self, bytes: &Bytes) -> Vec<std::result::Result<Event, Infallible>> {
+        let mut events = self.start();
+        for chunk in drain_chat_completion_chunks(&mut self.buffer, bytes) {
+            events.extend(self.push_chunk(&chunk));
+        }
+        events
+    }
+
+    /// Consumes a chat completion chunk, returning the translated events.
+    fn push_chunk(&mut self, chunk: &Value) -> Vec<std::result::Result<Event, Infallible>> {
+        if let Some(usage) = chunk
+            .get("usage")
+            .and_then(ResponseUsage::from_chat_completion_usage)
+        {
+            self.response.usage = Some(usage);
+        }
+        let Some(choice) = chunk.get("choices").and_then(|choices| choices.get(0)) else {
+            return vec![];
+        };
+        if let Some(finish_reason) = choice.get("finish_reason").and_then(Value::as_str) {
+            self.finish_reason = Some(finish_reason.to_string());
+        }
+        let Some(delta) = choice
+            .get("delta")
+            .and_then(|delta| delta.get("content"))
+            .and_then(Value::as_str)
+            .filter(|delta| !delta.is_empty())
+        else {
+            return vec![];
+        };
+        self.response.output[0].content[0].text.push_str(delta);
+        let item_id = self.response.output[0].id.clone();
+        vec![self.event(
+            "response.output_text.delta",
+            json!({
+                "item_id": item_id,
+                "output_index": 0,
+                "content_index": 0,
+                "delta": delta,
+            }),
+        )]
+    }
+
+    /// Completes the response, returning the final events.
+    fn finish(&mut self) -> Vec<std::result::Result<Event, Infallible>> {
+        let mut events = self.start();
+        let (status, incomplete_details) = response_status(self.finish_reason.as_deref());
+        let (item_id, text) = self.output_text();
+        self.response.status = status.to_string();
+        self.response.incomplete_details = incomplete_details;
+        self.response.output[0].status = status.to_string();
+        let part = json!({ "type": "output_text", "text": text, "annotations": [] });
+        let item = json!(self.response.output[0]);
+        let response = json!(self.response);
+        events.extend([
+            self.event(
+                "response.output_text.done",
+                json!({ "item_id": item_id, "output_index": 0, "content_index": 0, "text": text }),
+            ),
+            self.event(
+                "response.content_part.done",
+                json!({ "item_id": item_id, "output_index": 0, "content_index": 0, "part": part }),
+            ),
+            self.event(
+                "response.output_item.done",
+                json!({ "output_index": 0, "item": item }),
+            ),
+            self.event(
+                &format!("response.{status}"),
+                json!({ "response": response }),
+            ),
+        ]);
+        events
+    }
+
+    /// Fails the response, returning the final event.
+    fn fail(&mut self) -> Vec<std::result::Result<Event, Infallible>> {
+        let mut events = self.start();
+        self.response.status = "failed".to_string();
+        let response = json!(self.response);
+        events.push(self.event("response.failed", json!({ "response": response })));
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_translation() {
+        let chat_completion_request = responses_request_to_chat_completion_request(&json!({
+            "model": "llama",
+            "instructions": "Be concise",
+            "input": [
+                { "role": "developer", "content": "Answer in English" },
+                {
+                    "type": "message",
+                    "role": "user",
+                    "content": [
+                        { "type": "input_text", "text": "What is in this image?" },
+                        { "type": "input_image", "image_url": "https://example.com/cat.png" },
+                    ],
+                },
+            ],
+            "max_output_tokens": 100,
+            "temperature": 0.5,
+            "stream": true,
+            "text": { "format": { "type": "json_schema", "name": "answer", "schema": {} } },
+        }))
+        .unwrap();
+        assert_eq!(
+            chat_completion_request,
+            json!({
+                "model": "llama",
+                "messages": [
+                    { "role": "system", "content": "Be concise" },
+                    { "role": "system", "content": "Answer in English" },
+                    {
+                        "role": "user",
+                        "content": [
+                            { "type": "text", "text": "What is in this image?" },
+                            { "type": "image_url", "image_url": { "url": "https://example.com/cat.png" } },
+                        ],
+                    },
+                ],
+                "max_completion_tokens": 100,
+                "temperature": 0.5,
+                "response_format": {
+                    "type": "json_schema",
+                    "json_schema": { "name": "answer", "schema": {} },
+                },
+                "stream": true,
+                "stream_options": { "include_usage": true },
+            })
+        );
+
+        assert!(responses_request_to_chat_completion_request(&json!({
+            "model": "llama",
+            "input": "Hello",
+            "tools": [{ "type": "web_search" }],
+        }))
+        .is_err());
+        assert!(responses_request_to_chat_completion_request(&json!({
+            "model": "llama",
+            "input": [{ "type": "function_call_output", "call_id": "1", "output": "{}" }],
+        }))
+        .is_err());
+    }
+
+    #[test]
+    fn test_chat_completion_translation() {
+        let response = chat_completion_to_response(&json!({
+            "id": "chatcmpl-1",
+            "created": 1,
+            "model": "llama",
+            "choices": [{
+                "index": 0,
+                "message": { "role": "assistant", "content": "Hello" },
+                "finish_reason": "length",
+            }],
+            "usage": { "prompt_tokens": 3, "completion_tokens": 2, "total_tokens": 5 },
+        }))
+        .unwrap();
+        assert_eq!(response.id, "resp_chatcmpl-1");
+        assert_eq!(response.status, "incomplete");
+        assert_eq!(response.output[0].content[0].text, "Hello");
+        assert_eq!(
+            response.usage,
+            Some(ResponseUsage {
+                input_tokens: 3,
+                output_tokens: 2,
+                total_tokens: 5,
+            })
+        );
+    }
+
+    #[test]
+    fn test_stream_translation() {
+        let mut translator = ResponseStreamTranslator::new("llama");
+        let chunk = |delta: &str| {
+            format!(
+                "data: {}\n\n",
+                json!({ "choices": [{ "index": 0, "delta": { "content": delta } }] })
+            )
+        };
+        let first = chunk("Hel");
+        let (first_start, first_end) = first.split_at(10);
+        // The first chunk is split across two reads
+        assert_eq!(
+            translator
+                .push_bytes(&Bytes::from(first_start.to_string()))
+                .len(),
+            3
+        );
+        assert_eq!(
+            translator
+                .push_bytes(&Bytes::from(format!(
+                    "{first_end}: keep-alive\n\n{}",
+                    chunk("lo")
+                )))
+                .len(),
+            2
+        );
+        let usage = json!({
+            "choices": [],
+            "usage": { "prompt_tokens": 3, "completion_tokens": 2, "total_tokens": 5 },
+        });
+        assert!(translator
+            .push_bytes(&Bytes::from(format!("data: {usage}\n\n")))
+            .is_empty());
+        assert_eq!(translator.finish().len(), 4);
+        assert_eq!(translator.response.status, "completed");
+        assert_eq!(translator.response.output[0].content[0].text, "Hello");
+        assert_eq!(translator.response.usage.as_ref().unwrap().total_tokens, 5);
+        assert_eq!(translator.sequence_number, 9);
+    }
+}
@@ -0,0 +1,309 @@
+use std::str::FromStr;
+
+use atoma_state::types::AtomaAtomaStateManagerEvent;
+use axum::{
+    extract::State,
+    http::{HeaderMap, HeaderName, HeaderValue},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sui_sdk::types::{
+    base_types::SuiAddress,
+    crypto::{PublicKey, Signature, SuiSignature},
+};
+use tracing::instrument;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::server::{check_auth, error::AtomaProxyError, http_server::ProxyState, Result};
+
+use super::{
+    query_state_manager, verify_response_hash_and_signature, RESPONSE_HASH_KEY, SIGNATURE_KEY,
+};
+
+/// Path for the endpoint verifying that a response was produced by a node.
+pub const VERIFY_PATH: &str = "/v1/verify";
+
+/// Header holding the hash of a response, base64 encoded.
+pub const X_ATOMA_RESPONSE_HASH: &str = "x-atoma-response-hash";
+
+/// Header holding the signature of the hash of a response, by the node that produced it.
+pub const X_ATOMA_SIGNATURE: &str = "x-atoma-signature";
+
+/// Header holding the small id of the node that produced a response.
+pub const X_ATOMA_NODE_SMALL_ID: &str = "x-atoma-node-small-id";
+
+/// Header holding the small id of the stack a response was paid with.
+pub const X_ATOMA_STACK_SMALL_ID: &str = "x-atoma-stack-small-id";
+
+/// Request header asking for the proof of a stream to be sent in a final SSE event.
+pub const X_ATOMA_STREAM_PROOF: &str = "x-atoma-stream-proof";
+
+/// Name of the final SSE event of a stream, holding the proof of its last chunk.
+pub const PROOF_EVENT: &str = "atoma.proof";
+
+/// Key of the ciphertext of confidential responses, whose hash is computed over the plaintext.
+const CIPHERTEXT_KEY: &str = "ciphertext";
+
+/// OpenAPI documentation for the verify endpoint.
+#[derive(OpenApi)]
+#[openapi(
+    paths(verify_create),
+    components(schemas(VerifyRequest, VerifyResponse, ResponseProof))
+)]
+pub struct VerifyOpenApi;
+
+/// Proof that a response was produced by a node, for a stack.
+///
+/// Non-streaming responses carry it in the `x-atoma-*` headers, streams in a final
+/// `atoma.proof` event, for the last chunk of the stream, if requested with the
+/// `x-atoma-stream-proof` header.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ResponseProof {
+    /// The Blake2b hash of the response, base64 encoded
+    pub response_hash: String,
+    /// The signature of the response hash, by the node
+    pub signature: String,
+    /// The small id of the node that produced the response
+    pub node_small_id: i64,
+    /// The small id of the stack the response was paid with
+    pub stack_small_id: i64,
+}
+
+impl ResponseProof {
+    /// Extracts the proof of a response signed by a node, if it holds its hash and signature.
+    pub fn from_response(
+        response: &Value,
+        node_small_id: i64,
+        stack_small_id: i64,
+    ) -> Option<Self> {
+        Some(Self {
+            response_hash: response.get(RESPONSE_HASH_KEY)?.as_str()?.to_string(),
+            signature: response.get(SIGNATURE_KEY)?.as_str()?.to_string(),
+            node_small_id,
+            stack_small_id,
+        })
+    }
+
+    /// Inserts the proof in the headers of a response.
+    pub fn insert_headers(&self, headers: &mut HeaderMap) {
+        let values = [
+            (X_ATOMA_RESPONSE_HASH, self.response_hash.clone()),
+            (X_ATOMA_SIGNATURE, self.signature.clone()),
+            (X_ATOMA_NODE_SMALL_ID, self.node_small_id.to_string()),
+            (X_ATOMA_STACK_SMALL_ID, self.stack_small_id.to_string()),
+        ];
+        for (name, value) in values {
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                headers.insert(HeaderName::from_static(name), value);
+            }
+        }
+    }
+}
+
+/// Inserts the proof of a response signed by a node in the headers of its HTTP response.
+pub fn insert_response_proof_headers(
+    headers: &mut HeaderMap,
+    response: &Value,
+    node_small_id: i64,
+    stack_small_id: i64,
+) {
+    if let Some(proof) = ResponseProof::from_response(response, node_small_id, stack_small_id) {
+        proof.insert_headers(headers);
+    }
+}
+
+/// Returns whether the client of a stream asked for the proof of the stream.
+pub fn wants_stream_proof(headers: &HeaderMap) -> bool {
+    headers
+        .get(X_ATOMA_STREAM_PROOF)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.eq_ignore_ascii_case("true"))
+}
+
+/// Request body for verifying a response
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct VerifyRequest {
+    /// The response, as returned by the proxy (a response body, or the data of a stream chunk),
+    /// including its `response_hash` and `signature`
+    pub response: Value,
+    /// The small id of the node claimed to have produced the response
+    pub node_small_id: i64,
+}
+
+/// Outcome of the verification of a response
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct VerifyResponse {
+    /// Whether the response was signed by the node, and its hash matches its content
+    pub valid: bool,
+    /// The small id of the node
+    pub node_small_id: i64,
+    /// The Sui address registered for the node
+    pub node_sui_address: String,
+    /// The Sui address of the key that signed the response, if the signature could be parsed
+    pub signer_sui_address: Option<String>,
+    /// Why the response is not valid
+    pub reason: Option<String>,
+}
+
+/// Verify response
+///
+/// Verifies that a response was signed by the key registered for a node, and that its hash
+/// matches its content. The hash of confidential responses, computed over their plaintext,
+/// cannot be checked, only its signature is.
+///
+/// ## Errors
+///   - `BAD_REQUEST` - The response does not hold a response hash and a signature
+///   - `NOT_FOUND` - The node is not registered
+#[utoipa::path(
+    post,
+    path = "",
+    request_body = VerifyRequest,
+    security(
+        ("bearerAuth" = [])
+    ),
+    responses(
+        (status = OK, description = "The outcome of the verification", body = VerifyResponse),
+        (status = BAD_REQUEST, description = "Bad request"),
+        (status = UNAUTHORIZED, description = "Unauthorized"),
+        (status = NOT_FOUND, description = "Node not found"),
+        (status = INTERNAL_SERVER_ERROR, description = "Internal server error")
+    )
+)]
+#[instrument(level = "info", skip_all, fields(endpoint = VERIFY_PATH, node_small_id = request.node_small_id))]
+pub async fn verify_create(
+    State(state): State<ProxyState>,
+    headers: HeaderMap,
+    Json(request): Json<VerifyRequest>,
+) -> Result<Json<VerifyResponse>> {
+    check_auth(&state, &headers, VERIFY_PATH, None).await?;
+    let holds_proof = [RESPONSE_HASH_KEY, SIGNATURE_KEY]
+        .iter()
+        .all(|key| request.response.get(key).is_some_and(Value::is_string));
+    if !holds_proof {
+        return Err(AtomaProxyError::RequestError {
+            message: format!("The response must hold a {RESPONSE_HASH_KEY} and a {SIGNATURE_KEY}"),
+            endpoint: VERIFY_PATH.to_string(),
+        });
+    }
+    let node_sui_address = query_state_manager(
+        &state.state_manager_sender,
+        |result_sender| AtomaAtomaStateManagerEvent::GetNodeSuiAddress {
+            node_small_id: request.node_small_id,
+            result_sender,
+        },
+        VERIFY_PATH,
+    )
+    .await?
+    .ok_or_else(|| AtomaProxyError::NotFound {
+        message: format!("Node {} not found", request.node_small_id),
+        endpoint: VERIFY_PATH.to_string(),
+    })?;
+    Ok(Json(verify_node_response(
+        &request.response,
+        request.node_small_id,
+        node_sui_address,
+    )))
+}
+
+/// Verifies that a response was signed by the node with the given Sui address, and that its
+/// hash matches its content, unless the response is confidential.
+fn verify_node_response(
+    response: &Value,
+    node_small_id: i64,
+    node_sui_address: String,
+) -> VerifyResponse {
+    let signer_sui_address = response
+        .get(SIGNATURE_KEY)
+        .and_then(Value::as_str)
+        .and_then(signer_sui_address);
+    let verify_hash = response.get(CIPHERTEXT_KEY).is_none();
+    let reason = match verify_response_hash_and_signature(response, verify_hash) {
+        Err(AtomaProxyError::InternalError { message, .. }) => Some(message),
+        Err(e) => Some(e.to_string()),
+        Ok(()) => match &signer_sui_address {
+            Some(signer) if *signer == node_sui_address => None,
+            Some(signer) => Some(format!(
+                "The response was signed by {signer}, not by node {node_small_id}"
+            )),
+            None => Some("Failed to parse the signature".to_string()),
+        },
+    };
+    VerifyResponse {
+        valid: reason.is_none(),
+        node_small_id,
+        node_sui_address,
+        signer_sui_address,
+        reason,
+    }
+}
+
+/// Returns the Sui address of the key of a signature.
+fn signer_sui_address(signature: &str) -> Option<String> {
+    let signature = Signature::from_str(signature).ok()?;
+    let public_key =
+        PublicKey::try_from_bytes(signature.scheme(), signature.public_key_bytes()).ok()?;
+    Some(SuiAddress::from(&public_key).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::engine::{general_purpose::STANDARD, Engine};
+    use blake2::{digest::consts::U32, Blake2b, Digest};
+    use fastcrypto::ed25519::Ed25519KeyPair;
+    use serde_json::json;
+    use sui_sdk::types::crypto::{get_key_pair, SuiKeyPair};
+
+    fn signed_response(keypair: &SuiKeyPair) -> Value {
+        let mut response = json!({"choices": [{"message": {"content": "Hello"}}]});
+        let hash: [u8; 32] = Blake2b::<U32>::digest(response.to_string().as_bytes()).into();
+        let signature = Signature::new_hashed(&hash, keypair);
+        response[RESPONSE_HASH_KEY] = json!(STANDARD.encode(hash));
+        response[SIGNATURE_KEY] = json!(STANDARD.encode(signature.as_ref()));
+        response
+    }
+
+    #[test]
+    fn test_response_proof_headers() {
+        let response = json!({"response_hash": "aGFzaA==", "signature": "c2lnbmF0dXJl"});
+        let mut headers = HeaderMap::new();
+        insert_response_proof_headers(&mut headers, &response, 3, 7);
+        assert_eq!(headers[X_ATOMA_RESPONSE_HASH], "aGFzaA==");
+        assert_eq!(headers[X_ATOMA_SIGNATURE], "c2lnbmF0dXJl");
+        assert_eq!(headers[X_ATOMA_NODE_SMALL_ID], "3");
+        assert_eq!(headers[X_ATOMA_STACK_SMALL_ID], "7");
+
+        // Responses without a signature carry no proof
+        let mut headers = HeaderMap::new();
+        insert_response_proof_headers(&mut headers, &json!({"response_hash": "aGFzaA=="}), 3, 7);
+        assert!(headers.is_empty());
+    }
+
+    #[test]
+    fn test_verify_node_response() {
+        let keypair = SuiKeyPair::Ed25519(get_key_pair::<Ed25519KeyPair>().1);
+        let node_sui_address = SuiAddress::from(&keypair.public()).to_string();
+        let response = signed_response(&keypair);
+        let verification = verify_node_response(&response, 1, node_sui_address.clone());
+        assert!(verification.valid);
+        assert_eq!(
+            verification.signer_sui_address,
+            Some(node_sui_address.clone())
+        );
+
+        // The content of the response was tampered with
+        let mut tampered = response.clone();
+        tampered["choices"][0]["message"]["content"] = json!("Goodbye");
+        let verification = verify_node_response(&tampered, 1, node_sui_address);
+        assert!(!verification.valid);
+        assert!(verification.reason.is_some());
+
+        // The response was signed by another node
+        let other_sui_address =
+            SuiAddress::from(&SuiKeyPair::Ed25519(get_key_pair::<Ed25519KeyPair>().1).public())
+                .to_string();
+        let verification = verify_node_response(&response, 1, other_sui_address);
+        assert!(!verification.valid);
+    }
+}
@@ -3,10 +3,12 @@ use std::collections::BinaryHeap;
 use std::sync::Arc;
 use std::time::Instant;
 
-use atoma_auth::Sui;
-use atoma_state::types::AtomaAtomaStateManagerEvent;
+use atoma_auth::{Auth, Sui};
+use atoma_state::{types::AtomaAtomaStateManagerEvent, BestAvailableNodesRequest};
 use axum::middleware::from_fn_with_state;
 use axum::{
+    extract::DefaultBodyLimit,
+    http::HeaderName,
     routing::{get, post},
     Json, Router,
 };
@@ -14,7 +16,6 @@ use dashmap::DashMap;
 use flume::Sender;
 use reqwest::Method;
 use serde::Serialize;
-use tokenizers::Tokenizer;
 use tokio::sync::watch;
 use tokio::{net::TcpListener, sync::RwLock};
 use tower::ServiceBuilder;
@@ -32,34 +33,72 @@ use crate::server::{
         embeddings::EMBEDDINGS_PATH,
         image_generations::image_generations_create,
         image_generations::IMAGE_GENERATIONS_PATH,
-        models::{models_list, MODELS_PATH},
+        models::{models_list, models_retrieve, MODELS_PATH, MODEL_PATH},
     },
     Result,
 };
 
+use super::batch_worker::run_batch_worker;
 use super::components;
+use super::handlers::admin::{
+    admin_nodes_health, admin_reload, ADMIN_NODES_HEALTH_PATH, ADMIN_RELOAD_PATH,
+};
+use super::handlers::audio::{
+    audio_speech_create, audio_transcriptions_create, AUDIO_SPEECH_PATH, AUDIO_TRANSCRIPTIONS_PATH,
+    MAX_AUDIO_TRANSCRIPTION_BODY_SIZE,
+};
+use super::handlers::batches::{
+    batches_cancel, batches_create, batches_list, batches_retrieve, BATCHES_PATH,
+    BATCH_CANCEL_PATH, BATCH_PATH,
+};
 use super::handlers::chat_completions::{
-    completions_create, confidential_chat_completions_create, COMPLETIONS_PATH,
-    CONFIDENTIAL_CHAT_COMPLETIONS_PATH,
+    chat_completions_resume_stream, completions_create, confidential_chat_completions_create,
+    CHAT_COMPLETIONS_STREAM_PATH, COMPLETIONS_PATH, CONFIDENTIAL_CHAT_COMPLETIONS_PATH,
 };
 use super::handlers::embeddings::{confidential_embeddings_create, CONFIDENTIAL_EMBEDDINGS_PATH};
+use super::handlers::files::{
+    files_content, files_create, files_retrieve, FILES_PATH, FILE_CONTENT_PATH, FILE_PATH,
+};
 use super::handlers::image_generations::{
     confidential_image_generations_create, CONFIDENTIAL_IMAGE_GENERATIONS_PATH,
 };
+use super::handlers::messages::{messages_create, MESSAGES_PATH};
 use super::handlers::models::{open_router_models_list, OPEN_ROUTER_MODELS_PATH};
 use super::handlers::nodes::{
     nodes_create, nodes_create_lock, NODES_CREATE_LOCK_PATH, NODES_CREATE_PATH,
 };
+use super::handlers::responses::{responses_create, RESPONSES_PATH};
+use super::handlers::verify::{
+    verify_create, VERIFY_PATH, X_ATOMA_NODE_SMALL_ID, X_ATOMA_RESPONSE_HASH, X_ATOMA_SIGNATURE,
+    X_ATOMA_STACK_SMALL_ID,
+};
+use super::loopback::generate_loopback_secret;
 use super::middleware::{
-    authenticate_middleware, confidential_compute_middleware, handle_locked_stack_middleware,
+    admin_auth_middleware, authenticate_middleware, confidential_compute_middleware,
+    handle_locked_stack_middleware,
+};
+#[cfg(unix)]
+use super::model_registry::run_reload_on_sighup;
+use super::model_registry::{ModelRegistry, ServedModels};
+use super::node_health::NodeHealthRegistry;
+use super::open_router::{run_open_router_feed_refresh, OpenRouterFeed};
+use super::rate_limiter::{run_rate_limiter_eviction, RateLimiter};
+use super::response_cache::ResponseCache;
+use super::stream_replay::StreamReplayRegistry;
+use super::tokenizer::{run_tokenizer_loader, TokenizerLoader};
+use super::{
+    AtomaServiceConfig, BatchConfig, BillingMode, ImageTokensConfig, ModelLimitsConfig,
+    NodeSelectionConfig,
 };
-use super::AtomaServiceConfig;
 
 /// Path for health check endpoint.
 ///
 /// This endpoint is used to check the health of the atoma proxy service.
 pub const HEALTH_PATH: &str = "/health";
 
+/// Room left in the body of file uploads for the multipart form, on top of the file itself.
+const MULTIPART_OVERHEAD_BYTES: usize = 1024 * 1024;
+
 pub type UserId = i64;
 
 pub type TaskId = i64;
@@ -97,6 +136,56 @@ pub struct ProxyState {
     /// updates and notifications across different components.
     pub state_manager_sender: Sender<AtomaAtomaStateManagerEvent>,
 
+    /// Channel sender for requesting the best available nodes for a model.
+    ///
+    /// The metrics collection task answers these requests with the latest
+    /// performance ranking of the nodes serving the model.
+    pub request_best_available_nodes_sender: Sender<BestAvailableNodesRequest>,
+
+    /// Node selection policy used when acquiring new stacks.
+    pub node_selection: NodeSelectionConfig,
+
+    /// Rate limiter for authenticated requests, per user and per API token.
+    ///
+    /// Requests are not rate limited when no rate limits are configured.
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+
+    /// Exact-match cache of the responses of embeddings and deterministic chat completion requests.
+    ///
+    /// Responses are not cached when the response cache is not configured.
+    pub response_cache: Option<Arc<ResponseCache>>,
+
+    /// Replay buffers of the chat completion streams, for clients to resume a dropped stream.
+    ///
+    /// Streams cannot be resumed when resumable streams are not configured.
+    pub resumable_streams: Option<Arc<StreamReplayRegistry>>,
+
+    /// Health registry of the nodes, used to eject unhealthy nodes from node and stack selection.
+    pub node_health: Arc<NodeHealthRegistry>,
+
+    /// API key required to access the admin endpoints.
+    ///
+    /// The admin endpoints are not served when no admin API key is configured.
+    pub admin_api_key: Option<Arc<str>>,
+
+    /// How users are charged for the stacks bought on their behalf.
+    pub billing_mode: BillingMode,
+
+    /// Image token policies of the models, used to estimate the input tokens of images.
+    pub image_tokens: Arc<ImageTokensConfig>,
+
+    /// Context window of the models, used to reject the requests that do not fit in it.
+    pub model_limits: Arc<ModelLimitsConfig>,
+
+    /// Limits of the batches, and of the worker executing their requests.
+    pub batches: BatchConfig,
+
+    /// Secret the requests forwarded by the proxy to itself are authenticated with.
+    ///
+    /// It is generated when the proxy starts, and only accepted together with the
+    /// id of the API token the request is executed with.
+    pub loopback_secret: Arc<str>,
+
     /// Map of user ids to their stack lock status.
     ///
     /// This map is used to prevent race conditions when multiple requests
@@ -116,23 +205,17 @@ pub struct ProxyState {
     /// such as acquiring new stack entries.
     pub sui: Arc<RwLock<Sui>>,
 
-    /// Tokenizer used for processing text input.
-    ///
-    /// The tokenizer is responsible for breaking down text input into
-    /// manageable tokens, which are then used in various natural language
-    /// processing tasks.
-    pub tokenizers: Arc<Vec<Arc<Tokenizer>>>,
+    /// `Auth` struct used to compute the keyed hash of the API tokens
+    /// presented by clients, as only token hashes are stored at rest.
+    pub auth: Auth,
 
-    /// List of available AI models.
+    /// Models served by the proxy, with their tokenizers, modalities, aliases and fallback models.
     ///
-    /// This list contains the names or identifiers of AI models that
-    /// the application can use for inference tasks. It allows the
-    /// application to dynamically select and switch between different
-    /// models as needed.
-    pub models: Arc<Vec<String>>,
+    /// The served models can be reloaded from the configuration file, without restarting the proxy.
+    pub model_registry: Arc<ModelRegistry>,
 
-    /// Open router models file.
-    pub open_router_models_file: String,
+    /// Models feed in the OpenRouter format, refreshed periodically.
+    pub open_router_feed: Arc<OpenRouterFeed>,
 
     /// The address and port on which the service is running.
     pub port: u16,
@@ -181,9 +264,19 @@ pub async fn health() -> Result<Json<HealthResponse>> {
 ///
 /// ## Standard Routes
 /// - POST `/v1/chat/completions` - Chat completion endpoint
+/// - POST `/v1/responses` - Responses endpoint, translated to chat completions
+/// - POST `/v1/messages` - Anthropic Messages endpoint, translated to chat completions
 /// - POST `/v1/embeddings` - Text embedding generation
 /// - POST `/v1/images/generations` - Image generation
+/// - POST `/v1/audio/transcriptions` - Audio transcription
+/// - POST `/v1/audio/speech` - Speech generation
 /// - GET `/v1/models` - List available AI models
+/// - GET `/v1/models/{model}` - Retrieve an available AI model
+/// - POST `/v1/files` - Upload a batch input file
+/// - GET `/v1/files/{file_id}` and `/v1/files/{file_id}/content` - Retrieve a file
+/// - POST/GET `/v1/batches` - Create and list batches
+/// - GET `/v1/batches/{batch_id}` - Retrieve a batch
+/// - POST `/v1/batches/{batch_id}/cancel` - Cancel a batch
 /// - POST `/node/registration` - Node public address registration
 /// - GET `/health` - Service health check
 /// - OpenAPI documentation routes
@@ -205,7 +298,16 @@ pub fn create_router(state: &ProxyState) -> Router {
     let cors = CorsLayer::new()
         .allow_origin(Any)
         .allow_methods(vec![Method::GET, Method::POST])
-        .allow_headers(Any);
+        .allow_headers(Any)
+        .expose_headers(
+            [
+                X_ATOMA_RESPONSE_HASH,
+                X_ATOMA_SIGNATURE,
+                X_ATOMA_NODE_SMALL_ID,
+                X_ATOMA_STACK_SMALL_ID,
+            ]
+            .map(HeaderName::from_static),
+        );
 
     let confidential_routes = Router::new()
         .route(
@@ -225,7 +327,13 @@ pub fn create_router(state: &ProxyState) -> Router {
         .route(MODELS_PATH, get(models_list))
         .route(CHAT_COMPLETIONS_PATH, post(chat_completions_create))
         .route(EMBEDDINGS_PATH, post(embeddings_create))
-        .route(IMAGE_GENERATIONS_PATH, post(image_generations_create));
+        .route(IMAGE_GENERATIONS_PATH, post(image_generations_create))
+        .route(
+            AUDIO_TRANSCRIPTIONS_PATH,
+            post(audio_transcriptions_create)
+                .layer(DefaultBodyLimit::max(MAX_AUDIO_TRANSCRIPTION_BODY_SIZE)),
+        )
+        .route(AUDIO_SPEECH_PATH, post(audio_speech_create));
 
     let node_routes = Router::new()
         .route(NODES_CREATE_PATH, post(nodes_create))
@@ -234,7 +342,36 @@ pub fn create_router(state: &ProxyState) -> Router {
     let public_routes = Router::new()
         .route(HEALTH_PATH, get(health))
         .route(OPEN_ROUTER_MODELS_PATH, get(open_router_models_list))
-        .route(COMPLETIONS_PATH, post(completions_create));
+        .route(MODEL_PATH, get(models_retrieve))
+        .route(VERIFY_PATH, post(verify_create))
+        .route(
+            CHAT_COMPLETIONS_STREAM_PATH,
+            get(chat_completions_resume_stream),
+        )
+        .route(COMPLETIONS_PATH, post(completions_create))
+        .route(RESPONSES_PATH, post(responses_create))
+        .route(MESSAGES_PATH, post(messages_create))
+        .route(
+            FILES_PATH,
+            post(files_create).layer(DefaultBodyLimit::max(
+                state.batches.max_file_size_bytes + MULTIPART_OVERHEAD_BYTES,
+            )),
+        )
+        .route(FILE_PATH, get(files_retrieve))
+        .route(FILE_CONTENT_PATH, get(files_content))
+        .route(BATCHES_PATH, post(batches_create).get(batches_list))
+        .route(BATCH_PATH, get(batches_retrieve))
+        .route(BATCH_CANCEL_PATH, post(batches_cancel));
+
+    // NOTE: The admin routes are only served when an admin API key is configured
+    let admin_routes = if state.admin_api_key.is_some() {
+        Router::new()
+            .route(ADMIN_NODES_HEALTH_PATH, get(admin_nodes_health))
+            .route(ADMIN_RELOAD_PATH, post(admin_reload))
+            .layer(from_fn_with_state(state.clone(), admin_auth_middleware))
+    } else {
+        Router::new()
+    };
 
     Router::new()
         .merge(
@@ -261,6 +398,7 @@ pub fn create_router(state: &ProxyState) -> Router {
             ),
         )
         .merge(node_routes)
+        .merge(admin_routes)
         .merge(public_routes)
         .with_state(state.clone())
         .merge(openapi_routes())
@@ -276,32 +414,91 @@ pub fn create_router(state: &ProxyState) -> Router {
 ///
 /// * `config`: The configuration for the atoma proxy service.
 /// * `state_manager_sender`: The sender channel for managing application events.
+/// * `request_best_available_nodes_sender`: The sender channel for requesting node rankings from the metrics collection task.
 /// * `sui`: The Sui struct for handling Sui-related operations.
+/// * `auth`: The Auth struct used to hash the API tokens presented by clients.
+/// * `config_path`: Path to the configuration file, the served models are reloaded from.
 ///
 /// # Errors
 ///
-/// Returns an error if the tcp listener fails to bind or the server fails to start.
+/// Returns an error if the tcp listener fails to bind, the rate limits or model routing
+/// configuration is invalid or the server fails to start.
 #[instrument(level = "info", skip_all, fields(service_bind_address = %config.service_bind_address))]
 pub async fn start_server(
     config: AtomaServiceConfig,
     state_manager_sender: Sender<AtomaAtomaStateManagerEvent>,
+    request_best_available_nodes_sender: Sender<BestAvailableNodesRequest>,
     sui: Arc<RwLock<Sui>>,
-    tokenizers: Vec<Arc<Tokenizer>>,
+    auth: Auth,
+    config_path: String,
     mut shutdown_receiver: watch::Receiver<bool>,
 ) -> anyhow::Result<()> {
     let tcp_listener = TcpListener::bind(config.service_bind_address).await?;
 
+    let served_models = ServedModels::new(
+        config.models,
+        config.revisions,
+        config.modalities,
+        Arc::new(TokenizerLoader::new(config.tokenizers, config.hf_token)),
+        config.model_routing,
+    )?;
     let proxy_state = ProxyState {
         state_manager_sender,
+        request_best_available_nodes_sender,
+        node_selection: config.node_selection,
+        rate_limiter: config
+            .rate_limits
+            .map(RateLimiter::new)
+            .transpose()?
+            .map(Arc::new),
+        response_cache: config
+            .response_cache
+            .as_ref()
+            .map(|config| Arc::new(ResponseCache::new(config))),
+        resumable_streams: config
+            .resumable_streams
+            .as_ref()
+            .map(|config| Arc::new(StreamReplayRegistry::new(config))),
+        node_health: Arc::new(NodeHealthRegistry::new(config.node_health)),
+        admin_api_key: config.admin_api_key.map(Arc::from),
+        billing_mode: config.billing_mode,
+        image_tokens: Arc::new(config.image_tokens),
+        model_limits: Arc::new(config.model_limits),
+        batches: config.batches,
+        loopback_secret: Arc::from(generate_loopback_secret()),
         users_buy_stack_lock_map: Arc::new(DashMap::new()),
         stack_locked_compute_units: Arc::new(DashMap::new()),
         sui,
-        tokenizers: Arc::new(tokenizers),
-        models: Arc::new(config.models),
-        open_router_models_file: config.open_router_models_file,
+        auth,
+        model_registry: Arc::new(ModelRegistry::new(served_models, config_path)),
+        open_router_feed: Arc::new(OpenRouterFeed::new(
+            config.open_router_models_file,
+            config.open_router_refresh_interval_secs,
+        )),
         port: tcp_listener.local_addr().unwrap().port(),
     };
     let router = create_router(&proxy_state);
+    tokio::spawn(run_rate_limiter_eviction(
+        proxy_state.clone(),
+        shutdown_receiver.clone(),
+    ));
+    tokio::spawn(run_batch_worker(
+        proxy_state.clone(),
+        shutdown_receiver.clone(),
+    ));
+    tokio::spawn(run_tokenizer_loader(
+        proxy_state.clone(),
+        shutdown_receiver.clone(),
+    ));
+    tokio::spawn(run_open_router_feed_refresh(
+        proxy_state.clone(),
+        shutdown_receiver.clone(),
+    ));
+    #[cfg(unix)]
+    tokio::spawn(run_reload_on_sighup(
+        proxy_state.clone(),
+        shutdown_receiver.clone(),
+    ));
     let server =
         axum::serve(tcp_listener, router.into_make_service()).with_graceful_shutdown(async move {
             shutdown_receiver
@@ -0,0 +1,316 @@
+use base64::engine::{general_purpose::STANDARD, Engine};
+use thiserror::Error;
+
+use super::{ImageFormat, ImageTokensFormula, ImageTokensPolicy};
+
+/// Prefix of the `data:` URLs of images sent inline.
+const DATA_URL_PREFIX: &str = "data:";
+
+/// Suffix of the media type of base64 encoded `data:` URLs.
+const BASE64_SUFFIX: &str = ";base64";
+
+/// Why an image of a chat completion request was rejected.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ImageTokensError {
+    /// The `data:` URL is malformed, or not base64 encoded
+    #[error("Invalid image data URL")]
+    InvalidDataUrl,
+    /// The decoded image is larger than allowed for the model
+    #[error("Image is larger than the maximum of {max_image_size_bytes} bytes")]
+    TooLarge { max_image_size_bytes: usize },
+    /// The image format can't be recognized from its header
+    #[error("Unrecognized image format")]
+    UnknownFormat,
+    /// The image format is not accepted by the model
+    #[error("Image format {format:?} is not supported by the model")]
+    UnsupportedFormat { format: ImageFormat },
+}
+
+/// Dimensions of an image, in pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ImageDimensions {
+    /// Width of the image
+    pub width: u32,
+    /// Height of the image
+    pub height: u32,
+}
+
+impl ImageTokensPolicy {
+    /// Estimates the number of input tokens of an image content part.
+    ///
+    /// Images sent inline, as `data:` URLs, are decoded and their header is inspected to get
+    /// their format and dimensions. Remote images can't be inspected without downloading them,
+    /// so they are charged the maximum number of tokens of an image.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `ImageTokensError` if the inline image is malformed, too large, or of a
+    /// format that is not accepted by the model.
+    pub fn estimate_tokens(&self, url: &str) -> Result<u64, ImageTokensError> {
+        let Some(data_url) = url.strip_prefix(DATA_URL_PREFIX) else {
+            return Ok(self.formula.max_tokens());
+        };
+        let (media_type, data) = data_url
+            .split_once(',')
+            .ok_or(ImageTokensError::InvalidDataUrl)?;
+        if !media_type.ends_with(BASE64_SUFFIX) {
+            return Err(ImageTokensError::InvalidDataUrl);
+        }
+        // NOTE: Reject oversized images before decoding them
+        if data.len() / 4 * 3 > self.max_image_size_bytes + 2 {
+            return Err(ImageTokensError::TooLarge {
+                max_image_size_bytes: self.max_image_size_bytes,
+            });
+        }
+        let image = STANDARD
+            .decode(data.trim())
+            .map_err(|_| ImageTokensError::InvalidDataUrl)?;
+        if image.len() > self.max_image_size_bytes {
+            return Err(ImageTokensError::TooLarge {
+                max_image_size_bytes: self.max_image_size_bytes,
+            });
+        }
+        let (format, dimensions) =
+            read_image_header(&image).ok_or(ImageTokensError::UnknownFormat)?;
+        if !self.allowed_formats.contains(&format) {
+            return Err(ImageTokensError::UnsupportedFormat { format });
+        }
+        Ok(self.formula.tokens(dimensions))
+    }
+}
+
+impl ImageTokensFormula {
+    /// Number of tokens of an image of the given dimensions.
+    #[must_use]
+    pub fn tokens(&self, dimensions: ImageDimensions) -> u64 {
+        match *self {
+            Self::Fixed { tokens_per_image } => tokens_per_image,
+            Self::Tiles {
+                tile_size,
+                tokens_per_tile,
+                base_tokens,
+                max_tiles,
+            } => {
+                let tile_size = tile_size.max(1);
+                let tiles = u64::from(dimensions.width.div_ceil(tile_size))
+                    * u64::from(dimensions.height.div_ceil(tile_size));
+                base_tokens + tiles.min(max_tiles) * tokens_per_tile
+            }
+        }
+    }
+
+    /// Maximum number of tokens of an image, whatever its dimensions.
+    #[must_use]
+    pub const fn max_tokens(&self) -> u64 {
+        match *self {
+            Self::Fixed { tokens_per_image } => tokens_per_image,
+            Self::Tiles {
+                tokens_per_tile,
+                base_tokens,
+                max_tiles,
+                ..
+            } => base_tokens + max_tiles * tokens_per_tile,
+        }
+    }
+}
+
+/// Reads the format and the dimensions of an image from its header.
+///
+/// Returns `None` if the image is not a PNG, JPEG, GIF or WebP image, or its header is truncated.
+#[must_use]
+pub fn read_image_header(image: &[u8]) -> Option<(ImageFormat, ImageDimensions)> {
+    let u16_be = |at: usize| Some(u16::from_be_bytes(image.get(at..at + 2)?.try_into().ok()?));
+    let u16_le = |at: usize| Some(u16::from_le_bytes(image.get(at..at + 2)?.try_into().ok()?));
+    let u24_le = |at: usize| {
+        let bytes = image.get(at..at + 3)?;
+        Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]))
+    };
+    let u32_be = |at: usize| Some(u32::from_be_bytes(image.get(at..at + 4)?.try_into().ok()?));
+
+    if image.starts_with(b"\x89PNG\r\n\x1a\n") {
+        // The IHDR chunk always comes first
+        return Some((
+            ImageFormat::Png,
+            ImageDimensions {
+                width: u32_be(16)?,
+                height: u32_be(20)?,
+            },
+        ));
+    }
+    if image.starts_with(b"GIF87a") || image.starts_with(b"GIF89a") {
+        return Some((
+            ImageFormat::Gif,
+            ImageDimensions {
+                width: u32::from(u16_le(6)?),
+                height: u32::from(u16_le(8)?),
+            },
+        ));
+    }
+    if image.starts_with(b"\xff\xd8") {
+        // Walk the segments up to the start of frame
+        let mut position = 2;
+        loop {
+            if *image.get(position)? != 0xff {
+                return None;
+            }
+            let marker = *image.get(position + 1)?;
+            match marker {
+                // Fill bytes
+                0xff => position += 1,
+                // Standalone markers, without a length
+                0x01 | 0xd0..=0xd7 => position += 2,
+                // Start of frame markers, excluding DHT, JPG and DAC
+                0xc0..=0xcf if !matches!(marker, 0xc4 | 0xc8 | 0xcc) => {
+                    return Some((
+                        ImageFormat::Jpeg,
+                        ImageDimensions {
+                            width: u32::from(u16_be(position + 7)?),
+                            height: u32::from(u16_be(position + 5)?),
+                        },
+                    ));
+                }
+                _ => position += 2 + usize::from(u16_be(position + 2)?),
+            }
+        }
+    }
+    if image.starts_with(b"RIFF") && image.get(8..12)? == b"WEBP" {
+        let dimensions = match image.get(12..16)? {
+            b"VP8 " => ImageDimensions {
+                width: u32::from(u16_le(26)? & 0x3fff),
+                height: u32::from(u16_le(28)? & 0x3fff),
+            },
+            b"VP8L" => {
+                let bits = u32::from_le_bytes(image.get(21..25)?.try_into().ok()?);
+                ImageDimensions {
+                    width: (bits & 0x3fff) + 1,
+                    height: ((bits >> 14) & 0x3fff) + 1,
+                }
+            }
+            b"VP8X" => ImageDimensions {
+                width: u24_le(24)? + 1,
+                height: u24_le(27)? + 1,
+            },
+            _ => return None,
+        };
+        return Some((ImageFormat::Webp, dimensions));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut image = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR".to_vec();
+        image.extend_from_slice(&width.to_be_bytes());
+        image.extend_from_slice(&height.to_be_bytes());
+        image.extend_from_slice(&[8, 6, 0, 0, 0]);
+        image
+    }
+
+    fn data_url(image: &[u8]) -> String {
+        format!("data:image/png;base64,{}", STANDARD.encode(image))
+    }
+
+    #[test]
+    fn test_read_image_header() {
+        assert_eq!(
+            read_image_header(&png(800, 600)),
+            Some((
+                ImageFormat::Png,
+                ImageDimensions {
+                    width: 800,
+                    height: 600,
+                }
+            ))
+        );
+        assert_eq!(
+            read_image_header(b"GIF89a\x20\x03\x58\x02\x00"),
+            Some((
+                ImageFormat::Gif,
+                ImageDimensions {
+                    width: 800,
+                    height: 600,
+                }
+            ))
+        );
+        // SOI, an APP0 segment, then a baseline start of frame
+        let jpeg = b"\xff\xd8\xff\xe0\x00\x04\x00\x00\xff\xc0\x00\x11\x08\x02\x58\x03\x20\x03";
+        assert_eq!(
+            read_image_header(jpeg),
+            Some((
+                ImageFormat::Jpeg,
+                ImageDimensions {
+                    width: 800,
+                    height: 600,
+                }
+            ))
+        );
+        let mut webp = b"RIFF\x00\x00\x00\x00WEBPVP8X\x0a\x00\x00\x00\x00\x00\x00\x00".to_vec();
+        webp.extend_from_slice(&[0x1f, 0x03, 0x00, 0x57, 0x02, 0x00]);
+        assert_eq!(
+            read_image_header(&webp),
+            Some((
+                ImageFormat::Webp,
+                ImageDimensions {
+                    width: 800,
+                    height: 600,
+                }
+            ))
+        );
+        assert_eq!(read_image_header(b"\x89PNG\r\n\x1a\n"), None);
+        assert_eq!(read_image_header(b"not an image"), None);
+    }
+
+    #[test]
+    fn test_estimate_tokens() {
+        let policy = ImageTokensPolicy::default();
+        // 2x2 tiles of 512 pixels
+        assert_eq!(
+            policy.estimate_tokens(&data_url(&png(800, 600))),
+            Ok(85 + 4 * 170)
+        );
+        // Capped to the maximum number of tiles
+        assert_eq!(
+            policy.estimate_tokens(&data_url(&png(8000, 6000))),
+            Ok(85 + 16 * 170)
+        );
+        assert_eq!(
+            policy.estimate_tokens("https://example.com/cat.png"),
+            Ok(85 + 16 * 170)
+        );
+
+        let policy = ImageTokensPolicy {
+            formula: ImageTokensFormula::Fixed {
+                tokens_per_image: 576,
+            },
+            max_image_size_bytes: 64,
+            allowed_formats: vec![ImageFormat::Jpeg],
+        };
+        assert_eq!(
+            policy.estimate_tokens(&data_url(&png(800, 600))),
+            Err(ImageTokensError::UnsupportedFormat {
+                format: ImageFormat::Png
+            })
+        );
+        assert_eq!(
+            policy.estimate_tokens(&data_url(&[0; 128])),
+            Err(ImageTokensError::TooLarge {
+                max_image_size_bytes: 64
+            })
+        );
+        assert_eq!(
+            policy.estimate_tokens("data:image/png,raw"),
+            Err(ImageTokensError::InvalidDataUrl)
+        );
+        assert_eq!(
+            policy.estimate_tokens(&data_url(b"not an image")),
+            Err(ImageTokensError::UnknownFormat)
+        );
+        assert_eq!(
+            policy.estimate_tokens("https://example.com/cat.jpg"),
+            Ok(576)
+        );
+    }
+}
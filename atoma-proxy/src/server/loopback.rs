@@ -0,0 +1,76 @@
+use axum::http::HeaderMap;
+use rand::{distributions::Alphanumeric, Rng};
+use subtle::ConstantTimeEq;
+
+use super::http_server::ProxyState;
+
+/// Header naming the API token a request forwarded by the proxy to itself is executed with.
+///
+/// It is only trusted when the request is authenticated with the loopback secret.
+pub const LOOPBACK_API_TOKEN_ID_HEADER: &str = "x-atoma-loopback-api-token-id";
+
+/// Header naming the endpoint the scopes of the API token of a request forwarded by the proxy
+/// to itself are enforced for (e.g. the Responses API requests, forwarded to the chat completions
+/// endpoint).
+///
+/// Like `LOOPBACK_API_TOKEN_ID_HEADER`, it is only trusted when the request is authenticated
+/// with the loopback secret.
+pub const LOOPBACK_SCOPE_ENDPOINT_HEADER: &str = "x-atoma-loopback-scope-endpoint";
+
+/// Length of the secret the requests forwarded by the proxy to itself are authenticated with.
+const LOOPBACK_SECRET_LENGTH: usize = 64;
+
+/// Generates the secret the requests forwarded by the proxy to itself are authenticated with.
+///
+/// A new secret is generated every time the proxy starts, and never leaves the proxy.
+#[must_use]
+pub fn generate_loopback_secret() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(LOOPBACK_SECRET_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+/// Returns the URL the proxy forwards requests to itself on, for the given endpoint.
+#[must_use]
+pub fn loopback_url(state: &ProxyState, endpoint: &str) -> String {
+    format!("http://localhost:{}{endpoint}", state.port)
+}
+
+/// Returns the API token a request is executed with, if the request was forwarded by the proxy to itself.
+///
+/// The proxy forwards requests to itself (e.g. the requests of batches, or the requests translated
+/// to chat completions), authenticated with the loopback secret instead of an API token, so that
+/// they go through the same middleware (stack locking, rate limiting, billing) as the requests
+/// sent by clients.
+pub fn loopback_api_token_id(state: &ProxyState, headers: &HeaderMap, token: &str) -> Option<i64> {
+    // NOTE: Compared in constant time, not to leak the secret through timing
+    if !bool::from(token.as_bytes().ct_eq(state.loopback_secret.as_bytes())) {
+        return None;
+    }
+    headers
+        .get(LOOPBACK_API_TOKEN_ID_HEADER)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
+/// Returns the endpoint the scopes of the API token of a request forwarded by the proxy to itself
+/// are enforced for, if it differs from the endpoint of the request.
+pub fn loopback_scope_endpoint(headers: &HeaderMap) -> Option<&str> {
+    headers.get(LOOPBACK_SCOPE_ENDPOINT_HEADER)?.to_str().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_loopback_secret() {
+        let secret = generate_loopback_secret();
+        assert_eq!(secret.len(), LOOPBACK_SECRET_LENGTH);
+        assert_ne!(secret, generate_loopback_secret());
+    }
+}
@@ -1,8 +1,10 @@
+use std::{sync::Arc, time::Instant};
+
 use atoma_state::types::AtomaAtomaStateManagerEvent;
 use atoma_utils::constants;
 use auth::{
-    get_cheapest_node_and_acquire_new_stack, get_node_metadata_from_state_manager,
-    GetSelectedNodeArgs, ProcessedRequest, SelectedNodeMetadata, StackMetadata,
+    get_node_metadata_from_state_manager, select_node_and_acquire_new_stack, GetSelectedNodeArgs,
+    ProcessedRequest, SelectedNodeMetadata, StackMetadata,
 };
 use axum::{
     body::Body,
@@ -14,10 +16,11 @@ use axum::{
 use base64::engine::{general_purpose::STANDARD, Engine};
 use opentelemetry::KeyValue;
 use reqwest::{
-    header::{AUTHORIZATION, CONTENT_LENGTH},
+    header::{AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE},
     StatusCode,
 };
 use serde_json::Value;
+use subtle::ConstantTimeEq;
 use tracing::instrument;
 use utils::is_confidential_compute_endpoint;
 
@@ -25,16 +28,26 @@ use super::{
     check_auth,
     error::AtomaProxyError,
     handlers::{
+        audio::{
+            transcription_multipart_to_json, AUDIO_TRANSCRIPTIONS_PATH,
+            MAX_AUDIO_TRANSCRIPTION_BODY_SIZE,
+        },
         image_generations::CONFIDENTIAL_IMAGE_GENERATIONS_PATH,
         metrics::{
-            LOCKED_STACK_COUNTER_PER_USER, STACK_LOCKED_COUNTER, STACK_NUM_REQUESTS_COUNTER,
-            STACK_UNAVAILABLE_COUNTER, UNAVAILABLE_STACK_COUNTER_PER_USER,
+            LOCKED_STACK_COUNTER_PER_USER, NODE_FAILOVER_COUNTER, RATE_LIMITED_REQUESTS_PER_USER,
+            STACK_LOCKED_COUNTER, STACK_NUM_REQUESTS_COUNTER, STACK_UNAVAILABLE_COUNTER,
+            UNAVAILABLE_STACK_COUNTER_PER_USER,
         },
         models::MODELS_PATH,
         nodes::MAX_NUM_TOKENS_FOR_CONFIDENTIAL_COMPUTE,
-        update_state_manager,
+        record_request_usage, serve_cached_response, update_state_manager,
     },
     http_server::ProxyState,
+    model_registry::ServedModels,
+    model_router::X_ATOMA_MODEL,
+    rate_limiter::RateLimitStatus,
+    response_cache::CacheLookup,
+    MAX_COMPLETION_TOKENS, MAX_TOKENS, MODEL,
 };
 use super::{types::ConfidentialComputeRequest, Result};
 
@@ -44,6 +57,10 @@ use super::{types::ConfidentialComputeRequest, Result};
 /// as hardcoded in Atoma's smart contract.
 pub const STACK_SIZE_TO_BUY: i64 = 1_000_000;
 
+/// Maximum number of times a request is rerouted to another node, after the node
+/// processing it failed before sending any response to the client.
+const MAX_FAILOVER_ATTEMPTS: usize = 2;
+
 /// Default image resolution for image generations, in pixels.
 const DEFAULT_IMAGE_RESOLUTION: u64 = 1024 * 1024;
 
@@ -51,6 +68,17 @@ const DEFAULT_IMAGE_RESOLUTION: u64 = 1024 * 1024;
 /// This is to prevent DoS attacks by limiting the size of the request body.
 const MAX_BODY_SIZE: usize = 1024 * 1024; // 1MB
 
+/// Returns the maximum size of the body of a request to `endpoint`, in bytes.
+///
+/// Audio transcription requests carry the whole audio file, so they get a larger limit.
+fn max_body_size(endpoint: &str) -> usize {
+    if endpoint == AUDIO_TRANSCRIPTIONS_PATH {
+        MAX_AUDIO_TRANSCRIPTION_BODY_SIZE
+    } else {
+        MAX_BODY_SIZE
+    }
+}
+
 /// Metadata extension for tracking request-specific information about the selected inference node.
 ///
 /// This extension is attached to requests during authentication middleware processing
@@ -77,6 +105,9 @@ pub struct RequestMetadataExtension {
     /// The user id for this request.
     pub user_id: i64,
 
+    /// The id of the API token used to authenticate this request.
+    pub api_token_id: i64,
+
     /// Selected stack small id for this request.
     pub selected_stack_small_id: i64,
 
@@ -85,6 +116,10 @@ pub struct RequestMetadataExtension {
 
     /// Model name
     pub model_name: String,
+
+    /// The key under which the response of the request is cached once processed,
+    /// if the request is cacheable and its response was not cached yet.
+    pub cache_key: Option<String>,
 }
 
 impl RequestMetadataExtension {
@@ -155,6 +190,22 @@ impl RequestMetadataExtension {
         self
     }
 
+    /// Adds an API token id to the request metadata.
+    ///
+    /// This method is used to set the id of the API token that authenticated the request.
+    ///
+    /// # Arguments
+    ///
+    /// * `api_token_id` - The API token id to set
+    ///
+    /// # Returns
+    ///
+    /// Returns self with the API token id field populated, enabling method chaining
+    pub const fn with_api_token_id(mut self, api_token_id: i64) -> Self {
+        self.api_token_id = api_token_id;
+        self
+    }
+
     /// Adds a stack small id to the request metadata.
     ///
     /// This method is used to set the stack small id that will be used for the request.
@@ -272,14 +323,15 @@ pub async fn authenticate_middleware(
     req: Request<Body>,
     next: Next,
 ) -> Result<Response> {
+    let start = Instant::now();
     let (mut req_parts, body) = req.into_parts();
     let endpoint = req_parts.uri.path().to_string();
     if endpoint == MODELS_PATH {
-        check_auth(&state.state_manager_sender, &req_parts.headers, &endpoint).await?;
+        check_auth(&state, &req_parts.headers, &endpoint, None).await?;
         let req = Request::from_parts(req_parts, body);
         return Ok(next.run(req).await);
     }
-    let body_bytes = axum::body::to_bytes(body, MAX_BODY_SIZE)
+    let body_bytes = axum::body::to_bytes(body, max_body_size(&endpoint))
         .await
         .map_err(|e| {
             if let Some(source) = std::error::Error::source(&e) {
@@ -296,52 +348,140 @@ pub async fn authenticate_middleware(
                 endpoint: req_parts.uri.path().to_string(),
             }
         })?;
-    let body_json: Value =
-        serde_json::from_slice(&body_bytes).map_err(|e| AtomaProxyError::RequestError {
+    let multipart_content_type = req_parts
+        .headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| value.starts_with("multipart/form-data"))
+        .map(ToString::to_string);
+    let mut body_json: Value = match multipart_content_type {
+        // NOTE: Audio uploads are converted to JSON, so that they are signed and forwarded to the node like any other request
+        Some(content_type) if endpoint == AUDIO_TRANSCRIPTIONS_PATH => {
+            let body_json = transcription_multipart_to_json(&content_type, &body_bytes)?;
+            req_parts
+                .headers
+                .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+            body_json
+        }
+        _ => serde_json::from_slice(&body_bytes).map_err(|e| AtomaProxyError::RequestError {
             message: format!("Failed to parse body as JSON: {e}"),
             endpoint: req_parts.uri.path().to_string(),
-        })?;
+        })?,
+    };
 
     // Authenticate request and lock compute units for a Stack.
     //
     // NOTE: If this method succeeds and the `optional_stack` is Some, this means that the proxy has locked
     // enough compute units for the request, within the state manager. Otherwise, this has not been the case.
 
+    // NOTE: The served models are read once, so that a concurrent reload does not resolve the model of the request
+    // and estimate its compute units with different configurations
+    let served_models = state.model_registry.current();
     let endpoint_clone = endpoint.clone();
     tokio::spawn(async move {
+        // Resolves the requested model (or alias) to the models that can serve the request, in order of preference.
+        //
+        // NOTE: A fallback model is only tried when no node is available for the previous models, in which case
+        // no compute units have been locked, nor a stack bought, for the previous models.
+        let mut candidates = body_json
+            .get(MODEL)
+            .and_then(Value::as_str)
+            .map(|model| served_models.model_router.candidates(model))
+            .unwrap_or_default()
+            .into_iter()
+            .peekable();
+        // NOTE: The request is charged to the rate limits for its first model only, the fallback models
+        // are only tried once no node was available for it
+        let mut rate_limit_status = None;
+        let mut is_first_candidate = true;
+        let (stack_metadata, selected_node_metadata) = loop {
+            if let Some(candidate) = candidates.next() {
+                body_json[MODEL] = Value::String(candidate);
+            }
+            let mut stack_metadata = auth::handle_authenticate_and_lock_compute_units(
+                &state,
+                &served_models,
+                &req_parts.headers,
+                &body_json,
+                &endpoint,
+                is_first_candidate,
+            )
+            .await?;
+            if is_first_candidate {
+                rate_limit_status = stack_metadata.rate_limit_status;
+                is_first_candidate = false;
+            }
+            if let CacheLookup::Hit(cached_response) =
+                std::mem::replace(&mut stack_metadata.cache_lookup, CacheLookup::Uncacheable)
+            {
+                let mut response = serve_cached_response(
+                    &state,
+                    stack_metadata.user_id,
+                    stack_metadata.api_token_id,
+                    &stack_metadata.model,
+                    &endpoint,
+                    cached_response,
+                )
+                .await?;
+                if let Ok(model) = HeaderValue::from_str(&stack_metadata.model) {
+                    response.headers_mut().insert(X_ATOMA_MODEL, model);
+                }
+                if let Some(status) = rate_limit_status {
+                    status.insert_headers(response.headers_mut());
+                }
+                return Ok(response);
+            }
+
+            // Selects an appropriate node to process the request (if there is no available node for the stacks the proxy holds, it buys a new stack)
+            //
+            // NOTE: IF `optional_stack` is Some, this means that the proxy has locked enough compute units for the request, within the state manager, already.
+            // In this case, this method cannot error (as it just returns the underlying stack data). Otherwise, it will try to buy a new stack.
+            // If this method succeeds, this means that the proxy has locked enough compute units for the request, within the state manager.
+            // Otherwise, we are safe to assume that the proxy has not locked enough compute units for the request, within the state manager, and we will not be able to process the request.
+            match auth::get_selected_node(GetSelectedNodeArgs {
+                model: &stack_metadata.model,
+                state: &state,
+                optional_stack: stack_metadata.optional_stack.take(),
+                total_tokens: stack_metadata.max_total_compute_units,
+                user_id: stack_metadata.user_id,
+                endpoint: &endpoint,
+            })
+            .await
+            {
+                Ok(selected_node_metadata) => break (stack_metadata, selected_node_metadata),
+                Err(AtomaProxyError::NoAvailableNode { message, .. })
+                    if candidates.peek().is_some() =>
+                {
+                    tracing::info!(
+                        target = "atoma-service",
+                        "{message}, falling back to the next model for the request"
+                    );
+                }
+                Err(e) => return Err(e),
+            }
+        };
         let StackMetadata {
-            optional_stack,
             num_input_compute_units,
             max_total_compute_units,
+            max_completion_tokens,
             model,
             user_id,
-        } = auth::handle_authenticate_and_lock_compute_units(
-            &state,
-            &req_parts.headers,
-            &body_json,
-            &endpoint,
-        )
-        .await?;
-
-        // Selects an appropriate node to process the request (if there is no available node for the stacks the proxy holds, it buys a new stack)
-        //
-        // NOTE: IF `optional_stack` is Some, this means that the proxy has locked enough compute units for the request, within the state manager, already.
-        // In this case, this method cannot error (as it just returns the underlying stack data). Otherwise, it will try to buy a new stack.
-        // If this method succeeds, this means that the proxy has locked enough compute units for the request, within the state manager.
-        // Otherwise, we are safe to assume that the proxy has not locked enough compute units for the request, within the state manager, and we will not be able to process the request.
+            api_token_id,
+            cache_lookup,
+            ..
+        } = stack_metadata;
+        // NOTE: The request was only accepted with its maximum number of output tokens lowered to the context window of the model
+        if let Some(max_completion_tokens) = max_completion_tokens {
+            body_json[MAX_COMPLETION_TOKENS] = Value::from(max_completion_tokens);
+            if body_json.get(MAX_TOKENS).is_some() {
+                body_json[MAX_TOKENS] = Value::from(max_completion_tokens);
+            }
+        }
         let SelectedNodeMetadata {
             stack_small_id,
             selected_node_id,
             tx_digest,
-        } = auth::get_selected_node(GetSelectedNodeArgs {
-            model: &model,
-            state: &state,
-            optional_stack,
-            total_tokens: max_total_compute_units,
-            user_id,
-            endpoint: &endpoint,
-        })
-        .await?;
+        } = selected_node_metadata;
 
         STACK_NUM_REQUESTS_COUNTER.add(1, &[KeyValue::new("stack_small_id", stack_small_id)]);
 
@@ -349,7 +489,7 @@ pub async fn authenticate_middleware(
         //
         // NOTE: If this method fails, we need to rollback the compute units that we locked for the stack, back to 0. Otherwise,
         // the proxy will be in an inconsistent state for the current stack.
-        let req = match utils::try_validate_stack_for_request(
+        let mut req = match utils::try_validate_stack_for_request(
             &state,
             &body_json,
             &mut req_parts,
@@ -359,6 +499,7 @@ pub async fn authenticate_middleware(
             max_total_compute_units,
             tx_digest,
             user_id,
+            api_token_id,
             &endpoint,
         )
         .await
@@ -370,12 +511,36 @@ pub async fn authenticate_middleware(
                     stack_small_id,
                     max_total_compute_units as i64,
                     0,
+                    0,
                     &endpoint,
                 )?;
                 return Err(e);
             }
         };
-        Ok(next.run(req).await)
+        if let (CacheLookup::Miss(key), Some(request_metadata)) = (
+            cache_lookup,
+            req.extensions_mut().get_mut::<RequestMetadataExtension>(),
+        ) {
+            request_metadata.cache_key = Some(key);
+        }
+        // NOTE: The served models are passed down, so that the request is rerouted to the fallbacks of the same configuration
+        req.extensions_mut().insert(served_models);
+        let request_metadata = req.extensions().get::<RequestMetadataExtension>().cloned();
+        let mut response = next.run(req).await;
+        if let Some(request_metadata) = request_metadata.filter(|_| !response.status().is_success())
+        {
+            record_failed_request(&state, &request_metadata, response.status(), start);
+        }
+        // NOTE: The header might have been set already, if the request was rerouted to a fallback model
+        if !response.headers().contains_key(X_ATOMA_MODEL) {
+            if let Ok(model) = HeaderValue::from_str(&model) {
+                response.headers_mut().insert(X_ATOMA_MODEL, model);
+            }
+        }
+        if let Some(status) = rate_limit_status {
+            status.insert_headers(response.headers_mut());
+        }
+        Ok(response)
     })
     .await
     .map_err(|e| AtomaProxyError::InternalError {
@@ -450,14 +615,13 @@ pub async fn confidential_compute_middleware(
     req: Request<Body>,
     next: Next,
 ) -> Result<Response> {
+    let start = Instant::now();
     let (mut req_parts, body) = req.into_parts();
     let endpoint = req_parts.uri.path().to_string();
     let endpoint_clone = endpoint.clone();
     // NOTE: We spawn a new task to avoid the executor cleaning up the
     // execution state, without the full updates being applied.
     tokio::spawn(async move {
-        let user_id =
-            check_auth(&state.state_manager_sender, &req_parts.headers, &endpoint).await?;
         let body_bytes = axum::body::to_bytes(body, MAX_BODY_SIZE)
             .await
             .map_err(|e| {
@@ -480,6 +644,13 @@ pub async fn confidential_compute_middleware(
                 message: format!("Failed to parse body as JSON: {e}"),
                 endpoint: req_parts.uri.path().to_string(),
             })?;
+        let api_token = check_auth(
+            &state,
+            &req_parts.headers,
+            &endpoint,
+            Some(&confidential_compute_request.model_name),
+        )
+        .await?;
 
         let num_compute_units = if endpoint == CONFIDENTIAL_IMAGE_GENERATIONS_PATH {
             confidential_compute_request
@@ -492,6 +663,14 @@ pub async fn confidential_compute_middleware(
             )
         };
 
+        let rate_limit_status = check_rate_limits(
+            &state,
+            api_token.user_id,
+            api_token.id,
+            num_compute_units as u64,
+            &endpoint,
+        )?;
+
         let plaintext_body_hash = STANDARD
             .decode(confidential_compute_request.plaintext_body_hash)
             .map_err(|e| AtomaProxyError::RequestError {
@@ -554,12 +733,20 @@ pub async fn confidential_compute_middleware(
             .with_node_small_id(node_small_id)
             .with_stack_small_id(confidential_compute_request.stack_small_id as i64)
             .with_max_total_num_compute_units(num_compute_units as u64)
-            .with_user_id(user_id)
+            .with_user_id(api_token.user_id)
+            .with_api_token_id(api_token.id)
             .with_model_name(confidential_compute_request.model_name)
             .with_endpoint(endpoint);
-        req_parts.extensions.insert(request_metadata);
+        req_parts.extensions.insert(request_metadata.clone());
         let req = Request::from_parts(req_parts, Body::from(body_bytes));
-        Ok(next.run(req).await)
+        let mut response = next.run(req).await;
+        if !response.status().is_success() {
+            record_failed_request(&state, &request_metadata, response.status(), start);
+        }
+        if let Some(status) = rate_limit_status {
+            status.insert_headers(response.headers_mut());
+        }
+        Ok(response)
     })
     .await
     .map_err(|e| AtomaProxyError::InternalError {
@@ -569,12 +756,151 @@ pub async fn confidential_compute_middleware(
     })?
 }
 
+/// Enforces the per user and per API token rate limits on a request, and charges it to them.
+///
+/// This is called right after the request is authenticated and its number of compute units is
+/// estimated, before any compute units are locked or any stack is bought for it, so that a
+/// rejected request costs nothing to its user.
+///
+/// # Arguments
+///
+/// * `state` - The state of the proxy server.
+/// * `user_id` - The id of the user making the request.
+/// * `api_token_id` - The id of the API token used for the request.
+/// * `num_compute_units` - The estimated number of compute units of the request.
+/// * `endpoint` - The endpoint of the request.
+///
+/// # Returns
+///
+/// Returns the state of the rate limits, to be reported in the `x-ratelimit-*` headers of the
+/// response, or `None` if no rate limits apply.
+///
+/// # Errors
+///
+/// Returns `AtomaProxyError::TooManyRequests`, with the `Retry-After` and `x-ratelimit-*`
+/// headers to respond with, if the request exceeds the rate limits.
+pub fn check_rate_limits(
+    state: &ProxyState,
+    user_id: i64,
+    api_token_id: i64,
+    num_compute_units: u64,
+    endpoint: &str,
+) -> Result<Option<RateLimitStatus>> {
+    let Some(rate_limiter) = state.rate_limiter.as_ref() else {
+        return Ok(None);
+    };
+    rate_limiter
+        .check(user_id, api_token_id, num_compute_units)
+        .map_err(|exceeded| {
+            RATE_LIMITED_REQUESTS_PER_USER.add(1, &[KeyValue::new("user_id", user_id)]);
+            AtomaProxyError::TooManyRequests {
+                message: format!(
+                    "Rate limit exceeded for user {user_id} and api token {api_token_id}, retry after {:?}",
+                    exceeded.retry_after
+                ),
+                rate_limit: Some(exceeded),
+                endpoint: endpoint.to_string(),
+            }
+        })
+}
+
+/// Records a request that failed once a stack and a node were selected for it, in the usage
+/// ledger of its user.
+///
+/// Failed requests are not billed, so they are recorded with no tokens, along with the status
+/// code of their response. Successful requests are recorded by their handlers, with their usage.
+///
+/// # Arguments
+///
+/// * `state` - The state of the proxy server.
+/// * `metadata` - The metadata of the request, set by the middleware.
+/// * `status` - The status code of the response.
+/// * `start` - The time the request was received.
+fn record_failed_request(
+    state: &ProxyState,
+    metadata: &RequestMetadataExtension,
+    status: StatusCode,
+    start: Instant,
+) {
+    if let Err(e) = record_request_usage(&state.state_manager_sender, metadata, 0, 0, start, status)
+    {
+        tracing::error!(
+            target = "atoma-service",
+            "Failed to record failed request in the usage ledger: {e}"
+        );
+    }
+}
+
+/// Middleware that authenticates requests to the admin endpoints.
+///
+/// The request must carry the configured admin API key as a bearer token in its
+/// `Authorization` header. The admin routes are only mounted when an admin API key
+/// is configured.
+///
+/// # Arguments
+///
+/// * `state` - The state of the proxy server.
+/// * `req` - The incoming HTTP request.
+/// * `next` - The next middleware in the chain.
+///
+/// # Returns
+///
+/// Returns the processed response from downstream handlers.
+///
+/// # Errors
+///
+/// Returns `AtomaProxyError::AuthError` if the admin API key is missing or invalid.
+#[instrument(
+    level = "info",
+    name = "admin_auth_middleware",
+    skip_all,
+    fields(endpoint = %req.uri().path()),
+    err
+)]
+pub async fn admin_auth_middleware(
+    state: State<ProxyState>,
+    req: Request<Body>,
+    next: Next,
+) -> Result<Response> {
+    let endpoint = req.uri().path().to_string();
+    let is_authorized = state
+        .admin_api_key
+        .as_deref()
+        .zip(
+            req.headers()
+                .get(AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer ")),
+        )
+        .is_some_and(|(admin_api_key, token)| {
+            // NOTE: Compared in constant time, not to leak the admin API key through timing
+            bool::from(admin_api_key.as_bytes().ct_eq(token.as_bytes()))
+        });
+    if !is_authorized {
+        return Err(AtomaProxyError::AuthError {
+            auth_error: "Invalid or missing admin API key".to_string(),
+            endpoint,
+        });
+    }
+    Ok(next.run(req).await)
+}
+
 /// Middleware that handles locked stack requests.
 ///
 /// This middleware checks if the current request is for a locked stack and, if so,
 /// attempts to lock a new stack for the request. It then forwards the request to the
 /// next middleware in the chain.
 ///
+/// When the node processing a non-confidential request fails (the handler returns
+/// `502 Bad Gateway`, after a connection error or a server error of the node), the
+/// request is rerouted to a stack on another node for the same model, up to
+/// `MAX_FAILOVER_ATTEMPTS` times, and then to the fallback models of the model, in order.
+/// Streaming requests are only rerouted if the node failed before the stream started, so
+/// no partial response is ever sent twice.
+///
+/// The outcome of every request forwarded to a node is recorded in the node health
+/// registry, which ejects failing nodes from node and stack selection.
+///
 /// # Arguments
 ///
 /// * `state` - The state of the proxy server.
@@ -622,7 +948,7 @@ pub async fn handle_locked_stack_middleware(
                 message: "Authorization header not found, this should never happen".to_string(),
                 endpoint: endpoint.to_string(),
             })?;
-    let body_bytes = axum::body::to_bytes(body, MAX_BODY_SIZE)
+    let body_bytes = axum::body::to_bytes(body, max_body_size(&endpoint))
         .await
         .map_err(|e| {
             if let Some(source) = std::error::Error::source(&e) {
@@ -641,6 +967,9 @@ pub async fn handle_locked_stack_middleware(
         })?;
     let original_req = Request::from_parts(req_parts.clone(), Body::from(body_bytes.clone()));
     let response = next.clone().run(original_req).await;
+    if let Some(request_metadata) = req_parts.extensions.get::<RequestMetadataExtension>() {
+        record_node_outcome(&state, request_metadata.node_id, response.status());
+    }
     match response.status() {
         StatusCode::LOCKED => {
             let request_metadata = req_parts
@@ -719,81 +1048,335 @@ pub async fn handle_locked_stack_middleware(
                 });
             }
             // We need to acquire a new stack for the request, to be able to retry
-            let user_id = request_metadata.user_id;
-            let max_total_num_compute_units = request_metadata.max_total_num_compute_units;
-            // 1. Try to get a Stack from the state manager
-            let maybe_stack = get_node_metadata_from_state_manager(
+            let req = reroute_request_to_new_stack(
                 &state,
-                &request_metadata.model_name,
-                user_id,
-                max_total_num_compute_units as i64,
-                is_confidential_compute_endpoint(&endpoint),
+                req_parts,
+                authorization_header,
+                &body_bytes,
+                &request_metadata,
+                &[],
                 &endpoint,
             )
             .await?;
-            let selected_node_metadata = match maybe_stack {
-                Some(stack) => SelectedNodeMetadata {
-                    selected_node_id: stack.selected_node_id,
-                    stack_small_id: stack.stack_small_id,
-                    tx_digest: None,
-                },
-                None => {
-                    // 2. Acquire a new stack for the request, this will also lock compute units for the new acquired stack
-                    get_cheapest_node_and_acquire_new_stack(
-                        &state,
-                        user_id,
-                        &request_metadata.model_name,
-                        &request_metadata.endpoint,
-                        max_total_num_compute_units,
-                    )
-                    .await?
-                }
-            };
-            // 3. Update the request headers with the new acquired stack's information
-            req_parts
-                .headers
-                .insert(AUTHORIZATION, authorization_header);
-            req_parts.headers.insert(
-                constants::STACK_SMALL_ID,
-                HeaderValue::from_str(&selected_node_metadata.stack_small_id.to_string()).unwrap(),
-            );
-            let body_json =
-                serde_json::from_slice(&body_bytes).map_err(|e| AtomaProxyError::RequestError {
-                    message: format!("Failed to parse body as JSON: {e}"),
+            let node_small_id = req
+                .extensions()
+                .get::<RequestMetadataExtension>()
+                .map(|request_metadata| request_metadata.node_id);
+            let response = next.run(req).await;
+            if let Some(node_small_id) = node_small_id {
+                record_node_outcome(&state, node_small_id, response.status());
+            }
+            Ok(response)
+        }
+        StatusCode::BAD_GATEWAY if !is_confidential_compute_endpoint(&endpoint) => {
+            // NOTE: The node failed to process the request before any response byte was sent to the client
+            // (a connection error or a server error), and the handler already released the compute units locked
+            // on its stack. Confidential compute requests are encrypted for a specific node, so they cannot be
+            // rerouted and are left to the client to retry.
+            let mut request_metadata = req_parts
+                .extensions
+                .get::<RequestMetadataExtension>()
+                .cloned()
+                .ok_or_else(|| AtomaProxyError::InternalError {
+                    message: "Request metadata not found, this should never happen".to_string(),
+                    client_message: None,
                     endpoint: endpoint.to_string(),
                 })?;
-            let req = match utils::try_validate_stack_for_request(
-                &state,
-                &body_json,
-                &mut req_parts,
-                selected_node_metadata.selected_node_id,
-                selected_node_metadata.stack_small_id,
-                request_metadata.num_input_tokens.unwrap_or_default(),
-                max_total_num_compute_units,
-                selected_node_metadata.tx_digest,
-                user_id,
-                &endpoint,
-            )
-            .await
-            {
-                Ok(req) => req,
-                Err(e) => {
-                    update_state_manager(
-                        &state.state_manager_sender,
-                        selected_node_metadata.stack_small_id,
-                        max_total_num_compute_units as i64,
-                        0,
-                        &endpoint,
-                    )?;
-                    return Err(e);
+            let mut failed_node_small_ids = vec![request_metadata.node_id];
+            let mut response = response;
+            for attempt in 1..=MAX_FAILOVER_ATTEMPTS {
+                NODE_FAILOVER_COUNTER.add(
+                    1,
+                    &[KeyValue::new("model", request_metadata.model_name.clone())],
+                );
+                tracing::warn!(
+                    target = "atoma-service",
+                    attempt,
+                    failed_node_small_id = request_metadata.node_id,
+                    failed_stack_small_id = request_metadata.selected_stack_small_id,
+                    ?failed_node_small_ids,
+                    "Node failed to process the request, retrying on another node"
+                );
+                let req = match reroute_request_to_new_stack(
+                    &state,
+                    req_parts.clone(),
+                    authorization_header.clone(),
+                    &body_bytes,
+                    &request_metadata,
+                    &failed_node_small_ids,
+                    &endpoint,
+                )
+                .await
+                {
+                    Ok(req) => req,
+                    Err(e) => {
+                        tracing::warn!(
+                            target = "atoma-service",
+                            attempt,
+                            ?failed_node_small_ids,
+                            error = %e,
+                            "No other node available to retry the request"
+                        );
+                        break;
+                    }
+                };
+                request_metadata = req
+                    .extensions()
+                    .get::<RequestMetadataExtension>()
+                    .cloned()
+                    .ok_or_else(|| AtomaProxyError::InternalError {
+                        message: "Request metadata not found, this should never happen".to_string(),
+                        client_message: None,
+                        endpoint: endpoint.to_string(),
+                    })?;
+                response = next.clone().run(req).await;
+                record_node_outcome(&state, request_metadata.node_id, response.status());
+                if response.status() != StatusCode::BAD_GATEWAY {
+                    tracing::info!(
+                        target = "atoma-service",
+                        attempt,
+                        node_small_id = request_metadata.node_id,
+                        stack_small_id = request_metadata.selected_stack_small_id,
+                        status = %response.status(),
+                        "Request rerouted to another node"
+                    );
+                    return Ok(response);
                 }
-            };
-            Ok(next.run(req).await)
+                failed_node_small_ids.push(request_metadata.node_id);
+            }
+            // NOTE: No node could serve the model, so the request is rerouted to the fallback models of the model, in order.
+            // Each fallback model is authenticated against the scopes of the API token, and the compute units of the request
+            // are estimated again with its tokenizer, within its context window. Fallback models the request is not allowed to
+            // use, or does not fit in, are skipped.
+            let model = request_metadata.model_name.clone();
+            let served_models = req_parts
+                .extensions
+                .get::<Arc<ServedModels>>()
+                .cloned()
+                .unwrap_or_else(|| state.model_registry.current());
+            let mut headers = req_parts.headers.clone();
+            headers.insert(AUTHORIZATION, authorization_header.clone());
+            for fallback_model in served_models.model_router.fallbacks(&model) {
+                let mut body_json: Value = serde_json::from_slice(&body_bytes).map_err(|e| {
+                    AtomaProxyError::RequestError {
+                        message: format!("Failed to parse body as JSON: {e}"),
+                        endpoint: endpoint.to_string(),
+                    }
+                })?;
+                body_json[MODEL] = Value::String(fallback_model.clone());
+                let estimated_request = match auth::handle_authenticate_and_estimate_compute_units(
+                    &state,
+                    &served_models,
+                    &headers,
+                    &body_json,
+                    &endpoint,
+                )
+                .await
+                {
+                    Ok(estimated_request) => estimated_request,
+                    Err(e) => {
+                        tracing::warn!(
+                            target = "atoma-service",
+                            %fallback_model,
+                            error = %e,
+                            "The request cannot be served by the fallback model"
+                        );
+                        continue;
+                    }
+                };
+                if let Some(max_completion_tokens) = estimated_request.max_completion_tokens {
+                    body_json[MAX_COMPLETION_TOKENS] = Value::from(max_completion_tokens);
+                    if body_json.get(MAX_TOKENS).is_some() {
+                        body_json[MAX_TOKENS] = Value::from(max_completion_tokens);
+                    }
+                }
+                request_metadata.model_name.clone_from(fallback_model);
+                request_metadata.num_input_tokens = Some(estimated_request.num_input_compute_units);
+                request_metadata.max_total_num_compute_units =
+                    estimated_request.max_total_compute_units;
+                let req = match reroute_request_to_new_stack(
+                    &state,
+                    req_parts.clone(),
+                    authorization_header.clone(),
+                    body_json.to_string().as_bytes(),
+                    &request_metadata,
+                    &failed_node_small_ids,
+                    &endpoint,
+                )
+                .await
+                {
+                    Ok(req) => req,
+                    Err(e) => {
+                        tracing::warn!(
+                            target = "atoma-service",
+                            %fallback_model,
+                            error = %e,
+                            "No node available to serve the request with the fallback model"
+                        );
+                        continue;
+                    }
+                };
+                request_metadata = req
+                    .extensions()
+                    .get::<RequestMetadataExtension>()
+                    .cloned()
+                    .ok_or_else(|| AtomaProxyError::InternalError {
+                        message: "Request metadata not found, this should never happen".to_string(),
+                        client_message: None,
+                        endpoint: endpoint.to_string(),
+                    })?;
+                response = next.clone().run(req).await;
+                record_node_outcome(&state, request_metadata.node_id, response.status());
+                if response.status() != StatusCode::BAD_GATEWAY {
+                    tracing::info!(
+                        target = "atoma-service",
+                        %model,
+                        %fallback_model,
+                        node_small_id = request_metadata.node_id,
+                        status = %response.status(),
+                        "Request rerouted to a fallback model"
+                    );
+                    if let Ok(fallback_model) = HeaderValue::from_str(fallback_model) {
+                        response.headers_mut().insert(X_ATOMA_MODEL, fallback_model);
+                    }
+                    return Ok(response);
+                }
+                failed_node_small_ids.push(request_metadata.node_id);
+            }
+            tracing::error!(
+                target = "atoma-service",
+                ?failed_node_small_ids,
+                "All failover attempts failed"
+            );
+            Ok(response)
         }
         _ => Ok(response),
     }
 }
 
+/// Records the outcome of a request forwarded to a node in the node health registry.
+///
+/// A `502 Bad Gateway` response, returned by the handlers when the node could not be reached
+/// or answered with a server error, counts as a failure of the node, and a successful response
+/// as a success. Other responses (client errors, locked stacks, etc.) do not reflect the health
+/// of the node and are ignored.
+fn record_node_outcome(state: &ProxyState, node_small_id: i64, status: StatusCode) {
+    if status == StatusCode::BAD_GATEWAY {
+        state.node_health.record_failure(node_small_id);
+    } else if status.is_success() {
+        state.node_health.record_success(node_small_id);
+    }
+}
+
+/// Selects another stack for a non-confidential request that could not be processed on its
+/// current stack, and prepares the request to be forwarded to the node of the new stack.
+///
+/// The stack is taken from the user's stacks for the requested model when one has enough
+/// free compute units, otherwise a new stack is acquired. Stacks on excluded nodes are never selected.
+///
+/// # Arguments
+///
+/// * `state` - The state of the proxy server.
+/// * `req_parts` - The parts of the original request.
+/// * `authorization_header` - The `Authorization` header of the original request.
+/// * `body_bytes` - The body of the original request.
+/// * `request_metadata` - The metadata of the original request.
+/// * `excluded_node_small_ids` - Nodes that must not be selected for the request.
+/// * `endpoint` - The endpoint of the request.
+///
+/// # Returns
+///
+/// Returns the request, with its headers and `RequestMetadataExtension` updated for the new stack.
+///
+/// # Errors
+///
+/// Returns an `AtomaProxyError` if no stack can be selected or acquired, or if the request
+/// cannot be prepared for the new stack. In the latter case, the compute units locked on the
+/// new stack are released.
+async fn reroute_request_to_new_stack(
+    state: &State<ProxyState>,
+    mut req_parts: Parts,
+    authorization_header: HeaderValue,
+    body_bytes: &[u8],
+    request_metadata: &RequestMetadataExtension,
+    excluded_node_small_ids: &[i64],
+    endpoint: &str,
+) -> Result<Request<Body>> {
+    let user_id = request_metadata.user_id;
+    let max_total_num_compute_units = request_metadata.max_total_num_compute_units;
+    // 1. Try to get a Stack from the state manager
+    let maybe_stack = get_node_metadata_from_state_manager(
+        state,
+        &request_metadata.model_name,
+        user_id,
+        max_total_num_compute_units as i64,
+        is_confidential_compute_endpoint(endpoint),
+        endpoint,
+        excluded_node_small_ids,
+    )
+    .await?;
+    let selected_node_metadata = match maybe_stack {
+        Some(stack) => SelectedNodeMetadata {
+            selected_node_id: stack.selected_node_id,
+            stack_small_id: stack.stack_small_id,
+            tx_digest: None,
+        },
+        None => {
+            // 2. Acquire a new stack for the request, this will also lock compute units for the new acquired stack
+            select_node_and_acquire_new_stack(
+                state,
+                user_id,
+                &request_metadata.model_name,
+                &request_metadata.endpoint,
+                max_total_num_compute_units,
+                excluded_node_small_ids,
+            )
+            .await?
+        }
+    };
+    // 3. Update the request headers with the new acquired stack's information
+    req_parts
+        .headers
+        .insert(AUTHORIZATION, authorization_header);
+    req_parts.headers.insert(
+        constants::STACK_SMALL_ID,
+        HeaderValue::from_str(&selected_node_metadata.stack_small_id.to_string()).unwrap(),
+    );
+    let body_json =
+        serde_json::from_slice(body_bytes).map_err(|e| AtomaProxyError::RequestError {
+            message: format!("Failed to parse body as JSON: {e}"),
+            endpoint: endpoint.to_string(),
+        })?;
+    match utils::try_validate_stack_for_request(
+        state,
+        &body_json,
+        &mut req_parts,
+        selected_node_metadata.selected_node_id,
+        selected_node_metadata.stack_small_id,
+        request_metadata.num_input_tokens.unwrap_or_default(),
+        max_total_num_compute_units,
+        selected_node_metadata.tx_digest,
+        user_id,
+        request_metadata.api_token_id,
+        endpoint,
+    )
+    .await
+    {
+        Ok(req) => Ok(req),
+        Err(e) => {
+            update_state_manager(
+                &state.state_manager_sender,
+                selected_node_metadata.stack_small_id,
+                max_total_num_compute_units as i64,
+                0,
+                0,
+                endpoint,
+            )?;
+            Err(e)
+        }
+    }
+}
+
 pub mod auth {
     use std::sync::Arc;
     use std::time::Duration;
@@ -809,6 +1392,10 @@ pub mod auth {
     use tokio::sync::{oneshot, RwLock};
     use tracing::instrument;
 
+    use crate::server::handlers::audio::RequestModelAudioSpeech;
+    use crate::server::handlers::audio::RequestModelAudioTranscriptions;
+    use crate::server::handlers::audio::AUDIO_SPEECH_PATH;
+    use crate::server::handlers::audio::AUDIO_TRANSCRIPTIONS_PATH;
     use crate::server::handlers::chat_completions::RequestModelChatCompletions;
     use crate::server::handlers::chat_completions::CHAT_COMPLETIONS_PATH;
     use crate::server::handlers::embeddings::RequestModelEmbeddings;
@@ -816,11 +1403,15 @@ pub mod auth {
     use crate::server::handlers::image_generations::RequestModelImageGenerations;
     use crate::server::handlers::image_generations::CONFIDENTIAL_IMAGE_GENERATIONS_PATH;
     use crate::server::handlers::image_generations::IMAGE_GENERATIONS_PATH;
+    use crate::server::handlers::lookup_response_cache;
     use crate::server::handlers::request_model::ComputeUnitsEstimate;
     use crate::server::http_server::UserId;
+    use crate::server::model_registry::ServedModels;
+    use crate::server::response_cache::CacheLookup;
     use crate::server::{
         check_auth, error::AtomaProxyError, handlers::request_model::RequestModel,
-        http_server::ProxyState, Result, ONE_MILLION,
+        http_server::ProxyState, rate_limiter::RateLimitStatus, BillingMode, NodeSelectionStrategy,
+        Result, ONE_MILLION,
     };
 
     use super::acquire_stack_lock;
@@ -835,6 +1426,10 @@ pub mod auth {
     /// The maximum number of attempts to wait for a stack to be created.
     const MAX_STACK_WAIT_ATTEMPTS: usize = 10;
 
+    /// The maximum time to wait for the metrics collection task to answer
+    /// a request for the best available nodes of a model.
+    const BEST_AVAILABLE_NODES_TIMEOUT: Duration = Duration::from_millis(100);
+
     /// Metadata about the stack that was selected for the request.
     /// This is used to update the stack's num_tokens after the request is processed.  
     #[derive(Clone, Debug)]
@@ -845,31 +1440,109 @@ pub mod auth {
         pub num_input_compute_units: u64,
         /// The maximum total compute units for the request.
         pub max_total_compute_units: u64,
+        /// The maximum number of output tokens the request has to be updated with, when it was
+        /// lowered to fit in the context window of the model.
+        pub max_completion_tokens: Option<u64>,
         /// The model that was selected for the request.
         pub model: String,
         /// The user ID that made the request.
         pub user_id: i64,
+        /// The ID of the API token that authenticated the request.
+        pub api_token_id: i64,
+        /// The state of the rate limits after the request was charged to them, if any apply.
+        pub rate_limit_status: Option<RateLimitStatus>,
+        /// The lookup of the request in the response cache. On a hit, no compute units were locked for the request.
+        pub cache_lookup: CacheLookup,
+    }
+
+    /// A request that was authenticated, and whose compute units were estimated, before any compute units are locked for it.
+    #[derive(Clone, Debug)]
+    pub struct EstimatedRequest {
+        /// The model of the request.
+        pub model: String,
+        /// The user ID that made the request.
+        pub user_id: i64,
+        /// The ID of the API token that authenticated the request.
+        pub api_token_id: i64,
+        /// The number of input compute units for the request.
+        pub num_input_compute_units: u64,
+        /// The maximum total compute units for the request.
+        pub max_total_compute_units: u64,
+        /// The maximum number of output tokens the request has to be updated with, when it was
+        /// lowered to fit in the context window of the model.
+        pub max_completion_tokens: Option<u64>,
+    }
+
+    /// Handles authentication and compute unit locking for incoming API requests.
+    ///
+    /// The request is authenticated and its compute units estimated, see
+    /// `handle_authenticate_and_estimate_compute_units`, before compute units are locked for it.
+    ///
+    /// # Arguments
+    ///
+    /// * `state` - Reference to the proxy server state containing shared resources
+    /// * `served_models` - The models served by the proxy, read once for the request
+    /// * `headers` - HTTP headers from the incoming request, used for authentication
+    /// * `body_json` - The parsed JSON body of the request
+    /// * `endpoint` - The API endpoint path being accessed (e.g., "/v1/chat/completions")
+    /// * `check_rate_limits` - Whether to charge the request to the rate limits of its user
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result<StackMetadata>` where:
+    /// * `Ok(StackMetadata)` - Authentication succeeded and compute units were locked,
+    ///   unless the response of the request is cached
+    /// * `Err(AtomaProxyError)` - Processing failed with specific error details
+    ///
+    /// # Errors
+    ///
+    /// Returns `AtomaProxyError` if the request cannot be authenticated or estimated,
+    /// or if compute units cannot be locked for it.
+    #[instrument(
+        level = "info",
+        skip_all,
+        fields(endpoint = %endpoint),
+        err
+    )]
+    pub async fn handle_authenticate_and_lock_compute_units(
+        state: &ProxyState,
+        served_models: &ServedModels,
+        headers: &HeaderMap,
+        body_json: &Value,
+        endpoint: &str,
+        check_rate_limits: bool,
+    ) -> Result<StackMetadata> {
+        let request = handle_authenticate_and_estimate_compute_units(
+            state,
+            served_models,
+            headers,
+            body_json,
+            endpoint,
+        )
+        .await?;
+        lock_compute_units(state, request, body_json, endpoint, check_rate_limits).await
     }
 
-    /// Handles authentication and compute unit locking for incoming API requests.
+    /// Handles authentication and compute units estimation for incoming API requests.
     ///
     /// This function serves as a routing layer that processes different types of API requests
     /// (chat completions, embeddings, and image generations) by:
     /// 1. Validating the request body against the appropriate model type
-    /// 2. Authenticating the request
-    /// 3. Locking the required compute units for processing
+    /// 2. Authenticating the request for its model
+    /// 3. Estimating the compute units of the request, within the context window of its model
     ///
     /// # Arguments
     ///
     /// * `state` - Reference to the proxy server state containing shared resources
+    /// * `served_models` - The models served by the proxy, read once for the request
     /// * `headers` - HTTP headers from the incoming request, used for authentication
     /// * `body_json` - The parsed JSON body of the request
     /// * `endpoint` - The API endpoint path being accessed (e.g., "/v1/chat/completions")
     ///
     /// # Returns
     ///
-    /// Returns a `Result<StackMetadata>` where:
-    /// * `Ok(StackMetadata)` - Authentication succeeded and compute units were locked
+    /// Returns a `Result<EstimatedRequest>` where:
+    /// * `Ok(EstimatedRequest)` - Authentication succeeded and compute units were estimated
     /// * `Err(AtomaProxyError)` - Processing failed with specific error details
     ///
     /// # Errors
@@ -884,18 +1557,19 @@ pub mod auth {
     /// use axum::http::HeaderMap;
     /// use serde_json::json;
     ///
-    /// async fn process_chat_request(state: &ProxyState) -> Result<Option<Stack>> {
+    /// async fn process_chat_request(state: &ProxyState) -> Result<EstimatedRequest> {
     ///     let headers = HeaderMap::new();
     ///     let body = json!({
     ///         "model": "gpt-4",
     ///         "messages": [{"role": "user", "content": "Hello"}]
     ///     });
     ///
-    ///     handle_authenticate_and_lock_compute_units(
+    ///     handle_authenticate_and_estimate_compute_units(
     ///         state,
+    ///         &state.model_registry.current(),
     ///         &headers,
     ///         &body,
-    ///         "/v1/chat/completions"
+    ///         "/v1/chat/completions",
     ///     ).await
     /// }
     /// ```
@@ -905,31 +1579,34 @@ pub mod auth {
     /// * `CHAT_COMPLETIONS_PATH` - For chat completion requests
     /// * `EMBEDDINGS_PATH` - For text embedding requests
     /// * `IMAGE_GENERATIONS_PATH` - For image generation requests
+    /// * `AUDIO_TRANSCRIPTIONS_PATH` - For audio transcription requests
+    /// * `AUDIO_SPEECH_PATH` - For speech generation requests
     ///
     /// # Request Flow
     ///
     /// 1. Matches the endpoint to determine request type
     /// 2. Parses request body into appropriate model struct
-    /// 3. Authenticates request and locks compute units
-    /// 4. Returns stack information if successful
+    /// 3. Authenticates request and estimates its compute units
+    /// 4. Returns the estimated request if successful
     ///
     /// # Security Considerations
     ///
     /// * Ensures all requests are properly authenticated
     /// * Validates request body format before processing
-    /// * Locks compute units to prevent resource exhaustion
+    /// * Rejects the requests that do not fit in the context window of their model
     #[instrument(
         level = "info",
         skip_all,
         fields(endpoint = %endpoint),
         err
     )]
-    pub async fn handle_authenticate_and_lock_compute_units(
+    pub async fn handle_authenticate_and_estimate_compute_units(
         state: &ProxyState,
+        served_models: &ServedModels,
         headers: &HeaderMap,
         body_json: &Value,
         endpoint: &str,
-    ) -> Result<StackMetadata> {
+    ) -> Result<EstimatedRequest> {
         match endpoint {
             CHAT_COMPLETIONS_PATH => {
                 let request_model = RequestModelChatCompletions::new(body_json).map_err(|e| {
@@ -940,7 +1617,19 @@ pub mod auth {
                         endpoint: endpoint.to_string(),
                     }
                 })?;
-                authenticate_and_lock_compute_units(state, headers, request_model, endpoint).await
+                let image_tokens_policy = state
+                    .image_tokens
+                    .policy_for_model(&request_model.get_model())
+                    .clone();
+                let request_model = request_model.with_image_tokens_policy(image_tokens_policy);
+                authenticate_and_estimate_compute_units(
+                    state,
+                    served_models,
+                    headers,
+                    request_model,
+                    endpoint,
+                )
+                .await
             }
             EMBEDDINGS_PATH => {
                 let request_model = RequestModelEmbeddings::new(body_json).map_err(|e| {
@@ -949,7 +1638,14 @@ pub mod auth {
                         endpoint: endpoint.to_string(),
                     }
                 })?;
-                authenticate_and_lock_compute_units(state, headers, request_model, endpoint).await
+                authenticate_and_estimate_compute_units(
+                    state,
+                    served_models,
+                    headers,
+                    request_model,
+                    endpoint,
+                )
+                .await
             }
             IMAGE_GENERATIONS_PATH => {
                 let request_model = RequestModelImageGenerations::new(body_json).map_err(|e| {
@@ -960,7 +1656,49 @@ pub mod auth {
                         endpoint: endpoint.to_string(),
                     }
                 })?;
-                authenticate_and_lock_compute_units(state, headers, request_model, endpoint).await
+                authenticate_and_estimate_compute_units(
+                    state,
+                    served_models,
+                    headers,
+                    request_model,
+                    endpoint,
+                )
+                .await
+            }
+            AUDIO_TRANSCRIPTIONS_PATH => {
+                let request_model =
+                    RequestModelAudioTranscriptions::new(body_json).map_err(|e| {
+                        AtomaProxyError::RequestError {
+                            message: format!(
+                                "Failed to parse body as audio transcriptions request model: {e}"
+                            ),
+                            endpoint: endpoint.to_string(),
+                        }
+                    })?;
+                authenticate_and_estimate_compute_units(
+                    state,
+                    served_models,
+                    headers,
+                    request_model,
+                    endpoint,
+                )
+                .await
+            }
+            AUDIO_SPEECH_PATH => {
+                let request_model = RequestModelAudioSpeech::new(body_json).map_err(|e| {
+                    AtomaProxyError::RequestError {
+                        message: format!("Failed to parse body as audio speech request model: {e}"),
+                        endpoint: endpoint.to_string(),
+                    }
+                })?;
+                authenticate_and_estimate_compute_units(
+                    state,
+                    served_models,
+                    headers,
+                    request_model,
+                    endpoint,
+                )
+                .await
             }
             _ => {
                 return Err(AtomaProxyError::InternalError {
@@ -974,96 +1712,189 @@ pub mod auth {
         }
     }
 
-    /// Authenticates a request and attempts to lock compute units for model execution.
+    /// Authenticates a request, and estimates its compute units for model execution.
     ///
     /// This function performs several key operations in sequence:
-    /// 1. Authenticates the user using provided headers
-    /// 2. Estimates required compute units for the request
-    /// 3. Attempts to find and lock available compute units from existing stacks
+    /// 1. Authenticates the user using provided headers, for the model of the request
+    /// 2. Estimates required compute units for the request, with the tokenizer of its model
+    /// 3. Fits the request in the context window of its model
     ///
     /// # Arguments
     ///
     /// * `state` - Server state containing authentication and resource management components
+    /// * `served_models` - The models served by the proxy, read once for the request
     /// * `headers` - HTTP request headers containing authentication information
     /// * `request_model` - The parsed request model implementing the `RequestModel` trait
     /// * `endpoint` - The API endpoint path being accessed
     ///
     /// # Returns
     ///
-    /// Returns a `Result<StackMetadata>` where:
-    /// * `Ok(StackMetadata)` - Authentication succeeded and compute units were successfully locked
-    /// * `Err(AtomaProxyError)` - Authentication or compute unit locking failed
+    /// Returns the authenticated request, with its estimated compute units.
     ///
     /// # Errors
     ///
     /// Returns `AtomaProxyError` in the following cases:
-    /// * Authentication failure
+    /// * Authentication failure, or the API token is not allowed to use the model
     /// * Failed to estimate compute units
-    /// * Failed to communicate with state manager
-    /// * Failed to lock compute units
+    /// * The request does not fit in the context window of the model
+    #[instrument(
+        level = "info",
+        skip_all,
+        fields(endpoint = %endpoint),
+        err
+    )]
+    pub async fn authenticate_and_estimate_compute_units(
+        state: &ProxyState,
+        served_models: &ServedModels,
+        headers: &HeaderMap,
+        request_model: impl RequestModel + Send + 'static,
+        endpoint: &str,
+    ) -> Result<EstimatedRequest> {
+        // Retrieve the model and the appropriate tokenizer
+        let model = request_model.get_model();
+        let api_token = check_auth(state, headers, endpoint, Some(&model)).await?;
+        let user_id = api_token.user_id;
+        let compute_units_estimate = if [
+            IMAGE_GENERATIONS_PATH,
+            CONFIDENTIAL_IMAGE_GENERATIONS_PATH,
+            AUDIO_TRANSCRIPTIONS_PATH,
+            AUDIO_SPEECH_PATH,
+        ]
+        .contains(&endpoint)
+        {
+            request_model.get_compute_units_estimate(None)?
+        } else {
+            if !served_models.serves(&model) {
+                return Err(AtomaProxyError::RequestError {
+                    message: "Model not supported".to_string(),
+                    endpoint: CHAT_COMPLETIONS_PATH.to_string(),
+                });
+            }
+            // NOTE: Tokenizers are loaded lazily, requests are rejected until the model's tokenizer is loaded
+            let tokenizer = served_models.tokenizer(&model);
+            // NOTE: Tokenizing long prompts is CPU bound, so it runs on the blocking threads, not to stall the async runtime
+            let estimated_request_model = request_model.clone();
+            tokio::task::spawn_blocking(move || {
+                estimated_request_model.get_compute_units_estimate(tokenizer.as_deref())
+            })
+            .await
+            .map_err(|e| AtomaProxyError::InternalError {
+                message: format!("Failed to estimate compute units: {e}"),
+                client_message: None,
+                endpoint: endpoint.to_string(),
+            })??
+        };
+
+        // Rejects the requests that do not fit in the context window of the model, before any compute units are locked for them
+        let (
+            ComputeUnitsEstimate {
+                num_input_compute_units,
+                max_total_compute_units,
+            },
+            max_completion_tokens,
+        ) = match state.model_limits.limits_for_model(&model) {
+            Some(limits) => compute_units_estimate.fit_context_window(
+                limits,
+                request_model.get_max_completion_tokens(),
+                state.model_limits.clamp_max_completion_tokens,
+                endpoint,
+            )?,
+            None => (compute_units_estimate, None),
+        };
+
+        Ok(EstimatedRequest {
+            model,
+            user_id,
+            api_token_id: api_token.id,
+            num_input_compute_units,
+            max_total_compute_units,
+            max_completion_tokens,
+        })
+    }
+
+    /// Attempts to lock compute units for an authenticated request.
     ///
-    /// # Example
+    /// This function performs several key operations in sequence:
+    /// 1. Charges the request to the rate limits of its user, if requested
+    /// 2. Looks up the response of the request in the response cache
+    /// 3. Attempts to find and lock available compute units from existing stacks, on a cache miss
     ///
-    /// ```rust,no_run
-    /// use axum::http::HeaderMap;
+    /// # Arguments
     ///
-    /// async fn process_request(state: &ProxyState, headers: &HeaderMap) -> Result<()> {
-    ///     let request_model = ChatCompletionsModel::new(&body)?;
-    ///     let result = authenticate_and_lock_compute_units(
-    ///         state,
-    ///         headers,
-    ///         request_model,
-    ///         "/v1/chat/completions"
-    ///     ).await?;
+    /// * `state` - Server state containing authentication and resource management components
+    /// * `request` - The authenticated request, with its estimated compute units
+    /// * `payload` - The body of the request, to look up its response in the response cache
+    /// * `endpoint` - The API endpoint path being accessed
+    /// * `check_rate_limits` - Whether to charge the request to the rate limits of its user,
+    ///   before any compute units are locked
     ///
-    ///     match result {
-    ///         Some(stack) => println!("Compute units locked on stack {}", stack.id),
-    ///         None => println!("No suitable stack found"),
-    ///     }
-    ///     Ok(())
-    /// }
-    /// ```
+    /// # Returns
+    ///
+    /// Returns a `Result<StackMetadata>` where:
+    /// * `Ok(StackMetadata)` - Compute units were successfully locked, or the response of
+    ///   the request is cached, in which case no compute units were locked
+    /// * `Err(AtomaProxyError)` - Compute unit locking failed
+    ///
+    /// # Errors
+    ///
+    /// Returns `AtomaProxyError` in the following cases:
+    /// * Rate limits exceeded
+    /// * Failed to communicate with state manager
+    /// * Failed to lock compute units
     ///
     /// # Implementation Notes
     ///
-    /// * The function is instrumented with tracing at the info level
     /// * Non-confidential compute is assumed (is_confidential is hardcoded to false)
-    /// * Compute units are estimated based on the specific request model implementation
     #[instrument(
         level = "info",
         skip_all,
         fields(endpoint = %endpoint),
         err
     )]
-    pub async fn authenticate_and_lock_compute_units(
+    pub async fn lock_compute_units(
         state: &ProxyState,
-        headers: &HeaderMap,
-        request_model: impl RequestModel + Send,
+        request: EstimatedRequest,
+        payload: &Value,
         endpoint: &str,
+        check_rate_limits: bool,
     ) -> Result<StackMetadata> {
-        let user_id = check_auth(&state.state_manager_sender, headers, endpoint).await?;
-
-        // Retrieve the model and the appropriate tokenizer
-        let model = request_model.get_model();
-        let ComputeUnitsEstimate {
+        let EstimatedRequest {
+            model,
+            user_id,
+            api_token_id,
             num_input_compute_units,
             max_total_compute_units,
-        } = if [IMAGE_GENERATIONS_PATH, CONFIDENTIAL_IMAGE_GENERATIONS_PATH].contains(&endpoint) {
-            request_model.get_compute_units_estimate(None)?
+            max_completion_tokens,
+        } = request;
+        // Rejects the requests that exceed the rate limits, before any compute units are locked or any stack is bought for them
+        let rate_limit_status = if check_rate_limits {
+            super::check_rate_limits(
+                state,
+                user_id,
+                api_token_id,
+                max_total_compute_units,
+                endpoint,
+            )?
         } else {
-            let tokenizer_index =
-                state
-                    .models
-                    .iter()
-                    .position(|m| m == &model)
-                    .ok_or_else(|| AtomaProxyError::RequestError {
-                        message: "Model not supported".to_string(),
-                        endpoint: CHAT_COMPLETIONS_PATH.to_string(),
-                    })?;
-            let tokenizer = state.tokenizers[tokenizer_index].clone();
-            request_model.get_compute_units_estimate(Some(&tokenizer))?
+            None
         };
 
+        // Cached responses are served without locking any compute units, nor buying any stack, for them
+        let cache_lookup = lookup_response_cache(state, user_id, &model, endpoint, payload).await;
+        if matches!(cache_lookup, CacheLookup::Hit(_)) {
+            return Ok(StackMetadata {
+                optional_stack: None,
+                num_input_compute_units,
+                max_total_compute_units,
+                max_completion_tokens,
+                model,
+                user_id,
+                api_token_id,
+                rate_limit_status,
+                cache_lookup,
+            });
+        }
+
         let (result_sender, result_receiver) = oneshot::channel();
 
         state
@@ -1073,6 +1904,7 @@ pub mod auth {
                 free_compute_units: max_total_compute_units as i64,
                 user_id,
                 is_confidential: false, // NOTE: This method is only used for non-confidential compute
+                excluded_node_small_ids: state.node_health.ejected_node_small_ids(),
                 result_sender,
             })
             .map_err(|err| AtomaProxyError::InternalError {
@@ -1098,8 +1930,12 @@ pub mod auth {
             optional_stack,
             num_input_compute_units,
             max_total_compute_units,
+            max_completion_tokens,
             model,
             user_id,
+            api_token_id,
+            rate_limit_status,
+            cache_lookup,
         })
     }
 
@@ -1283,9 +2119,14 @@ pub mod auth {
     /// The new tokio task also captures the lock_guard, so it will be released when the task finishes,
     /// and the new stack acquisition operation has been completed.
     ///
+    /// With the `Stack` billing mode, the user is charged for the whole stack. With the `Metered`
+    /// billing mode, the user is only charged for the `total_tokens` locked for the current request,
+    /// and the charge is settled to the actual usage once the request completes.
+    ///
     /// #Arguments
     ///
     /// * `node` - The cheapest node to acquire a stack for
+    /// * `billing_mode` - How the user is charged for the new stack
     ///
     /// #Returns
     ///
@@ -1293,6 +2134,7 @@ pub mod auth {
     /// * `stack_small_id` - The identifier for the selected/created stack
     /// * `selected_node_id` - The identifier for the node that will process the request
     #[instrument(level = "info", skip_all, err)]
+    #[allow(clippy::too_many_arguments)]
     pub async fn acquire_new_stack(
         state_manager_sender: Sender<AtomaAtomaStateManagerEvent>,
         user_id: i64,
@@ -1301,6 +2143,7 @@ pub mod auth {
         total_tokens: u64,
         sui: Arc<RwLock<Sui>>,
         node: atoma_state::types::CheapestNode,
+        billing_mode: BillingMode,
     ) -> Result<SelectedNodeMetadata> {
         tracing::info!(
             "Attempting to acquire new stack for user {} with task small id {} and max compute units {}",
@@ -1320,12 +2163,19 @@ pub mod auth {
             // and preventing race conditions where multiple requests might try to acquire
             // a stack concurrently after premature lock release.
             let _moved_lock_guard = lock_guard;
+            let price_per_one_million_compute_units =
+                node.price_per_one_million_compute_units as u64;
+            let is_metered = billing_mode == BillingMode::Metered;
+            let usdc_amount = if is_metered {
+                (price_per_one_million_compute_units * total_tokens).div_ceil(ONE_MILLION)
+            } else {
+                price_per_one_million_compute_units * STACK_SIZE_TO_BUY as u64 / ONE_MILLION
+            } as i64;
             // 1. Deduct USDC from the user's balance. This will fail if the balance is not enough.
             deduct_usdc(
                 state_manager_sender.clone(),
                 user_id,
-                node.price_per_one_million_compute_units as u64,
-                STACK_SIZE_TO_BUY as u64,
+                usdc_amount,
                 endpoint_clone.clone(),
             )
             .await?;
@@ -1337,7 +2187,9 @@ pub mod auth {
                 user_id,
                 task_small_id: node.task_small_id as u64,
                 stack_size_to_buy: STACK_SIZE_TO_BUY as u64,
-                price_per_million_compute_units: node.price_per_one_million_compute_units as u64,
+                price_per_million_compute_units: price_per_one_million_compute_units,
+                usdc_amount,
+                is_metered,
                 endpoint: endpoint_clone.clone(),
                 total_tokens,
             })
@@ -1365,6 +2217,10 @@ pub mod auth {
         stack_size_to_buy: u64,
         /// The price per million compute units for the stack.
         price_per_million_compute_units: u64,
+        /// The amount of USDC deducted from the user's balance, refunded if the stack is not acquired.
+        usdc_amount: i64,
+        /// Whether the user is charged by actual usage instead of for the whole stack.
+        is_metered: bool,
         /// The endpoint of the request.
         endpoint: String,
         /// The total number of tokens for the request.
@@ -1397,20 +2253,12 @@ pub mod auth {
         let endpoint = args.endpoint.clone();
         let user_id = args.user_id;
         let state_manager_sender = args.state_manager_sender.clone();
-        let price_per_million_compute_units = args.price_per_million_compute_units;
+        let usdc_amount = args.usdc_amount;
         match acquire_new_stack_on_usdc_deduction(args).await {
             Ok(result) => Ok(result),
             Err(e) => {
                 tracing::error!("Failed to acquire new stack: {e}");
-                match refund_usdc(
-                    state_manager_sender,
-                    user_id,
-                    price_per_million_compute_units,
-                    STACK_SIZE_TO_BUY as u64,
-                    endpoint,
-                )
-                .await
-                {
+                match refund_usdc(state_manager_sender, user_id, usdc_amount, endpoint).await {
                     Ok(()) => (),
                     Err(e) => {
                         tracing::error!("Failed to refund USDC: {e}");
@@ -1450,6 +2298,8 @@ pub mod auth {
             task_small_id,
             stack_size_to_buy,
             price_per_million_compute_units,
+            usdc_amount: _,
+            is_metered,
             endpoint,
             total_tokens,
         } = args;
@@ -1484,6 +2334,7 @@ pub mod auth {
                 locked_compute_units: total_tokens as i64,
                 transaction_timestamp: timestamp_to_datetime_or_now(timestamp_ms),
                 user_id,
+                is_metered,
                 result_sender,
             })
             .map_err(|err| AtomaProxyError::InternalError {
@@ -1519,8 +2370,6 @@ pub mod auth {
     /// * `state_manager_sender` - The sender for the state manager event.
     /// * `user_id` - The user ID of the request.
     /// * `amount` - The amount to deduct.
-    /// * `price_per_one_million_compute_units` - The price per one million compute units for the stack.
-    /// * `stack_size_to_buy` - The size of the stack to buy.
     /// * `endpoint` - The endpoint of the request.
     ///
     /// # Returns
@@ -1538,16 +2387,14 @@ pub mod auth {
     async fn deduct_usdc(
         state_manager_sender: Sender<AtomaAtomaStateManagerEvent>,
         user_id: UserId,
-        price_per_one_million_compute_units: u64,
-        stack_size_to_buy: u64,
+        amount: i64,
         endpoint: String,
     ) -> Result<()> {
         let (result_sender, result_receiver) = oneshot::channel();
         state_manager_sender
             .send(AtomaAtomaStateManagerEvent::DeductFromUsdc {
                 user_id,
-                amount: (price_per_one_million_compute_units * stack_size_to_buy / ONE_MILLION)
-                    as i64,
+                amount,
                 result_sender,
             })
             .map_err(|err| AtomaProxyError::InternalError {
@@ -1595,16 +2442,14 @@ pub mod auth {
     async fn refund_usdc(
         state_manager_sender: Sender<AtomaAtomaStateManagerEvent>,
         user_id: UserId,
-        price_per_one_million_compute_units: u64,
-        stack_size_to_buy: u64,
+        amount: i64,
         endpoint: String,
     ) -> Result<()> {
         let (result_sender, result_receiver) = oneshot::channel();
         state_manager_sender
             .send(AtomaAtomaStateManagerEvent::RefundUsdc {
                 user_id,
-                amount: (price_per_one_million_compute_units * stack_size_to_buy / ONE_MILLION)
-                    as i64,
+                amount,
                 result_sender,
             })
             .map_err(|err| AtomaProxyError::InternalError {
@@ -1652,6 +2497,8 @@ pub mod auth {
     /// * `CHAT_COMPLETIONS_PATH` - Handles chat completion requests
     /// * `EMBEDDINGS_PATH` - Handles embedding generation requests
     /// * `IMAGE_GENERATIONS_PATH` - Handles image generation requests
+    /// * `AUDIO_TRANSCRIPTIONS_PATH` - Handles audio transcription requests
+    /// * `AUDIO_SPEECH_PATH` - Handles speech generation requests
     ///
     /// # Implementation Details
     /// * Matches on the endpoint type to determine the appropriate request model
@@ -1666,7 +2513,11 @@ pub mod auth {
         total_tokens: u64,
     ) -> Result<SelectedNodeMetadata> {
         match endpoint {
-            CHAT_COMPLETIONS_PATH | EMBEDDINGS_PATH | IMAGE_GENERATIONS_PATH => {
+            CHAT_COMPLETIONS_PATH
+            | EMBEDDINGS_PATH
+            | IMAGE_GENERATIONS_PATH
+            | AUDIO_TRANSCRIPTIONS_PATH
+            | AUDIO_SPEECH_PATH => {
                 get_stack_if_locked_with_request_model(
                     state,
                     user_id,
@@ -1833,16 +2684,15 @@ pub mod auth {
                     endpoint: endpoint.to_string(),
                 });
         }
-        get_cheapest_node_and_acquire_new_stack(state, user_id, model, endpoint, total_tokens).await
+        select_node_and_acquire_new_stack(state, user_id, model, endpoint, total_tokens, &[]).await
     }
 
-    /// Gets the cheapest node for a model and acquires a new stack for the request.
+    /// Selects a node for a model, following the configured node selection policy, and acquires a new stack for the request.
     ///
     /// This function follows a two-step process:
-    /// 1. First, it attempts to find existing stacks that can handle the requested model and compute units
-    /// 2. If no suitable stacks exist, it acquires a new stack entry by:
-    ///    - Finding available tasks for the model
-    ///    - Creating a new stack entry with predefined compute units and price
+    /// 1. First, it selects the node on which to buy the stack, through `select_node_for_model`
+    /// 2. Then, it acquires a new stack entry by:
+    ///    - Creating a new stack entry with predefined compute units and the node's price
     ///    - Registering the new stack with the state manager
     ///
     /// # Arguments
@@ -1851,6 +2701,7 @@ pub mod auth {
     /// * `endpoint` - The API endpoint being accessed
     /// * `user_id` - The ID of the user making the request
     /// * `total_tokens` - The total number of compute units (tokens) needed for the request
+    /// * `excluded_node_small_ids` - Nodes on which no stack should be bought (e.g. nodes that just failed the request)
     ///
     /// # Returns
     ///
@@ -1863,6 +2714,7 @@ pub mod auth {
     ///
     /// Returns a `AtomaProxyError` error in the following cases:
     /// * `INTERNAL_SERVER_ERROR` - Communication errors with state manager or Sui interface
+    /// * `BAD_REQUEST` - No node is available for the requested model
     #[instrument(
         level = "info",
         skip_all,
@@ -1874,47 +2726,15 @@ pub mod auth {
         ),
         err
     )]
-    pub async fn get_cheapest_node_and_acquire_new_stack(
+    pub async fn select_node_and_acquire_new_stack(
         state: &ProxyState,
         user_id: i64,
         model: &str,
         endpoint: &str,
         total_tokens: u64,
+        excluded_node_small_ids: &[i64],
     ) -> Result<SelectedNodeMetadata> {
-        let (result_sender, result_receiver) = oneshot::channel();
-        state
-            .state_manager_sender
-            .send(AtomaAtomaStateManagerEvent::GetCheapestNodeForModel {
-                model: model.to_string(),
-                is_confidential: false,
-                result_sender,
-            })
-            .map_err(|err| AtomaProxyError::InternalError {
-                message: format!("Failed to send GetTasksForModel event: {err:?}"),
-                client_message: None,
-                endpoint: endpoint.to_string(),
-            })?;
-        let node = result_receiver
-            .await
-            .map_err(|err| AtomaProxyError::InternalError {
-                message: format!("Failed to receive GetTasksForModel result: {err:?}"),
-                client_message: None,
-                endpoint: endpoint.to_string(),
-            })?
-            .map_err(|err| AtomaProxyError::InternalError {
-                message: format!("Failed to get retrieve `CheapestNode` from the state manager with result: {err:?}"),
-                client_message: None,
-                endpoint: endpoint.to_string(),
-            })?;
-        let node: atoma_state::types::CheapestNode = match node {
-            Some(node) => node,
-            None => {
-                return Err(AtomaProxyError::RequestError {
-                    message: format!("No node found for model {model}"),
-                    endpoint: endpoint.to_string(),
-                });
-            }
-        };
+        let node = select_node_for_model(state, model, endpoint, excluded_node_small_ids).await?;
         tracing::info!(
             "Attempting to acquire lock guard to buy a new stack for user {} with model {} and max compute units {}",
             user_id,
@@ -1940,6 +2760,7 @@ pub mod auth {
             total_tokens,
             Arc::clone(&state.sui),
             node,
+            state.billing_mode,
         )
         .await
         // NOTE: The `acquire_new_stack` method will emit a stack creation event, and it will stored it
@@ -1949,6 +2770,206 @@ pub mod auth {
         // even if the `acquire_new_stack` returned an error, previously, as this is handled at drop time.
     }
 
+    /// Selects the node on which a new stack should be bought, following the configured node selection policy.
+    ///
+    /// With the `BestPerformance` strategy, the metrics collection task is asked for the latest ranking of the
+    /// best available nodes for the model. If the ranking is fresh, the best ranked node with a valid subscription
+    /// within the configured price ceiling is selected. Otherwise (or with the `Cheapest` strategy), the node with
+    /// the cheapest price per one million compute units is selected.
+    ///
+    /// # Arguments
+    ///
+    /// * `state` - The state of the proxy
+    /// * `model` - The name/identifier of the AI model being requested
+    /// * `endpoint` - The API endpoint being accessed
+    /// * `excluded_node_small_ids` - Nodes that must not be selected, in addition to the nodes ejected by the node health registry
+    ///
+    /// # Returns
+    ///
+    /// Returns the `CheapestNode` settings (task, price and node) of the selected node subscription.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `AtomaProxyError` error in the following cases:
+    /// * `INTERNAL_SERVER_ERROR` - Communication errors with the state manager
+    /// * `BAD_REQUEST` - No node is available for the requested model
+    #[instrument(level = "info", skip_all, fields(model = %model, strategy = ?state.node_selection.strategy), err)]
+    async fn select_node_for_model(
+        state: &ProxyState,
+        model: &str,
+        endpoint: &str,
+        excluded_node_small_ids: &[i64],
+    ) -> Result<atoma_state::types::CheapestNode> {
+        let excluded_node_small_ids = state
+            .node_health
+            .excluded_node_small_ids(excluded_node_small_ids);
+        if state.node_selection.strategy == NodeSelectionStrategy::BestPerformance {
+            if let Some(ranked_node_small_ids) = get_best_available_nodes(state, model)
+                .await
+                .map(|node_small_ids| {
+                    node_small_ids
+                        .into_iter()
+                        .filter(|node_small_id| !excluded_node_small_ids.contains(node_small_id))
+                        .collect::<Vec<_>>()
+                })
+                .filter(|node_small_ids| !node_small_ids.is_empty())
+            {
+                if let Some(node) =
+                    get_best_ranked_node(state, model, ranked_node_small_ids, endpoint).await?
+                {
+                    tracing::info!(
+                        target = "atoma-service",
+                        node_small_id = node.node_small_id,
+                        "Selected best ranked node for model {model}"
+                    );
+                    return Ok(node);
+                }
+            }
+            tracing::info!(
+                target = "atoma-service",
+                "No fresh ranked node available for model {model}, falling back to the cheapest node"
+            );
+        }
+        let (result_sender, result_receiver) = oneshot::channel();
+        state
+            .state_manager_sender
+            .send(AtomaAtomaStateManagerEvent::GetCheapestNodeForModel {
+                model: model.to_string(),
+                is_confidential: false,
+                excluded_node_small_ids,
+                result_sender,
+            })
+            .map_err(|err| AtomaProxyError::InternalError {
+                message: format!("Failed to send GetCheapestNodeForModel event: {err:?}"),
+                client_message: None,
+                endpoint: endpoint.to_string(),
+            })?;
+        result_receiver
+            .await
+            .map_err(|err| AtomaProxyError::InternalError {
+                message: format!("Failed to receive GetCheapestNodeForModel result: {err:?}"),
+                client_message: None,
+                endpoint: endpoint.to_string(),
+            })?
+            .map_err(|err| AtomaProxyError::InternalError {
+                message: format!("Failed to get retrieve `CheapestNode` from the state manager with result: {err:?}"),
+                client_message: None,
+                endpoint: endpoint.to_string(),
+            })?
+            .ok_or_else(|| AtomaProxyError::NoAvailableNode {
+                message: format!("No node found for model {model}"),
+                endpoint: endpoint.to_string(),
+            })
+    }
+
+    /// Requests the latest ranking of the best available nodes for a model from the metrics collection task.
+    ///
+    /// # Arguments
+    ///
+    /// * `state` - The state of the proxy
+    /// * `model` - The name/identifier of the AI model being requested
+    ///
+    /// # Returns
+    ///
+    /// Returns the ranked node small ids (best node first), or `None` if the metrics collection task
+    /// did not answer in time, has no ranking for the model, or its ranking is older than the configured
+    /// maximum metrics age. Failures are logged and never returned, as the caller falls back to the cheapest node.
+    #[instrument(level = "debug", skip_all, fields(model = %model))]
+    async fn get_best_available_nodes(state: &ProxyState, model: &str) -> Option<Vec<i64>> {
+        let (result_sender, result_receiver) = oneshot::channel();
+        if let Err(e) = state
+            .request_best_available_nodes_sender
+            .send((model.to_string(), result_sender))
+        {
+            tracing::warn!(
+                target = "atoma-service",
+                error = %e,
+                "Failed to request best available nodes from the metrics collection task"
+            );
+            return None;
+        }
+        let best_available_nodes =
+            match tokio::time::timeout(BEST_AVAILABLE_NODES_TIMEOUT, result_receiver).await {
+                Ok(Ok(best_available_nodes)) => best_available_nodes?,
+                Ok(Err(e)) => {
+                    tracing::warn!(
+                        target = "atoma-service",
+                        error = %e,
+                        "Failed to receive best available nodes from the metrics collection task"
+                    );
+                    return None;
+                }
+                Err(_) => {
+                    tracing::warn!(
+                    target = "atoma-service",
+                    "Timed out waiting for best available nodes from the metrics collection task"
+                );
+                    return None;
+                }
+            };
+        let max_metrics_age = Duration::from_secs(state.node_selection.max_metrics_age_secs);
+        if best_available_nodes.collected_at.elapsed() > max_metrics_age
+            || best_available_nodes.node_small_ids.is_empty()
+        {
+            return None;
+        }
+        Some(best_available_nodes.node_small_ids)
+    }
+
+    /// Gets the best ranked node for a model, among the ranked node small ids, within the configured price ceiling.
+    ///
+    /// # Arguments
+    ///
+    /// * `state` - The state of the proxy
+    /// * `model` - The name/identifier of the AI model being requested
+    /// * `ranked_node_small_ids` - The candidate node small ids, sorted by preference (best node first)
+    /// * `endpoint` - The API endpoint being accessed
+    ///
+    /// # Returns
+    ///
+    /// Returns the best ranked node, or `None` if none of the ranked nodes has a valid subscription within the price ceiling.
+    ///
+    /// # Errors
+    ///
+    /// * `AtomaProxyError::InternalError` - Failed to send or receive message to the state manager
+    #[instrument(level = "debug", skip_all, fields(model = %model, ?ranked_node_small_ids), err)]
+    async fn get_best_ranked_node(
+        state: &ProxyState,
+        model: &str,
+        ranked_node_small_ids: Vec<i64>,
+        endpoint: &str,
+    ) -> Result<Option<atoma_state::types::CheapestNode>> {
+        let (result_sender, result_receiver) = oneshot::channel();
+        state
+            .state_manager_sender
+            .send(AtomaAtomaStateManagerEvent::GetBestRankedNodeForModel {
+                model: model.to_string(),
+                ranked_node_small_ids,
+                max_price_per_one_million_compute_units: state
+                    .node_selection
+                    .max_price_per_one_million_compute_units,
+                is_confidential: false,
+                result_sender,
+            })
+            .map_err(|err| AtomaProxyError::InternalError {
+                message: format!("Failed to send GetBestRankedNodeForModel event: {err:?}"),
+                client_message: None,
+                endpoint: endpoint.to_string(),
+            })?;
+        result_receiver
+            .await
+            .map_err(|err| AtomaProxyError::InternalError {
+                message: format!("Failed to receive GetBestRankedNodeForModel result: {err:?}"),
+                client_message: None,
+                endpoint: endpoint.to_string(),
+            })?
+            .map_err(|err| AtomaProxyError::InternalError {
+                message: format!("Failed to get GetBestRankedNodeForModel result: {err:?}"),
+                client_message: None,
+                endpoint: endpoint.to_string(),
+            })
+    }
+
     /// Gets a stack from the state manager for a given model and user ID.
     ///
     /// This function sends a request to the state manager to retrieve a stack that can handle the
@@ -1961,6 +2982,7 @@ pub mod auth {
     /// * `free_compute_units` - The number of free compute units (tokens) needed for the request
     /// * `is_confidential` - Whether the request is confidential
     /// * `endpoint` - The API endpoint being accessed
+    /// * `excluded_node_small_ids` - Nodes whose stacks must not be selected, in addition to the nodes ejected by the node health registry
     ///
     /// # Returns
     /// * `Result<Option<SelectedNodeMetadata>>` - The stack if found, otherwise None   
@@ -1980,6 +3002,7 @@ pub mod auth {
         free_compute_units: i64,
         is_confidential: bool,
         endpoint: &str,
+        excluded_node_small_ids: &[i64],
     ) -> Result<Option<SelectedNodeMetadata>> {
         let (result_sender, result_receiver) = oneshot::channel();
         state
@@ -1989,6 +3012,9 @@ pub mod auth {
                 user_id,
                 free_compute_units,
                 is_confidential,
+                excluded_node_small_ids: state
+                    .node_health
+                    .excluded_node_small_ids(excluded_node_small_ids),
                 result_sender,
             })
             .map_err(|err| AtomaProxyError::InternalError {
@@ -2056,6 +3082,7 @@ pub mod utils {
     /// * `total_compute_units` - Total compute units required for this request
     /// * `tx_digest` - Optional transaction digest if a new stack was created
     /// * `user_id` - ID of the user making the request
+    /// * `api_token_id` - ID of the API token that authenticated the request
     /// * `endpoint` - API endpoint path being accessed
     ///
     /// # Returns
@@ -2091,6 +3118,7 @@ pub mod utils {
     ///     compute_units,
     ///     Some(tx_digest),
     ///     user_id,
+    ///     api_token_id,
     ///     "/v1/chat/completions"
     /// ).await?;
     /// ```
@@ -2119,6 +3147,7 @@ pub mod utils {
         total_compute_units: u64,
         tx_digest: Option<TransactionDigest>,
         user_id: i64,
+        api_token_id: i64,
         endpoint: &str,
     ) -> Result<Request<Body>> {
         let ProcessedRequest {
@@ -2180,9 +3209,11 @@ pub mod utils {
             num_input_tokens: Some(num_input_tokens),
             max_total_num_compute_units: total_compute_units,
             user_id,
+            api_token_id,
             selected_stack_small_id,
             endpoint: endpoint.to_string(),
             model_name: request_model.to_string(),
+            cache_key: None,
         });
 
         // update headers
@@ -2299,6 +3330,7 @@ pub mod utils {
                     stack_small_id,
                     lock.max_num_tokens,
                     0,
+                    0,
                     endpoint,
                 )?;
                 details.pop();
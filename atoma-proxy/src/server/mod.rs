@@ -3,21 +3,41 @@
 #![allow(clippy::cast_sign_loss)]
 #![allow(clippy::cognitive_complexity)]
 
+pub mod batch_worker;
 pub mod components;
 mod config;
 pub mod error;
 pub mod handlers;
 pub mod http_server;
+pub mod image_tokens;
+pub mod loopback;
 pub mod middleware;
+pub mod model_registry;
+pub mod model_router;
+pub mod multipart;
+pub mod node_health;
+pub mod open_router;
+pub mod rate_limiter;
+pub mod response_cache;
+pub mod stream_replay;
 pub mod streamer;
+pub mod tokenizer;
 pub mod types;
 
-use atoma_state::types::AtomaAtomaStateManagerEvent;
+use atoma_state::types::{ApiToken, AtomaAtomaStateManagerEvent};
 use axum::http::HeaderMap;
-pub use config::AtomaServiceConfig;
+pub use config::{
+    AtomaServiceConfig, BatchConfig, BillingMode, CacheHitBilling, ImageFormat, ImageTokensConfig,
+    ImageTokensFormula, ImageTokensPolicy, ModelLimits, ModelLimitsConfig, ModelRoutingConfig,
+    NodeHealthConfig, NodeSelectionConfig, NodeSelectionStrategy, RateLimit, RateLimitConfig,
+    RateLimitTier, ResponseCacheConfig, ResumableStreamsConfig, TokenizersConfig,
+};
 use error::AtomaProxyError;
-use flume::Sender;
+use handlers::{models::MODELS_PATH, nodes::NODES_CREATE_LOCK_PATH};
 pub use http_server::start_server;
+use http_server::ProxyState;
+use middleware::utils::is_confidential_compute_endpoint;
+use sqlx::types::chrono::Utc;
 use tokio::sync::oneshot;
 use tracing::instrument;
 
@@ -40,52 +60,72 @@ pub const MODEL: &str = "model";
 
 /// Checks the authentication of the request.
 ///
-/// This function checks the authentication of the request by comparing the
-/// provided Bearer token from the `Authorization` header against the stored tokens.
+/// This function checks the authentication of the request by comparing the keyed
+/// hash of the provided Bearer token from the `Authorization` header against the
+/// stored token hashes. The expiration, scopes and spending limit of the token are
+/// then enforced for the requested endpoint and model.
 ///
 /// # Arguments
 ///
-/// * `state_manager_sender`: The sender for the state manager channel.
+/// * `state`: The proxy state, holding the state manager channel and the token hasher.
 /// * `headers`: The headers of the request.
+/// * `endpoint`: The endpoint being requested.
+/// * `model`: The model being requested, if the endpoint is model specific.
 ///
 /// # Returns
 ///
-/// Returns `true` if the authentication is successful, `false` otherwise.
+/// Returns the `ApiToken` used to authenticate the request, including the id of its owner.
 ///
 /// # Errors
 ///
-/// Returns a `AtomaProxyError` error if there is an internal server error.
+/// Returns a `AtomaProxyError` error if:
+/// - The token is missing, invalid or expired (`AuthError`).
+/// - The token is not allowed to be used for the endpoint or model, or its spending limit was reached (`Forbidden`).
+/// - There is an internal server error.
 ///
 /// # Example
 ///
 /// ```rust,ignore
-/// let is_authenticated = check_auth(
-///     &state_manager_sender,
-///     &headers
+/// let api_token = check_auth(
+///     &state,
+///     &headers,
+///     CHAT_COMPLETIONS_PATH,
+///     Some("meta-llama/Llama-3.3-70B-Instruct"),
 /// ).await?;
-/// println!("Token is : {}", is_authenticated?"Valid":"Invalid");
+/// println!("Request authenticated for user {}", api_token.user_id);
 /// ```
 #[instrument(level = "info", skip_all)]
 async fn check_auth(
-    state_manager_sender: &Sender<AtomaAtomaStateManagerEvent>,
+    state: &ProxyState,
     headers: &HeaderMap,
     endpoint: &str,
-) -> Result<i64> {
+    model: Option<&str>,
+) -> Result<ApiToken> {
     if let Some(auth) = headers.get("Authorization") {
         if let Ok(auth) = auth.to_str() {
             if let Some(token) = auth.strip_prefix("Bearer ") {
+                // NOTE: Requests forwarded by the proxy to itself (e.g. the requests of batches) are sent on
+                // behalf of the API token of the client
                 let (sender, receiver) = oneshot::channel();
-                state_manager_sender
-                    .send(AtomaAtomaStateManagerEvent::IsApiTokenValid {
-                        api_token: token.to_string(),
+                let loopback_api_token_id = loopback::loopback_api_token_id(state, headers, token);
+                let event = match loopback_api_token_id {
+                    Some(api_token_id) => AtomaAtomaStateManagerEvent::GetApiTokenById {
+                        api_token_id,
                         result_sender: sender,
-                    })
-                    .map_err(|err| AtomaProxyError::InternalError {
+                    },
+                    None => AtomaAtomaStateManagerEvent::IsApiTokenValid {
+                        api_token_hash: state.auth.hash_api_token(token),
+                        result_sender: sender,
+                    },
+                };
+                state.state_manager_sender.send(event).map_err(|err| {
+                    AtomaProxyError::InternalError {
                         message: format!("Failed to send IsApiTokenValid event: {err:?}"),
                         client_message: None,
                         endpoint: endpoint.to_string(),
-                    })?;
-                return receiver
+                    }
+                })?;
+                let api_token = receiver
                     .await
                     .map_err(|err| AtomaProxyError::InternalError {
                         message: format!("Failed to receive IsApiTokenValid result: {err:?}"),
@@ -95,7 +135,13 @@ async fn check_auth(
                     .map_err(|err| AtomaProxyError::AuthError {
                         auth_error: format!("Invalid or missing api token for request: {err:?}"),
                         endpoint: endpoint.to_string(),
-                    });
+                    })?;
+                // NOTE: Requests forwarded by the proxy to itself are scoped to the endpoint the client requested
+                let scope_endpoint = loopback_api_token_id
+                    .and_then(|_| loopback::loopback_scope_endpoint(headers))
+                    .unwrap_or(endpoint);
+                check_api_token_restrictions(&api_token, scope_endpoint, model)?;
+                return Ok(api_token);
             }
         }
     }
@@ -104,3 +150,62 @@ async fn check_auth(
         endpoint: endpoint.to_string(),
     })
 }
+
+/// Enforces the expiration, scopes and spending limit of an API token for a request.
+///
+/// Confidential-only tokens can still list the models and lock nodes, as both are
+/// needed by clients to prepare confidential requests.
+///
+/// # Errors
+///
+/// Returns `AuthError` if the token has expired, or `Forbidden` if the token is not
+/// allowed to be used for the endpoint or model, or if its spending limit was reached.
+fn check_api_token_restrictions(
+    api_token: &ApiToken,
+    endpoint: &str,
+    model: Option<&str>,
+) -> Result<()> {
+    if api_token
+        .expires_at
+        .is_some_and(|expires_at| expires_at <= Utc::now())
+    {
+        return Err(AtomaProxyError::AuthError {
+            auth_error: format!("Api token {} has expired", api_token.id),
+            endpoint: endpoint.to_string(),
+        });
+    }
+    let scopes = &api_token.scopes;
+    if !scopes.endpoints.is_empty() && !scopes.endpoints.iter().any(|e| e == endpoint) {
+        return Err(AtomaProxyError::Forbidden {
+            message: format!("Api token is not allowed to use endpoint {endpoint}"),
+            endpoint: endpoint.to_string(),
+        });
+    }
+    if scopes.confidential_only
+        && !is_confidential_compute_endpoint(endpoint)
+        && ![MODELS_PATH, NODES_CREATE_LOCK_PATH].contains(&endpoint)
+    {
+        return Err(AtomaProxyError::Forbidden {
+            message: "Api token is only allowed to use confidential compute endpoints".to_string(),
+            endpoint: endpoint.to_string(),
+        });
+    }
+    if let Some(model) = model {
+        if !scopes.models.is_empty() && !scopes.models.iter().any(|m| m == model) {
+            return Err(AtomaProxyError::Forbidden {
+                message: format!("Api token is not allowed to use model {model}"),
+                endpoint: endpoint.to_string(),
+            });
+        }
+    }
+    if api_token
+        .spending_limit
+        .is_some_and(|spending_limit| api_token.amount_spent >= spending_limit)
+    {
+        return Err(AtomaProxyError::Forbidden {
+            message: "Api token spending limit reached".to_string(),
+            endpoint: endpoint.to_string(),
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,366 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, PoisonError, RwLock},
+};
+
+use atoma_proxy_service::ModelModality;
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{watch, Mutex};
+use tracing::{error, info, instrument};
+use utoipa::ToSchema;
+
+use super::{
+    http_server::ProxyState,
+    model_router::ModelRouter,
+    tokenizer::{ModelTokenizer, TokenCounter, TokenizerLoader},
+    AtomaServiceConfig, ModelRoutingConfig,
+};
+
+/// The models served by the proxy, with their tokenizers, modalities and routing.
+///
+/// A snapshot is never modified: reloading the configuration builds a new snapshot,
+/// so that a request sees the same models from start to end. Tokenizers are the exception,
+/// as they are loaded lazily, after the snapshot is created.
+pub struct ServedModels {
+    /// The names of the models.
+    pub models: Vec<String>,
+    /// The revisions of the models, the tokenizers are loaded from.
+    pub revisions: Vec<String>,
+    /// The tokenizers of the models, in the same order as the models.
+    pub tokenizers: Vec<Arc<ModelTokenizer>>,
+    /// The loader of the tokenizers.
+    pub tokenizer_loader: Arc<TokenizerLoader>,
+    /// The modalities of the models, indexed by model.
+    pub modalities: HashMap<String, Vec<ModelModality>>,
+    /// The aliases and fallback models of the models.
+    pub model_router: ModelRouter,
+}
+
+impl ServedModels {
+    /// Creates a new snapshot of the served models, whose tokenizers are not loaded yet.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is not one revision per model, or if the model routing
+    /// configuration is invalid.
+    pub fn new(
+        models: Vec<String>,
+        revisions: Vec<String>,
+        modalities: Vec<Vec<ModelModality>>,
+        tokenizer_loader: Arc<TokenizerLoader>,
+        model_routing: ModelRoutingConfig,
+    ) -> anyhow::Result<Self> {
+        if revisions.len() != models.len() {
+            anyhow::bail!(
+                "Expected one revision per model, got {} revisions for {} models",
+                revisions.len(),
+                models.len()
+            );
+        }
+        let model_router = ModelRouter::new(model_routing, &models)?;
+        let tokenizers = models
+            .iter()
+            .zip(&revisions)
+            .map(|(model, revision)| {
+                Arc::new(ModelTokenizer::new(
+                    model.clone(),
+                    revision.clone(),
+                    Arc::clone(&tokenizer_loader),
+                ))
+            })
+            .collect();
+        let modalities = models.iter().cloned().zip(modalities).collect();
+        Ok(Self {
+            models,
+            revisions,
+            tokenizers,
+            tokenizer_loader,
+            modalities,
+            model_router,
+        })
+    }
+
+    /// Whether the model is served by the proxy.
+    #[must_use]
+    pub fn serves(&self, model: &str) -> bool {
+        self.models.iter().any(|m| m == model)
+    }
+
+    /// Returns the tokenizer of a model, if the model is served by the proxy and its
+    /// tokenizer is loaded.
+    #[must_use]
+    pub fn tokenizer(&self, model: &str) -> Option<Arc<dyn TokenCounter>> {
+        self.models
+            .iter()
+            .position(|m| m == model)
+            .and_then(|index| self.tokenizers[index].get())
+    }
+
+    /// Reuses the tokenizers already loaded by a previous snapshot, for the models served
+    /// with the same revision.
+    fn reuse_loaded_tokenizers(&mut self, previous: &Self) {
+        for (model, tokenizer) in self.models.iter().zip(self.tokenizers.iter_mut()) {
+            let reusable =
+                previous
+                    .models
+                    .iter()
+                    .zip(&previous.tokenizers)
+                    .find(|(m, previous_tokenizer)| {
+                        *m == model
+                            && previous_tokenizer.revision() == tokenizer.revision()
+                            && previous_tokenizer.is_loaded()
+                    });
+            if let Some((_, previous_tokenizer)) = reusable {
+                *tokenizer = Arc::clone(previous_tokenizer);
+            }
+        }
+    }
+}
+
+/// Outcome of a reload of the served models.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ReloadSummary {
+    /// The models served after the reload
+    pub models: Vec<String>,
+    /// The models that were not served before the reload
+    pub added_models: Vec<String>,
+    /// The models that are no longer served
+    pub removed_models: Vec<String>,
+    /// The models whose tokenizer was loaded by the reload, new models and models whose revision changed
+    pub loaded_tokenizers: Vec<String>,
+    /// The models whose tokenizer failed to load, and is retried in the background
+    pub pending_tokenizers: Vec<String>,
+}
+
+/// Registry of the models served by the proxy, which can be reloaded from the configuration
+/// file without restarting the proxy.
+///
+/// Reloads re-read the models, revisions, modalities, model routing and tokenizer sources of
+/// the service configuration, only load the tokenizers of new models (or new revisions), and
+/// swap the served models atomically. Requests in flight keep the snapshot they started with,
+/// and the other settings of the service configuration are only applied on restart.
+pub struct ModelRegistry {
+    /// Path to the configuration file.
+    config_path: String,
+    /// The current snapshot of the served models.
+    served_models: RwLock<Arc<ServedModels>>,
+    /// Serializes the reloads, so that tokenizers are not loaded twice.
+    reload_lock: Mutex<()>,
+}
+
+impl ModelRegistry {
+    /// Creates a new model registry, serving the given models.
+    #[must_use]
+    pub fn new(served_models: ServedModels, config_path: String) -> Self {
+        Self {
+            config_path,
+            served_models: RwLock::new(Arc::new(served_models)),
+            reload_lock: Mutex::new(()),
+        }
+    }
+
+    /// Returns the current snapshot of the served models.
+    #[must_use]
+    pub fn current(&self) -> Arc<ServedModels> {
+        Arc::clone(
+            &self
+                .served_models
+                .read()
+                .unwrap_or_else(PoisonError::into_inner),
+        )
+    }
+
+    /// Reloads the served models from the configuration file.
+    ///
+    /// Tokenizers that fail to load do not fail the reload: requests for their models are
+    /// rejected until the background tokenizer loader manages to load them.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the configuration file cannot be read, or the new configuration
+    /// is invalid. In that case, the served models are left unchanged.
+    #[instrument(level = "info", skip_all, fields(config_path = %self.config_path))]
+    pub async fn reload(&self) -> anyhow::Result<ReloadSummary> {
+        let _reload_guard = self.reload_lock.lock().await;
+        let config = AtomaServiceConfig::try_from_file_path(&self.config_path)?;
+        let current = self.current();
+
+        let mut served_models = ServedModels::new(
+            config.models,
+            config.revisions,
+            config.modalities,
+            Arc::new(TokenizerLoader::new(config.tokenizers, config.hf_token)),
+            config.model_routing,
+        )?;
+        served_models.reuse_loaded_tokenizers(&current);
+        let results = join_all(
+            served_models
+                .models
+                .iter()
+                .zip(&served_models.tokenizers)
+                .filter(|(_, tokenizer)| !tokenizer.is_loaded())
+                .map(|(model, tokenizer)| async move { (model.clone(), tokenizer.load().await) }),
+        )
+        .await;
+        let mut loaded_tokenizers = Vec::new();
+        let mut pending_tokenizers = Vec::new();
+        for (model, result) in results {
+            match result {
+                Ok(()) => loaded_tokenizers.push(model),
+                Err(e) => {
+                    error!(
+                        target = "atoma-service",
+                        event = "model-registry-reload",
+                        "Failed to load tokenizer for model {model}, retrying later: {e:#}"
+                    );
+                    pending_tokenizers.push(model);
+                }
+            }
+        }
+
+        let summary = ReloadSummary {
+            added_models: served_models
+                .models
+                .iter()
+                .filter(|model| !current.serves(model))
+                .cloned()
+                .collect(),
+            removed_models: current
+                .models
+                .iter()
+                .filter(|model| !served_models.serves(model))
+                .cloned()
+                .collect(),
+            models: served_models.models.clone(),
+            loaded_tokenizers,
+            pending_tokenizers,
+        };
+        *self
+            .served_models
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = Arc::new(served_models);
+        info!(
+            target = "atoma-service",
+            event = "model-registry-reload",
+            added_models = ?summary.added_models,
+            removed_models = ?summary.removed_models,
+            loaded_tokenizers = ?summary.loaded_tokenizers,
+            pending_tokenizers = ?summary.pending_tokenizers,
+            "Reloaded the served models"
+        );
+        Ok(summary)
+    }
+}
+
+/// Reloads the served models every time the proxy receives a `SIGHUP`, until it shuts down.
+#[cfg(unix)]
+pub async fn run_reload_on_sighup(state: ProxyState, mut shutdown_receiver: watch::Receiver<bool>) {
+    let mut sighup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(sighup) => sighup,
+        Err(e) => {
+            error!(
+                    target = "atoma-service",
+                    event = "model-registry-reload",
+                    "Failed to listen for SIGHUP, the served models can only be reloaded through the admin endpoint: {e}"
+                );
+            return;
+        }
+    };
+    loop {
+        tokio::select! {
+            _ = sighup.recv() => {
+                if let Err(e) = state.model_registry.reload().await {
+                    error!(
+                        target = "atoma-service",
+                        event = "model-registry-reload",
+                        "Failed to reload the served models: {e:#}"
+                    );
+                }
+            }
+            _ = shutdown_receiver.changed() => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::TokenizersConfig;
+
+    fn served_models(revisions: [&str; 2], files: HashMap<String, String>) -> ServedModels {
+        ServedModels::new(
+            vec!["model-a".to_string(), "model-b".to_string()],
+            revisions.iter().map(ToString::to_string).collect(),
+            vec![
+                vec![ModelModality::ChatCompletions],
+                vec![ModelModality::Embeddings],
+            ],
+            Arc::new(TokenizerLoader::new(
+                TokenizersConfig {
+                    files,
+                    offline: true,
+                    ..TokenizersConfig::default()
+                },
+                String::new(),
+            )),
+            ModelRoutingConfig::default(),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_served_models() {
+        let served_models = served_models(["main", "main"], HashMap::new());
+        assert!(served_models.serves("model-a"));
+        assert!(!served_models.serves("model-c"));
+        assert!(matches!(
+            served_models.modalities["model-b"].as_slice(),
+            [ModelModality::Embeddings]
+        ));
+        // Tokenizers are not loaded yet, and cannot be loaded offline without local files
+        assert!(served_models.tokenizer("model-a").is_none());
+        assert!(served_models.tokenizers[0].load().await.is_err());
+        assert!(served_models.tokenizer("model-c").is_none());
+
+        // A model without a revision has no tokenizer
+        assert!(ServedModels::new(
+            vec!["model-a".to_string(), "model-b".to_string()],
+            vec!["main".to_string()],
+            vec![],
+            Arc::clone(&served_models.tokenizer_loader),
+            ModelRoutingConfig::default(),
+        )
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reuse_loaded_tokenizers() {
+        let file = std::env::temp_dir().join(format!("{}.tiktoken", uuid::Uuid::new_v4()));
+        std::fs::write(&file, "YQ== 0\n").unwrap();
+        let file = file.to_string_lossy().to_string();
+        let files = HashMap::from([("model-b".to_string(), file.clone())]);
+
+        let previous = served_models(["main", "main"], files.clone());
+        previous.tokenizers[1].load().await.unwrap();
+        assert!(previous.tokenizer("model-b").is_some());
+
+        let mut served_models = served_models(["main", "v2"], files);
+        served_models.reuse_loaded_tokenizers(&previous);
+        // model-a's tokenizer is not loaded, and model-b's revision changed
+        assert!(!Arc::ptr_eq(
+            &served_models.tokenizers[0],
+            &previous.tokenizers[0]
+        ));
+        assert!(served_models.tokenizer("model-b").is_none());
+
+        let mut served_models = self::served_models(["v2", "main"], HashMap::new());
+        served_models.reuse_loaded_tokenizers(&previous);
+        assert!(Arc::ptr_eq(
+            &served_models.tokenizers[1],
+            &previous.tokenizers[1]
+        ));
+        assert!(served_models.tokenizer("model-b").is_some());
+        std::fs::remove_file(file).unwrap();
+    }
+}
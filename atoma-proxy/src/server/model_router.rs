@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+
+use super::ModelRoutingConfig;
+
+/// Header with the model that served the request, once its alias and fallbacks are resolved.
+pub const X_ATOMA_MODEL: &str = "x-atoma-model";
+
+/// Routes the models requested by clients to the models served by the proxy.
+///
+/// Clients can request a model by a short alias, and a request for a model is served by its
+/// fallback models, in order, when no node is available for the model or all its nodes fail.
+#[derive(Debug, Default)]
+pub struct ModelRouter {
+    /// Models, indexed by alias.
+    aliases: HashMap<String, String>,
+    /// Fallback models, in order of preference, indexed by model.
+    fallbacks: HashMap<String, Vec<String>>,
+}
+
+impl ModelRouter {
+    /// Creates a new model router, resolving the aliases of the fallback models.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The aliases and fallback models
+    /// * `models` - The models served by the proxy
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an alias is also the name of a served model, or if an alias,
+    /// a model with fallbacks or a fallback model does not resolve to a served model.
+    pub fn new(config: ModelRoutingConfig, models: &[String]) -> anyhow::Result<Self> {
+        for (alias, model) in &config.aliases {
+            if models.contains(alias) {
+                anyhow::bail!("Model alias `{alias}` is also the name of a served model");
+            }
+            if !models.contains(model) {
+                anyhow::bail!("Model alias `{alias}` refers to `{model}`, which is not served");
+            }
+        }
+        let mut router = Self {
+            aliases: config.aliases,
+            fallbacks: HashMap::new(),
+        };
+        for (model, fallbacks) in config.fallbacks {
+            let model = router.resolve(&model).to_string();
+            if !models.contains(&model) {
+                anyhow::bail!("Model `{model}` has fallbacks, but it is not served");
+            }
+            let mut resolved_fallbacks: Vec<String> = Vec::with_capacity(fallbacks.len());
+            for fallback in fallbacks {
+                let fallback = router.resolve(&fallback).to_string();
+                if !models.contains(&fallback) {
+                    anyhow::bail!("Fallback `{fallback}` of model `{model}` is not served");
+                }
+                if fallback != model && !resolved_fallbacks.contains(&fallback) {
+                    resolved_fallbacks.push(fallback);
+                }
+            }
+            router.fallbacks.insert(model, resolved_fallbacks);
+        }
+        Ok(router)
+    }
+
+    /// Resolves a model alias to its model. Models that are not aliases are returned as is.
+    #[must_use]
+    pub fn resolve<'a>(&'a self, model: &'a str) -> &'a str {
+        self.aliases.get(model).map_or(model, String::as_str)
+    }
+
+    /// Returns the fallback models of a model, in order of preference.
+    #[must_use]
+    pub fn fallbacks(&self, model: &str) -> &[String] {
+        self.fallbacks
+            .get(self.resolve(model))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns the models that can serve a request for a model (or alias), in order of preference:
+    /// the resolved model first, then its fallback models.
+    #[must_use]
+    pub fn candidates(&self, model: &str) -> Vec<String> {
+        std::iter::once(self.resolve(model))
+            .chain(self.fallbacks(model).iter().map(String::as_str))
+            .map(ToString::to_string)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LLAMA_70B: &str = "Infermatic/Llama-3.3-70B-Instruct-FP8-Dynamic";
+    const LLAMA_8B: &str = "meta-llama/Llama-3.1-8B-Instruct";
+    const QWEN: &str = "Qwen/Qwen2.5-72B-Instruct";
+
+    fn models() -> Vec<String> {
+        vec![
+            LLAMA_70B.to_string(),
+            LLAMA_8B.to_string(),
+            QWEN.to_string(),
+        ]
+    }
+
+    fn config() -> ModelRoutingConfig {
+        ModelRoutingConfig {
+            aliases: HashMap::from([
+                ("llama-3.3-70b".to_string(), LLAMA_70B.to_string()),
+                ("llama-3.1-8b".to_string(), LLAMA_8B.to_string()),
+            ]),
+            fallbacks: HashMap::from([(
+                "llama-3.3-70b".to_string(),
+                vec![
+                    QWEN.to_string(),
+                    "llama-3.1-8b".to_string(),
+                    LLAMA_70B.to_string(),
+                ],
+            )]),
+        }
+    }
+
+    #[test]
+    fn test_model_router_resolves_aliases_and_fallbacks() {
+        let router = ModelRouter::new(config(), &models()).unwrap();
+        assert_eq!(router.resolve("llama-3.3-70b"), LLAMA_70B);
+        assert_eq!(router.resolve(QWEN), QWEN);
+        assert_eq!(router.resolve("unknown"), "unknown");
+        assert_eq!(
+            router.candidates("llama-3.3-70b"),
+            vec![LLAMA_70B, QWEN, LLAMA_8B]
+        );
+        assert_eq!(
+            router.candidates(LLAMA_70B),
+            router.candidates("llama-3.3-70b")
+        );
+        assert_eq!(router.fallbacks(LLAMA_70B), [QWEN, LLAMA_8B]);
+        assert_eq!(router.candidates("llama-3.1-8b"), vec![LLAMA_8B]);
+        assert!(router.fallbacks(QWEN).is_empty());
+    }
+
+    #[test]
+    fn test_model_router_rejects_unserved_models() {
+        let mut config = config();
+        config
+            .aliases
+            .insert("mistral".to_string(), "mistralai/Mistral-7B".to_string());
+        assert!(ModelRouter::new(config, &models()).is_err());
+
+        let mut config = self::config();
+        config
+            .aliases
+            .insert(QWEN.to_string(), LLAMA_70B.to_string());
+        assert!(ModelRouter::new(config, &models()).is_err());
+
+        let mut config = self::config();
+        config
+            .fallbacks
+            .insert(QWEN.to_string(), vec!["mistral".to_string()]);
+        assert!(ModelRouter::new(config, &models()).is_err());
+    }
+}
@@ -0,0 +1,161 @@
+use crate::server::{error::AtomaProxyError, Result};
+
+/// A field of a `multipart/form-data` body.
+#[derive(Debug, PartialEq, Eq)]
+pub struct FormField<'a> {
+    /// The name of the field
+    pub name: String,
+    /// The name of the uploaded file, for file fields
+    pub filename: Option<String>,
+    /// The raw content of the field
+    pub content: &'a [u8],
+}
+
+impl FormField<'_> {
+    /// Returns the content of the field as text.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `RequestError` if the content is not valid UTF-8.
+    pub fn text(&self, endpoint: &str) -> Result<String> {
+        String::from_utf8(self.content.to_vec()).map_err(|_| AtomaProxyError::RequestError {
+            message: format!("Form field '{}' is not valid UTF-8", self.name),
+            endpoint: endpoint.to_string(),
+        })
+    }
+}
+
+/// Parses the fields of a `multipart/form-data` body.
+///
+/// The proxy only receives small forms, with at most one uploaded file, which are fully
+/// buffered by the middleware before being parsed, so a streaming parser is not needed.
+///
+/// # Errors
+///
+/// Returns a `RequestError` if the content type has no boundary, or the body is not a valid
+/// multipart form.
+pub fn parse_multipart_form<'a>(
+    content_type: &str,
+    body: &'a [u8],
+    endpoint: &str,
+) -> Result<Vec<FormField<'a>>> {
+    let request_error = |message: &str| AtomaProxyError::RequestError {
+        message: message.to_string(),
+        endpoint: endpoint.to_string(),
+    };
+    let boundary = content_type
+        .split(';')
+        .map(str::trim)
+        .find_map(|parameter| parameter.strip_prefix("boundary="))
+        .map(|boundary| boundary.trim_matches('"'))
+        .ok_or_else(|| request_error("Missing multipart boundary"))?;
+    let delimiter = format!("--{boundary}");
+
+    let mut fields = Vec::new();
+    for part in split_bytes(body, delimiter.as_bytes()).into_iter().skip(1) {
+        // The closing delimiter is followed by `--`
+        if part.starts_with(b"--") {
+            break;
+        }
+        let part = part
+            .strip_prefix(b"\r\n")
+            .ok_or_else(|| request_error("Invalid multipart body"))?;
+        let headers_end =
+            find_bytes(part, b"\r\n\r\n").ok_or_else(|| request_error("Invalid multipart body"))?;
+        let headers = std::str::from_utf8(&part[..headers_end])
+            .map_err(|_| request_error("Invalid multipart headers"))?;
+        let content = &part[headers_end + 4..];
+        let content = content.strip_suffix(b"\r\n").unwrap_or(content);
+
+        let content_disposition = headers
+            .lines()
+            .find_map(|line| {
+                line.split_once(':')
+                    .filter(|(name, _)| name.trim().eq_ignore_ascii_case("content-disposition"))
+                    .map(|(_, value)| value)
+            })
+            .ok_or_else(|| request_error("Missing multipart content disposition"))?;
+        let parameter = |key: &str| {
+            content_disposition
+                .split(';')
+                .map(str::trim)
+                .find_map(|parameter| {
+                    parameter
+                        .strip_prefix(key)
+                        .and_then(|value| value.strip_prefix('='))
+                        .map(|value| value.trim_matches('"').to_string())
+                })
+        };
+        fields.push(FormField {
+            name: parameter("name").ok_or_else(|| request_error("Missing form field name"))?,
+            filename: parameter("filename"),
+            content,
+        });
+    }
+    Ok(fields)
+}
+
+/// Returns the position of the first occurrence of `needle` in `haystack`.
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Splits `bytes` on every occurrence of `delimiter`.
+fn split_bytes<'a>(mut bytes: &'a [u8], delimiter: &[u8]) -> Vec<&'a [u8]> {
+    let mut parts = Vec::new();
+    while let Some(position) = find_bytes(bytes, delimiter) {
+        parts.push(&bytes[..position]);
+        bytes = &bytes[position + delimiter.len()..];
+    }
+    parts.push(bytes);
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_multipart_form() {
+        let body = b"preamble\r\n--boundary\r\n\
+            Content-Disposition: form-data; name=\"purpose\"\r\n\r\n\
+            batch\r\n\
+            --boundary\r\n\
+            content-disposition: form-data; name=\"file\"; filename=\"requests.jsonl\"\r\n\
+            Content-Type: application/jsonl\r\n\r\n\
+            {}\r\n{}\r\n\
+            --boundary--\r\n";
+        let fields = parse_multipart_form(
+            "multipart/form-data; boundary=\"boundary\"",
+            body,
+            "/v1/files",
+        )
+        .unwrap();
+        assert_eq!(
+            fields,
+            vec![
+                FormField {
+                    name: "purpose".to_string(),
+                    filename: None,
+                    content: b"batch",
+                },
+                FormField {
+                    name: "file".to_string(),
+                    filename: Some("requests.jsonl".to_string()),
+                    content: b"{}\r\n{}",
+                },
+            ]
+        );
+        assert_eq!(fields[0].text("/v1/files").unwrap(), "batch");
+
+        assert!(parse_multipart_form("multipart/form-data", body, "/v1/files").is_err());
+        assert!(parse_multipart_form(
+            "multipart/form-data; boundary=boundary",
+            b"--boundary\r\nno headers end",
+            "/v1/files"
+        )
+        .is_err());
+    }
+}
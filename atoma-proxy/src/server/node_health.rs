@@ -0,0 +1,330 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::NodeHealthConfig;
+
+/// State of the circuit breaker of a node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CircuitState {
+    /// The node is healthy and receives requests.
+    Closed,
+    /// The node is ejected from node and stack selection until the cooldown expires.
+    Open {
+        /// The time at which the node is half-opened.
+        until: Instant,
+    },
+    /// The cooldown expired, the node receives requests again, and is closed on its
+    /// next success or opened again on its next failure.
+    HalfOpen,
+}
+
+/// Health of a single node, as observed by the proxy.
+#[derive(Debug)]
+struct NodeHealth {
+    /// Number of failures since the last success.
+    consecutive_failures: u32,
+    /// Outcomes of the most recent requests, `true` for a success.
+    outcomes: VecDeque<bool>,
+    /// The time of the last successful request.
+    last_success: Option<Instant>,
+    /// The time of the last failed request.
+    last_failure: Option<Instant>,
+    /// The state of the circuit breaker.
+    circuit: CircuitState,
+}
+
+impl NodeHealth {
+    const fn new() -> Self {
+        Self {
+            consecutive_failures: 0,
+            outcomes: VecDeque::new(),
+            last_success: None,
+            last_failure: None,
+            circuit: CircuitState::Closed,
+        }
+    }
+
+    fn push_outcome(&mut self, success: bool, window_size: usize) {
+        self.outcomes.push_back(success);
+        while self.outcomes.len() > window_size {
+            self.outcomes.pop_front();
+        }
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn error_rate(&self) -> f64 {
+        if self.outcomes.is_empty() {
+            return 0.0;
+        }
+        let num_failures = self.outcomes.iter().filter(|success| !**success).count();
+        num_failures as f64 / self.outcomes.len() as f64
+    }
+
+    /// Half-opens the circuit if its cooldown has expired.
+    fn refresh(&mut self, now: Instant) {
+        if let CircuitState::Open { until } = self.circuit {
+            if until <= now {
+                self.circuit = CircuitState::HalfOpen;
+            }
+        }
+    }
+}
+
+/// The externally visible state of the circuit breaker of a node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeCircuitState {
+    /// The node is healthy.
+    Closed,
+    /// The node is ejected from node and stack selection.
+    Open,
+    /// The node is being probed after its cooldown.
+    HalfOpen,
+}
+
+/// Health of a node, as reported by the admin endpoint.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct NodeHealthStatus {
+    /// The node small id.
+    pub node_small_id: i64,
+    /// The state of the circuit breaker of the node.
+    pub state: NodeCircuitState,
+    /// Number of failures since the last success.
+    pub consecutive_failures: u32,
+    /// Error rate over the most recent requests, between 0 and 1.
+    pub error_rate: f64,
+    /// Number of recent requests the error rate is computed over.
+    pub num_recent_requests: usize,
+    /// Seconds elapsed since the last successful request, if any.
+    pub last_success_secs_ago: Option<u64>,
+    /// Seconds elapsed since the last failed request, if any.
+    pub last_failure_secs_ago: Option<u64>,
+    /// Seconds remaining before the node is half-opened, if the node is ejected.
+    pub open_remaining_secs: Option<u64>,
+}
+
+/// In-memory registry of the health of the nodes the proxy forwards requests to.
+///
+/// Each node has a circuit breaker, opened after too many consecutive failures or a too
+/// high error rate over its most recent requests. Nodes with an open circuit are ejected
+/// from node and stack selection until a cooldown expires, after which the circuit is
+/// half-opened: the node receives requests again, and its next outcome either closes the
+/// circuit or opens it for another cooldown.
+#[derive(Debug)]
+pub struct NodeHealthRegistry {
+    /// The circuit breaker thresholds.
+    config: NodeHealthConfig,
+    /// The health of each node, indexed by node small id.
+    nodes: DashMap<i64, NodeHealth>,
+}
+
+impl NodeHealthRegistry {
+    /// Creates a new, empty, node health registry.
+    #[must_use]
+    pub fn new(config: NodeHealthConfig) -> Self {
+        Self {
+            config,
+            nodes: DashMap::new(),
+        }
+    }
+
+    /// Records a request successfully processed by a node.
+    pub fn record_success(&self, node_small_id: i64) {
+        self.record_success_at(node_small_id, Instant::now());
+    }
+
+    /// Records a request that a node failed to process (connection error, timeout or server error).
+    pub fn record_failure(&self, node_small_id: i64) {
+        self.record_failure_at(node_small_id, Instant::now());
+    }
+
+    /// Returns the nodes currently ejected from node and stack selection.
+    #[must_use]
+    pub fn ejected_node_small_ids(&self) -> Vec<i64> {
+        self.ejected_node_small_ids_at(Instant::now())
+    }
+
+    /// Returns the given nodes, together with the nodes currently ejected from node and stack selection.
+    #[must_use]
+    pub fn excluded_node_small_ids(&self, excluded_node_small_ids: &[i64]) -> Vec<i64> {
+        let mut node_small_ids = excluded_node_small_ids.to_vec();
+        for node_small_id in self.ejected_node_small_ids() {
+            if !node_small_ids.contains(&node_small_id) {
+                node_small_ids.push(node_small_id);
+            }
+        }
+        node_small_ids
+    }
+
+    /// Returns the health of all the nodes that served requests, sorted by node small id.
+    #[must_use]
+    pub fn statuses(&self) -> Vec<NodeHealthStatus> {
+        let now = Instant::now();
+        let mut statuses = self
+            .nodes
+            .iter_mut()
+            .map(|mut entry| {
+                let node_small_id = *entry.key();
+                let health = entry.value_mut();
+                health.refresh(now);
+                let (state, open_remaining_secs) = match health.circuit {
+                    CircuitState::Closed => (NodeCircuitState::Closed, None),
+                    CircuitState::Open { until } => (
+                        NodeCircuitState::Open,
+                        Some(until.saturating_duration_since(now).as_secs()),
+                    ),
+                    CircuitState::HalfOpen => (NodeCircuitState::HalfOpen, None),
+                };
+                let secs_ago = |instant: Instant| now.saturating_duration_since(instant).as_secs();
+                NodeHealthStatus {
+                    node_small_id,
+                    state,
+                    consecutive_failures: health.consecutive_failures,
+                    error_rate: health.error_rate(),
+                    num_recent_requests: health.outcomes.len(),
+                    last_success_secs_ago: health.last_success.map(secs_ago),
+                    last_failure_secs_ago: health.last_failure.map(secs_ago),
+                    open_remaining_secs,
+                }
+            })
+            .collect::<Vec<_>>();
+        statuses.sort_by_key(|status| status.node_small_id);
+        statuses
+    }
+
+    fn record_success_at(&self, node_small_id: i64, now: Instant) {
+        let mut health = self
+            .nodes
+            .entry(node_small_id)
+            .or_insert_with(NodeHealth::new);
+        if health.circuit != CircuitState::Closed {
+            tracing::info!(
+                target = "atoma-service",
+                node_small_id,
+                "Node recovered, closing its circuit breaker"
+            );
+            // NOTE: Forget the failures that opened the circuit, so that a single failure
+            // right after recovering does not open it again because of the error rate.
+            health.outcomes.clear();
+        }
+        health.consecutive_failures = 0;
+        health.push_outcome(true, self.config.window_size);
+        health.last_success = Some(now);
+        health.circuit = CircuitState::Closed;
+    }
+
+    fn record_failure_at(&self, node_small_id: i64, now: Instant) {
+        let mut health = self
+            .nodes
+            .entry(node_small_id)
+            .or_insert_with(NodeHealth::new);
+        health.refresh(now);
+        health.consecutive_failures += 1;
+        health.push_outcome(false, self.config.window_size);
+        health.last_failure = Some(now);
+        let should_open = match health.circuit {
+            CircuitState::Closed => {
+                health.consecutive_failures >= self.config.consecutive_failures_threshold
+                    || (health.outcomes.len() >= self.config.window_size
+                        && health.error_rate() >= self.config.error_rate_threshold)
+            }
+            CircuitState::HalfOpen => true,
+            CircuitState::Open { .. } => false,
+        };
+        if should_open {
+            tracing::warn!(
+                target = "atoma-service",
+                node_small_id,
+                consecutive_failures = health.consecutive_failures,
+                error_rate = health.error_rate(),
+                "Node is unhealthy, opening its circuit breaker"
+            );
+            health.circuit = CircuitState::Open {
+                until: now + Duration::from_secs(self.config.cooldown_secs),
+            };
+        }
+    }
+
+    fn ejected_node_small_ids_at(&self, now: Instant) -> Vec<i64> {
+        self.nodes
+            .iter_mut()
+            .filter_map(|mut entry| {
+                entry.value_mut().refresh(now);
+                matches!(entry.circuit, CircuitState::Open { .. }).then_some(*entry.key())
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> NodeHealthRegistry {
+        NodeHealthRegistry::new(NodeHealthConfig {
+            consecutive_failures_threshold: 3,
+            error_rate_threshold: 0.5,
+            window_size: 4,
+            cooldown_secs: 30,
+        })
+    }
+
+    #[test]
+    fn test_consecutive_failures_open_circuit() {
+        let registry = registry();
+        let now = Instant::now();
+
+        registry.record_failure_at(1, now);
+        registry.record_failure_at(1, now);
+        assert!(registry.ejected_node_small_ids_at(now).is_empty());
+        registry.record_failure_at(1, now);
+        assert_eq!(registry.ejected_node_small_ids_at(now), vec![1]);
+    }
+
+    #[test]
+    fn test_error_rate_opens_circuit() {
+        let registry = registry();
+        let now = Instant::now();
+
+        for success in [true, false, true, false] {
+            if success {
+                registry.record_success_at(1, now);
+            } else {
+                registry.record_failure_at(1, now);
+            }
+        }
+        assert_eq!(registry.ejected_node_small_ids_at(now), vec![1]);
+    }
+
+    #[test]
+    fn test_half_open_after_cooldown() {
+        let registry = registry();
+        let now = Instant::now();
+        for _ in 0..3 {
+            registry.record_failure_at(1, now);
+        }
+
+        // The node is half-opened after the cooldown, and opened again on failure
+        let later = now + Duration::from_secs(30);
+        assert!(registry.ejected_node_small_ids_at(later).is_empty());
+        registry.record_failure_at(1, later);
+        assert_eq!(registry.ejected_node_small_ids_at(later), vec![1]);
+
+        // A success after the next cooldown closes the circuit
+        let even_later = later + Duration::from_secs(30);
+        assert!(registry.ejected_node_small_ids_at(even_later).is_empty());
+        registry.record_success_at(1, even_later);
+        registry.record_failure_at(1, even_later);
+        assert!(registry.ejected_node_small_ids_at(even_later).is_empty());
+        let status = &registry.statuses()[0];
+        assert_eq!(status.state, NodeCircuitState::Closed);
+        assert_eq!(status.consecutive_failures, 1);
+        assert_eq!(status.num_recent_requests, 2);
+    }
+}
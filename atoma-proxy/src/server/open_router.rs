@@ -0,0 +1,285 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, PoisonError, RwLock},
+    time::Duration,
+};
+
+use serde_json::{json, Value};
+use tokio::sync::watch;
+use tracing::{error, instrument};
+
+use super::{
+    error::AtomaProxyError,
+    handlers::models::{get_live_models, Model, OPEN_ROUTER_MODELS_PATH},
+    http_server::ProxyState,
+    Result, ONE_MILLION,
+};
+
+/// Number of base units in one USDC, prices and balances being stored in USDC base units.
+const USDC_BASE_UNITS: f64 = 1_000_000.0;
+
+/// Quantization reported for the models whose name does not tell it.
+const UNKNOWN_QUANTIZATION: &str = "unknown";
+
+/// Quantizations recognized in model names, in order of precedence.
+const QUANTIZATIONS: [&str; 6] = ["fp4", "fp6", "fp8", "int4", "int8", "bf16"];
+
+/// Models feed in the OpenRouter format, generated from the models served by the proxy and
+/// the prices of the nodes subscribed to them.
+///
+/// The feed is cached, and refreshed periodically by `run_open_router_feed_refresh`. Entries
+/// of the optional overrides file, in the OpenRouter format, are merged into the generated
+/// entries of the same id, e.g. to set a display name, a description or a fixed pricing.
+pub struct OpenRouterFeed {
+    /// Path to the JSON file of per-model overrides.
+    overrides_file: Option<String>,
+    /// Time between two refreshes of the feed.
+    refresh_interval: Duration,
+    /// The last generated feed, if any.
+    feed: RwLock<Option<Arc<Value>>>,
+}
+
+impl OpenRouterFeed {
+    /// Creates a new, empty, OpenRouter models feed.
+    #[must_use]
+    pub const fn new(overrides_file: Option<String>, refresh_interval_secs: u64) -> Self {
+        Self {
+            overrides_file,
+            refresh_interval: Duration::from_secs(refresh_interval_secs),
+            feed: RwLock::new(None),
+        }
+    }
+
+    /// Returns the cached feed, generating it first if it was never generated.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the feed has to be generated, and the models or the overrides
+    /// cannot be retrieved.
+    pub async fn get(&self, state: &ProxyState) -> Result<Arc<Value>> {
+        let feed = self
+            .feed
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        match feed {
+            Some(feed) => Ok(feed),
+            None => self.refresh(state).await,
+        }
+    }
+
+    /// Generates the feed from the live models and the overrides file, and caches it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the models cannot be retrieved from the state manager, or if
+    /// the overrides file cannot be read or parsed.
+    #[instrument(level = "trace", skip_all)]
+    pub async fn refresh(&self, state: &ProxyState) -> Result<Arc<Value>> {
+        let models = get_live_models(state, OPEN_ROUTER_MODELS_PATH).await?;
+        let overrides = match &self.overrides_file {
+            Some(overrides_file) => read_overrides(overrides_file).await?,
+            None => HashMap::new(),
+        };
+        let feed = Arc::new(build_open_router_feed(models, overrides));
+        *self.feed.write().unwrap_or_else(PoisonError::into_inner) = Some(Arc::clone(&feed));
+        Ok(feed)
+    }
+}
+
+/// Refreshes the OpenRouter models feed periodically, until the proxy shuts down.
+///
+/// On failure, the previously generated feed is kept until the next refresh.
+pub async fn run_open_router_feed_refresh(
+    state: ProxyState,
+    mut shutdown_receiver: watch::Receiver<bool>,
+) {
+    loop {
+        if let Err(e) = state.open_router_feed.refresh(&state).await {
+            error!(
+                target = "atoma-service",
+                event = "open-router-feed",
+                "Failed to refresh the OpenRouter models feed: {e}"
+            );
+        }
+        tokio::select! {
+            () = tokio::time::sleep(state.open_router_feed.refresh_interval) => {}
+            _ = shutdown_receiver.changed() => break,
+        }
+    }
+}
+
+/// Reads the per-model overrides of the feed, indexed by model id, from a JSON file
+/// in the OpenRouter format (`{"data": [{"id": ..., ...}]}`).
+async fn read_overrides(overrides_file: &str) -> Result<HashMap<String, Value>> {
+    let internal_error = |message: String| AtomaProxyError::InternalError {
+        message,
+        client_message: None,
+        endpoint: OPEN_ROUTER_MODELS_PATH.to_string(),
+    };
+    let file_content = tokio::fs::read_to_string(overrides_file)
+        .await
+        .map_err(|err| internal_error(format!("Failed to read OpenRouter models file: {err}")))?;
+    let overrides: Value = serde_json::from_str(&file_content)
+        .map_err(|err| internal_error(format!("Failed to parse OpenRouter models file: {err}")))?;
+    Ok(overrides
+        .get("data")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| {
+            let id = entry.get("id")?.as_str()?;
+            Some((id.to_string(), entry.clone()))
+        })
+        .collect())
+}
+
+/// Builds the OpenRouter models feed from the live models, merging the overrides of each model
+/// into its generated entry. Overrides of models that are not live are ignored.
+fn build_open_router_feed(models: Vec<Model>, mut overrides: HashMap<String, Value>) -> Value {
+    let data: Vec<Value> = models
+        .into_iter()
+        .map(|model| {
+            // NOTE: Compute units account for both input and output tokens, at the same price
+            let price_per_token =
+                price_per_token(model.pricing.min_price_per_one_million_compute_units);
+            let mut entry = json!({
+                "id": model.id,
+                "name": model.id,
+                "created": model.created,
+                "context_length": model.context_length,
+                "quantization": quantization(&model.id),
+                "pricing": {
+                    "prompt": price_per_token,
+                    "completion": price_per_token,
+                    "image": "0",
+                    "request": "0",
+                },
+            });
+            if let Some(model_overrides) = overrides.remove(&model.id) {
+                merge_json(&mut entry, model_overrides);
+            }
+            entry
+        })
+        .collect();
+    json!({ "data": data })
+}
+
+/// Converts a price per one million compute units, in USDC base units, into the price
+/// of a single token in USD, as expected by OpenRouter.
+fn price_per_token(price_per_one_million_compute_units: i64) -> String {
+    #[allow(clippy::cast_precision_loss)]
+    let price = price_per_one_million_compute_units as f64 / ONE_MILLION as f64 / USDC_BASE_UNITS;
+    let price = format!("{price:.12}");
+    let price = price.trim_end_matches('0').trim_end_matches('.');
+    price.to_string()
+}
+
+/// Infers the quantization of a model from its name (e.g. `Llama-3.3-70B-Instruct-FP8-Dynamic`).
+fn quantization(model: &str) -> &'static str {
+    let model = model.to_lowercase();
+    QUANTIZATIONS
+        .into_iter()
+        .find(|quantization| model.contains(quantization))
+        .unwrap_or(UNKNOWN_QUANTIZATION)
+}
+
+/// Merges `overrides` into `value`: objects are merged key by key, and any other value
+/// of `overrides` replaces the value of `value`.
+fn merge_json(value: &mut Value, overrides: Value) {
+    match (value, overrides) {
+        (Value::Object(value), Value::Object(overrides)) => {
+            for (key, override_value) in overrides {
+                match value.get_mut(&key) {
+                    Some(value) => merge_json(value, override_value),
+                    None => {
+                        value.insert(key, override_value);
+                    }
+                }
+            }
+        }
+        (value, overrides) => *value = overrides,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::handlers::models::ModelPricing;
+
+    const LLAMA: &str = "Infermatic/Llama-3.3-70B-Instruct-FP8-Dynamic";
+
+    fn model(id: &str, min_price_per_one_million_compute_units: i64) -> Model {
+        Model {
+            id: id.to_string(),
+            object: "model".to_string(),
+            created: 1_700_000_000,
+            owned_by: "atoma".to_string(),
+            modalities: vec![],
+            context_length: 128_000,
+            pricing: ModelPricing {
+                min_price_per_one_million_compute_units,
+                median_price_per_one_million_compute_units: min_price_per_one_million_compute_units,
+            },
+            num_nodes: 1,
+            confidential_compute: false,
+        }
+    }
+
+    #[test]
+    fn test_price_per_token() {
+        // 0.5 USDC per one million compute units
+        assert_eq!(price_per_token(500_000), "0.0000005");
+        assert_eq!(price_per_token(1_000_000), "0.000001");
+        assert_eq!(price_per_token(0), "0");
+    }
+
+    #[test]
+    fn test_quantization() {
+        assert_eq!(quantization(LLAMA), "fp8");
+        assert_eq!(quantization("Qwen/Qwen2.5-7B-Instruct-AWQ-INT4"), "int4");
+        assert_eq!(quantization("meta-llama/Llama-3.1-8B-Instruct"), "unknown");
+    }
+
+    #[test]
+    fn test_build_open_router_feed() {
+        let overrides = HashMap::from([
+            (
+                LLAMA.to_string(),
+                json!({
+                    "id": LLAMA,
+                    "name": "Meta: Llama 3.3 70B Instruct",
+                    "max_completion_tokens": 4096,
+                    "pricing": { "completion": "0.000002" },
+                }),
+            ),
+            ("not/live".to_string(), json!({ "id": "not/live" })),
+        ]);
+        let feed = build_open_router_feed(
+            vec![model(LLAMA, 500_000), model("other/model", 1_000_000)],
+            overrides,
+        );
+        let data = feed["data"].as_array().unwrap();
+        assert_eq!(data.len(), 2);
+        assert_eq!(
+            data[0],
+            json!({
+                "id": LLAMA,
+                "name": "Meta: Llama 3.3 70B Instruct",
+                "created": 1_700_000_000,
+                "context_length": 128_000,
+                "quantization": "fp8",
+                "max_completion_tokens": 4096,
+                "pricing": {
+                    "prompt": "0.0000005",
+                    "completion": "0.000002",
+                    "image": "0",
+                    "request": "0",
+                },
+            })
+        );
+        assert_eq!(data[1]["name"], "other/model");
+        assert_eq!(data[1]["quantization"], "unknown");
+        assert_eq!(data[1]["pricing"]["prompt"], "0.000001");
+    }
+}
@@ -0,0 +1,569 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use axum::http::{header::RETRY_AFTER, HeaderMap, HeaderValue};
+use dashmap::DashMap;
+use tokio::sync::watch;
+
+use super::{http_server::ProxyState, RateLimit, RateLimitConfig};
+
+/// Header with the maximum number of requests per minute.
+pub const X_RATELIMIT_LIMIT_REQUESTS: &str = "x-ratelimit-limit-requests";
+
+/// Header with the number of requests remaining before the limit is reached.
+pub const X_RATELIMIT_REMAINING_REQUESTS: &str = "x-ratelimit-remaining-requests";
+
+/// Header with the number of seconds until the request limit is fully replenished.
+pub const X_RATELIMIT_RESET_REQUESTS: &str = "x-ratelimit-reset-requests";
+
+/// Header with the maximum number of tokens per minute.
+pub const X_RATELIMIT_LIMIT_TOKENS: &str = "x-ratelimit-limit-tokens";
+
+/// Header with the number of tokens remaining before the limit is reached.
+pub const X_RATELIMIT_REMAINING_TOKENS: &str = "x-ratelimit-remaining-tokens";
+
+/// Header with the number of seconds until the token limit is fully replenished.
+pub const X_RATELIMIT_RESET_TOKENS: &str = "x-ratelimit-reset-tokens";
+
+/// Number of seconds over which the per minute limits are replenished.
+const SECONDS_PER_MINUTE: f64 = 60.0;
+
+/// Time after which idle buckets are fully replenished, and can be evicted.
+const IDLE_BUCKETS_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A token bucket, holding up to `capacity` units and refilled continuously
+/// at `capacity` units per minute.
+#[derive(Clone, Copy, Debug)]
+struct TokenBucket {
+    /// Maximum number of units in the bucket.
+    capacity: f64,
+    /// Number of units currently available.
+    available: f64,
+    /// The last time the bucket was refilled.
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    #[allow(clippy::cast_precision_loss)]
+    const fn new(per_minute: u64, now: Instant) -> Self {
+        let capacity = per_minute as f64;
+        Self {
+            capacity,
+            available: capacity,
+            last_refill: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.available = self
+            .capacity
+            .min(self.available + elapsed.as_secs_f64() * self.capacity / SECONDS_PER_MINUTE);
+        self.last_refill = now;
+    }
+
+    /// Caps a cost to the capacity of the bucket, so that requests larger than
+    /// the limit can still go through once the bucket is full.
+    #[allow(clippy::cast_precision_loss)]
+    fn cost(&self, amount: u64) -> f64 {
+        (amount as f64).min(self.capacity)
+    }
+
+    /// Time to wait until `amount` units are available.
+    fn wait_time(&self, amount: u64) -> Duration {
+        let missing = self.cost(amount) - self.available;
+        if missing <= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(missing * SECONDS_PER_MINUTE / self.capacity)
+    }
+
+    fn consume(&mut self, amount: u64) {
+        self.available -= self.cost(amount);
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn remaining(&self) -> u64 {
+        self.available.max(0.0).floor() as u64
+    }
+
+    fn time_until_full(&self) -> Duration {
+        Duration::from_secs_f64(
+            (self.capacity - self.available).max(0.0) * SECONDS_PER_MINUTE / self.capacity,
+        )
+    }
+
+    /// Whether the bucket was not used for long enough to be fully replenished, in which case
+    /// it is the same as a new bucket.
+    fn is_idle(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.last_refill) >= IDLE_BUCKETS_TIMEOUT
+    }
+}
+
+/// The request and token buckets enforcing a single `RateLimit`.
+#[derive(Clone, Copy, Debug)]
+struct RateLimitBuckets {
+    limit: RateLimit,
+    requests: TokenBucket,
+    tokens: TokenBucket,
+}
+
+impl RateLimitBuckets {
+    const fn new(limit: RateLimit, now: Instant) -> Self {
+        Self {
+            limit,
+            requests: TokenBucket::new(limit.requests_per_minute, now),
+            tokens: TokenBucket::new(limit.tokens_per_minute, now),
+        }
+    }
+
+    /// Refills the buckets, resetting them if the limit has changed (e.g. the
+    /// user moved to another tier).
+    fn refill(&mut self, limit: RateLimit, now: Instant) {
+        if self.limit == limit {
+            self.requests.refill(now);
+            self.tokens.refill(now);
+        } else {
+            *self = Self::new(limit, now);
+        }
+    }
+
+    fn wait_time(&self, num_tokens: u64) -> Duration {
+        self.requests
+            .wait_time(1)
+            .max(self.tokens.wait_time(num_tokens))
+    }
+
+    fn consume(&mut self, num_tokens: u64) {
+        self.requests.consume(1);
+        self.tokens.consume(num_tokens);
+    }
+
+    fn is_idle(&self, now: Instant) -> bool {
+        self.requests.is_idle(now) && self.tokens.is_idle(now)
+    }
+
+    fn status(&self) -> RateLimitStatus {
+        RateLimitStatus {
+            limit_requests: self.limit.requests_per_minute,
+            remaining_requests: self.requests.remaining(),
+            reset_requests: self.requests.time_until_full(),
+            limit_tokens: self.limit.tokens_per_minute,
+            remaining_tokens: self.tokens.remaining(),
+            reset_tokens: self.tokens.time_until_full(),
+        }
+    }
+}
+
+/// The buckets of a user, and of each of the user's API tokens.
+#[derive(Debug)]
+struct UserBuckets {
+    user: RateLimitBuckets,
+    api_tokens: HashMap<i64, RateLimitBuckets>,
+}
+
+/// The state of the rate limits of a request, reported to clients through the
+/// `x-ratelimit-*` headers.
+///
+/// When both the user and the API token limits apply, the most restrictive one
+/// is reported for each of requests and tokens.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimitStatus {
+    /// Maximum number of requests per minute.
+    pub limit_requests: u64,
+    /// Number of requests remaining.
+    pub remaining_requests: u64,
+    /// Time until the request limit is fully replenished.
+    pub reset_requests: Duration,
+    /// Maximum number of tokens per minute.
+    pub limit_tokens: u64,
+    /// Number of tokens remaining.
+    pub remaining_tokens: u64,
+    /// Time until the token limit is fully replenished.
+    pub reset_tokens: Duration,
+}
+
+impl RateLimitStatus {
+    const fn most_restrictive(self, other: Self) -> Self {
+        let (limit_requests, remaining_requests, reset_requests) =
+            if other.remaining_requests < self.remaining_requests {
+                (
+                    other.limit_requests,
+                    other.remaining_requests,
+                    other.reset_requests,
+                )
+            } else {
+                (
+                    self.limit_requests,
+                    self.remaining_requests,
+                    self.reset_requests,
+                )
+            };
+        let (limit_tokens, remaining_tokens, reset_tokens) =
+            if other.remaining_tokens < self.remaining_tokens {
+                (
+                    other.limit_tokens,
+                    other.remaining_tokens,
+                    other.reset_tokens,
+                )
+            } else {
+                (self.limit_tokens, self.remaining_tokens, self.reset_tokens)
+            };
+        Self {
+            limit_requests,
+            remaining_requests,
+            reset_requests,
+            limit_tokens,
+            remaining_tokens,
+            reset_tokens,
+        }
+    }
+
+    /// Inserts the `x-ratelimit-*` headers into a response.
+    pub fn insert_headers(&self, headers: &mut HeaderMap) {
+        headers.insert(X_RATELIMIT_LIMIT_REQUESTS, self.limit_requests.into());
+        headers.insert(
+            X_RATELIMIT_REMAINING_REQUESTS,
+            self.remaining_requests.into(),
+        );
+        headers.insert(
+            X_RATELIMIT_RESET_REQUESTS,
+            ceil_secs(self.reset_requests).into(),
+        );
+        headers.insert(X_RATELIMIT_LIMIT_TOKENS, self.limit_tokens.into());
+        headers.insert(X_RATELIMIT_REMAINING_TOKENS, self.remaining_tokens.into());
+        headers.insert(
+            X_RATELIMIT_RESET_TOKENS,
+            ceil_secs(self.reset_tokens).into(),
+        );
+    }
+}
+
+/// A request rejected by the rate limiter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimitExceeded {
+    /// Time to wait before the request can be accepted.
+    pub retry_after: Duration,
+    /// The state of the rate limits when the request was rejected.
+    pub status: RateLimitStatus,
+}
+
+impl RateLimitExceeded {
+    /// Inserts the `Retry-After` and `x-ratelimit-*` headers into a response.
+    pub fn insert_headers(&self, headers: &mut HeaderMap) {
+        self.status.insert_headers(headers);
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from(ceil_secs(self.retry_after).max(1)),
+        );
+    }
+}
+
+/// Rounds a duration up to a whole number of seconds.
+fn ceil_secs(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+/// Token bucket rate limiter for authenticated requests.
+///
+/// Each user has a pair of buckets (requests per minute and tokens per minute) set by
+/// the user's tier, and each API token of the user has its own pair of buckets when the
+/// tier sets per token limits. A request is accepted only if all the buckets that apply
+/// to it have enough capacity, in which case it is charged to all of them.
+#[derive(Debug)]
+pub struct RateLimiter {
+    /// The rate limit tiers.
+    config: RateLimitConfig,
+    /// The buckets of each user, indexed by user id.
+    buckets: DashMap<i64, UserBuckets>,
+}
+
+impl RateLimiter {
+    /// Creates a new rate limiter.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the default tier is not defined, or if any of the limits is zero.
+    pub fn new(config: RateLimitConfig) -> anyhow::Result<Self> {
+        if !config.tiers.contains_key(&config.default_tier) {
+            anyhow::bail!(
+                "Default rate limit tier `{}` is not defined",
+                config.default_tier
+            );
+        }
+        for (name, tier) in &config.tiers {
+            for limit in std::iter::once(&tier.user).chain(tier.api_token.as_ref()) {
+                if limit.requests_per_minute == 0 || limit.tokens_per_minute == 0 {
+                    anyhow::bail!("Rate limit tier `{name}` has a zero limit");
+                }
+            }
+        }
+        Ok(Self {
+            config,
+            buckets: DashMap::new(),
+        })
+    }
+
+    /// Checks whether a request is within the rate limits of its user and API token,
+    /// and charges it to the corresponding buckets if so.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The id of the user making the request.
+    /// * `api_token_id` - The id of the API token used for the request.
+    /// * `num_tokens` - The estimated number of tokens (compute units) of the request.
+    ///
+    /// # Returns
+    ///
+    /// Returns the state of the rate limits after the request was accepted, or `None` if
+    /// no tier applies to the user.
+    ///
+    /// # Errors
+    ///
+    /// Returns `RateLimitExceeded` if any of the limits would be exceeded by the request.
+    pub fn check(
+        &self,
+        user_id: i64,
+        api_token_id: i64,
+        num_tokens: u64,
+    ) -> Result<Option<RateLimitStatus>, RateLimitExceeded> {
+        self.check_at(user_id, api_token_id, num_tokens, Instant::now())
+    }
+
+    fn check_at(
+        &self,
+        user_id: i64,
+        api_token_id: i64,
+        num_tokens: u64,
+        now: Instant,
+    ) -> Result<Option<RateLimitStatus>, RateLimitExceeded> {
+        let Some(tier) = self.config.tier_for_user(user_id) else {
+            return Ok(None);
+        };
+        let mut entry = self.buckets.entry(user_id).or_insert_with(|| UserBuckets {
+            user: RateLimitBuckets::new(tier.user, now),
+            api_tokens: HashMap::new(),
+        });
+        let UserBuckets { user, api_tokens } = &mut *entry;
+        user.refill(tier.user, now);
+        let mut api_token = match tier.api_token {
+            Some(limit) => {
+                let buckets = api_tokens
+                    .entry(api_token_id)
+                    .or_insert_with(|| RateLimitBuckets::new(limit, now));
+                buckets.refill(limit, now);
+                Some(buckets)
+            }
+            None => {
+                api_tokens.remove(&api_token_id);
+                None
+            }
+        };
+
+        let retry_after = api_token
+            .as_ref()
+            .map_or(Duration::ZERO, |buckets| buckets.wait_time(num_tokens))
+            .max(user.wait_time(num_tokens));
+        if retry_after.is_zero() {
+            user.consume(num_tokens);
+            if let Some(buckets) = api_token.as_mut() {
+                buckets.consume(num_tokens);
+            }
+        }
+
+        let status = api_token.map_or_else(
+            || user.status(),
+            |buckets| user.status().most_restrictive(buckets.status()),
+        );
+        drop(entry);
+
+        if retry_after.is_zero() {
+            Ok(Some(status))
+        } else {
+            Err(RateLimitExceeded {
+                retry_after,
+                status,
+            })
+        }
+    }
+
+    /// Evicts the buckets that were not used for long enough to be fully replenished, so that
+    /// the buckets of users and API tokens that stopped sending requests do not pile up.
+    pub fn evict_idle_buckets(&self) {
+        self.evict_idle_buckets_at(Instant::now());
+    }
+
+    fn evict_idle_buckets_at(&self, now: Instant) {
+        self.buckets.retain(|_, UserBuckets { user, api_tokens }| {
+            api_tokens.retain(|_, buckets| !buckets.is_idle(now));
+            !user.is_idle(now) || !api_tokens.is_empty()
+        });
+    }
+}
+
+/// Evicts the idle buckets of the rate limiter periodically, until the proxy shuts down.
+pub async fn run_rate_limiter_eviction(
+    state: ProxyState,
+    mut shutdown_receiver: watch::Receiver<bool>,
+) {
+    let Some(rate_limiter) = state.rate_limiter else {
+        return;
+    };
+    loop {
+        tokio::select! {
+            () = tokio::time::sleep(IDLE_BUCKETS_TIMEOUT) => rate_limiter.evict_idle_buckets(),
+            _ = shutdown_receiver.changed() => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::server::RateLimitTier;
+
+    fn rate_limiter() -> RateLimiter {
+        RateLimiter::new(RateLimitConfig {
+            default_tier: "free".to_string(),
+            tiers: HashMap::from([
+                (
+                    "free".to_string(),
+                    RateLimitTier {
+                        user_ids: vec![],
+                        user: RateLimit {
+                            requests_per_minute: 3,
+                            tokens_per_minute: 600,
+                        },
+                        api_token: Some(RateLimit {
+                            requests_per_minute: 2,
+                            tokens_per_minute: 600,
+                        }),
+                    },
+                ),
+                (
+                    "pro".to_string(),
+                    RateLimitTier {
+                        user_ids: vec![2],
+                        user: RateLimit {
+                            requests_per_minute: 60,
+                            tokens_per_minute: 6_000,
+                        },
+                        api_token: None,
+                    },
+                ),
+            ]),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_missing_default_tier() {
+        let result = RateLimiter::new(RateLimitConfig {
+            default_tier: "free".to_string(),
+            tiers: HashMap::new(),
+        });
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_request_limits_per_api_token_and_user() {
+        let rate_limiter = rate_limiter();
+        let now = Instant::now();
+
+        let status = rate_limiter.check_at(1, 1, 10, now).unwrap().unwrap();
+        assert_eq!(status.limit_requests, 2);
+        assert_eq!(status.remaining_requests, 1);
+        assert_eq!(status.remaining_tokens, 590);
+        rate_limiter.check_at(1, 1, 10, now).unwrap();
+
+        // The API token is out of requests, and refills one request every 30 seconds
+        let exceeded = rate_limiter.check_at(1, 1, 10, now).unwrap_err();
+        assert_eq!(exceeded.retry_after, Duration::from_secs(30));
+        assert_eq!(exceeded.status.remaining_requests, 0);
+
+        // Another API token of the same user is limited by the user limit
+        rate_limiter.check_at(1, 2, 10, now).unwrap();
+        let exceeded = rate_limiter.check_at(1, 2, 10, now).unwrap_err();
+        assert_eq!(exceeded.retry_after, Duration::from_secs(20));
+
+        // Buckets refill over time
+        let later = now + Duration::from_secs(30);
+        rate_limiter.check_at(1, 1, 10, later).unwrap();
+    }
+
+    #[test]
+    fn test_token_limits() {
+        let rate_limiter = rate_limiter();
+        let now = Instant::now();
+
+        rate_limiter.check_at(1, 1, 500, now).unwrap();
+        let exceeded = rate_limiter.check_at(1, 1, 200, now).unwrap_err();
+        // 100 tokens are missing, at 10 tokens per second
+        assert_eq!(exceeded.retry_after, Duration::from_secs(10));
+
+        // Requests larger than the limit are accepted once the bucket is full
+        let later = now + Duration::from_secs(60);
+        let status = rate_limiter.check_at(1, 1, 1_000, later).unwrap().unwrap();
+        assert_eq!(status.remaining_tokens, 0);
+    }
+
+    #[test]
+    fn test_user_tiers() {
+        let rate_limiter = rate_limiter();
+        let now = Instant::now();
+
+        let status = rate_limiter.check_at(2, 1, 10, now).unwrap().unwrap();
+        assert_eq!(status.limit_requests, 60);
+        assert_eq!(status.remaining_requests, 59);
+        assert_eq!(status.limit_tokens, 6_000);
+    }
+
+    #[test]
+    fn test_idle_buckets_are_evicted() {
+        let rate_limiter = rate_limiter();
+        let now = Instant::now();
+
+        rate_limiter.check_at(1, 1, 10, now).unwrap();
+        rate_limiter
+            .check_at(1, 2, 10, now + Duration::from_secs(30))
+            .unwrap();
+        rate_limiter.check_at(2, 1, 10, now).unwrap();
+
+        // The first API token of user 1 and user 2 are idle, user 1 is still in use
+        rate_limiter.evict_idle_buckets_at(now + Duration::from_secs(60));
+        assert!(!rate_limiter.buckets.contains_key(&2));
+        let buckets = rate_limiter.buckets.get(&1).unwrap();
+        assert!(!buckets.api_tokens.contains_key(&1));
+        assert!(buckets.api_tokens.contains_key(&2));
+        drop(buckets);
+
+        rate_limiter.evict_idle_buckets_at(now + Duration::from_secs(90));
+        assert!(rate_limiter.buckets.is_empty());
+    }
+
+    #[test]
+    fn test_rate_limit_headers() {
+        let mut headers = HeaderMap::new();
+        RateLimitExceeded {
+            retry_after: Duration::from_millis(1_500),
+            status: RateLimitStatus {
+                limit_requests: 2,
+                remaining_requests: 0,
+                reset_requests: Duration::from_millis(59_500),
+                limit_tokens: 600,
+                remaining_tokens: 580,
+                reset_tokens: Duration::from_secs(2),
+            },
+        }
+        .insert_headers(&mut headers);
+        assert_eq!(headers[RETRY_AFTER], "2");
+        assert_eq!(headers[X_RATELIMIT_LIMIT_REQUESTS], "2");
+        assert_eq!(headers[X_RATELIMIT_REMAINING_REQUESTS], "0");
+        assert_eq!(headers[X_RATELIMIT_RESET_REQUESTS], "60");
+        assert_eq!(headers[X_RATELIMIT_REMAINING_TOKENS], "580");
+        assert_eq!(headers[X_RATELIMIT_RESET_TOKENS], "2");
+    }
+}
@@ -0,0 +1,413 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use atoma_state::types::AtomaAtomaStateManagerEvent;
+use blake2::digest::consts::U32;
+use blake2::{Blake2b, Digest};
+use flume::Sender;
+use serde_json::{Map, Value};
+use sqlx::types::chrono::Utc;
+use tracing::{error, instrument};
+
+use super::{
+    handlers::{
+        chat_completions::CHAT_COMPLETIONS_PATH, embeddings::EMBEDDINGS_PATH, query_state_manager,
+    },
+    CacheHitBilling, ResponseCacheConfig, MODEL,
+};
+
+/// Header telling whether the response was served from the response cache, `HIT` or `MISS`.
+///
+/// It is only set on the responses of cacheable requests.
+pub const X_ATOMA_CACHE: &str = "x-atoma-cache";
+
+/// Value of the cache header, for responses served from the cache.
+pub const CACHE_HIT: &str = "HIT";
+
+/// Value of the cache header, for responses of cacheable requests processed by a node.
+pub const CACHE_MISS: &str = "MISS";
+
+/// Fields of the requests that do not change their responses, and are left out of the cache key.
+const IGNORED_FIELDS: [&str; 3] = ["user", "stream", "stream_options"];
+
+/// Outcome of the lookup of a request in the response cache.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CacheLookup {
+    /// The request is not cacheable, or the response cache is disabled.
+    Uncacheable,
+    /// The cached response of the request.
+    Hit(Value),
+    /// No response is cached for the request, its response is cached under this key once processed.
+    Miss(String),
+}
+
+/// Exact-match cache of the responses of embeddings and deterministic chat completion requests.
+///
+/// Responses are cached in memory, up to a maximum total size, and optionally in Postgres,
+/// to be shared by all the proxy instances.
+pub struct ResponseCache {
+    /// Time a response stays cached.
+    ttl: Duration,
+    /// How users are charged for the requests served from the cache.
+    hit_billing: CacheHitBilling,
+    /// Whether responses are also cached in Postgres.
+    postgres: bool,
+    /// The in-memory tier of the cache.
+    memory: Mutex<MemoryTier>,
+}
+
+/// A response cached in memory.
+struct CachedResponse {
+    /// The response.
+    response: Value,
+    /// Size of the JSON encoded response, in bytes.
+    size: usize,
+    /// When the response expires.
+    expires_at: Instant,
+    /// Insertion sequence number, used to tell apart stale eviction entries.
+    sequence: u64,
+}
+
+/// Responses cached in memory, evicted in insertion order once the maximum size is reached.
+struct MemoryTier {
+    /// Maximum total size of the cached responses, in bytes.
+    max_bytes: usize,
+    /// Total size of the cached responses, in bytes.
+    total_bytes: usize,
+    /// The cached responses, by cache key.
+    entries: HashMap<String, CachedResponse>,
+    /// Cache keys, with the sequence number of their insertion, oldest first.
+    eviction_queue: VecDeque<(u64, String)>,
+    /// Sequence number of the next insertion.
+    next_sequence: u64,
+}
+
+impl MemoryTier {
+    fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            total_bytes: 0,
+            entries: HashMap::new(),
+            eviction_queue: VecDeque::new(),
+            next_sequence: 0,
+        }
+    }
+
+    fn get(&mut self, key: &str, now: Instant) -> Option<Value> {
+        let entry = self.entries.get(key)?;
+        if entry.expires_at <= now {
+            self.remove(key);
+            return None;
+        }
+        Some(entry.response.clone())
+    }
+
+    fn insert(&mut self, key: String, response: Value, size: usize, expires_at: Instant) {
+        if size > self.max_bytes {
+            return;
+        }
+        self.remove(&key);
+        while self.total_bytes + size > self.max_bytes {
+            let Some((sequence, oldest_key)) = self.eviction_queue.pop_front() else {
+                break;
+            };
+            if self
+                .entries
+                .get(&oldest_key)
+                .is_some_and(|entry| entry.sequence == sequence)
+            {
+                self.remove(&oldest_key);
+            }
+        }
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+        self.total_bytes += size;
+        self.eviction_queue.push_back((sequence, key.clone()));
+        self.entries.insert(
+            key,
+            CachedResponse {
+                response,
+                size,
+                expires_at,
+                sequence,
+            },
+        );
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.total_bytes -= entry.size;
+        }
+    }
+}
+
+impl ResponseCache {
+    /// Creates a new response cache, from its configuration.
+    #[must_use]
+    pub fn new(config: &ResponseCacheConfig) -> Self {
+        Self {
+            ttl: Duration::from_secs(config.ttl_secs),
+            hit_billing: config.hit_billing,
+            postgres: config.postgres,
+            memory: Mutex::new(MemoryTier::new(config.max_memory_bytes)),
+        }
+    }
+
+    /// How users are charged for the requests served from the cache.
+    #[must_use]
+    pub const fn hit_billing(&self) -> CacheHitBilling {
+        self.hit_billing
+    }
+
+    /// Computes the cache key of a request, if its response can be cached.
+    ///
+    /// Embeddings requests are always cacheable. Chat completion requests are only cacheable
+    /// when they are deterministic, that is with a `temperature` of 0 and a `seed`, and when
+    /// they are not streamed.
+    ///
+    /// The key is the hash of the user, of the endpoint and of the request, with its fields sorted
+    /// and without the fields that do not change the response. Responses are never shared between
+    /// users, so that a user cannot tell from the cache header which requests other users made.
+    #[must_use]
+    pub fn cache_key(endpoint: &str, user_id: i64, payload: &Value) -> Option<String> {
+        let model = payload.get(MODEL)?.as_str()?;
+        match endpoint {
+            EMBEDDINGS_PATH => {}
+            CHAT_COMPLETIONS_PATH => {
+                let is_streaming = payload
+                    .get("stream")
+                    .and_then(Value::as_bool)
+                    .unwrap_or_default();
+                let is_deterministic = payload.get("temperature").and_then(Value::as_f64)
+                    == Some(0.0)
+                    && payload.get("seed").is_some_and(|seed| !seed.is_null());
+                if is_streaming || !is_deterministic {
+                    return None;
+                }
+            }
+            _ => return None,
+        }
+        let mut request = payload.as_object()?.clone();
+        for field in IGNORED_FIELDS {
+            request.remove(field);
+        }
+        let request = normalize(Value::Object(request));
+
+        let mut hasher = Blake2b::<U32>::new();
+        hasher.update(user_id.to_le_bytes());
+        hasher.update(endpoint.as_bytes());
+        hasher.update([0]);
+        hasher.update(model.as_bytes());
+        hasher.update([0]);
+        hasher.update(request.to_string().as_bytes());
+        Some(
+            hasher
+                .finalize()
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect(),
+        )
+    }
+
+    /// Retrieves the cached response of a request, from memory first, then from Postgres.
+    ///
+    /// Failures of the Postgres tier are logged, and treated as cache misses.
+    #[instrument(level = "debug", skip(self, state_manager_sender))]
+    pub async fn get(
+        &self,
+        state_manager_sender: &Sender<AtomaAtomaStateManagerEvent>,
+        key: &str,
+        endpoint: &str,
+    ) -> Option<Value> {
+        let response = self
+            .memory
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(key, Instant::now());
+        if response.is_some() || !self.postgres {
+            return response;
+        }
+        let response = query_state_manager(
+            state_manager_sender,
+            |result_sender| AtomaAtomaStateManagerEvent::GetCachedResponse {
+                key: key.to_string(),
+                result_sender,
+            },
+            endpoint,
+        )
+        .await
+        .inspect_err(|e| {
+            error!(
+                target = "atoma-service",
+                event = "response-cache",
+                "Failed to retrieve cached response: {e}"
+            );
+        })
+        .ok()??;
+        let response = serde_json::from_str::<Value>(&response).ok()?;
+        // NOTE: The expiration of the response in Postgres is not known, so it stays in memory for a whole TTL
+        self.insert_in_memory(key.to_string(), &response);
+        Some(response)
+    }
+
+    /// Caches the response of a request, in memory, and in Postgres if enabled.
+    #[instrument(level = "debug", skip(self, state_manager_sender, response))]
+    pub fn insert(
+        &self,
+        state_manager_sender: &Sender<AtomaAtomaStateManagerEvent>,
+        key: String,
+        model: &str,
+        endpoint: &str,
+        response: &Value,
+    ) {
+        if self.postgres {
+            let expires_at = Utc::now()
+                + sqlx::types::chrono::Duration::from_std(self.ttl)
+                    .unwrap_or(sqlx::types::chrono::Duration::MAX);
+            if let Err(e) =
+                state_manager_sender.send(AtomaAtomaStateManagerEvent::InsertCachedResponse {
+                    key: key.clone(),
+                    model: model.to_string(),
+                    endpoint: endpoint.to_string(),
+                    response: response.to_string(),
+                    expires_at,
+                })
+            {
+                error!(
+                    target = "atoma-service",
+                    event = "response-cache",
+                    "Failed to cache response: {e}"
+                );
+            }
+        }
+        self.insert_in_memory(key, response);
+    }
+
+    fn insert_in_memory(&self, key: String, response: &Value) {
+        let size = response.to_string().len();
+        self.memory
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(key, response.clone(), size, Instant::now() + self.ttl);
+    }
+}
+
+/// Sorts the fields of the objects of a JSON value, recursively.
+fn normalize(value: Value) -> Value {
+    match value {
+        Value::Object(object) => {
+            let mut fields = object.into_iter().collect::<Vec<_>>();
+            fields.sort_by(|(a, _), (b, _)| a.cmp(b));
+            Value::Object(
+                fields
+                    .into_iter()
+                    .map(|(field, value)| (field, normalize(value)))
+                    .collect::<Map<_, _>>(),
+            )
+        }
+        Value::Array(values) => Value::Array(values.into_iter().map(normalize).collect()),
+        value => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_cache_key_normalizes_requests() {
+        let key = ResponseCache::cache_key(
+            EMBEDDINGS_PATH,
+            1,
+            &json!({"model": "e5", "input": "hello", "user": "user-1"}),
+        );
+        assert!(key.is_some());
+        assert_eq!(
+            key,
+            ResponseCache::cache_key(
+                EMBEDDINGS_PATH,
+                1,
+                &json!({"input": "hello", "model": "e5"})
+            )
+        );
+        assert_ne!(
+            key,
+            ResponseCache::cache_key(
+                EMBEDDINGS_PATH,
+                1,
+                &json!({"model": "e5", "input": "world"})
+            )
+        );
+        assert_ne!(
+            key,
+            ResponseCache::cache_key(
+                EMBEDDINGS_PATH,
+                1,
+                &json!({"model": "e6", "input": "hello"})
+            )
+        );
+        // Responses are not shared between users
+        assert_ne!(
+            key,
+            ResponseCache::cache_key(
+                EMBEDDINGS_PATH,
+                2,
+                &json!({"model": "e5", "input": "hello"})
+            )
+        );
+    }
+
+    #[test]
+    fn test_cache_key_only_caches_deterministic_chat_completions() {
+        let request = json!({
+            "model": "llama",
+            "messages": [{"role": "user", "content": "hello"}],
+            "temperature": 0,
+            "seed": 42,
+        });
+        assert!(ResponseCache::cache_key(CHAT_COMPLETIONS_PATH, 1, &request).is_some());
+
+        let mut streaming = request.clone();
+        streaming["stream"] = json!(true);
+        assert!(ResponseCache::cache_key(CHAT_COMPLETIONS_PATH, 1, &streaming).is_none());
+
+        let mut sampled = request.clone();
+        sampled["temperature"] = json!(0.7);
+        assert!(ResponseCache::cache_key(CHAT_COMPLETIONS_PATH, 1, &sampled).is_none());
+
+        let mut unseeded = request.clone();
+        unseeded.as_object_mut().unwrap().remove("seed");
+        assert!(ResponseCache::cache_key(CHAT_COMPLETIONS_PATH, 1, &unseeded).is_none());
+
+        assert!(ResponseCache::cache_key("/v1/images/generations", 1, &request).is_none());
+    }
+
+    #[test]
+    fn test_memory_tier_evicts_oldest_responses() {
+        let now = Instant::now();
+        let expires_at = now + Duration::from_secs(60);
+        let mut memory = MemoryTier::new(10);
+        memory.insert("a".to_string(), json!(1), 4, expires_at);
+        memory.insert("b".to_string(), json!(2), 4, expires_at);
+        // Replacing a response moves it to the back of the eviction queue
+        memory.insert("a".to_string(), json!(3), 4, expires_at);
+        memory.insert("c".to_string(), json!(4), 4, expires_at);
+        assert_eq!(memory.get("a", now), Some(json!(3)));
+        assert_eq!(memory.get("b", now), None);
+        assert_eq!(memory.get("c", now), Some(json!(4)));
+        assert_eq!(memory.total_bytes, 8);
+
+        // Responses larger than the cache are never cached
+        memory.insert("d".to_string(), json!(5), 11, expires_at);
+        assert_eq!(memory.get("d", now), None);
+
+        // Expired responses are removed
+        assert_eq!(memory.get("a", expires_at), None);
+        assert_eq!(memory.total_bytes, 4);
+    }
+}
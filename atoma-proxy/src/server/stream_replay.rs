@@ -0,0 +1,386 @@
+use std::{
+    collections::VecDeque,
+    convert::Infallible,
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+
+use axum::response::sse::Event;
+use dashmap::DashMap;
+use flume::Sender;
+use futures::Stream;
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+use super::ResumableStreamsConfig;
+
+/// Header holding the identifier of a resumable stream, to resume it once the connection dropped.
+pub const X_ATOMA_STREAM_ID: &str = "x-atoma-stream-id";
+
+/// Header holding the identifier of the last event received by a client resuming a stream.
+pub const LAST_EVENT_ID: &str = "last-event-id";
+
+/// Signal sent to the task generating a stream, when its clients come and go.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClientSignal {
+    /// The last client of the stream disconnected before the end of the stream.
+    Disconnected,
+    /// A client subscribed to the stream, while no other client was connected.
+    Reconnected,
+}
+
+/// Events of a stream, and the progress of its clients.
+struct ReplayState {
+    /// The retained events, with their identifiers, in order.
+    events: VecDeque<(u64, Event)>,
+    /// Identifier of the next event of the stream.
+    next_event_id: u64,
+    /// Identifier of the first event not yet delivered to any client.
+    next_undelivered_event_id: u64,
+    /// Whether the stream ended.
+    done: bool,
+    /// Number of clients currently subscribed to the stream.
+    num_clients: usize,
+}
+
+/// Outcome of reading the next event of a stream.
+enum NextEvent {
+    /// The event is available.
+    Ready(Event),
+    /// The event is not generated yet.
+    Pending,
+    /// The stream ended, or the event was evicted.
+    End,
+}
+
+/// Events of a chat completion stream, retained to be delivered to its clients and replayed to
+/// the clients resuming the stream.
+///
+/// Each event is given an identifier, its index in the stream. Events are retained until they are
+/// delivered, and then up to `max_events` delivered events are kept to be replayed.
+pub struct StreamReplay {
+    /// The user that created the stream, the only one allowed to resume it.
+    user_id: i64,
+    /// The endpoint the stream was created on.
+    endpoint: String,
+    /// Maximum number of delivered events retained.
+    max_events: usize,
+    /// Sender of the signals to the task generating the stream.
+    client_signal: Sender<ClientSignal>,
+    /// The events of the stream, and the progress of its clients.
+    state: Mutex<ReplayState>,
+    /// Notifies the clients waiting for new events.
+    notify: Notify,
+}
+
+impl StreamReplay {
+    /// Creates the replay buffer of a stream.
+    pub fn new(
+        user_id: i64,
+        endpoint: String,
+        max_events: usize,
+        client_signal: Sender<ClientSignal>,
+    ) -> Self {
+        Self {
+            user_id,
+            endpoint,
+            max_events,
+            client_signal,
+            state: Mutex::new(ReplayState {
+                events: VecDeque::new(),
+                next_event_id: 0,
+                next_undelivered_event_id: 0,
+                done: false,
+                num_clients: 0,
+            }),
+            notify: Notify::new(),
+        }
+    }
+
+    /// Returns the user that created the stream.
+    pub const fn user_id(&self) -> i64 {
+        self.user_id
+    }
+
+    /// Returns the endpoint the stream was created on.
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    /// Appends an event to the stream, and evicts the oldest delivered events beyond the
+    /// maximum number of retained events.
+    pub fn push(&self, event: Event) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let event_id = state.next_event_id;
+        state.next_event_id += 1;
+        state
+            .events
+            .push_back((event_id, event.id(event_id.to_string())));
+        self.evict_delivered_events(&mut state);
+        drop(state);
+        self.notify.notify_waiters();
+    }
+
+    /// Marks the stream as ended, its clients are disconnected once they read all its events.
+    pub fn finish(&self) {
+        self.state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .done = true;
+        self.notify.notify_waiters();
+    }
+
+    /// Subscribes a client to the stream, from the event following `last_event_id`, or from the
+    /// first event of the stream.
+    ///
+    /// Returns `None` if the requested events are no longer retained, or do not exist.
+    pub fn subscribe(
+        self: &Arc<Self>,
+        last_event_id: Option<u64>,
+    ) -> Option<impl Stream<Item = Result<Event, Infallible>> + Send + 'static> {
+        let next_event_id = last_event_id.map_or(Some(0), |id| id.checked_add(1))?;
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let first_retained_event_id = state
+            .events
+            .front()
+            .map_or(state.next_event_id, |(id, _)| *id);
+        if next_event_id < first_retained_event_id || next_event_id > state.next_event_id {
+            return None;
+        }
+        state.num_clients += 1;
+        if state.num_clients == 1 {
+            let _ = self.client_signal.send(ClientSignal::Reconnected);
+        }
+        drop(state);
+        let cursor = ReplayCursor {
+            replay: Arc::clone(self),
+            next_event_id,
+        };
+        Some(futures::stream::unfold(cursor, |mut cursor| async move {
+            let replay = Arc::clone(&cursor.replay);
+            loop {
+                // NOTE: The notification is registered before reading the state, not to miss
+                // events pushed in between
+                let notified = replay.notify.notified();
+                tokio::pin!(notified);
+                notified.as_mut().enable();
+                match replay.next_event(cursor.next_event_id) {
+                    NextEvent::Ready(event) => {
+                        cursor.next_event_id += 1;
+                        return Some((Ok(event), cursor));
+                    }
+                    NextEvent::Pending => notified.await,
+                    NextEvent::End => return None,
+                }
+            }
+        }))
+    }
+
+    /// Reads the event `event_id` of the stream, and marks it as delivered.
+    fn next_event(&self, event_id: u64) -> NextEvent {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let first_retained_event_id = state
+            .events
+            .front()
+            .map_or(state.next_event_id, |(id, _)| *id);
+        if event_id < first_retained_event_id {
+            warn!(
+                target = "atoma-service-streamer",
+                "Event {event_id} was evicted before being delivered to a slow client, ending its stream"
+            );
+            return NextEvent::End;
+        }
+        let Some((_, event)) = state
+            .events
+            .get((event_id - first_retained_event_id) as usize)
+        else {
+            return if state.done {
+                NextEvent::End
+            } else {
+                NextEvent::Pending
+            };
+        };
+        let event = event.clone();
+        state.next_undelivered_event_id = state.next_undelivered_event_id.max(event_id + 1);
+        self.evict_delivered_events(&mut state);
+        NextEvent::Ready(event)
+    }
+
+    /// Evicts the oldest delivered events, beyond the maximum number of retained events.
+    fn evict_delivered_events(&self, state: &mut ReplayState) {
+        while state.events.len() > self.max_events
+            && state
+                .events
+                .front()
+                .is_some_and(|(id, _)| *id < state.next_undelivered_event_id)
+        {
+            state.events.pop_front();
+        }
+    }
+}
+
+/// Position of a client in a stream, unsubscribing the client when dropped.
+struct ReplayCursor {
+    /// The stream the client is subscribed to.
+    replay: Arc<StreamReplay>,
+    /// Identifier of the next event delivered to the client.
+    next_event_id: u64,
+}
+
+impl Drop for ReplayCursor {
+    fn drop(&mut self) {
+        let mut state = self
+            .replay
+            .state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        state.num_clients -= 1;
+        if state.num_clients == 0 && !state.done {
+            info!(
+                target = "atoma-service-streamer",
+                "Last client disconnected from stream after {} events", self.next_event_id
+            );
+            let _ = self.replay.client_signal.send(ClientSignal::Disconnected);
+        }
+    }
+}
+
+/// The resumable streams of the proxy, indexed by stream identifier.
+pub struct StreamReplayRegistry {
+    /// Time the generation goes on after the client disconnected, waiting for it to resume.
+    resume_window: Duration,
+    /// Time a stream can still be resumed after it ended.
+    retention: Duration,
+    /// Maximum number of delivered events retained per stream.
+    max_events: usize,
+    /// The streams, by identifier.
+    streams: DashMap<String, Arc<StreamReplay>>,
+}
+
+impl StreamReplayRegistry {
+    /// Creates an empty registry of resumable streams.
+    pub fn new(config: &ResumableStreamsConfig) -> Self {
+        Self {
+            resume_window: Duration::from_secs(config.resume_window_secs),
+            retention: Duration::from_secs(config.retention_secs),
+            max_events: config.max_events,
+            streams: DashMap::new(),
+        }
+    }
+
+    /// Returns the time the generation goes on after the client disconnected.
+    pub const fn resume_window(&self) -> Duration {
+        self.resume_window
+    }
+
+    /// Returns the time a stream can still be resumed after it ended.
+    pub const fn retention(&self) -> Duration {
+        self.retention
+    }
+
+    /// Creates the replay buffer of a new stream, and registers it to be resumed.
+    pub fn register(
+        &self,
+        stream_id: String,
+        user_id: i64,
+        endpoint: String,
+        client_signal: Sender<ClientSignal>,
+    ) -> Arc<StreamReplay> {
+        let replay = Arc::new(StreamReplay::new(
+            user_id,
+            endpoint,
+            self.max_events,
+            client_signal,
+        ));
+        self.streams.insert(stream_id, Arc::clone(&replay));
+        replay
+    }
+
+    /// Returns the stream `stream_id`, if it can be resumed.
+    pub fn get(&self, stream_id: &str) -> Option<Arc<StreamReplay>> {
+        self.streams
+            .get(stream_id)
+            .map(|replay| Arc::clone(&replay))
+    }
+
+    /// Removes the stream `stream_id`, it can no longer be resumed.
+    pub fn remove(&self, stream_id: &str) {
+        self.streams.remove(stream_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    fn replay(max_events: usize) -> (Arc<StreamReplay>, flume::Receiver<ClientSignal>) {
+        let (client_signal_sender, client_signal_receiver) = flume::unbounded();
+        let replay = Arc::new(StreamReplay::new(
+            1,
+            "/v1/chat/completions".to_string(),
+            max_events,
+            client_signal_sender,
+        ));
+        (replay, client_signal_receiver)
+    }
+
+    fn event(data: &str) -> Event {
+        Event::default().data(data)
+    }
+
+    #[tokio::test]
+    async fn test_resume_replays_the_events_after_the_last_event_id() {
+        let (replay, client_signals) = replay(16);
+        let mut events = replay.subscribe(None).unwrap().boxed();
+        assert_eq!(client_signals.try_recv(), Ok(ClientSignal::Reconnected));
+        replay.push(event("a"));
+        replay.push(event("b"));
+        assert!(events.next().await.is_some());
+        drop(events);
+        assert_eq!(client_signals.try_recv(), Ok(ClientSignal::Disconnected));
+
+        replay.push(event("c"));
+        replay.finish();
+        let resumed = replay.subscribe(Some(0)).unwrap().collect::<Vec<_>>().await;
+        assert_eq!(resumed.len(), 2);
+        assert_eq!(client_signals.try_recv(), Ok(ClientSignal::Reconnected));
+        // Once the stream ended, dropping its last client is not a disconnection
+        assert!(client_signals.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_delivered_events_are_evicted_beyond_max_events() {
+        let (replay, _client_signals) = replay(1);
+        let mut events = replay.subscribe(None).unwrap().boxed();
+        for data in ["a", "b", "c"] {
+            replay.push(event(data));
+        }
+        // Undelivered events are never evicted
+        assert!(replay.subscribe(None).is_some());
+        for _ in 0..3 {
+            assert!(events.next().await.is_some());
+        }
+        assert!(replay.subscribe(None).is_none());
+        assert!(replay.subscribe(Some(0)).is_none());
+        assert!(replay.subscribe(Some(1)).is_some());
+        // Events that do not exist yet cannot be resumed from
+        assert!(replay.subscribe(Some(3)).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_subscriber_waits_for_new_events() {
+        let (replay, _client_signals) = replay(0);
+        let mut events = replay.subscribe(None).unwrap().boxed();
+        let pusher = {
+            let replay = Arc::clone(&replay);
+            tokio::spawn(async move {
+                replay.push(event("a"));
+                replay.finish();
+            })
+        };
+        assert!(events.next().await.is_some());
+        assert!(events.next().await.is_none());
+        pusher.await.unwrap();
+    }
+}
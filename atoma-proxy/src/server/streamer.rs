@@ -3,31 +3,40 @@
 
 use atoma_state::types::AtomaAtomaStateManagerEvent;
 use axum::body::Bytes;
+use axum::http::StatusCode;
 use axum::{response::sse::Event, Error};
-use flume::{RecvError, Sender};
-use futures::{FutureExt, Stream};
+use flume::Sender;
+use futures::Stream;
 use opentelemetry::KeyValue;
 use reqwest;
 use serde_json::Value;
 use std::{
     pin::Pin,
+    sync::Arc,
     task::{Context, Poll},
     time::Instant,
 };
 use tracing::{error, info, instrument, warn};
 
-use crate::server::handlers::{chat_completions::CHAT_COMPLETIONS_PATH, update_state_manager};
+use crate::server::handlers::{
+    chat_completions::CHAT_COMPLETIONS_PATH, record_request_usage, update_api_token_spend,
+    update_state_manager,
+};
 
 use super::handlers::chat_completions::CONFIDENTIAL_CHAT_COMPLETIONS_PATH;
 use super::handlers::metrics::{
     CANCELLED_STREAM_CHAT_COMPLETION_REQUESTS_PER_USER, CHAT_COMPLETIONS_COMPLETIONS_TOKENS,
     CHAT_COMPLETIONS_COMPLETIONS_TOKENS_PER_USER, CHAT_COMPLETIONS_INPUT_TOKENS,
     CHAT_COMPLETIONS_INPUT_TOKENS_PER_USER, CHAT_COMPLETIONS_INTER_TOKEN_GENERATION_TIME,
-    CHAT_COMPLETIONS_STREAMING_LATENCY_METRICS, CHAT_COMPLETIONS_TIME_TO_FIRST_TOKEN,
-    CHAT_COMPLETIONS_TOTAL_TOKENS, CHAT_COMPLETIONS_TOTAL_TOKENS_PER_USER,
+    CHAT_COMPLETIONS_PROXY_COUNTED_USAGE, CHAT_COMPLETIONS_STREAMING_LATENCY_METRICS,
+    CHAT_COMPLETIONS_TIME_TO_FIRST_TOKEN, CHAT_COMPLETIONS_TOTAL_TOKENS,
+    CHAT_COMPLETIONS_TOTAL_TOKENS_PER_USER, CHAT_COMPLETIONS_USAGE_DISCREPANCY_TOKENS,
     CHAT_COMPLETION_REQUESTS_PER_USER, TOTAL_COMPLETED_REQUESTS,
 };
+use super::handlers::verify::ResponseProof;
 use super::handlers::verify_response_hash_and_signature;
+use super::middleware::RequestMetadataExtension;
+use super::tokenizer::TokenCounter;
 
 /// The chunk that indicates the end of a streaming response
 const DONE_CHUNK: &str = "[DONE]";
@@ -47,6 +56,47 @@ const CHOICES: &str = "choices";
 /// The usage key
 const USAGE: &str = "usage";
 
+/// The delta key, of the choices of a chunk
+const DELTA: &str = "delta";
+
+/// The keys of the generated text, in the delta of a choice
+const DELTA_TEXT_KEYS: [&str; 2] = ["content", "reasoning_content"];
+
+/// Status recorded in the usage records of the streams whose client disconnected before their end
+/// (the non-standard `499 Client Closed Request` status).
+const CLIENT_CLOSED_REQUEST_STATUS: u16 = 499;
+
+/// Output delivered to the client when it disconnected.
+#[derive(Debug, Clone, Copy)]
+struct DeliveredOutput {
+    /// Length, in bytes, of the text generated when the client disconnected
+    text_len: usize,
+    /// Number of chunks generated when the client disconnected
+    num_chunks: i64,
+}
+
+/// Usage of a request, in tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Usage {
+    /// The number of input tokens
+    input_tokens: i64,
+    /// The number of output tokens
+    output_tokens: i64,
+    /// The total number of tokens
+    total_tokens: i64,
+}
+
+impl Usage {
+    /// Parses the usage reported by the node, if it is complete.
+    fn from_node_usage(usage: &Value) -> Option<Self> {
+        Some(Self {
+            input_tokens: usage.get("prompt_tokens")?.as_i64()?,
+            output_tokens: usage.get("completion_tokens")?.as_i64()?,
+            total_tokens: usage.get("total_tokens")?.as_i64()?,
+        })
+    }
+}
+
 /// A structure for streaming chat completion chunks.
 pub struct Streamer {
     /// The stream of bytes currently being processed
@@ -55,18 +105,12 @@ pub struct Streamer {
     status: StreamStatus,
     /// Estimated total tokens for the stream
     estimated_total_tokens: i64,
-    /// Stack small id
-    stack_small_id: i64,
     /// State manager sender
     state_manager_sender: Sender<AtomaAtomaStateManagerEvent>,
     /// Start time of the request
     start: Instant,
-    /// The user id which requested the inference
-    user_id: i64,
-    /// Model name
-    model_name: String,
-    /// Endpoint
-    endpoint: String,
+    /// The metadata of the request (user, API token, model, endpoint, selected stack and node)
+    metadata: RequestMetadataExtension,
     /// A chunk buffer (needed as some chunks might be split into multiple parts)
     chunk_buffer: String,
     /// The position in the keep-alive chunk buffer
@@ -84,6 +128,23 @@ pub struct Streamer {
     /// Number of generated tokens so far. It is only used when the client
     /// drops the connection, before the final chunk is processed.
     num_generated_tokens: i64,
+    /// Number of input tokens, as estimated by the proxy
+    num_input_tokens: i64,
+    /// The tokenizer of the model, used to count the generated tokens when the
+    /// node does not report the usage of the request. It is `None` for confidential
+    /// requests, whose chunks are encrypted.
+    tokenizer: Option<Arc<dyn TokenCounter>>,
+    /// The text generated so far, only accumulated when the tokenizer is set
+    generated_text: String,
+    /// The output delivered to the client, if it disconnected before the end of the stream.
+    /// The user is only billed for this output, while the stack's compute units are settled
+    /// with the usage of the request.
+    delivered_on_disconnect: Option<DeliveredOutput>,
+    /// The output delivered to the client, if it disconnected and may still resume the stream.
+    /// It is only billed for this output if it does not resume the stream in time.
+    delivered_on_detach: Option<DeliveredOutput>,
+    /// The proof of the last chunk signed by the node
+    last_proof: Option<ResponseProof>,
 }
 
 /// Represents the various states of a streaming process
@@ -106,38 +167,184 @@ impl Streamer {
         stream: impl Stream<Item = Result<Bytes, reqwest::Error>> + Send + 'static,
         state_manager_sender: Sender<AtomaAtomaStateManagerEvent>,
         stack_small_id: i64,
+        node_small_id: i64,
         num_input_tokens: i64,
         estimated_total_tokens: i64,
         start: Instant,
         user_id: i64,
+        api_token_id: i64,
         model_name: String,
         endpoint: String,
+        tokenizer: Option<Arc<dyn TokenCounter>>,
     ) -> Self {
         Self {
             stream: Box::pin(stream),
             status: StreamStatus::NotStarted,
             estimated_total_tokens,
-            stack_small_id,
             state_manager_sender,
             start,
-            user_id,
-            model_name,
-            endpoint,
+            metadata: RequestMetadataExtension {
+                node_id: node_small_id,
+                user_id,
+                api_token_id,
+                selected_stack_small_id: stack_small_id,
+                endpoint,
+                model_name,
+                ..Default::default()
+            },
             chunk_buffer: String::new(),
             keep_alive_pos: 0,
             first_token_generation_timer: Some(start),
             inter_stream_token_latency_timer: None,
             is_final_chunk_handled: false,
             num_generated_tokens: num_input_tokens,
+            num_input_tokens,
+            tokenizer,
+            generated_text: String::new(),
+            delivered_on_disconnect: None,
+            delivered_on_detach: None,
+            last_proof: None,
+        }
+    }
+
+    /// Records that the client disconnected, freezing the output it is billed for to the
+    /// output generated so far.
+    ///
+    /// If the client detached from the stream before, the output delivered when it detached
+    /// is billed.
+    pub fn client_disconnected(&mut self) {
+        if self.delivered_on_disconnect.is_none() {
+            self.delivered_on_disconnect = Some(
+                self.delivered_on_detach
+                    .take()
+                    .unwrap_or_else(|| self.delivered_output()),
+            );
+        }
+    }
+
+    /// Records that the client disconnected from a resumable stream, remembering the output
+    /// generated so far in case it does not resume the stream in time.
+    pub fn client_detached(&mut self) {
+        if self.delivered_on_detach.is_none() {
+            self.delivered_on_detach = Some(self.delivered_output());
+        }
+    }
+
+    /// Records that the client resumed the stream, it is billed for the whole output again.
+    pub fn client_resumed(&mut self) {
+        self.delivered_on_detach = None;
+    }
+
+    /// Returns the proof of the last chunk signed by the node, if any.
+    pub fn response_proof(&self) -> Option<&ResponseProof> {
+        self.last_proof.as_ref()
+    }
+
+    /// Returns the output generated so far.
+    fn delivered_output(&self) -> DeliveredOutput {
+        DeliveredOutput {
+            text_len: self.generated_text.len(),
+            num_chunks: self.num_generated_tokens - self.num_input_tokens,
+        }
+    }
+
+    /// Returns the usage of the request counted by the proxy, or else estimated from the number
+    /// of chunks generated so far, for streams that ended without usage.
+    fn partial_usage(&self) -> Usage {
+        self.proxy_usage().unwrap_or(Usage {
+            input_tokens: self.num_input_tokens,
+            output_tokens: self.num_generated_tokens - self.num_input_tokens,
+            total_tokens: self.num_generated_tokens,
+        })
+    }
+
+    /// Returns the usage billed to the user: the usage of the request, or only the output
+    /// delivered before the client disconnected.
+    fn billed_usage(&self, usage: Usage) -> Usage {
+        let Some(delivered) = self.delivered_on_disconnect else {
+            return usage;
+        };
+        let delivered_output_tokens = self
+            .tokenizer
+            .as_ref()
+            .and_then(|tokenizer| {
+                let delivered_text = self.generated_text.get(..delivered.text_len)?;
+                if delivered_text.is_empty() {
+                    return Some(0);
+                }
+                tokenizer.count_tokens(delivered_text).ok()
+            })
+            .map_or(delivered.num_chunks, |output_tokens| output_tokens as i64);
+        let output_tokens = delivered_output_tokens.min(usage.output_tokens);
+        Usage {
+            input_tokens: usage.input_tokens,
+            output_tokens,
+            total_tokens: usage.input_tokens + output_tokens,
+        }
+    }
+
+    /// Returns the status recorded in the usage record of the request.
+    ///
+    /// Streams ending without a final chunk, other than the ones whose client disconnected
+    /// or which completed without the node reporting their usage, failed on the node side.
+    fn usage_record_status(&self) -> StatusCode {
+        if self.delivered_on_disconnect.is_some() {
+            StatusCode::from_u16(CLIENT_CLOSED_REQUEST_STATUS).expect("499 is a valid status code")
+        } else if self.is_final_chunk_handled || self.status == StreamStatus::Completed {
+            StatusCode::OK
+        } else {
+            StatusCode::BAD_GATEWAY
         }
     }
 
+    /// Appends the text generated in a chunk to the text generated so far, if the
+    /// generated tokens are counted by the proxy.
+    fn accumulate_generated_text(&mut self, choices: &[Value]) {
+        if self.tokenizer.is_none() {
+            return;
+        }
+        for delta in choices.iter().filter_map(|choice| choice.get(DELTA)) {
+            for key in DELTA_TEXT_KEYS {
+                if let Some(text) = delta.get(key).and_then(Value::as_str) {
+                    self.generated_text.push_str(text);
+                }
+            }
+        }
+    }
+
+    /// Returns the usage of the request as counted by the proxy, from the estimated input tokens
+    /// and the generated text, if the proxy has the tokenizer of the model.
+    fn proxy_usage(&self) -> Option<Usage> {
+        let tokenizer = self.tokenizer.as_ref()?;
+        let output_tokens = if self.generated_text.is_empty() {
+            0
+        } else {
+            match tokenizer.count_tokens(&self.generated_text) {
+                Ok(output_tokens) => output_tokens as i64,
+                Err(e) => {
+                    error!(
+                        target = "atoma-service-streamer",
+                        level = "error",
+                        "Error counting the generated tokens: {e}"
+                    );
+                    return None;
+                }
+            }
+        };
+        Some(Usage {
+            input_tokens: self.num_input_tokens,
+            output_tokens,
+            total_tokens: self.num_input_tokens + output_tokens,
+        })
+    }
+
     /// Processes the final chunk of a streaming response, performing signature generation,
     /// token counting, and state updates.
     ///
     /// This method:
     /// 1. Signs the accumulated response data
-    /// 2. Extracts and validates token usage information
+    /// 2. Extracts and validates token usage information, falling back to the tokens
+    ///    counted by the proxy when the node does not report it
     /// 3. Updates the state manager with token counts
     /// 4. Calculates a total hash combining payload and response hashes
     /// 5. Updates the state manager with the total hash
@@ -145,8 +352,9 @@ impl Streamer {
     ///
     /// # Arguments
     ///
-    /// * `usage` - A JSON Value containing token usage information, expected to have a
-    ///             "total_tokens" field with an integer value
+    /// * `usage` - A JSON Value containing token usage information, expected to have
+    ///             "prompt_tokens", "completion_tokens" and "total_tokens" fields with integer values,
+    ///             or `None` if the stream ended without usage
     ///
     /// # Returns
     ///
@@ -154,7 +362,7 @@ impl Streamer {
     /// * `Event` - An SSE event containing the final message with signature
     /// * `Error` - An error that can occur during:
     ///   - Response signing
-    ///   - Token usage extraction
+    ///   - Token usage extraction, when the proxy cannot count the tokens either
     ///   - JSON serialization
     ///
     /// # State Updates
@@ -170,70 +378,84 @@ impl Streamer {
             estimated_total_tokens = self.estimated_total_tokens,
         )
     )]
-    fn handle_final_chunk(&mut self, usage: &Value) -> Result<(), Error> {
-        let input_tokens = usage
-            .get("prompt_tokens")
-            .and_then(serde_json::Value::as_i64)
-            .ok_or_else(|| {
-                error!(
-                    target = "atoma-service-streamer",
-                    level = "error",
-                    "Error getting prompt tokens from usage"
+    fn handle_final_chunk(&mut self, usage: Option<&Value>) -> Result<(), Error> {
+        let node_usage = usage.and_then(Usage::from_node_usage);
+        let usage = match (node_usage, self.proxy_usage()) {
+            (Some(node_usage), Some(proxy_usage)) => {
+                let discrepancy = node_usage.output_tokens.abs_diff(proxy_usage.output_tokens);
+                if discrepancy > 0 {
+                    info!(
+                        target = "atoma-service-streamer",
+                        level = "info",
+                        node_completion_tokens = node_usage.output_tokens,
+                        proxy_completion_tokens = proxy_usage.output_tokens,
+                        "Completion tokens reported by the node differ from the tokens counted by the proxy"
+                    );
+                }
+                CHAT_COMPLETIONS_USAGE_DISCREPANCY_TOKENS.add(
+                    discrepancy,
+                    &[KeyValue::new("model", self.metadata.model_name.clone())],
                 );
-                Error::new("Error getting prompt tokens from usage")
-            })?;
-        let output_tokens = usage
-            .get("completion_tokens")
-            .and_then(serde_json::Value::as_i64)
-            .ok_or_else(|| {
-                error!(
+                node_usage
+            }
+            (Some(node_usage), None) => node_usage,
+            (None, Some(proxy_usage)) => {
+                warn!(
                     target = "atoma-service-streamer",
-                    level = "error",
-                    "Error getting completion tokens from usage"
+                    level = "warn",
+                    "Node did not report the usage of the request, using the tokens counted by the proxy"
                 );
-                Error::new("Error getting completion tokens from usage")
-            })?;
-        let total_tokens = usage
-            .get("total_tokens")
-            .and_then(serde_json::Value::as_i64)
-            .ok_or_else(|| {
+                CHAT_COMPLETIONS_PROXY_COUNTED_USAGE.add(
+                    1,
+                    &[KeyValue::new("model", self.metadata.model_name.clone())],
+                );
+                proxy_usage
+            }
+            (None, None) => {
                 error!(
                     target = "atoma-service-streamer",
                     level = "error",
-                    "Error getting total tokens from usage"
+                    "Error getting prompt, completion and total tokens from usage"
                 );
-                Error::new("Error getting total tokens from usage")
-            })?;
+                return Err(Error::new(
+                    "Error getting prompt, completion and total tokens from usage",
+                ));
+            }
+        };
+        // NOTE: The stack is settled with the compute units used by the node, but users are only billed for the output
+        // they received, so metered balances, API token spend and the usage ledger are all charged the billed usage.
+        let billed_usage = self.billed_usage(usage);
         CHAT_COMPLETIONS_TOTAL_TOKENS.add(
-            total_tokens as u64,
-            &[KeyValue::new("model", self.model_name.clone())],
+            usage.total_tokens as u64,
+            &[KeyValue::new("model", self.metadata.model_name.clone())],
         );
         CHAT_COMPLETIONS_INPUT_TOKENS.add(
-            input_tokens as u64,
-            &[KeyValue::new("model", self.model_name.clone())],
+            usage.input_tokens as u64,
+            &[KeyValue::new("model", self.metadata.model_name.clone())],
         );
         CHAT_COMPLETIONS_COMPLETIONS_TOKENS.add(
-            output_tokens as u64,
-            &[KeyValue::new("model", self.model_name.clone())],
+            usage.output_tokens as u64,
+            &[KeyValue::new("model", self.metadata.model_name.clone())],
         );
         CHAT_COMPLETIONS_TOTAL_TOKENS_PER_USER.add(
-            total_tokens as u64,
-            &[KeyValue::new("user_id", self.user_id)],
+            billed_usage.total_tokens as u64,
+            &[KeyValue::new("user_id", self.metadata.user_id)],
         );
         CHAT_COMPLETIONS_INPUT_TOKENS_PER_USER.add(
-            input_tokens as u64,
-            &[KeyValue::new("user_id", self.user_id)],
+            billed_usage.input_tokens as u64,
+            &[KeyValue::new("user_id", self.metadata.user_id)],
         );
         CHAT_COMPLETIONS_COMPLETIONS_TOKENS_PER_USER.add(
-            output_tokens as u64,
-            &[KeyValue::new("user_id", self.user_id)],
+            billed_usage.output_tokens as u64,
+            &[KeyValue::new("user_id", self.metadata.user_id)],
         );
         if let Err(e) = update_state_manager(
             &self.state_manager_sender,
-            self.stack_small_id,
+            self.metadata.selected_stack_small_id,
             self.estimated_total_tokens,
-            total_tokens,
-            &self.endpoint,
+            usage.total_tokens,
+            billed_usage.total_tokens,
+            &self.metadata.endpoint,
         ) {
             error!(
                 target = "atoma-service-streamer",
@@ -245,9 +467,56 @@ impl Streamer {
                 "Error updating stack num tokens: {e:?}"
             )));
         }
+        if let Err(e) = update_api_token_spend(
+            &self.state_manager_sender,
+            self.metadata.api_token_id,
+            self.metadata.selected_stack_small_id,
+            billed_usage.total_tokens,
+            &self.metadata.endpoint,
+        ) {
+            error!(
+                target = "atoma-service-streamer",
+                level = "error",
+                "Error updating api token spend: {}",
+                e
+            );
+            return Err(Error::new(format!("Error updating api token spend: {e:?}")));
+        }
+        if let Err(e) = self.record_usage(billed_usage) {
+            error!(
+                target = "atoma-service-streamer",
+                level = "error",
+                "Error recording usage: {}",
+                e
+            );
+            return Err(Error::new(format!("Error recording usage: {e:?}")));
+        }
         self.is_final_chunk_handled = true;
         Ok(())
     }
+
+    /// Records the usage billed to the user for the request.
+    fn record_usage(&self, billed_usage: Usage) -> super::Result<()> {
+        record_request_usage(
+            &self.state_manager_sender,
+            &self.metadata,
+            billed_usage.input_tokens,
+            billed_usage.output_tokens,
+            self.start,
+            self.usage_record_status(),
+        )
+    }
+
+    /// Settles the request with the tokens counted by the proxy, when the stream ended without
+    /// the node reporting the usage of the request.
+    ///
+    /// Without a tokenizer, the request is settled when the streamer is dropped instead.
+    fn handle_final_chunk_without_usage(&mut self) -> Result<(), Error> {
+        if self.is_final_chunk_handled || self.tokenizer.is_none() {
+            return Ok(());
+        }
+        self.handle_final_chunk(None)
+    }
 }
 
 impl Stream for Streamer {
@@ -321,13 +590,14 @@ impl Stream for Streamer {
                 if let Some(timer) = self.first_token_generation_timer.take() {
                     CHAT_COMPLETIONS_TIME_TO_FIRST_TOKEN.record(
                         timer.elapsed().as_secs_f64(),
-                        &[KeyValue::new("model", self.model_name.clone())],
+                        &[KeyValue::new("model", self.metadata.model_name.clone())],
                     );
                 }
 
                 if chunk_str.starts_with(DONE_CHUNK) {
                     // This is the last chunk, meaning the inference streaming is complete
                     self.status = StreamStatus::Completed;
+                    self.handle_final_chunk_without_usage()?;
                     return Poll::Ready(None);
                 }
 
@@ -337,7 +607,7 @@ impl Stream for Streamer {
                             let elapsed = timer.elapsed();
                             CHAT_COMPLETIONS_INTER_TOKEN_GENERATION_TIME.record(
                                 elapsed.as_secs_f64(),
-                                &[KeyValue::new("model", self.model_name.clone())],
+                                &[KeyValue::new("model", self.metadata.model_name.clone())],
                             );
                         }
 
@@ -417,7 +687,7 @@ impl Stream for Streamer {
                 // We need to verify the hash when the endpoint is not confidential, as the node
                 // is not running within a secure enclave. Otherwise, the fact that the node can process requests
                 // with confidential data is proof of data integrity.
-                let verify_hash = self.endpoint != CONFIDENTIAL_CHAT_COMPLETIONS_PATH;
+                let verify_hash = self.metadata.endpoint != CONFIDENTIAL_CHAT_COMPLETIONS_PATH;
                 verify_response_hash_and_signature(&chunk, verify_hash).map_err(|e| {
                     error!(
                         target = "atoma-service-streamer",
@@ -427,8 +697,13 @@ impl Stream for Streamer {
                     );
                     Error::new(format!("Error verifying and signing response: {e:?}"))
                 })?;
+                self.last_proof = ResponseProof::from_response(
+                    &chunk,
+                    self.metadata.node_id,
+                    self.metadata.selected_stack_small_id,
+                );
 
-                if self.endpoint == CHAT_COMPLETIONS_PATH {
+                if self.metadata.endpoint == CHAT_COMPLETIONS_PATH {
                     let Some(choices) = chunk.get(CHOICES).and_then(|choices| choices.as_array())
                     else {
                         error!(
@@ -441,23 +716,25 @@ impl Stream for Streamer {
                         ))));
                     };
 
+                    self.accumulate_generated_text(choices);
+
                     if choices.is_empty() {
                         if let Some(usage) = chunk.get(USAGE) {
                             self.status = StreamStatus::Completed;
-                            self.handle_final_chunk(usage)?;
+                            self.handle_final_chunk(Some(usage))?;
                         }
-                    } else if let Some(usage) = chunk.get(USAGE) {
+                    } else if let Some(usage) = chunk.get(USAGE).filter(|usage| !usage.is_null()) {
                         info!(
                             target = "atoma-service-streamer",
                             level = "info",
                             "Client disconnected before the final chunk was processed, using usage transmitted by the node last live chunk to update the stack num tokens"
                         );
                         self.status = StreamStatus::Completed;
-                        self.handle_final_chunk(usage)?;
+                        self.handle_final_chunk(Some(usage))?;
                     }
                 } else if let Some(usage) = chunk.get(USAGE) {
                     self.status = StreamStatus::Completed;
-                    self.handle_final_chunk(usage)?;
+                    self.handle_final_chunk(Some(usage))?;
                 }
                 self.num_generated_tokens += 1;
                 Poll::Ready(Some(Ok(Event::default().json_data(&chunk)?)))
@@ -476,6 +753,7 @@ impl Stream for Streamer {
                     );
                 }
                 self.status = StreamStatus::Completed;
+                self.handle_final_chunk_without_usage()?;
                 Poll::Ready(None)
             }
             Poll::Pending => Poll::Pending,
@@ -499,8 +777,10 @@ impl Drop for Streamer {
     ///
     /// # State Updates
     ///
-    /// This method sends an event to the state manager to update the stack num tokens.
-    /// If the final chunk has not been handled, it will not update the stack num tokens.
+    /// If the final chunk has not been handled, this method sends events to the state manager to
+    /// settle the stack with the tokens generated so far, to bill the user for the tokens
+    /// delivered before the client disconnected, and to record the request in the usage ledger,
+    /// whether the client disconnected or the stream ended early on the node side.
     #[instrument(
         level = "info",
         skip_all,
@@ -508,29 +788,40 @@ impl Drop for Streamer {
             streamer = "drop-streamer",
             num_generated_tokens = self.num_generated_tokens,
             estimated_total_tokens = self.estimated_total_tokens,
-            stack_small_id = self.stack_small_id,
-            endpoint = self.endpoint,
+            stack_small_id = self.metadata.selected_stack_small_id,
+            endpoint = self.metadata.endpoint,
         )
     )]
     fn drop(&mut self) {
+        if self.delivered_on_disconnect.is_some() || !self.is_final_chunk_handled {
+            CANCELLED_STREAM_CHAT_COMPLETION_REQUESTS_PER_USER
+                .add(1, &[KeyValue::new("user_id", self.metadata.user_id)]);
+        }
         if self.is_final_chunk_handled {
-            TOTAL_COMPLETED_REQUESTS.add(1, &[KeyValue::new("model", self.model_name.clone())]);
-            // Record the request in the chat completions num requests metric
-            CHAT_COMPLETIONS_STREAMING_LATENCY_METRICS.record(
-                self.start.elapsed().as_secs_f64(),
-                &[KeyValue::new("model", self.model_name.clone())],
-            );
-            CHAT_COMPLETION_REQUESTS_PER_USER.add(1, &[KeyValue::new("user_id", self.user_id)]);
+            if self.delivered_on_disconnect.is_none() {
+                TOTAL_COMPLETED_REQUESTS.add(
+                    1,
+                    &[KeyValue::new("model", self.metadata.model_name.clone())],
+                );
+                // Record the request in the chat completions num requests metric
+                CHAT_COMPLETIONS_STREAMING_LATENCY_METRICS.record(
+                    self.start.elapsed().as_secs_f64(),
+                    &[KeyValue::new("model", self.metadata.model_name.clone())],
+                );
+                CHAT_COMPLETION_REQUESTS_PER_USER
+                    .add(1, &[KeyValue::new("user_id", self.metadata.user_id)]);
+            }
             return;
         }
-        CANCELLED_STREAM_CHAT_COMPLETION_REQUESTS_PER_USER
-            .add(1, &[KeyValue::new("user_id", self.user_id)]);
+        let usage = self.partial_usage();
+        let billed_usage = self.billed_usage(usage);
         if let Err(e) = update_state_manager(
             &self.state_manager_sender,
-            self.stack_small_id,
+            self.metadata.selected_stack_small_id,
             self.estimated_total_tokens,
-            self.num_generated_tokens,
-            &self.endpoint,
+            usage.total_tokens,
+            billed_usage.total_tokens,
+            &self.metadata.endpoint,
         ) {
             error!(
                 target = "atoma-service-streamer",
@@ -539,59 +830,176 @@ impl Drop for Streamer {
                 e
             );
         }
+        if let Err(e) = update_api_token_spend(
+            &self.state_manager_sender,
+            self.metadata.api_token_id,
+            self.metadata.selected_stack_small_id,
+            billed_usage.total_tokens,
+            &self.metadata.endpoint,
+        ) {
+            error!(
+                target = "atoma-service-streamer",
+                level = "error",
+                "Error updating api token spend: {}",
+                e
+            );
+        }
+        if let Err(e) = self.record_usage(billed_usage) {
+            error!(
+                target = "atoma-service-streamer",
+                level = "error",
+                "Error recording usage: {}",
+                e
+            );
+        }
         self.status = StreamStatus::Completed;
     }
 }
 
-/// A structure for streaming chat completion chunks from the client.
-pub struct ClientStreamer {
-    /// The receiver for the event chunks
-    pub chunk_receiver: flume::Receiver<Event>,
-    /// The sender for the kill signal
-    pub kill_signal: flume::Sender<()>,
-    /// If the streamer has reached done state
-    pub done: bool,
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::tokenizer::TiktokenBpe;
+    use serde_json::json;
 
-impl ClientStreamer {
-    /// Creates a new client streamer
-    pub const fn new(
-        chunk_receiver: flume::Receiver<Event>,
-        kill_signal: flume::Sender<()>,
-    ) -> Self {
-        Self {
-            chunk_receiver,
-            kill_signal,
-            done: false,
-        }
+    fn streamer(tokenizer: Option<Arc<dyn TokenCounter>>) -> Streamer {
+        let (state_manager_sender, _) = flume::unbounded();
+        Streamer::new(
+            futures::stream::empty(),
+            state_manager_sender,
+            1,
+            1,
+            10,
+            100,
+            Instant::now(),
+            1,
+            1,
+            "model".to_string(),
+            CHAT_COMPLETIONS_PATH.to_string(),
+            tokenizer,
+        )
     }
-}
 
-impl Stream for ClientStreamer {
-    type Item = Result<Event, Error>;
+    #[test]
+    fn test_usage_from_node_usage() {
+        let usage = json!({"prompt_tokens": 10, "completion_tokens": 20, "total_tokens": 30});
+        assert_eq!(
+            Usage::from_node_usage(&usage),
+            Some(Usage {
+                input_tokens: 10,
+                output_tokens: 20,
+                total_tokens: 30,
+            })
+        );
+        assert_eq!(Usage::from_node_usage(&json!({"prompt_tokens": 10})), None);
+        assert_eq!(Usage::from_node_usage(&Value::Null), None);
+    }
 
-    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        let this = self.get_mut();
-        let mut future = this.chunk_receiver.recv_async();
-        match future.poll_unpin(cx) {
-            Poll::Ready(Ok(chunk)) => Poll::Ready(Some(Ok(chunk))),
-            Poll::Ready(Err(RecvError::Disconnected)) => {
-                info!(
-                    target = "atoma-service-streamer",
-                    "ClientStreamer received disconnect signal, marking as done."
-                );
-                this.done = true;
-                Poll::Ready(None)
+    #[test]
+    fn test_proxy_usage() {
+        // Ranks of `a`, `b`, ` ` and `ab`
+        let tokenizer = TiktokenBpe::from_ranks("YQ== 0\nYg== 1\nIA== 2\nYWI= 3\n").unwrap();
+        let mut streamer = streamer(Some(Arc::new(tokenizer)));
+        streamer
+            .accumulate_generated_text(&[json!({"delta": {"role": "assistant", "content": "ab"}})]);
+        streamer.accumulate_generated_text(&[json!({"delta": {"content": " ab"}})]);
+        assert_eq!(
+            streamer.proxy_usage(),
+            Some(Usage {
+                input_tokens: 10,
+                output_tokens: 3,
+                total_tokens: 13,
+            })
+        );
+        // NOTE: The streamer is marked as handled, so that dropping it does not settle the stack
+        streamer.is_final_chunk_handled = true;
+
+        let mut streamer = self::streamer(None);
+        streamer.accumulate_generated_text(&[json!({"delta": {"content": "ab"}})]);
+        assert!(streamer.generated_text.is_empty());
+        assert_eq!(streamer.proxy_usage(), None);
+        streamer.is_final_chunk_handled = true;
+    }
+
+    #[test]
+    fn test_billed_usage_on_client_disconnect() {
+        let tokenizer = TiktokenBpe::from_ranks("YQ== 0\nYg== 1\nIA== 2\nYWI= 3\n").unwrap();
+        let mut streamer = streamer(Some(Arc::new(tokenizer)));
+        let node_usage = Usage {
+            input_tokens: 12,
+            output_tokens: 20,
+            total_tokens: 32,
+        };
+        streamer.accumulate_generated_text(&[json!({"delta": {"content": "ab"}})]);
+        streamer.num_generated_tokens += 1;
+        assert_eq!(streamer.billed_usage(node_usage), node_usage);
+        // A stream ending without a final chunk, whose client did not disconnect, failed
+        assert_eq!(streamer.usage_record_status(), StatusCode::BAD_GATEWAY);
+        streamer.status = StreamStatus::Completed;
+        assert_eq!(streamer.usage_record_status(), StatusCode::OK);
+
+        // The output generated after the client disconnected is not billed
+        streamer.client_disconnected();
+        streamer.accumulate_generated_text(&[json!({"delta": {"content": " ab ab"}})]);
+        streamer.client_disconnected();
+        assert_eq!(
+            streamer.billed_usage(node_usage),
+            Usage {
+                input_tokens: 12,
+                output_tokens: 1,
+                total_tokens: 13,
             }
-            Poll::Pending => Poll::Pending,
-        }
+        );
+        assert_eq!(
+            streamer.usage_record_status().as_u16(),
+            CLIENT_CLOSED_REQUEST_STATUS
+        );
+        streamer.is_final_chunk_handled = true;
+
+        // Without a tokenizer, each delivered chunk is billed as one token
+        let mut streamer = self::streamer(None);
+        streamer.num_generated_tokens += 2;
+        streamer.client_disconnected();
+        streamer.num_generated_tokens += 5;
+        assert_eq!(
+            streamer.partial_usage(),
+            Usage {
+                input_tokens: 10,
+                output_tokens: 7,
+                total_tokens: 17,
+            }
+        );
+        assert_eq!(
+            streamer
+                .billed_usage(streamer.partial_usage())
+                .output_tokens,
+            2
+        );
+        streamer.is_final_chunk_handled = true;
     }
-}
 
-impl Drop for ClientStreamer {
-    fn drop(&mut self) {
-        if !self.done {
-            let _ = self.kill_signal.send(());
-        }
+    #[test]
+    fn test_billed_usage_on_client_detach() {
+        let mut streamer = streamer(None);
+        let node_usage = Usage {
+            input_tokens: 10,
+            output_tokens: 7,
+            total_tokens: 17,
+        };
+        streamer.num_generated_tokens += 2;
+        streamer.client_detached();
+        streamer.num_generated_tokens += 3;
+        // A client that resumes the stream is billed for the whole output
+        streamer.client_resumed();
+        assert_eq!(streamer.billed_usage(node_usage), node_usage);
+
+        // A client that does not resume the stream in time is billed for the output delivered
+        // when it detached
+        streamer.client_detached();
+        streamer.num_generated_tokens += 2;
+        assert_eq!(streamer.billed_usage(node_usage), node_usage);
+        streamer.client_disconnected();
+        assert_eq!(streamer.billed_usage(node_usage).output_tokens, 5);
+        streamer.is_final_chunk_handled = true;
     }
 }
@@ -0,0 +1,383 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+
+use anyhow::Context;
+use base64::engine::{general_purpose::STANDARD, Engine};
+use futures::future::join_all;
+use hf_hub::{api::sync::ApiBuilder, Repo, RepoType};
+use regex::Regex;
+use tokenizers::Tokenizer;
+use tokio::sync::watch;
+use tracing::{error, info, instrument};
+
+use super::{http_server::ProxyState, TokenizersConfig};
+
+/// Name of the Hugging Face tokenizer file of a model.
+pub const HF_TOKENIZER_FILE: &str = "tokenizer.json";
+
+/// Name of the tiktoken BPE ranks file of a model.
+pub const TIKTOKEN_FILE: &str = "tokenizer.tiktoken";
+
+/// Extension of tiktoken BPE ranks files.
+const TIKTOKEN_EXTENSION: &str = "tiktoken";
+
+/// Pattern splitting texts into the pieces encoded by tiktoken BPE tokenizers (`cl100k_base`
+/// and later encodings).
+///
+/// NOTE: The `regex` crate does not support look-ahead, so runs of whitespace followed by
+/// a word are not split before their last whitespace, which may undercount tokens by one
+/// per such run. This is negligible for compute units estimates.
+const TIKTOKEN_PATTERN: &str = r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+";
+
+/// Maximum size, in bytes, of the parts the pieces of a text are merged in by tiktoken BPE tokenizers.
+///
+/// Merging a piece pair by pair is quadratic in its size, so long pieces (e.g. long runs of letters or
+/// whitespace) are split into parts of this size, merged separately. No merge happens across two parts,
+/// which may overcount tokens by one per part, and errs on the side of overestimating compute units.
+const TIKTOKEN_MAX_PART_BYTES: usize = 128;
+
+/// Counts the tokens of texts, to estimate the compute units of requests.
+pub trait TokenCounter: Send + Sync {
+    /// Returns the number of tokens of a text, including the special tokens added by the tokenizer.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the text cannot be encoded.
+    fn count_tokens(&self, text: &str) -> anyhow::Result<u64>;
+}
+
+impl TokenCounter for Tokenizer {
+    fn count_tokens(&self, text: &str) -> anyhow::Result<u64> {
+        let encoding = self
+            .encode(text, true)
+            .map_err(|e| anyhow::anyhow!("Failed to encode text: {e}"))?;
+        Ok(encoding.get_ids().len() as u64)
+    }
+}
+
+/// A tiktoken BPE tokenizer, loaded from a ranks file (one base64 encoded token and its rank per line).
+pub struct TiktokenBpe {
+    /// The ranks of the tokens, lower ranks being merged first.
+    ranks: HashMap<Vec<u8>, u32>,
+    /// The pattern splitting texts into pieces.
+    pattern: Regex,
+}
+
+impl TiktokenBpe {
+    /// Creates a new tiktoken BPE tokenizer from the content of a ranks file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a line of the ranks file is invalid.
+    pub fn from_ranks(ranks: &str) -> anyhow::Result<Self> {
+        let ranks = ranks
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let (token, rank) = line
+                    .split_once(' ')
+                    .with_context(|| format!("Invalid tiktoken ranks line: {line}"))?;
+                let token = STANDARD
+                    .decode(token)
+                    .with_context(|| format!("Invalid tiktoken token: {token}"))?;
+                let rank = rank
+                    .trim()
+                    .parse()
+                    .with_context(|| format!("Invalid tiktoken rank: {rank}"))?;
+                Ok((token, rank))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self {
+            ranks,
+            pattern: Regex::new(TIKTOKEN_PATTERN)?,
+        })
+    }
+
+    /// Returns the number of tokens a piece of text is encoded into, by merging its bytes
+    /// pair by pair, lowest rank first, in parts of at most `TIKTOKEN_MAX_PART_BYTES` bytes.
+    fn count_piece_tokens(&self, piece: &[u8]) -> usize {
+        if self.ranks.contains_key(piece) {
+            return 1;
+        }
+        piece
+            .chunks(TIKTOKEN_MAX_PART_BYTES)
+            .map(|part| self.count_part_tokens(part))
+            .sum()
+    }
+
+    /// Returns the number of tokens a part of a piece of text is encoded into, by merging its bytes
+    /// pair by pair, lowest rank first.
+    fn count_part_tokens(&self, piece: &[u8]) -> usize {
+        if self.ranks.contains_key(piece) {
+            return 1;
+        }
+        // NOTE: Boundaries of the parts the piece is currently split into, starting from single bytes
+        let mut boundaries: Vec<usize> = (0..=piece.len()).collect();
+        loop {
+            let mut best_merge: Option<(u32, usize)> = None;
+            for i in 0..boundaries.len().saturating_sub(2) {
+                if let Some(&rank) = self.ranks.get(&piece[boundaries[i]..boundaries[i + 2]]) {
+                    if !matches!(best_merge, Some((best_rank, _)) if best_rank <= rank) {
+                        best_merge = Some((rank, i));
+                    }
+                }
+            }
+            match best_merge {
+                Some((_, i)) => {
+                    boundaries.remove(i + 1);
+                }
+                None => break,
+            }
+        }
+        boundaries.len() - 1
+    }
+}
+
+impl TokenCounter for TiktokenBpe {
+    fn count_tokens(&self, text: &str) -> anyhow::Result<u64> {
+        Ok(self
+            .pattern
+            .find_iter(text)
+            .map(|piece| self.count_piece_tokens(piece.as_str().as_bytes()) as u64)
+            .sum())
+    }
+}
+
+/// Loads the tokenizers of the models from their configured sources.
+///
+/// A model's tokenizer is looked up, in order:
+/// 1. In the tokenizer file configured for the model
+/// 2. In the local tokenizers directory, as `<directory>/<model>/tokenizer.json` or
+///    `<directory>/<model>/tokenizer.tiktoken`
+/// 3. On the Hugging Face hub, unless the proxy is configured to be offline
+pub struct TokenizerLoader {
+    /// Sources of the tokenizers.
+    config: TokenizersConfig,
+    /// Hugging Face API token.
+    hf_token: String,
+}
+
+impl TokenizerLoader {
+    /// Creates a new tokenizer loader.
+    #[must_use]
+    pub const fn new(config: TokenizersConfig, hf_token: String) -> Self {
+        Self { config, hf_token }
+    }
+
+    /// Time between two attempts to load the tokenizers that failed to load.
+    #[must_use]
+    pub const fn retry_interval(&self) -> Duration {
+        Duration::from_secs(self.config.retry_interval_secs)
+    }
+
+    /// Loads the tokenizer of a model.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the tokenizer cannot be found or parsed.
+    fn load(&self, model: &str, revision: &str) -> anyhow::Result<Arc<dyn TokenCounter>> {
+        if let Some(path) = self.config.files.get(model) {
+            return load_tokenizer_file(Path::new(path));
+        }
+        if let Some(directory) = &self.config.directory {
+            let model_directory = Path::new(directory).join(model);
+            for file_name in [HF_TOKENIZER_FILE, TIKTOKEN_FILE] {
+                let path = model_directory.join(file_name);
+                if path.is_file() {
+                    return load_tokenizer_file(&path);
+                }
+            }
+        }
+        if self.config.offline {
+            anyhow::bail!("No local tokenizer found for model {model}, and the proxy is offline");
+        }
+        let api = ApiBuilder::new()
+            .with_progress(false)
+            .with_token(Some(self.hf_token.clone()))
+            .build()?;
+        let repo = api.repo(Repo::with_revision(
+            model.to_string(),
+            RepoType::Model,
+            revision.to_string(),
+        ));
+        let path: PathBuf = repo.get(HF_TOKENIZER_FILE)?;
+        load_tokenizer_file(&path)
+    }
+}
+
+/// Loads a tokenizer file, as tiktoken BPE ranks if it has the `.tiktoken` extension, and as
+/// a Hugging Face tokenizer otherwise.
+fn load_tokenizer_file(path: &Path) -> anyhow::Result<Arc<dyn TokenCounter>> {
+    if path
+        .extension()
+        .is_some_and(|extension| extension == TIKTOKEN_EXTENSION)
+    {
+        let ranks = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read tokenizer file {}", path.display()))?;
+        return Ok(Arc::new(TiktokenBpe::from_ranks(&ranks)?));
+    }
+    let tokenizer = Tokenizer::from_file(path)
+        .map_err(|e| anyhow::anyhow!("Failed to parse tokenizer file {}: {e}", path.display()))?;
+    Ok(Arc::new(tokenizer))
+}
+
+/// The tokenizer of a model, loaded lazily.
+///
+/// Requests for a model whose tokenizer is not loaded yet are rejected, until a load succeeds.
+pub struct ModelTokenizer {
+    /// The model.
+    model: String,
+    /// The revision of the model.
+    revision: String,
+    /// The loader of the tokenizer.
+    loader: Arc<TokenizerLoader>,
+    /// The tokenizer, once loaded.
+    tokenizer: OnceLock<Arc<dyn TokenCounter>>,
+}
+
+impl ModelTokenizer {
+    /// Creates the (not loaded yet) tokenizer of a model.
+    #[must_use]
+    pub const fn new(model: String, revision: String, loader: Arc<TokenizerLoader>) -> Self {
+        Self {
+            model,
+            revision,
+            loader,
+            tokenizer: OnceLock::new(),
+        }
+    }
+
+    /// The revision of the model.
+    #[must_use]
+    pub fn revision(&self) -> &str {
+        &self.revision
+    }
+
+    /// Returns the tokenizer, if it is loaded.
+    #[must_use]
+    pub fn get(&self) -> Option<Arc<dyn TokenCounter>> {
+        self.tokenizer.get().cloned()
+    }
+
+    /// Whether the tokenizer is loaded.
+    #[must_use]
+    pub fn is_loaded(&self) -> bool {
+        self.tokenizer.get().is_some()
+    }
+
+    /// Loads the tokenizer, if it is not loaded yet.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the tokenizer cannot be loaded.
+    #[instrument(level = "info", skip_all, fields(model = %self.model, revision = %self.revision))]
+    pub async fn load(self: &Arc<Self>) -> anyhow::Result<()> {
+        if self.is_loaded() {
+            return Ok(());
+        }
+        // NOTE: Tokenizers are read from disk or downloaded with a blocking client, off the async workers
+        let this = Arc::clone(self);
+        let tokenizer =
+            tokio::task::spawn_blocking(move || this.loader.load(&this.model, &this.revision))
+                .await??;
+        // NOTE: If the tokenizer was loaded concurrently, the first one is kept
+        let _ = self.tokenizer.set(tokenizer);
+        info!(
+            target = "atoma-service",
+            event = "tokenizer-loader",
+            "Loaded tokenizer for model {}",
+            self.model
+        );
+        Ok(())
+    }
+}
+
+/// Loads the tokenizers of the served models that are not loaded yet, right away and then
+/// periodically, until all are loaded or the proxy shuts down.
+///
+/// Tokenizers of models added by a reload are picked up on the next attempt.
+pub async fn run_tokenizer_loader(state: ProxyState, mut shutdown_receiver: watch::Receiver<bool>) {
+    loop {
+        let served_models = state.model_registry.current();
+        let results = join_all(
+            served_models
+                .tokenizers
+                .iter()
+                .filter(|tokenizer| !tokenizer.is_loaded())
+                .map(|tokenizer| async move { (tokenizer, tokenizer.load().await) }),
+        )
+        .await;
+        for (tokenizer, result) in results {
+            if let Err(e) = result {
+                error!(
+                    target = "atoma-service",
+                    event = "tokenizer-loader",
+                    "Failed to load tokenizer for model {}, retrying later: {e:#}",
+                    tokenizer.model
+                );
+            }
+        }
+        tokio::select! {
+            () = tokio::time::sleep(served_models.tokenizer_loader.retry_interval()) => {}
+            _ = shutdown_receiver.changed() => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ranks of `a`, `b`, ` ` and `ab`.
+    const RANKS: &str = "YQ== 0\nYg== 1\nIA== 2\nYWI= 3\n";
+
+    #[test]
+    fn test_tiktoken_bpe_count_tokens() {
+        let tokenizer = TiktokenBpe::from_ranks(RANKS).unwrap();
+        assert_eq!(tokenizer.count_tokens("").unwrap(), 0);
+        assert_eq!(tokenizer.count_tokens("ab").unwrap(), 1);
+        // `ab` and ` ab`, which is merged into ` ` and `ab`
+        assert_eq!(tokenizer.count_tokens("ab ab").unwrap(), 3);
+        // `ba` is not merged
+        assert_eq!(tokenizer.count_tokens("ba").unwrap(), 2);
+        assert!(TiktokenBpe::from_ranks("YQ==").is_err());
+    }
+
+    #[test]
+    fn test_tiktoken_bpe_count_tokens_of_long_pieces() {
+        let tokenizer = TiktokenBpe::from_ranks(RANKS).unwrap();
+        // A single piece, merged in parts of `TIKTOKEN_MAX_PART_BYTES` bytes
+        let text = "ab".repeat(100_000);
+        assert_eq!(tokenizer.count_tokens(&text).unwrap(), 100_000);
+        // `b` and `ab` repeated, split in three parts: the `ab` pairs across the two part boundaries
+        // are not merged, which counts one more token per boundary
+        let text = format!("b{}", "ab".repeat(TIKTOKEN_MAX_PART_BYTES));
+        assert_eq!(
+            tokenizer.count_tokens(&text).unwrap(),
+            TIKTOKEN_MAX_PART_BYTES as u64 + 1 + 2
+        );
+    }
+
+    #[test]
+    fn test_tokenizer_loader_offline() {
+        let directory = std::env::temp_dir().join(format!("tokenizers-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(directory.join("org/model")).unwrap();
+        std::fs::write(directory.join("org/model").join(TIKTOKEN_FILE), RANKS).unwrap();
+        let loader = TokenizerLoader::new(
+            TokenizersConfig {
+                directory: Some(directory.to_string_lossy().to_string()),
+                offline: true,
+                ..TokenizersConfig::default()
+            },
+            String::new(),
+        );
+        let tokenizer = loader.load("org/model", "main").unwrap();
+        assert_eq!(tokenizer.count_tokens("ab").unwrap(), 1);
+        assert!(loader.load("org/other-model", "main").is_err());
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -83,6 +83,12 @@ pub enum AtomaStateManagerError {
     FailedToRetrieveFmspc(String),
     #[error("Insufficient balance")]
     InsufficientBalance,
+    #[error("Webhook not found")]
+    WebhookNotFound,
+    #[error("Webhook secret is not a hex encoded 32 bytes key")]
+    InvalidWebhookSecret,
+    #[error("Failed to deliver webhook event: {0}")]
+    WebhookDeliveryError(#[source] reqwest::Error),
     #[error("Country is not a valid ISO 3166-1 alpha-2 code: {0}")]
     InvalidCountry(String),
     #[error("URL is not valid: {0}")]
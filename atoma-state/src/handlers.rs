@@ -16,9 +16,30 @@ use crate::{
     state_manager::Result,
     timestamp_to_datetime_or_now,
     types::{AtomaAtomaStateManagerEvent, Stack, StackSettlementTicket},
+    webhooks::{
+        notify_api_token_revoked, notify_balance_topped_up, notify_low_balance,
+        notify_monthly_spend_limit,
+    },
     AtomaStateManager, AtomaStateManagerError,
 };
 
+/// Runs a webhook notification in the background, so that it does not delay the handling
+/// of the next state manager events. Failures are only logged.
+fn spawn_webhook_notification<F>(notification: F)
+where
+    F: std::future::Future<Output = Result<()>> + Send + 'static,
+{
+    tokio::spawn(async move {
+        if let Err(e) = notification.await {
+            error!(
+                target = "atoma-state-handlers",
+                event = "webhook-notification",
+                "Failed to notify webhooks: {e}"
+            );
+        }
+    });
+}
+
 /// Handles various Atoma network events by delegating them to appropriate handler functions.
 ///
 /// This function serves as the main event handler for the Atoma network, processing different types of events
@@ -549,6 +570,7 @@ pub async fn handle_node_task_unsubscription_event(
 /// * `event` - A `StackCreatedEvent` containing the details of the stack creation event.
 /// * `node_small_ids` - A slice of `u64` values representing the small IDs of the current nodes.
 /// * `acquired_timestamp` - The timestamp of the event.
+/// * `is_metered` - Whether the user is charged by actual usage instead of for the whole stack.
 ///
 /// # Returns
 ///
@@ -573,6 +595,7 @@ pub async fn handle_stack_created_event(
     locked_compute_units: i64,
     user_id: i64,
     acquired_timestamp: DateTime<Utc>,
+    is_metered: bool,
 ) -> Result<()> {
     let node_small_id = event.selected_node_id.inner;
     trace!(
@@ -584,7 +607,7 @@ pub async fn handle_stack_created_event(
     stack.locked_compute_units = locked_compute_units;
     state_manager
         .state
-        .insert_new_stack(stack, user_id, acquired_timestamp)
+        .insert_new_stack(stack, user_id, acquired_timestamp, is_metered)
         .await?;
     Ok(())
 }
@@ -1003,6 +1026,7 @@ pub async fn handle_state_manager_event(
             stack_small_id,
             estimated_total_tokens,
             total_tokens,
+            billed_total_tokens,
         } => {
             trace!(
                 target = "atoma-state-handlers",
@@ -1012,7 +1036,12 @@ pub async fn handle_state_manager_event(
             );
             state_manager
                 .state
-                .update_stack_num_tokens(stack_small_id, estimated_total_tokens, total_tokens)
+                .update_stack_num_tokens(
+                    stack_small_id,
+                    estimated_total_tokens,
+                    total_tokens,
+                    billed_total_tokens,
+                )
                 .await?;
         }
         AtomaAtomaStateManagerEvent::UpdateStackTotalHash {
@@ -1035,6 +1064,7 @@ pub async fn handle_state_manager_event(
             free_compute_units,
             user_id,
             is_confidential,
+            excluded_node_small_ids,
             result_sender,
         } => {
             trace!(
@@ -1046,11 +1076,25 @@ pub async fn handle_state_manager_event(
             );
             let stack = state_manager
                 .state
-                .get_stacks_for_model(&model, free_compute_units, user_id, is_confidential)
+                .get_stacks_for_model(
+                    &model,
+                    free_compute_units,
+                    user_id,
+                    is_confidential,
+                    &excluded_node_small_ids,
+                )
                 .await;
+            // NOTE: Locking compute units on a metered stack reserves USDC from the user's balance
+            let is_locked = matches!(stack, Ok(Some(_)));
             result_sender
                 .send(stack)
                 .map_err(|_| AtomaStateManagerError::ChannelSendError)?;
+            if is_locked {
+                let state = state_manager.state.clone();
+                spawn_webhook_notification(
+                    async move { notify_low_balance(&state, user_id).await },
+                );
+            }
         }
         AtomaAtomaStateManagerEvent::GetStacksForTask {
             task_small_id,
@@ -1090,6 +1134,7 @@ pub async fn handle_state_manager_event(
         AtomaAtomaStateManagerEvent::GetCheapestNodeForModel {
             model,
             is_confidential,
+            excluded_node_small_ids,
             result_sender,
         } => {
             trace!(
@@ -1100,7 +1145,44 @@ pub async fn handle_state_manager_event(
             );
             let node = state_manager
                 .state
-                .get_cheapest_node_for_model(&model, is_confidential)
+                .get_cheapest_node_for_model(&model, is_confidential, &excluded_node_small_ids)
+                .await;
+            result_sender
+                .send(node)
+                .map_err(|_| AtomaStateManagerError::ChannelSendError)?;
+        }
+        AtomaAtomaStateManagerEvent::GetModelCatalog { result_sender } => {
+            trace!(
+                target = "atoma-state-handlers",
+                event = "handle-state-manager-event",
+                "Getting model catalog"
+            );
+            let models = state_manager.state.get_model_catalog().await;
+            result_sender
+                .send(models)
+                .map_err(|_| AtomaStateManagerError::ChannelSendError)?;
+        }
+        AtomaAtomaStateManagerEvent::GetBestRankedNodeForModel {
+            model,
+            ranked_node_small_ids,
+            max_price_per_one_million_compute_units,
+            is_confidential,
+            result_sender,
+        } => {
+            trace!(
+                target = "atoma-state-handlers",
+                event = "handle-state-manager-event",
+                "Getting best ranked node for model: {}",
+                model
+            );
+            let node = state_manager
+                .state
+                .get_best_ranked_node_for_model(
+                    &model,
+                    &ranked_node_small_ids,
+                    max_price_per_one_million_compute_units,
+                    is_confidential,
+                )
                 .await;
             result_sender
                 .send(node)
@@ -1214,6 +1296,7 @@ pub async fn handle_state_manager_event(
             locked_compute_units,
             transaction_timestamp,
             user_id,
+            is_metered,
             result_sender,
         } => {
             let result = handle_stack_created_event(
@@ -1222,6 +1305,7 @@ pub async fn handle_state_manager_event(
                 locked_compute_units,
                 user_id,
                 transaction_timestamp,
+                is_metered,
             )
             .await;
             result_sender
@@ -1323,32 +1407,80 @@ pub async fn handle_state_manager_event(
                 .await?;
         }
         AtomaAtomaStateManagerEvent::IsApiTokenValid {
-            api_token,
+            api_token_hash,
             result_sender,
         } => {
-            let user_id = state_manager.state.is_api_token_valid(&api_token).await;
+            let user_id = state_manager
+                .state
+                .is_api_token_valid(&api_token_hash)
+                .await;
             result_sender
                 .send(user_id)
                 .map_err(|_| AtomaStateManagerError::ChannelSendError)?;
         }
+        AtomaAtomaStateManagerEvent::GetApiTokenById {
+            api_token_id,
+            result_sender,
+        } => {
+            let api_token = state_manager.state.get_api_token_by_id(api_token_id).await;
+            result_sender
+                .send(api_token)
+                .map_err(|_| AtomaStateManagerError::ChannelSendError)?;
+        }
         AtomaAtomaStateManagerEvent::StoreNewApiToken {
             user_id,
-            api_token,
-            name,
+            api_token_hash,
+            api_token_prefix,
+            api_token_last_4,
+            request,
+        } => {
+            state_manager
+                .state
+                .store_api_token(
+                    user_id,
+                    &api_token_hash,
+                    &api_token_prefix,
+                    &api_token_last_4,
+                    &request,
+                )
+                .await?;
+        }
+        AtomaAtomaStateManagerEvent::UpdateApiTokenSpend {
+            api_token_id,
+            stack_small_id,
+            num_compute_units,
         } => {
             state_manager
                 .state
-                .store_api_token(user_id, &api_token, &name)
+                .update_api_token_spend(api_token_id, stack_small_id, num_compute_units)
+                .await?;
+        }
+        AtomaAtomaStateManagerEvent::RecordUsage { usage_record } => {
+            state_manager
+                .state
+                .insert_usage_record(&usage_record)
                 .await?;
+            let state = state_manager.state.clone();
+            let user_id = usage_record.user_id;
+            spawn_webhook_notification(
+                async move { notify_monthly_spend_limit(&state, user_id).await },
+            );
         }
         AtomaAtomaStateManagerEvent::RevokeApiToken {
             user_id,
             api_token_id,
         } => {
-            state_manager
+            let is_deleted = state_manager
                 .state
                 .delete_api_token(user_id, api_token_id)
                 .await?;
+            // NOTE: Only the revocation of an existing API token of the user is notified
+            if is_deleted {
+                let state = state_manager.state.clone();
+                spawn_webhook_notification(async move {
+                    notify_api_token_revoked(&state, user_id, api_token_id).await
+                });
+            }
         }
         AtomaAtomaStateManagerEvent::GetApiTokensForUser {
             user_id,
@@ -1389,6 +1521,10 @@ pub async fn handle_state_manager_event(
         }
         AtomaAtomaStateManagerEvent::TopUpBalance { user_id, amount } => {
             state_manager.state.top_up_balance(user_id, amount).await?;
+            let state = state_manager.state.clone();
+            spawn_webhook_notification(async move {
+                notify_balance_topped_up(&state, user_id, amount).await
+            });
         }
         AtomaAtomaStateManagerEvent::DeductFromUsdc {
             user_id,
@@ -1396,9 +1532,16 @@ pub async fn handle_state_manager_event(
             result_sender,
         } => {
             let success = state_manager.state.deduct_from_usdc(user_id, amount).await;
+            let is_deducted = success.is_ok();
             result_sender
                 .send(success)
                 .map_err(|_| AtomaStateManagerError::ChannelSendError)?;
+            if is_deducted {
+                let state = state_manager.state.clone();
+                spawn_webhook_notification(
+                    async move { notify_low_balance(&state, user_id).await },
+                );
+            }
         }
         AtomaAtomaStateManagerEvent::RefundUsdc {
             user_id,
@@ -1441,6 +1584,190 @@ pub async fn handle_state_manager_event(
                 .send(success)
                 .map_err(|_| AtomaStateManagerError::ChannelSendError)?;
         }
+        AtomaAtomaStateManagerEvent::InsertUserFile {
+            file,
+            result_sender,
+        } => {
+            let file = state_manager.state.insert_user_file(&file).await;
+            result_sender
+                .send(file)
+                .map_err(|_| AtomaStateManagerError::ChannelSendError)?;
+        }
+        AtomaAtomaStateManagerEvent::GetUserFile {
+            user_id,
+            file_id,
+            result_sender,
+        } => {
+            let file = state_manager.state.get_user_file(user_id, &file_id).await;
+            result_sender
+                .send(file)
+                .map_err(|_| AtomaStateManagerError::ChannelSendError)?;
+        }
+        AtomaAtomaStateManagerEvent::GetUserFileContent {
+            user_id,
+            file_id,
+            result_sender,
+        } => {
+            let content = state_manager
+                .state
+                .get_user_file_content(user_id, &file_id)
+                .await;
+            result_sender
+                .send(content)
+                .map_err(|_| AtomaStateManagerError::ChannelSendError)?;
+        }
+        AtomaAtomaStateManagerEvent::CreateBatch {
+            batch,
+            requests,
+            result_sender,
+        } => {
+            let batch = state_manager.state.insert_batch(&batch, &requests).await;
+            result_sender
+                .send(batch)
+                .map_err(|_| AtomaStateManagerError::ChannelSendError)?;
+        }
+        AtomaAtomaStateManagerEvent::GetBatch {
+            user_id,
+            batch_id,
+            result_sender,
+        } => {
+            let batch = state_manager.state.get_batch(user_id, &batch_id).await;
+            result_sender
+                .send(batch)
+                .map_err(|_| AtomaStateManagerError::ChannelSendError)?;
+        }
+        AtomaAtomaStateManagerEvent::ListBatches {
+            user_id,
+            after,
+            limit,
+            result_sender,
+        } => {
+            let batches = state_manager
+                .state
+                .list_batches(user_id, after.as_deref(), limit)
+                .await;
+            result_sender
+                .send(batches)
+                .map_err(|_| AtomaStateManagerError::ChannelSendError)?;
+        }
+        AtomaAtomaStateManagerEvent::CancelBatch {
+            user_id,
+            batch_id,
+            result_sender,
+        } => {
+            let batch = state_manager.state.cancel_batch(user_id, &batch_id).await;
+            result_sender
+                .send(batch)
+                .map_err(|_| AtomaStateManagerError::ChannelSendError)?;
+        }
+        AtomaAtomaStateManagerEvent::GetActiveBatches { result_sender } => {
+            let batches = state_manager.state.get_active_batches().await;
+            result_sender
+                .send(batches)
+                .map_err(|_| AtomaStateManagerError::ChannelSendError)?;
+        }
+        AtomaAtomaStateManagerEvent::ClaimBatchRequests {
+            batch_id,
+            limit,
+            result_sender,
+        } => {
+            let requests = state_manager
+                .state
+                .claim_batch_requests(&batch_id, limit)
+                .await;
+            result_sender
+                .send(requests)
+                .map_err(|_| AtomaStateManagerError::ChannelSendError)?;
+        }
+        AtomaAtomaStateManagerEvent::CompleteBatchRequest {
+            batch_id,
+            line_index,
+            outcome,
+        } => {
+            state_manager
+                .state
+                .complete_batch_request(&batch_id, line_index, &outcome)
+                .await?;
+        }
+        AtomaAtomaStateManagerEvent::ReleaseBatchRequest {
+            batch_id,
+            line_index,
+        } => {
+            state_manager
+                .state
+                .release_batch_request(&batch_id, line_index)
+                .await?;
+        }
+        AtomaAtomaStateManagerEvent::ReleaseRunningBatchRequests { result_sender } => {
+            let num_released = state_manager.state.release_running_batch_requests().await;
+            result_sender
+                .send(num_released)
+                .map_err(|_| AtomaStateManagerError::ChannelSendError)?;
+        }
+        AtomaAtomaStateManagerEvent::GetBatchRequests {
+            batch_id,
+            result_sender,
+        } => {
+            let requests = state_manager.state.get_batch_requests(&batch_id).await;
+            result_sender
+                .send(requests)
+                .map_err(|_| AtomaStateManagerError::ChannelSendError)?;
+        }
+        AtomaAtomaStateManagerEvent::FinalizeBatch {
+            batch_id,
+            status,
+            output_file,
+            error_file,
+            result_sender,
+        } => {
+            let batch = state_manager
+                .state
+                .finalize_batch(&batch_id, status, output_file.as_ref(), error_file.as_ref())
+                .await;
+            result_sender
+                .send(batch)
+                .map_err(|_| AtomaStateManagerError::ChannelSendError)?;
+        }
+        AtomaAtomaStateManagerEvent::GetCachedResponse { key, result_sender } => {
+            let response = state_manager.state.get_cached_response(&key).await;
+            result_sender
+                .send(response)
+                .map_err(|_| AtomaStateManagerError::ChannelSendError)?;
+        }
+        AtomaAtomaStateManagerEvent::InsertCachedResponse {
+            key,
+            model,
+            endpoint,
+            response,
+            expires_at,
+        } => {
+            state_manager
+                .state
+                .insert_cached_response(&key, &model, &endpoint, &response, expires_at)
+                .await?;
+        }
+        AtomaAtomaStateManagerEvent::ChargeCachedResponse {
+            usage_record,
+            cost,
+            result_sender,
+        } => {
+            let result = state_manager
+                .state
+                .charge_cached_response(&usage_record, cost)
+                .await;
+            let is_charged = result.is_ok() && cost > 0;
+            result_sender
+                .send(result)
+                .map_err(|_| AtomaStateManagerError::ChannelSendError)?;
+            if is_charged {
+                let state = state_manager.state.clone();
+                let user_id = usage_record.user_id;
+                spawn_webhook_notification(async move {
+                    notify_low_balance(&state, user_id).await?;
+                    notify_monthly_spend_limit(&state, user_id).await
+                });
+            }
+        }
     }
     Ok(())
 }
@@ -11,11 +11,15 @@ pub mod state_manager;
 #[cfg(test)]
 pub mod tests;
 pub mod types;
+pub mod webhooks;
 
 use chrono::{DateTime, Utc};
 pub use config::AtomaStateManagerConfig;
 pub use errors::AtomaStateManagerError;
-pub use metrics::{trigger_new_metrics_collection_task, NodeMetricsCollector};
+pub use metrics::{
+    trigger_new_metrics_collection_task, BestAvailableNodes, BestAvailableNodesRequest,
+    NodeMetricsCollector,
+};
 pub use sqlx::PgPool;
 use sqlx::Postgres;
 pub use state_manager::{AtomaState, AtomaStateManager};
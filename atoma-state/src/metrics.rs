@@ -1,4 +1,4 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, sync::Arc, time::Instant};
 
 use atoma_p2p::broadcast_metrics::{
     ChatCompletionsMetrics, EmbeddingsMetrics, ImageGenerationMetrics, ModelMetrics, NodeMetrics,
@@ -30,6 +30,20 @@ pub(crate) const DEFAULT_TOP_K_BEST_AVAILABLE_NODES: usize = 10;
 
 type Result<T> = std::result::Result<T, MetricsServiceError>;
 
+/// Request for the best available nodes of a given model, together with the channel
+/// on which the metrics collection task sends back the latest ranking (if any).
+pub type BestAvailableNodesRequest = (String, oneshot::Sender<Option<BestAvailableNodes>>);
+
+/// The latest ranking of the best available nodes for a model.
+#[derive(Clone, Debug)]
+pub struct BestAvailableNodes {
+    /// Node small ids, sorted by their performance score (best performing nodes first)
+    pub node_small_ids: Vec<i64>,
+
+    /// The instant at which the ranking was collected from Prometheus
+    pub collected_at: Instant,
+}
+
 /// HTTP client for the node metrics queries
 static HTTP_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
     reqwest::Client::builder()
@@ -83,7 +97,7 @@ pub async fn trigger_new_metrics_collection_task(
     node_metrics_collector: NodeMetricsCollector,
     metrics_collection_config: MetricsCollectionConfig,
     rx_collected_metrics: FlumeReceiver<(i64, NodeMetrics)>,
-    request_best_available_models_receiver: FlumeReceiver<(String, oneshot::Sender<Vec<i64>>)>,
+    request_best_available_models_receiver: FlumeReceiver<BestAvailableNodesRequest>,
     mut shutdown_signal: watch::Receiver<bool>,
 ) -> Result<()> {
     let best_available_nodes = Arc::new(RwLock::new(HashMap::with_capacity(
@@ -167,23 +181,22 @@ pub async fn trigger_new_metrics_collection_task(
 
 /// Sends the best available nodes for a requested model through a oneshot channel.
 ///
-/// This function retrieves the list of best available nodes for a specific model from the shared state
-/// and sends it through a oneshot channel to the requester. The function is used as part of the metrics
-/// collection system to provide information about the most optimal nodes for handling specific model requests.
+/// This function retrieves the latest ranking of best available nodes for a specific model from the shared state
+/// and sends it through a oneshot channel to the requester. If no ranking has been collected for the model yet,
+/// `None` is sent instead, so the requester can fall back to a different node selection policy.
 ///
 /// # Arguments
 ///
 /// * `request_best_available_models` - A Result containing a tuple of:
 ///   * A String representing the model identifier
-///   * A oneshot::Sender for sending the list of best available node IDs
+///   * A oneshot::Sender for sending the latest ranking of best available nodes
 /// * `best_available_nodes` - An Arc<RwLock<HashMap>> containing the cached best available nodes for each model
 ///
 /// # Returns
 ///
 /// Returns a `Result<()>` which is:
-/// * `Ok(())` if the nodes were successfully retrieved and sent
+/// * `Ok(())` if the ranking was successfully sent
 /// * `Err(MetricsServiceError)` if:
-///   * The model was not found in the cached best available nodes
 ///   * The channel send operation failed
 ///   * The input Result was an error
 ///
@@ -202,16 +215,13 @@ pub async fn trigger_new_metrics_collection_task(
 /// ```
 #[instrument(level = "debug", skip_all, fields(send_best_available_nodes = true,))]
 async fn send_best_available_nodes(
-    request_best_available_models: Result<(String, oneshot::Sender<Vec<i64>>)>,
-    best_available_nodes: Arc<RwLock<HashMap<String, Vec<i64>>>>,
+    request_best_available_models: Result<BestAvailableNodesRequest>,
+    best_available_nodes: Arc<RwLock<HashMap<String, BestAvailableNodes>>>,
 ) -> Result<()> {
     let (model, sender) = request_best_available_models?;
     let model_best_available_nodes = {
         let read_lock = best_available_nodes.read().await;
-        read_lock
-            .get(&model)
-            .ok_or(MetricsServiceError::ModelNotFound(model))?
-            .clone()
+        read_lock.get(&model).cloned()
     };
     sender
         .send(model_best_available_nodes)
@@ -253,7 +263,7 @@ async fn send_best_available_nodes(
     )
 )]
 async fn collect_best_available_nodes(
-    best_available_nodes: Arc<RwLock<HashMap<String, Vec<i64>>>>,
+    best_available_nodes: Arc<RwLock<HashMap<String, BestAvailableNodes>>>,
     metrics_collection_config: &MetricsCollectionConfig,
 ) -> Result<()> {
     let futures = metrics_collection_config
@@ -288,13 +298,20 @@ async fn collect_best_available_nodes(
                         )
                         .await?
                     }
+                    // NOTE: Nodes do not export performance metrics for audio models yet, so no
+                    // ranking is collected and stacks are bought from the cheapest node.
+                    Modalities::AudioTranscriptions | Modalities::AudioSpeech => Vec::new(),
                 };
 
-                best_available_nodes
-                    .write()
-                    .await
-                    .entry(modality_model.to_string())
-                    .or_insert(model_best_available_nodes);
+                // NOTE: We overwrite the previous ranking, so that requesters always get
+                // the ranking of the latest collection period.
+                best_available_nodes.write().await.insert(
+                    modality_model.to_string(),
+                    BestAvailableNodes {
+                        node_small_ids: model_best_available_nodes,
+                        collected_at: Instant::now(),
+                    },
+                );
 
                 Ok::<_, MetricsServiceError>((modality_model.to_string(), best_available_nodes))
             }
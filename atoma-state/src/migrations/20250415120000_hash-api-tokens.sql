@@ -0,0 +1,23 @@
+-- Store API tokens as keyed hashes instead of plaintext.
+--
+-- The hashing key is the `api_token_hash_key` of the auth configuration, which is
+-- not available to SQL, so the existing plaintext tokens are kept in `token` until
+-- the proxy hashes them on startup (see `AtomaState::hash_plaintext_api_tokens`),
+-- which then clears the `token` column. New tokens never store the plaintext value.
+ALTER TABLE api_tokens
+ADD COLUMN token_hash VARCHAR(64) UNIQUE,
+ADD COLUMN token_prefix VARCHAR(16),
+ADD COLUMN token_last_4 VARCHAR(4);
+
+-- The display prefix is not secret and can be derived for the existing tokens, as
+-- can the last 4 characters, still returned for one release (deprecated in favour
+-- of the display prefix).
+UPDATE api_tokens
+SET token_prefix = LEFT(token, 8),
+    token_last_4 = RIGHT(token, 4);
+
+ALTER TABLE api_tokens
+ALTER COLUMN token_prefix SET NOT NULL,
+ALTER COLUMN token_last_4 SET NOT NULL,
+ALTER COLUMN token DROP NOT NULL,
+ADD CONSTRAINT api_tokens_token_or_hash_check CHECK (token IS NOT NULL OR token_hash IS NOT NULL);
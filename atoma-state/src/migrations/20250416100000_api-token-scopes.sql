@@ -0,0 +1,13 @@
+-- Restrict what API tokens can be used for.
+--
+-- Empty `allowed_endpoints` / `allowed_models` arrays mean the token is not
+-- restricted, so existing tokens keep working on every route and model.
+ALTER TABLE api_tokens
+ADD COLUMN allowed_endpoints TEXT[] NOT NULL DEFAULT '{}',
+ADD COLUMN allowed_models TEXT[] NOT NULL DEFAULT '{}',
+ADD COLUMN confidential_only BOOLEAN NOT NULL DEFAULT FALSE,
+ADD COLUMN expires_at TIMESTAMPTZ,
+-- Maximum amount of USDC the token can spend, unlimited if NULL
+ADD COLUMN spending_limit BIGINT CHECK (spending_limit > 0),
+-- Amount of USDC spent by requests authorized with the token
+ADD COLUMN amount_spent BIGINT NOT NULL DEFAULT 0;
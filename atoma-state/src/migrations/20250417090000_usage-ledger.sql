@@ -0,0 +1,21 @@
+-- Durable per-request usage ledger, used by users to reconcile their invoices.
+CREATE TABLE IF NOT EXISTS usage_records (
+    id                BIGSERIAL PRIMARY KEY,
+    user_id           BIGINT NOT NULL,
+    api_token_id      BIGINT NOT NULL,
+    model             TEXT NOT NULL,
+    endpoint          TEXT NOT NULL,
+    stack_small_id    BIGINT NOT NULL,
+    node_small_id     BIGINT NOT NULL,
+    prompt_tokens     BIGINT NOT NULL,
+    completion_tokens BIGINT NOT NULL,
+    -- Cost of the request in USDC, computed from the price of the stack
+    cost              BIGINT NOT NULL,
+    -- Time taken to process the request, in milliseconds
+    latency_ms        BIGINT NOT NULL,
+    -- HTTP status code of the response
+    status            INTEGER NOT NULL,
+    created_at        TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_usage_records_user_id_created_at ON usage_records (user_id, created_at DESC, id DESC);
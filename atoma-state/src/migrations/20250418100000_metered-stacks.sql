@@ -0,0 +1,3 @@
+-- Stacks bought in metered billing mode: the user balance is not charged for the whole stack,
+-- but reserved for the estimated compute units of each request, and settled to the actual usage.
+ALTER TABLE stacks ADD COLUMN is_metered BOOLEAN NOT NULL DEFAULT FALSE;
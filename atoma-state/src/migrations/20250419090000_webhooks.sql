@@ -0,0 +1,19 @@
+-- Webhooks registered by users to be notified of billing and API token events.
+CREATE TABLE IF NOT EXISTS webhooks (
+    id                      BIGSERIAL PRIMARY KEY,
+    user_id                 BIGINT NOT NULL,
+    url                     TEXT NOT NULL,
+    -- Hex encoded key used to sign the payloads delivered to the webhook
+    secret                  TEXT NOT NULL,
+    -- Notify when the USDC balance of the user drops below this threshold
+    low_balance_threshold   BIGINT,
+    -- Notify when the USDC spent by the user in the current month reaches this limit
+    monthly_spend_limit     BIGINT,
+    -- Whether the low balance event was already delivered, reset when the balance is topped up
+    low_balance_notified    BOOLEAN NOT NULL DEFAULT FALSE,
+    -- When the monthly spend limit event was last delivered
+    spend_limit_notified_at TIMESTAMPTZ,
+    created_at              TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_webhooks_user_id ON webhooks (user_id);
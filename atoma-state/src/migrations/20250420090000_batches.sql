@@ -0,0 +1,61 @@
+-- Files uploaded by users, e.g. batch inputs, or generated by the proxy, e.g. batch outputs and errors.
+CREATE TABLE IF NOT EXISTS files (
+    id         TEXT PRIMARY KEY,
+    user_id    BIGINT NOT NULL,
+    purpose    TEXT NOT NULL,
+    filename   TEXT NOT NULL,
+    bytes      BIGINT NOT NULL,
+    content    BYTEA NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_files_user_id ON files (user_id);
+
+-- Batches of requests executed in the background, on behalf of an API token of a user.
+CREATE TABLE IF NOT EXISTS batches (
+    id                 TEXT PRIMARY KEY,
+    user_id            BIGINT NOT NULL,
+    api_token_id       BIGINT NOT NULL,
+    endpoint           TEXT NOT NULL,
+    input_file_id      TEXT NOT NULL,
+    completion_window  TEXT NOT NULL,
+    -- One of validating, in_progress, cancelling, completed, expired or cancelled
+    status             TEXT NOT NULL DEFAULT 'validating',
+    output_file_id     TEXT,
+    error_file_id      TEXT,
+    total_requests     BIGINT NOT NULL,
+    completed_requests BIGINT NOT NULL DEFAULT 0,
+    failed_requests    BIGINT NOT NULL DEFAULT 0,
+    -- JSON encoded metadata set by the user
+    metadata           TEXT,
+    created_at         TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    in_progress_at     TIMESTAMPTZ,
+    cancelling_at      TIMESTAMPTZ,
+    completed_at       TIMESTAMPTZ,
+    expired_at         TIMESTAMPTZ,
+    cancelled_at       TIMESTAMPTZ,
+    expires_at         TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_batches_user_id ON batches (user_id, created_at);
+CREATE INDEX IF NOT EXISTS idx_batches_status ON batches (status);
+
+-- The requests of a batch, one per line of its input file.
+CREATE TABLE IF NOT EXISTS batch_requests (
+    batch_id        TEXT NOT NULL,
+    line_index      BIGINT NOT NULL,
+    custom_id       TEXT NOT NULL,
+    -- JSON encoded body of the request
+    body            TEXT NOT NULL,
+    -- One of pending, running, completed or failed
+    status          TEXT NOT NULL DEFAULT 'pending',
+    response_status INTEGER,
+    -- JSON encoded body of the response
+    response_body   TEXT,
+    -- JSON encoded error, when the request could not be executed
+    error           TEXT,
+    updated_at      TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (batch_id, line_index)
+);
+
+CREATE INDEX IF NOT EXISTS idx_batch_requests_status ON batch_requests (batch_id, status);
@@ -0,0 +1,13 @@
+-- Responses cached by the proxy, shared by all the proxy instances.
+CREATE TABLE IF NOT EXISTS response_cache (
+    -- Hash of the model and of the normalized request
+    key         TEXT PRIMARY KEY,
+    model       TEXT NOT NULL,
+    endpoint    TEXT NOT NULL,
+    -- JSON encoded response
+    response    TEXT NOT NULL,
+    created_at  TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at  TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_response_cache_expires_at ON response_cache (expires_at);
@@ -0,0 +1,2 @@
+-- Time at which the task was registered, reported as the creation time of its model
+ALTER TABLE tasks ADD COLUMN IF NOT EXISTS created_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
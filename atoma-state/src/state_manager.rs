@@ -2,9 +2,12 @@ use std::time::Duration;
 
 use crate::handlers::{handle_atoma_event, handle_p2p_event, handle_state_manager_event};
 use crate::types::{
-    AtomaAtomaStateManagerEvent, CheapestNode, ComputedUnitsProcessedResponse, LatencyResponse,
-    NodeDistribution, NodePublicKey, NodeSubscription, Stack, StackAttestationDispute,
-    StackSettlementTicket, StatsStackResponse, Task, TokenResponse, UserProfile,
+    ApiToken, AtomaAtomaStateManagerEvent, Batch, BatchRequest, BatchRequestOutcome, BatchStatus,
+    CheapestNode, ComputedUnitsProcessedResponse, CreateTokenRequest, LatencyResponse,
+    ModelCatalogEntry, NewBatch, NewBatchRequest, NewUsageRecord, NewUserFile, NodeDistribution,
+    NodePublicKey, NodeSubscription, Stack, StackAttestationDispute, StackSettlementTicket,
+    StatsStackResponse, Task, TokenResponse, UsagePage, UsageRecord, UserFile, UserProfile,
+    Webhook,
 };
 use crate::{build_query_with_in, AtomaStateManagerError};
 
@@ -306,6 +309,12 @@ impl AtomaState {
     ///
     /// * `model` - The model name for the task.
     /// * `free_units` - The number of free units available.
+    /// * `user_id` - The ID of the user owning the stack.
+    /// * `is_confidential` - Whether the stack must be on a node supporting confidential compute.
+    /// * `excluded_node_small_ids` - Nodes whose stacks must not be selected (e.g. nodes that just failed a request).
+    ///
+    /// For metered stacks, the price of the locked compute units is reserved from the user's USDC balance,
+    /// in the same statement, and metered stacks are skipped if the balance is not enough.
     ///
     /// # Returns
     ///
@@ -323,6 +332,7 @@ impl AtomaState {
         free_units: i64,
         user_id: i64,
         is_confidential: bool,
+        excluded_node_small_ids: &[i64],
     ) -> Result<Option<Stack>> {
         let mut query = String::from(
             r"
@@ -358,7 +368,16 @@ impl AtomaState {
                 AND stacks.is_claimed = false
                 AND stacks.is_locked = false
                 AND stacks.in_settle_period = false
-                AND (stack_settlement_tickets.is_claimed = false OR stack_settlement_tickets.is_claimed IS NULL)",
+                AND (stack_settlement_tickets.is_claimed = false OR stack_settlement_tickets.is_claimed IS NULL)
+                AND NOT (stacks.selected_node_id = ANY($4))
+                AND (
+                    stacks.is_metered = false
+                    OR EXISTS (
+                        SELECT 1 FROM balance
+                        WHERE balance.user_id = stacks.user_id
+                        AND balance.usdc_balance >= CEIL($2 * stacks.price_per_one_million_compute_units / 1000000.0)
+                    )
+                )",
         );
 
         if is_confidential {
@@ -371,10 +390,21 @@ impl AtomaState {
         query.push_str(
             r"
                 LIMIT 1
+            ),
+            reserved_balance AS (
+                UPDATE balance
+                SET usdc_balance = balance.usdc_balance - CEIL($2 * stacks.price_per_one_million_compute_units / 1000000.0)::BIGINT
+                FROM stacks
+                WHERE stacks.stack_small_id IN (SELECT stack_small_id FROM selected_stack)
+                AND stacks.is_metered = true
+                AND balance.user_id = stacks.user_id
+                AND balance.usdc_balance >= CEIL($2 * stacks.price_per_one_million_compute_units / 1000000.0)
+                RETURNING balance.user_id
             )
             UPDATE stacks
             SET locked_compute_units = locked_compute_units + $2
             WHERE stack_small_id IN (SELECT stack_small_id FROM selected_stack)
+            AND (is_metered = false OR EXISTS (SELECT 1 FROM reserved_balance))
             RETURNING stacks.*",
         );
 
@@ -382,6 +412,7 @@ impl AtomaState {
             .bind(model)
             .bind(free_units)
             .bind(user_id)
+            .bind(excluded_node_small_ids)
             .fetch_optional(&self.db)
             .await?
             .map(|stack| Stack::from_row(&stack).map_err(AtomaStateManagerError::from))
@@ -401,6 +432,9 @@ impl AtomaState {
     /// * `free_units` - The number of compute units required and to be reserved.
     /// * `user_id` - The ID of the user requesting the stack.
     ///
+    /// For metered stacks, the price of the locked compute units is reserved from the user's USDC balance,
+    /// in the same statement, and metered stacks are skipped if the balance is not enough.
+    ///
     /// # Returns
     ///
     /// - `Result<Option<Stack>>`: A result containing either:
@@ -430,12 +464,31 @@ impl AtomaState {
                 AND is_claimed = false 
                 AND is_locked = false 
                 AND in_settle_period = false
+                AND (
+                    is_metered = false
+                    OR EXISTS (
+                        SELECT 1 FROM balance
+                        WHERE balance.user_id = stacks.user_id
+                        AND balance.usdc_balance >= CEIL($2 * stacks.price_per_one_million_compute_units / 1000000.0)
+                    )
+                )
                 LIMIT 1
                 FOR UPDATE
+            ),
+            reserved_balance AS (
+                UPDATE balance
+                SET usdc_balance = balance.usdc_balance - CEIL($2 * stacks.price_per_one_million_compute_units / 1000000.0)::BIGINT
+                FROM stacks
+                WHERE stacks.stack_small_id IN (SELECT stack_small_id FROM selected_stack)
+                AND stacks.is_metered = true
+                AND balance.user_id = stacks.user_id
+                AND balance.usdc_balance >= CEIL($2 * stacks.price_per_one_million_compute_units / 1000000.0)
+                RETURNING balance.user_id
             )
             UPDATE stacks
             SET locked_compute_units = locked_compute_units + $2
             WHERE stack_small_id IN (SELECT stack_small_id FROM selected_stack)
+            AND (is_metered = false OR EXISTS (SELECT 1 FROM reserved_balance))
             RETURNING *",
         )
         .bind(task_small_id)
@@ -519,6 +572,7 @@ impl AtomaState {
     ///
     /// * `model` - The name of the model to search for (e.g., "gpt-4", "llama-2")
     /// * `is_confidential` - Whether to only return nodes that support confidential computing
+    /// * `excluded_node_small_ids` - Nodes that must not be returned (e.g. nodes that just failed a request)
     ///
     /// # Returns
     ///
@@ -547,10 +601,10 @@ impl AtomaState {
     ///
     /// async fn find_cheapest_node(state: &AtomaState) -> anyhow::Result<()> {
     ///     // Find cheapest non-confidential node for GPT-4
-    ///     let regular_node = state.get_cheapest_node_for_model("gpt-4", false).await?;
+    ///     let regular_node = state.get_cheapest_node_for_model("gpt-4", false, &[]).await?;
     ///
     ///     // Find cheapest confidential node for GPT-4
-    ///     let confidential_node = state.get_cheapest_node_for_model("gpt-4", true).await?;
+    ///     let confidential_node = state.get_cheapest_node_for_model("gpt-4", true, &[]).await?;
     ///
     ///     Ok(())
     /// }
@@ -560,6 +614,7 @@ impl AtomaState {
         &self,
         model: &str,
         is_confidential: bool,
+        excluded_node_small_ids: &[i64],
     ) -> Result<Option<CheapestNode>> {
         // TODO: benchmark this query performance
         let mut query = String::from(
@@ -594,7 +649,8 @@ impl AtomaState {
             r"
             WHERE tasks.is_deprecated = false
             AND tasks.model_name = $1
-            AND node_subscriptions.valid = true",
+            AND node_subscriptions.valid = true
+            AND NOT (node_subscriptions.node_small_id = ANY($2))",
         );
 
         if is_confidential {
@@ -612,6 +668,170 @@ impl AtomaState {
 
         let node_settings = sqlx::query(&query)
             .bind(model)
+            .bind(excluded_node_small_ids)
+            .fetch_optional(&self.db)
+            .await?;
+        Ok(node_settings
+            .map(|node_settings| CheapestNode::from_row(&node_settings))
+            .transpose()?)
+    }
+
+    /// Gets the catalog of the models with live node subscriptions.
+    ///
+    /// This method aggregates the valid node subscriptions of non-deprecated tasks, per model, into
+    /// the number of nodes serving the model, the cheapest and median price per one million compute
+    /// units, the largest number of compute units a node accepts for the model, and whether any node
+    /// serves the model with confidential compute (a task with security level 1, on a node with valid
+    /// public keys for the latest key rotation).
+    ///
+    /// # Returns
+    ///
+    /// - `Result<Vec<ModelCatalogEntry>>`: A result containing one entry per model, sorted by model name.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database query fails to execute.
+    #[instrument(level = "trace", skip_all)]
+    pub async fn get_model_catalog(&self) -> Result<Vec<ModelCatalogEntry>> {
+        let models = sqlx::query_as(
+            r"
+            WITH latest_rotation AS (
+                SELECT key_rotation_counter
+                FROM key_rotations
+                ORDER BY key_rotation_counter DESC
+                LIMIT 1
+            ),
+            valid_nodes AS (
+                SELECT DISTINCT npk.node_small_id
+                FROM node_public_keys npk
+                INNER JOIN latest_rotation ON latest_rotation.key_rotation_counter = npk.key_rotation_counter
+                GROUP BY npk.node_small_id
+                HAVING bool_and(npk.is_valid) = true
+            )
+            SELECT tasks.model_name AS model,
+                MIN(tasks.created_at) AS created_at,
+                COUNT(DISTINCT node_subscriptions.node_small_id) AS num_nodes,
+                MIN(node_subscriptions.price_per_one_million_compute_units) AS min_price_per_one_million_compute_units,
+                CAST(percentile_cont(0.5) WITHIN GROUP (ORDER BY node_subscriptions.price_per_one_million_compute_units) AS BIGINT)
+                    AS median_price_per_one_million_compute_units,
+                MAX(node_subscriptions.max_num_compute_units) AS max_num_compute_units,
+                bool_or(tasks.security_level = 1 AND valid_nodes.node_small_id IS NOT NULL) AS is_confidential_available
+            FROM tasks
+            INNER JOIN node_subscriptions ON tasks.task_small_id = node_subscriptions.task_small_id
+            LEFT JOIN valid_nodes ON valid_nodes.node_small_id = node_subscriptions.node_small_id
+            WHERE tasks.is_deprecated = false
+            AND tasks.model_name IS NOT NULL
+            AND node_subscriptions.valid = true
+            GROUP BY tasks.model_name
+            ORDER BY tasks.model_name",
+        )
+        .fetch_all(&self.db)
+        .await?;
+        Ok(models)
+    }
+
+    /// Gets the best ranked node, among a list of candidate nodes, for a given model.
+    ///
+    /// This method queries the database for valid node subscriptions of the specified model that belong
+    /// to one of the `ranked_node_small_ids`, optionally filtering out subscriptions whose price per one million
+    /// compute units exceeds `max_price_per_one_million_compute_units`. Among the remaining subscriptions, the one
+    /// whose node appears first in `ranked_node_small_ids` is returned, with ties broken by price.
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - The name of the model to search for (e.g., "gpt-4", "llama-2")
+    /// * `ranked_node_small_ids` - The candidate node small ids, sorted by preference (best node first)
+    /// * `max_price_per_one_million_compute_units` - Optional price ceiling for the node subscription
+    /// * `is_confidential` - Whether to only return nodes that support confidential computing
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing:
+    /// - `Ok(Some(CheapestNode))` - If a valid node subscription is found among the candidates
+    /// - `Ok(None)` - If none of the candidates has a valid subscription within the price ceiling
+    /// - `Err(AtomaStateManagerError)` - If a database error occurs
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// # use atoma_state::AtomaState;
+    ///
+    /// async fn find_best_node(state: &AtomaState) -> anyhow::Result<()> {
+    ///     // Nodes 3, 1 and 2 are the best performing nodes, in that order
+    ///     let node = state
+    ///         .get_best_ranked_node_for_model("gpt-4", &[3, 1, 2], Some(1_000), false)
+    ///         .await?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    #[instrument(level = "trace", skip_all, fields(%model, ?ranked_node_small_ids))]
+    pub async fn get_best_ranked_node_for_model(
+        &self,
+        model: &str,
+        ranked_node_small_ids: &[i64],
+        max_price_per_one_million_compute_units: Option<i64>,
+        is_confidential: bool,
+    ) -> Result<Option<CheapestNode>> {
+        if ranked_node_small_ids.is_empty() {
+            return Ok(None);
+        }
+        let mut query = String::from(
+            r"
+            WITH latest_rotation AS (
+                SELECT key_rotation_counter
+                FROM key_rotations
+                ORDER BY key_rotation_counter DESC
+                LIMIT 1
+            ),
+            valid_nodes AS (
+                SELECT DISTINCT npk.node_small_id
+                FROM node_public_keys npk
+                INNER JOIN latest_rotation ON latest_rotation.key_rotation_counter = npk.key_rotation_counter
+                GROUP BY npk.node_small_id
+                HAVING bool_and(npk.is_valid) = true
+            )
+            SELECT tasks.task_small_id, node_subscriptions.price_per_one_million_compute_units,
+                node_subscriptions.max_num_compute_units, node_subscriptions.node_small_id
+            FROM tasks
+            INNER JOIN node_subscriptions ON tasks.task_small_id = node_subscriptions.task_small_id",
+        );
+
+        if is_confidential {
+            query.push_str(
+                r"
+            INNER JOIN valid_nodes ON valid_nodes.node_small_id = node_subscriptions.node_small_id",
+            );
+        }
+
+        query.push_str(
+            r"
+            WHERE tasks.is_deprecated = false
+            AND tasks.model_name = $1
+            AND node_subscriptions.valid = true
+            AND node_subscriptions.node_small_id = ANY($2)
+            AND ($3::BIGINT IS NULL OR node_subscriptions.price_per_one_million_compute_units <= $3)",
+        );
+
+        if is_confidential {
+            query.push_str(
+                r"
+            AND tasks.security_level = 1",
+            );
+        }
+
+        query.push_str(
+            r"
+            ORDER BY array_position($2, node_subscriptions.node_small_id),
+                node_subscriptions.price_per_one_million_compute_units
+            LIMIT 1",
+        );
+
+        let node_settings = sqlx::query(&query)
+            .bind(model)
+            .bind(ranked_node_small_ids)
+            .bind(max_price_per_one_million_compute_units)
             .fetch_optional(&self.db)
             .await?;
         Ok(node_settings
@@ -1845,6 +2065,8 @@ impl AtomaState {
     /// - The stack belongs to the specified owner (public key).
     /// - The stack has enough remaining compute units.
     /// - The stack is not in the settle period.
+    /// - For metered stacks, the user's USDC balance covers the price of the compute units,
+    ///   in which case that price is reserved from the balance.
     ///
     /// # Example
     ///
@@ -1880,12 +2102,26 @@ impl AtomaState {
         // Single query that updates and returns the modified row
         let maybe_stack = sqlx::query_as::<_, Stack>(
             r"
+            WITH reserved_balance AS (
+                UPDATE balance
+                SET usdc_balance = balance.usdc_balance - CEIL($1 * stacks.price_per_one_million_compute_units / 1000000.0)::BIGINT
+                FROM stacks
+                WHERE stacks.stack_small_id = $2
+                AND stacks.owner = $3
+                AND stacks.num_compute_units - stacks.already_computed_units - stacks.locked_compute_units >= $1
+                AND stacks.in_settle_period = false
+                AND stacks.is_metered = true
+                AND balance.user_id = stacks.user_id
+                AND balance.usdc_balance >= CEIL($1 * stacks.price_per_one_million_compute_units / 1000000.0)
+                RETURNING balance.user_id
+            )
             UPDATE stacks
             SET locked_compute_units = locked_compute_units + $1
             WHERE stack_small_id = $2
             AND owner = $3
             AND num_compute_units - already_computed_units - locked_compute_units>= $1
             AND in_settle_period = false
+            AND (is_metered = false OR EXISTS (SELECT 1 FROM reserved_balance))
             RETURNING *
             ",
         )
@@ -1919,6 +2155,8 @@ impl AtomaState {
     /// - The database query fails to execute.
     /// - The specified stack is not found (`AtomaStateManagerError::StackNotFound`).
     /// - The specified stack does not have enough compute units to lock.
+    /// - The specified stack is metered and the user's USDC balance does not cover the price of the
+    ///   compute units (otherwise that price is reserved from the balance).
     ///
     /// # Example
     ///
@@ -1946,10 +2184,22 @@ impl AtomaState {
         available_compute_units: i64,
     ) -> Result<()> {
         let result = sqlx::query(
-            "UPDATE stacks
+            "WITH reserved_balance AS (
+                UPDATE balance
+                SET usdc_balance = balance.usdc_balance - CEIL($1 * stacks.price_per_one_million_compute_units / 1000000.0)::BIGINT
+                FROM stacks
+                WHERE stacks.stack_small_id = $2
+                AND stacks.already_computed_units + stacks.locked_compute_units + $1 <= stacks.num_compute_units
+                AND stacks.is_metered = true
+                AND balance.user_id = stacks.user_id
+                AND balance.usdc_balance >= CEIL($1 * stacks.price_per_one_million_compute_units / 1000000.0)
+                RETURNING balance.user_id
+            )
+            UPDATE stacks
             SET locked_compute_units = locked_compute_units + $1
             WHERE stack_small_id = $2
-            AND already_computed_units + locked_compute_units + $1 <= num_compute_units",
+            AND already_computed_units + locked_compute_units + $1 <= num_compute_units
+            AND (is_metered = false OR EXISTS (SELECT 1 FROM reserved_balance))",
         )
         .bind(available_compute_units)
         .bind(stack_small_id)
@@ -1968,6 +2218,9 @@ impl AtomaState {
     /// # Arguments
     ///
     /// * `stack` - The `Stack` object to be inserted into the database.
+    /// * `user_id` - The ID of the user owning the stack.
+    /// * `acquired_timestamp` - The timestamp of the transaction that created the stack.
+    /// * `is_metered` - Whether the user is charged by actual usage instead of for the whole stack.
     ///
     /// # Returns
     ///
@@ -1985,7 +2238,7 @@ impl AtomaState {
     /// use atoma_node::atoma_state::AtomaStateManager;
     ///
     /// async fn insert_stack(state_manager: &AtomaStateManager, stack: Stack) -> Result<(), AtomaStateManagerError> {
-    ///     state_manager.insert_new_stack(stack).await
+    ///     state_manager.insert_new_stack(stack, 1, chrono::Utc::now(), false).await
     /// }
     /// ```
     #[instrument(
@@ -2003,11 +2256,12 @@ impl AtomaState {
         stack: Stack,
         user_id: i64,
         acquired_timestamp: DateTime<Utc>,
+        is_metered: bool,
     ) -> Result<()> {
         sqlx::query(
             "INSERT INTO stacks
-                (owner, stack_small_id, stack_id, task_small_id, selected_node_id, num_compute_units, price_per_one_million_compute_units, already_computed_units, locked_compute_units, in_settle_period, total_hash, num_total_messages, user_id, acquired_timestamp)
-                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)",
+                (owner, stack_small_id, stack_id, task_small_id, selected_node_id, num_compute_units, price_per_one_million_compute_units, already_computed_units, locked_compute_units, in_settle_period, total_hash, num_total_messages, user_id, acquired_timestamp, is_metered)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15)",
         )
             .bind(stack.owner)
             .bind(stack.stack_small_id)
@@ -2023,6 +2277,7 @@ impl AtomaState {
             .bind(stack.num_total_messages)
             .bind(user_id)
             .bind(acquired_timestamp)
+            .bind(is_metered)
             .execute(&self.db)
             .await?;
         Ok(())
@@ -2072,11 +2327,17 @@ impl AtomaState {
     /// This method updates the `already_computed_units` field in the `stacks` table
     /// for the specified `stack_small_id`.
     ///
+    /// If the stack is metered, the USDC reserved for the estimated total tokens is settled
+    /// to the billed total tokens: the difference is refunded to (or charged from) the user's
+    /// balance, in the same transaction.
+    ///
     /// # Arguments
     ///
     /// * `stack_small_id` - The unique small identifier of the stack to update.
     /// * `estimated_total_tokens` - The estimated total number of tokens.
-    /// * `total_tokens` - The total number of tokens.
+    /// * `total_tokens` - The total number of tokens computed by the node.
+    /// * `billed_total_tokens` - The total number of tokens billed to the user, which can be lower
+    ///   than `total_tokens` (e.g. when the client disconnected before receiving the whole output).
     ///
     /// # Returns
     ///
@@ -2093,36 +2354,56 @@ impl AtomaState {
     /// use atoma_node::atoma_state::AtomaStateManager;
     ///
     /// async fn update_stack_num_tokens(state_manager: &AtomaStateManager, stack_small_id: i64, estimated_total_tokens: i64, total_tokens: i64) -> Result<(), AtomaStateManagerError> {
-    ///     state_manager.update_stack_num_tokens(stack_small_id, estimated_total_tokens, total_tokens).await
+    ///     state_manager.update_stack_num_tokens(stack_small_id, estimated_total_tokens, total_tokens, total_tokens).await
     /// }
     /// ```
     #[instrument(
         level = "trace",
         skip_all,
-        fields(%stack_small_id, %estimated_total_tokens, %total_tokens)
+        fields(%stack_small_id, %estimated_total_tokens, %total_tokens, %billed_total_tokens)
     )]
     pub async fn update_stack_num_tokens(
         &self,
         stack_small_id: i64,
         estimated_total_tokens: i64,
         total_tokens: i64,
+        billed_total_tokens: i64,
     ) -> Result<()> {
-        let result = sqlx::query(
+        let mut tx = self.db.begin().await?;
+        let stack = sqlx::query(
             "UPDATE stacks
                 SET already_computed_units = already_computed_units + $2,
                     locked_compute_units = locked_compute_units - $1
                 WHERE stack_small_id = $3
+                RETURNING user_id, price_per_one_million_compute_units, is_metered
            ",
         )
         .bind(estimated_total_tokens)
         .bind(total_tokens)
         .bind(stack_small_id)
-        .execute(&self.db)
-        .await?;
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(AtomaStateManagerError::StackNotFound)?;
 
-        if result.rows_affected() == 0 {
-            return Err(AtomaStateManagerError::StackNotFound);
+        if stack.get::<bool, _>("is_metered") {
+            // NOTE: The balance can't go below zero, so if the actual usage exceeds the
+            // reservation by more than the remaining balance, the excess is not charged.
+            sqlx::query(
+                "UPDATE balance
+                    SET usdc_balance = GREATEST(
+                        usdc_balance + CEIL($2 * $4 / 1000000.0)::BIGINT - CEIL($3 * $4 / 1000000.0)::BIGINT,
+                        0
+                    )
+                    WHERE user_id = $1",
+            )
+            .bind(stack.get::<i64, _>("user_id"))
+            .bind(estimated_total_tokens)
+            .bind(billed_total_tokens)
+            .bind(stack.get::<i64, _>("price_per_one_million_compute_units"))
+            .execute(&mut *tx)
+            .await?;
         }
+        tx.commit().await?;
 
         Ok(())
     }
@@ -3699,7 +3980,7 @@ impl AtomaState {
     ///
     /// # Returns
     ///
-    /// - `Result<()>`: A result indicating success (Ok(())) or failure (Err(AtomaStateManagerError)).
+    /// - `Result<bool>`: A result containing whether the user had an api token with this id, that was deleted.
     ///
     /// # Errors
     ///
@@ -3711,67 +3992,110 @@ impl AtomaState {
     /// ```rust,ignore
     /// use atoma_node::atoma_state::AtomaStateManager;
     ///
-    /// async fn delete_token(state_manager: &AtomaStateManager, user_id: i64, api_token: &str) -> Result<(), AtomaStateManagerError> {
+    /// async fn delete_token(state_manager: &AtomaStateManager, user_id: i64, api_token: &str) -> Result<bool, AtomaStateManagerError> {
     ///    state_manager.delete_api_token(user_id, api_token).await
     /// }
     /// ```
     #[instrument(level = "trace", skip(self))]
-    pub async fn delete_api_token(&self, user_id: i64, api_token_id: i64) -> Result<()> {
-        sqlx::query("DELETE FROM api_tokens WHERE user_id = $1 AND id = $2")
+    pub async fn delete_api_token(&self, user_id: i64, api_token_id: i64) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM api_tokens WHERE user_id = $1 AND id = $2")
             .bind(user_id)
             .bind(api_token_id)
             .execute(&self.db)
             .await?;
-        Ok(())
+        Ok(result.rows_affected() > 0)
     }
 
     /// Checks if the api token is valid for the user.
     ///
     /// This method checks if the api token is valid for the user by querying the `api_tokens` table.
     ///
+    /// Tokens are stored as keyed hashes, so the caller is responsible for hashing the
+    /// bearer token presented by the client before calling this method.
+    ///
     /// # Arguments
     ///
-    /// * `user_id` - The unique identifier of the user.
-    /// * `api_token` - The api token to check.
+    /// * `api_token_hash` - The keyed hash of the api token to check.
+    ///
+    /// The scopes, expiration and spending limit of the token are returned along with its owner,
+    /// it is up to the caller to enforce them for the request being authenticated.
     ///
     /// # Returns
     ///
-    /// - `Result<bool>`: A result indicating whether the api token is valid for the user.
+    /// - `Result<ApiToken>`: The api token, with the id of the user owning it.
     ///
     /// # Errors
     ///
     /// This function will return an error if:
     /// - The database query fails to execute.
+    /// - No api token matches the given hash.
     ///
     /// # Example
     ///
     /// ```rust,ignore
     /// use atoma_node::atoma_state::AtomaStateManager;
     ///
-    /// async fn is_token_valid(state_manager: &AtomaStateManager, user_id: i64, api_token: &str) -> Result<bool> {
-    ///    state_manager.is_api_token_valid(user_id, api_token).await
+    /// async fn is_token_valid(state_manager: &AtomaStateManager, api_token_hash: &str) -> Result<ApiToken> {
+    ///    state_manager.is_api_token_valid(api_token_hash).await
     /// }
     /// ```
+    #[instrument(level = "trace", skip_all)]
+    pub async fn is_api_token_valid(&self, api_token_hash: &str) -> Result<ApiToken> {
+        let api_token = sqlx::query(
+            "UPDATE api_tokens SET last_used_timestamp = now()
+            WHERE token_hash = $1
+            RETURNING id, user_id, allowed_endpoints, allowed_models, confidential_only, expires_at, spending_limit, amount_spent",
+        )
+        .bind(api_token_hash)
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(ApiToken::from_row(&api_token)?)
+    }
+
+    /// Retrieves an API token by its id.
+    ///
+    /// This is used to execute the requests forwarded by the proxy to itself on behalf of the API token of the client.
+    /// Unlike [`Self::is_api_token_valid`], the last used timestamp of the token is not updated.
+    ///
+    /// # Arguments
+    ///
+    /// * `api_token_id` - The unique identifier of the API token.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<ApiToken>`: A result containing the API token, with its owner and scopes.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database query fails to execute.
+    /// - The API token does not exist, e.g. because it was revoked.
     #[instrument(level = "trace", skip(self))]
-    pub async fn is_api_token_valid(&self, api_token: &str) -> Result<i64> {
-        let is_valid = sqlx::query(
-            "UPDATE api_tokens SET last_used_timestamp = now() WHERE token = $1 RETURNING user_id",
+    pub async fn get_api_token_by_id(&self, api_token_id: i64) -> Result<ApiToken> {
+        let api_token = sqlx::query(
+            "SELECT id, user_id, allowed_endpoints, allowed_models, confidential_only, expires_at, spending_limit, amount_spent
+            FROM api_tokens WHERE id = $1",
         )
-        .bind(api_token)
+        .bind(api_token_id)
         .fetch_one(&self.db)
         .await?;
 
-        Ok(is_valid.get::<i64, _>(0))
+        Ok(ApiToken::from_row(&api_token)?)
     }
 
     /// Stores a new api token for a user.
     ///
     /// This method inserts a new api token into the `api_tokens` table for the specified user.
+    /// Only the keyed hash of the token, its non-secret display prefix and its last 4 chars are persisted.
     ///
     /// # Arguments
     ///
     /// * `user_id` - The unique identifier of the user.
-    /// * `api_token` - The api token to store.
+    /// * `api_token_hash` - The keyed hash of the api token.
+    /// * `api_token_prefix` - The display prefix of the api token.
+    /// * `api_token_last_4` - The last 4 chars of the api token, still returned for one release.
+    /// * `request` - The token creation request, with the name, scopes, expiration and spending limit of the token.
     ///
     /// # Returns
     ///
@@ -3787,35 +4111,61 @@ impl AtomaState {
     /// ```rust,ignore
     /// use atoma_node::atoma_state::AtomaStateManager;
     ///
-    /// async fn store_token(state_manager: &AtomaStateManager, user_id: i64, api_token: &str) -> Result<(), AtomaStateManagerError> {
-    ///    state_manager.store_api_token(user_id, api_token).await
+    /// async fn store_token(state_manager: &AtomaStateManager, user_id: i64, api_token_hash: &str, api_token_prefix: &str, api_token_last_4: &str) -> Result<(), AtomaStateManagerError> {
+    ///    let request = CreateTokenRequest {
+    ///        name: "my token".to_string(),
+    ///        scopes: ApiTokenScopes::default(),
+    ///        expires_at: None,
+    ///        spending_limit: None,
+    ///    };
+    ///    state_manager.store_api_token(user_id, api_token_hash, api_token_prefix, api_token_last_4, &request).await
     /// }
     /// ```
-    #[instrument(level = "trace", skip(self))]
-    pub async fn store_api_token(&self, user_id: i64, api_token: &str, name: &str) -> Result<()> {
-        sqlx::query("INSERT INTO api_tokens (user_id, token, name) VALUES ($1, $2, $3)")
-            .bind(user_id)
-            .bind(api_token)
-            .bind(name)
-            .execute(&self.db)
-            .await?;
+    #[instrument(level = "trace", skip(self, api_token_hash))]
+    pub async fn store_api_token(
+        &self,
+        user_id: i64,
+        api_token_hash: &str,
+        api_token_prefix: &str,
+        api_token_last_4: &str,
+        request: &CreateTokenRequest,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO api_tokens (
+                user_id, token_hash, token_prefix, token_last_4, name, allowed_endpoints,
+                allowed_models, confidential_only, expires_at, spending_limit
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+        )
+        .bind(user_id)
+        .bind(api_token_hash)
+        .bind(api_token_prefix)
+        .bind(api_token_last_4)
+        .bind(&request.name)
+        .bind(&request.scopes.endpoints)
+        .bind(&request.scopes.models)
+        .bind(request.scopes.confidential_only)
+        .bind(request.expires_at)
+        .bind(request.spending_limit)
+        .execute(&self.db)
+        .await?;
         Ok(())
     }
 
-    /// Retrieves all API tokens for a user.
+    /// Adds the cost of processed compute units to the amount spent by an api token.
     ///
-    /// This method fetches all API tokens from the `api_tokens` table for the specified user.
+    /// The cost is computed from the price per one million compute units of the stack that
+    /// processed the request, rounded up so that small requests still count towards the
+    /// spending limit of the token.
     ///
     /// # Arguments
     ///
-    /// * `user_id` - The unique identifier of the user.
+    /// * `api_token_id` - The unique identifier of the api token.
+    /// * `stack_small_id` - The unique small identifier of the stack that processed the request.
+    /// * `num_compute_units` - The number of compute units processed.
     ///
     /// # Returns
     ///
-    /// - `Result<Vec<TokenResponse>>`: A result containing either:
-    ///   - `Ok(Vec<TokenResponse>)`: A list of API tokens for the user.
-    ///   - `Err(AtomaStateManagerError)`: An error if the database query fails.
-    ///
+    /// - `Result<()>`: A result indicating success (Ok(())) or failure (Err(AtomaStateManagerError)).
     ///
     /// # Errors
     ///
@@ -3827,38 +4177,45 @@ impl AtomaState {
     /// ```rust,ignore
     /// use atoma_node::atoma_state::AtomaStateManager;
     ///
-    /// async fn get_tokens(state_manager: &AtomaStateManager, user_id: i64) -> Result<Vec<String>, AtomaStateManagerError> {
-    ///    state_manager.get_api_tokens_for_user(user_id).await
+    /// async fn update_spend(state_manager: &AtomaStateManager, api_token_id: i64, stack_small_id: i64) -> Result<(), AtomaStateManagerError> {
+    ///    state_manager.update_api_token_spend(api_token_id, stack_small_id, 1_000).await
     /// }
     /// ```
     #[instrument(level = "trace", skip(self))]
-    pub async fn get_api_tokens_for_user(&self, user_id: i64) -> Result<Vec<TokenResponse>> {
-        let tokens = sqlx::query(
-            "SELECT id, RIGHT(token,4) as token_last_4, last_used_timestamp, creation_timestamp as created_at, name FROM api_tokens WHERE user_id = $1",
+    pub async fn update_api_token_spend(
+        &self,
+        api_token_id: i64,
+        stack_small_id: i64,
+        num_compute_units: i64,
+    ) -> Result<()> {
+        sqlx::query(
+            "UPDATE api_tokens
+            SET amount_spent = api_tokens.amount_spent
+                + ($3 * stacks.price_per_one_million_compute_units + 999999) / 1000000
+            FROM stacks
+            WHERE api_tokens.id = $1 AND stacks.stack_small_id = $2",
         )
-        .bind(user_id)
-        .fetch_all(&self.db)
+        .bind(api_token_id)
+        .bind(stack_small_id)
+        .bind(num_compute_units)
+        .execute(&self.db)
         .await?;
-
-        tokens
-            .into_iter()
-            .map(|token| TokenResponse::from_row(&token).map_err(AtomaStateManagerError::from))
-            .collect()
+        Ok(())
     }
 
-    /// Get compute units processed for the last `last_hours` hours.
+    /// Records a billed request in the usage ledger.
     ///
-    /// This method fetches the compute units processed for the last `last_hours` hours from the `stats_compute_units_processed` table.
+    /// The cost of the request is computed from the price per one million compute units of the
+    /// stack it was billed on, rounded up the same way as the spend of api tokens. If the stack is
+    /// unknown, the request is recorded with a zero cost.
     ///
     /// # Arguments
     ///
-    /// * `last_hours` - The number of hours to fetch the compute units processed for.
+    /// * `usage_record` - The billing details of the request.
     ///
     /// # Returns
     ///
-    /// - `Result<Vec<ComputedUnitsProcessedResponse>>`: A result containing either:
-    ///   - `Ok(Vec<ComputedUnitsProcessedResponse>)`: The compute units processed for the last `last_hours` hours.
-    ///   - `Err(AtomaStateManagerError)`: An error if the database query fails.
+    /// - `Result<()>`: A result indicating success (Ok(())) or failure (Err(AtomaStateManagerError)).
     ///
     /// # Errors
     ///
@@ -3870,50 +4227,64 @@ impl AtomaState {
     /// ```rust,ignore
     /// use atoma_node::atoma_state::AtomaStateManager;
     ///
-    /// async fn get_compute_units_processed(state_manager: &AtomaStateManager, last_hours: usize) -> Result<Vec<ComputedUnitsProcessedResponse>, AtomaStateManagerError> {
-    ///    state_manager.get_compute_units_processed(last_hours).await
+    /// async fn record_usage(state_manager: &AtomaStateManager, usage_record: NewUsageRecord) -> Result<(), AtomaStateManagerError> {
+    ///    state_manager.insert_usage_record(&usage_record).await
     /// }
     /// ```
     #[instrument(level = "trace", skip(self))]
-    pub async fn get_compute_units_processed(
-        &self,
-        last_hours: usize,
-    ) -> Result<Vec<ComputedUnitsProcessedResponse>> {
-        let timestamp = Utc::now();
-        let start_timestamp = timestamp
-            .checked_sub_signed(chrono::Duration::hours(last_hours as i64))
-            .ok_or(AtomaStateManagerError::InvalidTimestamp)?;
-        let performances_per_hour = sqlx::query("SELECT timestamp, model_name, amount, requests, time FROM stats_compute_units_processed WHERE timestamp >= $1 ORDER BY timestamp ASC, model_name ASC")
-                        .bind(start_timestamp)
-                        .fetch_all(&self.db)
-                        .await?;
-        performances_per_hour
-            .into_iter()
-            .map(|performance| {
-                ComputedUnitsProcessedResponse::from_row(&performance)
-                    .map_err(AtomaStateManagerError::from)
-            })
-            .collect()
+    pub async fn insert_usage_record(&self, usage_record: &NewUsageRecord) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO usage_records (
+                user_id, api_token_id, model, endpoint, stack_small_id, node_small_id,
+                prompt_tokens, completion_tokens, cost, latency_ms, status
+            )
+            VALUES (
+                $1, $2, $3, $4, $5, $6, $7, $8,
+                COALESCE(
+                    (SELECT (($7 + $8) * price_per_one_million_compute_units + 999999) / 1000000
+                    FROM stacks WHERE stack_small_id = $5),
+                    0
+                ),
+                $9, $10
+            )",
+        )
+        .bind(usage_record.user_id)
+        .bind(usage_record.api_token_id)
+        .bind(&usage_record.model)
+        .bind(&usage_record.endpoint)
+        .bind(usage_record.stack_small_id)
+        .bind(usage_record.node_small_id)
+        .bind(usage_record.prompt_tokens)
+        .bind(usage_record.completion_tokens)
+        .bind(usage_record.latency_ms)
+        .bind(usage_record.status)
+        .execute(&self.db)
+        .await?;
+        Ok(())
     }
 
-    /// Get balance for a user.
+    /// Get a page of the usage ledger of a user.
     ///
-    /// This method fetches the balance for a user from the `balance` table.
+    /// Usage records are sorted by most recent first, and can be restricted to a time range,
+    /// e.g. the billing period of an invoice.
     ///
     /// # Arguments
     ///
     /// * `user_id` - The unique identifier of the user.
+    /// * `page` - The index of the page, starting at 0.
+    /// * `page_size` - The maximum number of usage records per page.
+    /// * `start_timestamp` - Only return usage records created at or after this timestamp, if set.
+    /// * `end_timestamp` - Only return usage records created before this timestamp, if set.
     ///
     /// # Returns
     ///
-    /// - `Result<i64>`: A result containing either:
-    ///   - `Ok(i64)`: The balance for the user.
+    /// - `Result<UsagePage>`: A result containing either:
+    ///   - `Ok(UsagePage)`: The usage records of the page, and the total number of matching usage records.
     ///   - `Err(AtomaStateManagerError)`: An error if the database query fails.
     ///
     /// # Errors
     ///
     /// This function will return an error if:
-    ///
     /// - The database query fails to execute.
     ///
     /// # Example
@@ -3921,34 +4292,69 @@ impl AtomaState {
     /// ```rust,ignore
     /// use atoma_node::atoma_state::AtomaStateManager;
     ///
-    /// async fn get_balance(state_manager: &AtomaStateManager, user_id: i64) -> Result<i64, AtomaStateManagerError> {
-    ///     state_manager.get_balance_for_user(user_id).await
+    /// async fn get_usage(state_manager: &AtomaStateManager, user_id: i64) -> Result<UsagePage, AtomaStateManagerError> {
+    ///    state_manager.get_usage_records_for_user(user_id, 0, 100, None, None).await
     /// }
     /// ```
     #[instrument(level = "trace", skip(self))]
-    pub async fn get_balance_for_user(&self, user_id: i64) -> Result<i64> {
-        let balance = sqlx::query("SELECT usdc_balance FROM balance WHERE user_id = $1")
-            .bind(user_id)
-            .fetch_optional(&self.db)
-            .await?
-            .map_or(0, |row| row.get::<i64, _>("usdc_balance"));
-
-        Ok(balance)
+    pub async fn get_usage_records_for_user(
+        &self,
+        user_id: i64,
+        page: i64,
+        page_size: i64,
+        start_timestamp: Option<DateTime<Utc>>,
+        end_timestamp: Option<DateTime<Utc>>,
+    ) -> Result<UsagePage> {
+        let total = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM usage_records
+            WHERE user_id = $1
+            AND ($2::TIMESTAMPTZ IS NULL OR created_at >= $2)
+            AND ($3::TIMESTAMPTZ IS NULL OR created_at < $3)",
+        )
+        .bind(user_id)
+        .bind(start_timestamp)
+        .bind(end_timestamp)
+        .fetch_one(&self.db)
+        .await?;
+        let records = sqlx::query(
+            "SELECT * FROM usage_records
+            WHERE user_id = $1
+            AND ($2::TIMESTAMPTZ IS NULL OR created_at >= $2)
+            AND ($3::TIMESTAMPTZ IS NULL OR created_at < $3)
+            ORDER BY created_at DESC, id DESC
+            LIMIT $4 OFFSET $5",
+        )
+        .bind(user_id)
+        .bind(start_timestamp)
+        .bind(end_timestamp)
+        .bind(page_size)
+        .bind(page.saturating_mul(page_size))
+        .fetch_all(&self.db)
+        .await?
+        .into_iter()
+        .map(|record| UsageRecord::from_row(&record).map_err(AtomaStateManagerError::from))
+        .collect::<Result<Vec<_>>>()?;
+        Ok(UsagePage {
+            records,
+            page,
+            page_size,
+            total,
+        })
     }
 
-    /// Get the user profile by user_id.
-    ///
-    /// This method fetches the user profile by user_id from the `users` table.
+    /// Registers a webhook for a user.
     ///
     /// # Arguments
     ///
     /// * `user_id` - The unique identifier of the user.
+    /// * `url` - The URL the events are delivered to.
+    /// * `secret` - The hex encoded key used to sign the delivered payloads.
+    /// * `low_balance_threshold` - Notify when the USDC balance drops below this threshold.
+    /// * `monthly_spend_limit` - Notify when the USDC spent in the current month reaches this limit.
     ///
     /// # Returns
     ///
-    /// - `Result<UserProfile>`: A result containing either:
-    ///   - `Ok(UserProfile)`: The user profile for the user_id.
-    ///   - `Err(AtomaStateManagerError)`: An error if the database query fails.
+    /// - `Result<Webhook>`: A result containing the registered webhook.
     ///
     /// # Errors
     ///
@@ -3960,33 +4366,43 @@ impl AtomaState {
     /// ```rust,ignore
     /// use atoma_node::atoma_state::AtomaStateManager;
     ///
-    /// async fn get_profile(state_manager: &AtomaStateManager, user_id: i64) -> Result<UserProfile, AtomaStateManagerError> {
-    ///     state_manager.get_user_profile(user_id).await
+    /// async fn insert_webhook(state_manager: &AtomaStateManager, user_id: i64, url: &str, secret: &str) -> Result<Webhook, AtomaStateManagerError> {
+    ///    state_manager.insert_webhook(user_id, url, secret, Some(1_000_000), None).await
     /// }
     /// ```
-    #[instrument(level = "trace", skip_all)]
-    pub async fn get_user_profile(&self, user_id: i64) -> Result<UserProfile> {
-        let user = sqlx::query("SELECT email FROM users WHERE id = $1")
-            .bind(user_id)
-            .fetch_one(&self.db)
-            .await?;
-
-        UserProfile::from_row(&user).map_err(AtomaStateManagerError::from)
+    #[instrument(level = "trace", skip(self, secret))]
+    pub async fn insert_webhook(
+        &self,
+        user_id: i64,
+        url: &str,
+        secret: &str,
+        low_balance_threshold: Option<i64>,
+        monthly_spend_limit: Option<i64>,
+    ) -> Result<Webhook> {
+        let webhook = sqlx::query_as::<_, Webhook>(
+            "INSERT INTO webhooks (user_id, url, secret, low_balance_threshold, monthly_spend_limit)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING *",
+        )
+        .bind(user_id)
+        .bind(url)
+        .bind(secret)
+        .bind(low_balance_threshold)
+        .bind(monthly_spend_limit)
+        .fetch_one(&self.db)
+        .await?;
+        Ok(webhook)
     }
 
-    /// Get latency performance for the last `last_hours` hours.
-    ///
-    /// This method fetches the latency performance for the last `last_hours` hours from the `stats_latency` table.
+    /// Retrieves all the webhooks registered by a user.
     ///
     /// # Arguments
     ///
-    /// * `last_hours` - The number of hours to fetch the latency performance for.
+    /// * `user_id` - The unique identifier of the user.
     ///
     /// # Returns
     ///
-    /// - `Result<Vec<LatencyResponse>>`: A result containing either:
-    ///   - `Ok(Vec<LatencyResponse>)`: The latency performance for the last `last_hours` hours.
-    ///   - `Err(AtomaStateManagerError)`: An error if the database query fails.
+    /// - `Result<Vec<Webhook>>`: A result containing the webhooks of the user, oldest first.
     ///
     /// # Errors
     ///
@@ -3998,39 +4414,26 @@ impl AtomaState {
     /// ```rust,ignore
     /// use atoma_node::atoma_state::AtomaStateManager;
     ///
-    /// async fn get_latency_performance(state_manager: &AtomaStateManager, last_hours: usize) -> Result<Vec<LatencyResponse>, AtomaStateManagerError> {
-    ///   state_manager.get_latency_performance(last_hours).await
+    /// async fn get_webhooks(state_manager: &AtomaStateManager, user_id: i64) -> Result<Vec<Webhook>, AtomaStateManagerError> {
+    ///    state_manager.get_webhooks_for_user(user_id).await
     /// }
     /// ```
     #[instrument(level = "trace", skip(self))]
-    pub async fn get_latency_performance(&self, last_hours: usize) -> Result<Vec<LatencyResponse>> {
-        let timestamp = Utc::now();
-        let start_timestamp = timestamp
-            .checked_sub_signed(chrono::Duration::hours(last_hours as i64))
-            .ok_or(AtomaStateManagerError::InvalidTimestamp)?;
-        let performances_per_hour = sqlx::query(
-            "SELECT timestamp, latency, requests FROM stats_latency WHERE timestamp >= $1 ORDER BY timestamp ASC",
-        )
-        .bind(start_timestamp)
-        .fetch_all(&self.db)
-        .await?;
-        performances_per_hour
-            .into_iter()
-            .map(|performance| {
-                LatencyResponse::from_row(&performance).map_err(AtomaStateManagerError::from)
-            })
-            .collect()
+    pub async fn get_webhooks_for_user(&self, user_id: i64) -> Result<Vec<Webhook>> {
+        let webhooks =
+            sqlx::query_as::<_, Webhook>("SELECT * FROM webhooks WHERE user_id = $1 ORDER BY id")
+                .bind(user_id)
+                .fetch_all(&self.db)
+                .await?;
+        Ok(webhooks)
     }
 
-    /// Add compute units processed to the database.
-    ///
-    /// This method inserts the compute units processed into the `stats_compute_units_processed` table.
+    /// Deletes a webhook registered by a user.
     ///
     /// # Arguments
     ///
-    /// * `timestamp` - The timestamp of the data.
-    /// * `compute_units_processed` - The number of compute units processed.
-    /// * `time` - The time taken to process the compute units.
+    /// * `user_id` - The unique identifier of the user.
+    /// * `webhook_id` - The unique identifier of the webhook.
     ///
     /// # Returns
     ///
@@ -4040,59 +4443,43 @@ impl AtomaState {
     ///
     /// This function will return an error if:
     /// - The database query fails to execute.
+    /// - The user has no webhook with the given id (`AtomaStateManagerError::WebhookNotFound`).
     ///
     /// # Example
     ///
     /// ```rust,ignore
     /// use atoma_node::atoma_state::AtomaStateManager;
-    /// use chrono::{DateTime, Utc};
     ///
-    /// async fn add_compute_units_processed(state_manager: &AtomaStateManager, timestamp: DateTime<Utc>, compute_units_processed: i64, time: f64) -> Result<(), AtomaStateManagerError> {
-    ///   state_manager.add_compute_units_processed(timestamp, compute_units_processed, time).await
+    /// async fn delete_webhook(state_manager: &AtomaStateManager, user_id: i64, webhook_id: i64) -> Result<(), AtomaStateManagerError> {
+    ///    state_manager.delete_webhook(user_id, webhook_id).await
     /// }
     /// ```
     #[instrument(level = "trace", skip(self))]
-    pub async fn add_compute_units_processed(
-        &self,
-        timestamp: DateTime<Utc>,
-        model_name: String,
-        compute_units_processed: i64,
-        time: f64,
-    ) -> Result<()> {
-        // We want the table to gather data in hourly intervals
-        let timestamp = timestamp
-            .with_second(0)
-            .and_then(|t| t.with_minute(0))
-            .and_then(|t| t.with_nanosecond(0))
-            .ok_or(AtomaStateManagerError::InvalidTimestamp)?;
-        sqlx::query(
-            "INSERT INTO stats_compute_units_processed (timestamp, model_name, amount, time) VALUES ($1, $2, $3, $4)
-                 ON CONFLICT (timestamp, model_name) DO UPDATE SET
-                    amount = stats_compute_units_processed.amount + EXCLUDED.amount,
-                    time = stats_compute_units_processed.time + EXCLUDED.time,
-                    requests = stats_compute_units_processed.requests + 1",
-        )
-        .bind(timestamp)
-        .bind(model_name)
-        .bind(compute_units_processed)
-        .bind(time)
-        .execute(&self.db)
-        .await?;
+    pub async fn delete_webhook(&self, user_id: i64, webhook_id: i64) -> Result<()> {
+        let result = sqlx::query("DELETE FROM webhooks WHERE id = $1 AND user_id = $2")
+            .bind(webhook_id)
+            .bind(user_id)
+            .execute(&self.db)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(AtomaStateManagerError::WebhookNotFound);
+        }
         Ok(())
     }
 
-    /// Add latency to the database.
+    /// Retrieves the webhooks of a user whose low balance threshold was just crossed.
     ///
-    /// This method inserts the latency into the `stats_latency` table. This measure the time from the request to first generated token.
+    /// The returned webhooks are marked as notified, so that the low balance event is delivered
+    /// once, until the balance is topped up above the threshold again
+    /// (see [`Self::reset_low_balance_webhooks`]).
     ///
     /// # Arguments
     ///
-    /// * `timestamp` - The timestamp of the data.
-    /// * `latency` - The latency of the data.
+    /// * `user_id` - The unique identifier of the user.
     ///
     /// # Returns
     ///
-    /// - `Result<()>`: A result indicating success (Ok(())) or failure (Err(AtomaStateManagerError)).
+    /// - `Result<Vec<Webhook>>`: A result containing the webhooks to notify.
     ///
     /// # Errors
     ///
@@ -4103,43 +4490,33 @@ impl AtomaState {
     ///
     /// ```rust,ignore
     /// use atoma_node::atoma_state::AtomaStateManager;
-    /// use chrono::{DateTime, Utc};
     ///
-    /// async fn add_latency(state_manager: &AtomaStateManager, timestamp: DateTime<Utc>, latency: f64) -> Result<(), AtomaStateManagerError> {
-    ///    state_manager.add_latency(timestamp, latency).await
+    /// async fn claim_low_balance_webhooks(state_manager: &AtomaStateManager, user_id: i64) -> Result<Vec<Webhook>, AtomaStateManagerError> {
+    ///    state_manager.claim_low_balance_webhooks(user_id).await
     /// }
     /// ```
     #[instrument(level = "trace", skip(self))]
-    pub async fn add_latency(&self, timestamp: DateTime<Utc>, latency: f64) -> Result<()> {
-        // We want the table to gather data in hourly intervals
-        let timestamp = timestamp
-            .with_second(0)
-            .and_then(|t| t.with_minute(0))
-            .and_then(|t| t.with_nanosecond(0))
-            .ok_or(AtomaStateManagerError::InvalidTimestamp)?;
-        sqlx::query(
-            "INSERT INTO stats_latency (timestamp, latency) VALUES ($1, $2)
-                 ON CONFLICT (timestamp) DO UPDATE SET
-                    latency = stats_latency.latency + EXCLUDED.latency,
-                    requests = stats_latency.requests + 1",
+    pub async fn claim_low_balance_webhooks(&self, user_id: i64) -> Result<Vec<Webhook>> {
+        let webhooks = sqlx::query_as::<_, Webhook>(
+            "UPDATE webhooks
+            SET low_balance_notified = true
+            WHERE user_id = $1
+            AND low_balance_threshold IS NOT NULL
+            AND low_balance_notified = false
+            AND COALESCE((SELECT usdc_balance FROM balance WHERE balance.user_id = $1), 0) < low_balance_threshold
+            RETURNING *",
         )
-        .bind(timestamp)
-        .bind(latency)
-        .execute(&self.db)
+        .bind(user_id)
+        .fetch_all(&self.db)
         .await?;
-        Ok(())
+        Ok(webhooks)
     }
 
-    /// Records statistics about a new stack in the database.
-    ///
-    /// This method inserts or updates hourly statistics about stack compute units in the `stats_stacks` table.
-    /// The timestamp is rounded down to the nearest hour, and if an entry already exists for that hour,
-    /// the compute units are added to the existing total.
+    /// Re-arms the low balance event of the webhooks of a user whose balance is back above their threshold.
     ///
     /// # Arguments
     ///
-    /// * `stack` - The `Stack` object containing information about the new stack.
-    /// * `timestamp` - The timestamp when the stack was created.
+    /// * `user_id` - The unique identifier of the user.
     ///
     /// # Returns
     ///
@@ -4149,56 +4526,48 @@ impl AtomaState {
     ///
     /// This function will return an error if:
     /// - The database query fails to execute.
-    /// - The timestamp cannot be normalized to an hour boundary.
     ///
     /// # Example
     ///
     /// ```rust,ignore
-    /// use atoma_node::atoma_state::{AtomaStateManager, Stack};
-    /// use chrono::{DateTime, Utc};
+    /// use atoma_node::atoma_state::AtomaStateManager;
     ///
-    /// async fn record_stack_stats(state_manager: &AtomaStateManager, stack: Stack) -> Result<(), AtomaStateManagerError> {
-    ///     let timestamp = Utc::now();
-    ///     state_manager.new_stats_stack(stack, timestamp).await
+    /// async fn reset_low_balance_webhooks(state_manager: &AtomaStateManager, user_id: i64) -> Result<(), AtomaStateManagerError> {
+    ///    state_manager.reset_low_balance_webhooks(user_id).await
     /// }
     /// ```
     #[instrument(level = "trace", skip(self))]
-    pub async fn new_stats_stack(&self, stack: Stack, timestamp: DateTime<Utc>) -> Result<()> {
-        let timestamp = timestamp
-            .with_second(0)
-            .and_then(|t| t.with_minute(0))
-            .and_then(|t| t.with_nanosecond(0))
-            .ok_or(AtomaStateManagerError::InvalidTimestamp)?;
+    pub async fn reset_low_balance_webhooks(&self, user_id: i64) -> Result<()> {
         sqlx::query(
-            "INSERT into stats_stacks (timestamp,num_compute_units) VALUES ($1,$2)
-                 ON CONFLICT (timestamp)
-                 DO UPDATE SET
-                    num_compute_units = stats_stacks.num_compute_units + EXCLUDED.num_compute_units",
+            "UPDATE webhooks
+            SET low_balance_notified = false
+            WHERE user_id = $1
+            AND low_balance_notified = true
+            AND COALESCE((SELECT usdc_balance FROM balance WHERE balance.user_id = $1), 0) >= low_balance_threshold",
         )
-        .bind(timestamp)
-        .bind(stack.num_compute_units)
+        .bind(user_id)
         .execute(&self.db)
         .await?;
         Ok(())
     }
 
-    /// Updates the sui address for the user.
+    /// Retrieves the webhooks of a user whose monthly spend limit was just reached.
     ///
-    /// This method updates the `sui_address` field for the user in the `users` table.
+    /// The monthly spend is the total cost of the usage records of the user since the start of the
+    /// current month. The returned webhooks are marked as notified for the current month, so that the
+    /// monthly spend limit event is delivered at most once per month.
     ///
     /// # Arguments
     ///
     /// * `user_id` - The unique identifier of the user.
-    /// * `sui_address` - The SUI address to store for the user.
     ///
     /// # Returns
     ///
-    /// - `Result<()>`: A result indicating success (Ok(())) or failure (Err(AtomaStateManagerError)).
+    /// - `Result<Vec<(Webhook, i64)>>`: A result containing the webhooks to notify, together with the monthly spend.
     ///
     /// # Errors
     ///
     /// This function will return an error if:
-    ///
     /// - The database query fails to execute.
     ///
     /// # Example
@@ -4206,39 +4575,57 @@ impl AtomaState {
     /// ```rust,ignore
     /// use atoma_node::atoma_state::AtomaStateManager;
     ///
-    /// async fn store_sui_address(state_manager: &AtomaStateManager, user_id: i64, sui_address: String) -> Result<(), AtomaStateManagerError> {
-    ///    state_manager.store_sui_address(user_id, sui_address).await
+    /// async fn claim_spend_limit_webhooks(state_manager: &AtomaStateManager, user_id: i64) -> Result<Vec<(Webhook, i64)>, AtomaStateManagerError> {
+    ///    state_manager.claim_monthly_spend_limit_webhooks(user_id).await
     /// }
     /// ```
     #[instrument(level = "trace", skip(self))]
-    pub async fn update_sui_address(&self, user_id: i64, sui_address: String) -> Result<()> {
-        sqlx::query("UPDATE users SET sui_address = $1 WHERE id = $2")
-            .bind(sui_address)
-            .bind(user_id)
-            .execute(&self.db)
-            .await?;
-        Ok(())
+    pub async fn claim_monthly_spend_limit_webhooks(
+        &self,
+        user_id: i64,
+    ) -> Result<Vec<(Webhook, i64)>> {
+        sqlx::query(
+            "WITH monthly_spend AS (
+                SELECT COALESCE(SUM(cost), 0)::BIGINT AS spend
+                FROM usage_records
+                WHERE user_id = $1
+                AND created_at >= date_trunc('month', NOW())
+            )
+            UPDATE webhooks
+            SET spend_limit_notified_at = NOW()
+            FROM monthly_spend
+            WHERE webhooks.user_id = $1
+            AND webhooks.monthly_spend_limit IS NOT NULL
+            AND monthly_spend.spend >= webhooks.monthly_spend_limit
+            AND (webhooks.spend_limit_notified_at IS NULL OR webhooks.spend_limit_notified_at < date_trunc('month', NOW()))
+            RETURNING webhooks.*, monthly_spend.spend",
+        )
+        .bind(user_id)
+        .fetch_all(&self.db)
+        .await?
+        .into_iter()
+        .map(|row| Ok((Webhook::from_row(&row)?, row.get::<i64, _>("spend"))))
+        .collect()
     }
 
-    /// Retrieves the SUI address for the user.
+    /// Hashes the api tokens that are still stored in plaintext.
     ///
-    /// This method retrieves the SUI address for the user from the `users` table.
+    /// Tokens created before api tokens were hashed at rest keep their plaintext value in the
+    /// `token` column, as the hashing key is not available to the database migrations. This method
+    /// replaces each of them with its keyed hash and clears the plaintext value. It is idempotent
+    /// and meant to be called once on startup.
     ///
     /// # Arguments
     ///
-    /// * `user_id` - The unique identifier of the user.
+    /// * `hash_api_token` - The function computing the keyed hash of an api token.
     ///
     /// # Returns
     ///
-    /// - `Result<Option<String>>`: A result containing either:
-    ///  - `Ok(Some(String))`: The SUI address of the user.
-    /// - `Ok(None)`: If the user is not found.
-    /// - `Err(AtomaStateManagerError)`: An error if the database query fails.
+    /// - `Result<u64>`: The number of api tokens that were hashed.
     ///
     /// # Errors
     ///
     /// This function will return an error if:
-    ///
     /// - The database query fails to execute.
     ///
     /// # Example
@@ -4246,41 +4633,55 @@ impl AtomaState {
     /// ```rust,ignore
     /// use atoma_node::atoma_state::AtomaStateManager;
     ///
-    /// async fn get_sui_address(state_manager: &AtomaStateManager, user_id: i64) -> Result<Option<String>, AtomaStateManagerError> {
-    ///    state_manager.get_sui_address(user_id).await
+    /// async fn hash_tokens(state_manager: &AtomaStateManager, auth: &Auth) -> Result<u64, AtomaStateManagerError> {
+    ///    state_manager.hash_plaintext_api_tokens(|token| auth.hash_api_token(token)).await
     /// }
     /// ```
-    #[instrument(level = "trace", skip(self))]
-    pub async fn get_sui_address(&self, user_id: i64) -> Result<Option<String>> {
-        let sui_address =
-            sqlx::query_scalar::<_, Option<String>>("SELECT sui_address FROM users WHERE id = $1")
-                .bind(user_id)
-                .fetch_optional(&self.db)
+    #[instrument(level = "trace", skip_all)]
+    pub async fn hash_plaintext_api_tokens<F>(&self, hash_api_token: F) -> Result<u64>
+    where
+        F: Fn(&str) -> String + Send + Sync,
+    {
+        let mut tx = self.db.begin().await?;
+        let tokens =
+            sqlx::query("SELECT id, token FROM api_tokens WHERE token IS NOT NULL FOR UPDATE")
+                .fetch_all(&mut *tx)
                 .await?;
 
-        Ok(sui_address.flatten())
+        let mut hashed = 0;
+        for token in tokens {
+            let id = token.get::<i64, _>("id");
+            let api_token = token.get::<String, _>("token");
+            hashed +=
+                sqlx::query("UPDATE api_tokens SET token_hash = $2, token = NULL WHERE id = $1")
+                    .bind(id)
+                    .bind(hash_api_token(&api_token))
+                    .execute(&mut *tx)
+                    .await?
+                    .rows_affected();
+        }
+        tx.commit().await?;
+        Ok(hashed)
     }
 
-    /// Confirms that the user is associated with the wallet.
+    /// Retrieves all API tokens for a user.
     ///
-    /// This method confirms that users has this wallet associated with them.
+    /// This method fetches all API tokens from the `api_tokens` table for the specified user.
     ///
     /// # Arguments
     ///
-    /// * `sui_address` - The sui_address of the user.
     /// * `user_id` - The unique identifier of the user.
     ///
     /// # Returns
     ///
-    /// - `Result<bool>`: A result containing either:
-    /// - `Ok(true)`: If the user is associated with the wallet.
-    /// - `Ok(false)`: If the user is not associated with the wallet.
-    /// - `Err(AtomaStateManagerError)`: An error if the database query fails.
+    /// - `Result<Vec<TokenResponse>>`: A result containing either:
+    ///   - `Ok(Vec<TokenResponse>)`: A list of API tokens for the user.
+    ///   - `Err(AtomaStateManagerError)`: An error if the database query fails.
+    ///
     ///
     /// # Errors
     ///
     /// This function will return an error if:
-    ///
     /// - The database query fails to execute.
     ///
     /// # Example
@@ -4288,39 +4689,44 @@ impl AtomaState {
     /// ```rust,ignore
     /// use atoma_node::atoma_state::AtomaStateManager;
     ///
-    /// async fn confirm_user(state_manager: &AtomaStateManager, sui_address: String, user_id: i64) -> Result<Option<i64>, AtomaStateManagerError> {
-    ///    state_manager.confirm_user(sui_address, user_id).await
+    /// async fn get_tokens(state_manager: &AtomaStateManager, user_id: i64) -> Result<Vec<String>, AtomaStateManagerError> {
+    ///    state_manager.get_api_tokens_for_user(user_id).await
     /// }
     /// ```
     #[instrument(level = "trace", skip(self))]
-    pub async fn confirm_user(&self, sui_address: String, user_id: i64) -> Result<bool> {
-        let exists = sqlx::query_scalar::<_, bool>(
-            "SELECT EXISTS(SELECT 1 FROM users WHERE sui_address = $1 and id = $2)",
+    pub async fn get_api_tokens_for_user(&self, user_id: i64) -> Result<Vec<TokenResponse>> {
+        let tokens = sqlx::query(
+            "SELECT id, token_prefix, token_last_4, last_used_timestamp, creation_timestamp as created_at, name,
+                allowed_endpoints, allowed_models, confidential_only, expires_at, spending_limit, amount_spent
+            FROM api_tokens WHERE user_id = $1",
         )
-        .bind(sui_address)
         .bind(user_id)
-        .fetch_one(&self.db)
+        .fetch_all(&self.db)
         .await?;
-        Ok(exists)
+
+        tokens
+            .into_iter()
+            .map(|token| TokenResponse::from_row(&token).map_err(AtomaStateManagerError::from))
+            .collect()
     }
 
-    /// Update the balance for the user.
+    /// Get compute units processed for the last `last_hours` hours.
     ///
-    /// This method updates the `balance` field for the user in the `users` table.
+    /// This method fetches the compute units processed for the last `last_hours` hours from the `stats_compute_units_processed` table.
     ///
     /// # Arguments
     ///
-    /// * `user_id` - The unique identifier of the user.
-    /// * `balance` - The new balance to store for the user.
+    /// * `last_hours` - The number of hours to fetch the compute units processed for.
     ///
     /// # Returns
     ///
-    /// - `Result<()>`: A result indicating success (Ok(())) or failure (Err(AtomaStateManagerError)).
+    /// - `Result<Vec<ComputedUnitsProcessedResponse>>`: A result containing either:
+    ///   - `Ok(Vec<ComputedUnitsProcessedResponse>)`: The compute units processed for the last `last_hours` hours.
+    ///   - `Err(AtomaStateManagerError)`: An error if the database query fails.
     ///
     /// # Errors
     ///
     /// This function will return an error if:
-    ///
     /// - The database query fails to execute.
     ///
     /// # Example
@@ -4328,12 +4734,470 @@ impl AtomaState {
     /// ```rust,ignore
     /// use atoma_node::atoma_state::AtomaStateManager;
     ///
-    /// async fn update_balance(state_manager: &AtomaStateManager, user_id: i64, balance: i64) -> Result<(), AtomaStateManagerError> {
-    ///    state_manager.update_balance(user_id, balance).await
+    /// async fn get_compute_units_processed(state_manager: &AtomaStateManager, last_hours: usize) -> Result<Vec<ComputedUnitsProcessedResponse>, AtomaStateManagerError> {
+    ///    state_manager.get_compute_units_processed(last_hours).await
     /// }
     /// ```
     #[instrument(level = "trace", skip(self))]
-    pub async fn top_up_balance(&self, user_id: i64, balance: i64) -> Result<()> {
+    pub async fn get_compute_units_processed(
+        &self,
+        last_hours: usize,
+    ) -> Result<Vec<ComputedUnitsProcessedResponse>> {
+        let timestamp = Utc::now();
+        let start_timestamp = timestamp
+            .checked_sub_signed(chrono::Duration::hours(last_hours as i64))
+            .ok_or(AtomaStateManagerError::InvalidTimestamp)?;
+        let performances_per_hour = sqlx::query("SELECT timestamp, model_name, amount, requests, time FROM stats_compute_units_processed WHERE timestamp >= $1 ORDER BY timestamp ASC, model_name ASC")
+                        .bind(start_timestamp)
+                        .fetch_all(&self.db)
+                        .await?;
+        performances_per_hour
+            .into_iter()
+            .map(|performance| {
+                ComputedUnitsProcessedResponse::from_row(&performance)
+                    .map_err(AtomaStateManagerError::from)
+            })
+            .collect()
+    }
+
+    /// Get balance for a user.
+    ///
+    /// This method fetches the balance for a user from the `balance` table.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The unique identifier of the user.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<i64>`: A result containing either:
+    ///   - `Ok(i64)`: The balance for the user.
+    ///   - `Err(AtomaStateManagerError)`: An error if the database query fails.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    ///
+    /// - The database query fails to execute.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// use atoma_node::atoma_state::AtomaStateManager;
+    ///
+    /// async fn get_balance(state_manager: &AtomaStateManager, user_id: i64) -> Result<i64, AtomaStateManagerError> {
+    ///     state_manager.get_balance_for_user(user_id).await
+    /// }
+    /// ```
+    #[instrument(level = "trace", skip(self))]
+    pub async fn get_balance_for_user(&self, user_id: i64) -> Result<i64> {
+        let balance = sqlx::query("SELECT usdc_balance FROM balance WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_optional(&self.db)
+            .await?
+            .map_or(0, |row| row.get::<i64, _>("usdc_balance"));
+
+        Ok(balance)
+    }
+
+    /// Get the user profile by user_id.
+    ///
+    /// This method fetches the user profile by user_id from the `users` table.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The unique identifier of the user.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<UserProfile>`: A result containing either:
+    ///   - `Ok(UserProfile)`: The user profile for the user_id.
+    ///   - `Err(AtomaStateManagerError)`: An error if the database query fails.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database query fails to execute.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// use atoma_node::atoma_state::AtomaStateManager;
+    ///
+    /// async fn get_profile(state_manager: &AtomaStateManager, user_id: i64) -> Result<UserProfile, AtomaStateManagerError> {
+    ///     state_manager.get_user_profile(user_id).await
+    /// }
+    /// ```
+    #[instrument(level = "trace", skip_all)]
+    pub async fn get_user_profile(&self, user_id: i64) -> Result<UserProfile> {
+        let user = sqlx::query("SELECT email FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_one(&self.db)
+            .await?;
+
+        UserProfile::from_row(&user).map_err(AtomaStateManagerError::from)
+    }
+
+    /// Get latency performance for the last `last_hours` hours.
+    ///
+    /// This method fetches the latency performance for the last `last_hours` hours from the `stats_latency` table.
+    ///
+    /// # Arguments
+    ///
+    /// * `last_hours` - The number of hours to fetch the latency performance for.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<Vec<LatencyResponse>>`: A result containing either:
+    ///   - `Ok(Vec<LatencyResponse>)`: The latency performance for the last `last_hours` hours.
+    ///   - `Err(AtomaStateManagerError)`: An error if the database query fails.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database query fails to execute.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// use atoma_node::atoma_state::AtomaStateManager;
+    ///
+    /// async fn get_latency_performance(state_manager: &AtomaStateManager, last_hours: usize) -> Result<Vec<LatencyResponse>, AtomaStateManagerError> {
+    ///   state_manager.get_latency_performance(last_hours).await
+    /// }
+    /// ```
+    #[instrument(level = "trace", skip(self))]
+    pub async fn get_latency_performance(&self, last_hours: usize) -> Result<Vec<LatencyResponse>> {
+        let timestamp = Utc::now();
+        let start_timestamp = timestamp
+            .checked_sub_signed(chrono::Duration::hours(last_hours as i64))
+            .ok_or(AtomaStateManagerError::InvalidTimestamp)?;
+        let performances_per_hour = sqlx::query(
+            "SELECT timestamp, latency, requests FROM stats_latency WHERE timestamp >= $1 ORDER BY timestamp ASC",
+        )
+        .bind(start_timestamp)
+        .fetch_all(&self.db)
+        .await?;
+        performances_per_hour
+            .into_iter()
+            .map(|performance| {
+                LatencyResponse::from_row(&performance).map_err(AtomaStateManagerError::from)
+            })
+            .collect()
+    }
+
+    /// Add compute units processed to the database.
+    ///
+    /// This method inserts the compute units processed into the `stats_compute_units_processed` table.
+    ///
+    /// # Arguments
+    ///
+    /// * `timestamp` - The timestamp of the data.
+    /// * `compute_units_processed` - The number of compute units processed.
+    /// * `time` - The time taken to process the compute units.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<()>`: A result indicating success (Ok(())) or failure (Err(AtomaStateManagerError)).
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database query fails to execute.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// use atoma_node::atoma_state::AtomaStateManager;
+    /// use chrono::{DateTime, Utc};
+    ///
+    /// async fn add_compute_units_processed(state_manager: &AtomaStateManager, timestamp: DateTime<Utc>, compute_units_processed: i64, time: f64) -> Result<(), AtomaStateManagerError> {
+    ///   state_manager.add_compute_units_processed(timestamp, compute_units_processed, time).await
+    /// }
+    /// ```
+    #[instrument(level = "trace", skip(self))]
+    pub async fn add_compute_units_processed(
+        &self,
+        timestamp: DateTime<Utc>,
+        model_name: String,
+        compute_units_processed: i64,
+        time: f64,
+    ) -> Result<()> {
+        // We want the table to gather data in hourly intervals
+        let timestamp = timestamp
+            .with_second(0)
+            .and_then(|t| t.with_minute(0))
+            .and_then(|t| t.with_nanosecond(0))
+            .ok_or(AtomaStateManagerError::InvalidTimestamp)?;
+        sqlx::query(
+            "INSERT INTO stats_compute_units_processed (timestamp, model_name, amount, time) VALUES ($1, $2, $3, $4)
+                 ON CONFLICT (timestamp, model_name) DO UPDATE SET
+                    amount = stats_compute_units_processed.amount + EXCLUDED.amount,
+                    time = stats_compute_units_processed.time + EXCLUDED.time,
+                    requests = stats_compute_units_processed.requests + 1",
+        )
+        .bind(timestamp)
+        .bind(model_name)
+        .bind(compute_units_processed)
+        .bind(time)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    /// Add latency to the database.
+    ///
+    /// This method inserts the latency into the `stats_latency` table. This measure the time from the request to first generated token.
+    ///
+    /// # Arguments
+    ///
+    /// * `timestamp` - The timestamp of the data.
+    /// * `latency` - The latency of the data.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<()>`: A result indicating success (Ok(())) or failure (Err(AtomaStateManagerError)).
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database query fails to execute.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// use atoma_node::atoma_state::AtomaStateManager;
+    /// use chrono::{DateTime, Utc};
+    ///
+    /// async fn add_latency(state_manager: &AtomaStateManager, timestamp: DateTime<Utc>, latency: f64) -> Result<(), AtomaStateManagerError> {
+    ///    state_manager.add_latency(timestamp, latency).await
+    /// }
+    /// ```
+    #[instrument(level = "trace", skip(self))]
+    pub async fn add_latency(&self, timestamp: DateTime<Utc>, latency: f64) -> Result<()> {
+        // We want the table to gather data in hourly intervals
+        let timestamp = timestamp
+            .with_second(0)
+            .and_then(|t| t.with_minute(0))
+            .and_then(|t| t.with_nanosecond(0))
+            .ok_or(AtomaStateManagerError::InvalidTimestamp)?;
+        sqlx::query(
+            "INSERT INTO stats_latency (timestamp, latency) VALUES ($1, $2)
+                 ON CONFLICT (timestamp) DO UPDATE SET
+                    latency = stats_latency.latency + EXCLUDED.latency,
+                    requests = stats_latency.requests + 1",
+        )
+        .bind(timestamp)
+        .bind(latency)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    /// Records statistics about a new stack in the database.
+    ///
+    /// This method inserts or updates hourly statistics about stack compute units in the `stats_stacks` table.
+    /// The timestamp is rounded down to the nearest hour, and if an entry already exists for that hour,
+    /// the compute units are added to the existing total.
+    ///
+    /// # Arguments
+    ///
+    /// * `stack` - The `Stack` object containing information about the new stack.
+    /// * `timestamp` - The timestamp when the stack was created.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<()>`: A result indicating success (Ok(())) or failure (Err(AtomaStateManagerError)).
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database query fails to execute.
+    /// - The timestamp cannot be normalized to an hour boundary.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// use atoma_node::atoma_state::{AtomaStateManager, Stack};
+    /// use chrono::{DateTime, Utc};
+    ///
+    /// async fn record_stack_stats(state_manager: &AtomaStateManager, stack: Stack) -> Result<(), AtomaStateManagerError> {
+    ///     let timestamp = Utc::now();
+    ///     state_manager.new_stats_stack(stack, timestamp).await
+    /// }
+    /// ```
+    #[instrument(level = "trace", skip(self))]
+    pub async fn new_stats_stack(&self, stack: Stack, timestamp: DateTime<Utc>) -> Result<()> {
+        let timestamp = timestamp
+            .with_second(0)
+            .and_then(|t| t.with_minute(0))
+            .and_then(|t| t.with_nanosecond(0))
+            .ok_or(AtomaStateManagerError::InvalidTimestamp)?;
+        sqlx::query(
+            "INSERT into stats_stacks (timestamp,num_compute_units) VALUES ($1,$2)
+                 ON CONFLICT (timestamp)
+                 DO UPDATE SET
+                    num_compute_units = stats_stacks.num_compute_units + EXCLUDED.num_compute_units",
+        )
+        .bind(timestamp)
+        .bind(stack.num_compute_units)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    /// Updates the sui address for the user.
+    ///
+    /// This method updates the `sui_address` field for the user in the `users` table.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The unique identifier of the user.
+    /// * `sui_address` - The SUI address to store for the user.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<()>`: A result indicating success (Ok(())) or failure (Err(AtomaStateManagerError)).
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    ///
+    /// - The database query fails to execute.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// use atoma_node::atoma_state::AtomaStateManager;
+    ///
+    /// async fn store_sui_address(state_manager: &AtomaStateManager, user_id: i64, sui_address: String) -> Result<(), AtomaStateManagerError> {
+    ///    state_manager.store_sui_address(user_id, sui_address).await
+    /// }
+    /// ```
+    #[instrument(level = "trace", skip(self))]
+    pub async fn update_sui_address(&self, user_id: i64, sui_address: String) -> Result<()> {
+        sqlx::query("UPDATE users SET sui_address = $1 WHERE id = $2")
+            .bind(sui_address)
+            .bind(user_id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    /// Retrieves the SUI address for the user.
+    ///
+    /// This method retrieves the SUI address for the user from the `users` table.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The unique identifier of the user.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<Option<String>>`: A result containing either:
+    ///  - `Ok(Some(String))`: The SUI address of the user.
+    /// - `Ok(None)`: If the user is not found.
+    /// - `Err(AtomaStateManagerError)`: An error if the database query fails.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    ///
+    /// - The database query fails to execute.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// use atoma_node::atoma_state::AtomaStateManager;
+    ///
+    /// async fn get_sui_address(state_manager: &AtomaStateManager, user_id: i64) -> Result<Option<String>, AtomaStateManagerError> {
+    ///    state_manager.get_sui_address(user_id).await
+    /// }
+    /// ```
+    #[instrument(level = "trace", skip(self))]
+    pub async fn get_sui_address(&self, user_id: i64) -> Result<Option<String>> {
+        let sui_address =
+            sqlx::query_scalar::<_, Option<String>>("SELECT sui_address FROM users WHERE id = $1")
+                .bind(user_id)
+                .fetch_optional(&self.db)
+                .await?;
+
+        Ok(sui_address.flatten())
+    }
+
+    /// Confirms that the user is associated with the wallet.
+    ///
+    /// This method confirms that users has this wallet associated with them.
+    ///
+    /// # Arguments
+    ///
+    /// * `sui_address` - The sui_address of the user.
+    /// * `user_id` - The unique identifier of the user.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<bool>`: A result containing either:
+    /// - `Ok(true)`: If the user is associated with the wallet.
+    /// - `Ok(false)`: If the user is not associated with the wallet.
+    /// - `Err(AtomaStateManagerError)`: An error if the database query fails.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    ///
+    /// - The database query fails to execute.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// use atoma_node::atoma_state::AtomaStateManager;
+    ///
+    /// async fn confirm_user(state_manager: &AtomaStateManager, sui_address: String, user_id: i64) -> Result<Option<i64>, AtomaStateManagerError> {
+    ///    state_manager.confirm_user(sui_address, user_id).await
+    /// }
+    /// ```
+    #[instrument(level = "trace", skip(self))]
+    pub async fn confirm_user(&self, sui_address: String, user_id: i64) -> Result<bool> {
+        let exists = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS(SELECT 1 FROM users WHERE sui_address = $1 and id = $2)",
+        )
+        .bind(sui_address)
+        .bind(user_id)
+        .fetch_one(&self.db)
+        .await?;
+        Ok(exists)
+    }
+
+    /// Update the balance for the user.
+    ///
+    /// This method updates the `balance` field for the user in the `users` table.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The unique identifier of the user.
+    /// * `balance` - The new balance to store for the user.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<()>`: A result indicating success (Ok(())) or failure (Err(AtomaStateManagerError)).
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    ///
+    /// - The database query fails to execute.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// use atoma_node::atoma_state::AtomaStateManager;
+    ///
+    /// async fn update_balance(state_manager: &AtomaStateManager, user_id: i64, balance: i64) -> Result<(), AtomaStateManagerError> {
+    ///    state_manager.update_balance(user_id, balance).await
+    /// }
+    /// ```
+    #[instrument(level = "trace", skip(self))]
+    pub async fn top_up_balance(&self, user_id: i64, balance: i64) -> Result<()> {
         sqlx::query(
             "INSERT INTO balance (user_id, usdc_balance)
                          VALUES ($1, $2)
@@ -4342,78 +5206,547 @@ impl AtomaState {
                             usdc_balance = balance.usdc_balance + EXCLUDED.usdc_balance",
         )
         .bind(user_id)
-        .bind(balance)
-        .execute(&self.db)
+        .bind(balance)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    /// Deduct from the usdc balance for the user.
+    ///
+    /// This method withdraws the balance from the user in the `users` table.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The unique identifier of the user.
+    /// * `balance` - The balance to withdraw from the user.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<()>`: A result indicating success (Ok(())) or failure (Err(AtomaStateManagerError)).
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    ///
+    /// - The database query fails to execute (that could mean the balance is not available)
+    #[instrument(level = "trace", skip(self))]
+    pub async fn deduct_from_usdc(&self, user_id: i64, balance: i64) -> Result<()> {
+        let result = sqlx::query("UPDATE balance SET usdc_balance = usdc_balance - $2 WHERE user_id = $1 AND usdc_balance >= $2")
+            .bind(user_id)
+            .bind(balance)
+            .execute(&self.db)
+            .await?;
+        if result.rows_affected() != 1 {
+            return Err(AtomaStateManagerError::InsufficientBalance);
+        }
+        Ok(())
+    }
+
+    /// Refunds a USDC payment.
+    ///
+    /// This method refunds a USDC payment to the user in the `balance` table.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The unique identifier of the user.
+    /// * `amount` - The amount to refund.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<()>`: A result indicating success (Ok(())) or failure (Err(AtomaStateManagerError)).
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    ///
+    /// - The database query fails to execute.
+    #[instrument(level = "trace", skip(self))]
+    pub async fn refund_usdc(&self, user_id: i64, amount: i64) -> Result<()> {
+        sqlx::query("UPDATE balance SET usdc_balance = usdc_balance + $2 WHERE user_id = $1")
+            .bind(user_id)
+            .bind(amount)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    /// Insert a new usdc payment digest.
+    ///
+    /// This method inserts a new usdc payment digest into the `usdc_payment_digests` table. It fails if the digest already exists.
+    ///
+    /// # Arguments
+    ///
+    /// * `digest` - The digest to insert.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<()>`: A result indicating success (Ok(())) or failure (Err(AtomaStateManagerError)).
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    ///
+    /// - The database query fails to execute. Including if the digest already exists.
+    #[instrument(level = "trace", skip(self))]
+    pub async fn insert_new_usdc_payment_digest(&self, digest: String) -> Result<()> {
+        sqlx::query("INSERT INTO usdc_payment_digests (digest) VALUES ($1)")
+            .bind(digest)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    /// Gets the zk_salt for the user.
+    ///
+    /// This method fetches the zk_salt for the user from the `users` table.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The unique identifier of the user.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<Option<String>>`: A result containing either:
+    ///   - `Ok(Some(String))`: The zk_salt for the user.
+    ///   - `Ok(None)`: If the user is not found.
+    ///   - `Err(AtomaStateManagerError)`: An error if the database query fails.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    ///
+    /// - The database query fails to execute.
+    #[instrument(level = "trace", skip(self))]
+    pub async fn get_zk_salt(&self, user_id: i64) -> Result<Option<String>> {
+        let zk_salt =
+            sqlx::query_scalar::<_, Option<String>>("SELECT zk_salt FROM users WHERE id = $1")
+                .bind(user_id)
+                .fetch_optional(&self.db)
+                .await?;
+
+        Ok(zk_salt.flatten())
+    }
+
+    /// Sets the zk_salt for the user.
+    ///
+    /// This method updates the `zk_salt` field for the user in the `users` table.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The unique identifier of the user.
+    /// * `zk_salt` - The new zk_salt to store for the user.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<()>`: A result indicating success (Ok(())) or failure (Err(AtomaStateManagerError)).
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    ///
+    /// - The database query fails to execute.
+    pub async fn set_zk_salt(&self, user_id: i64, zk_salt: &str) -> Result<()> {
+        sqlx::query("UPDATE users SET zk_salt = $1 WHERE id = $2")
+            .bind(zk_salt)
+            .bind(user_id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    /// Stores a new file.
+    ///
+    /// # Arguments
+    ///
+    /// * `file` - The file to store, with its content.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<UserFile>`: A result containing the stored file, without its content.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database query fails to execute.
+    #[instrument(level = "trace", skip_all, fields(file_id = %file.id))]
+    pub async fn insert_user_file(&self, file: &NewUserFile) -> Result<UserFile> {
+        let file = sqlx::query_as::<_, UserFile>(
+            "INSERT INTO files (id, user_id, purpose, filename, bytes, content)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id, user_id, purpose, filename, bytes, created_at",
+        )
+        .bind(&file.id)
+        .bind(file.user_id)
+        .bind(&file.purpose)
+        .bind(&file.filename)
+        .bind(file.content.len() as i64)
+        .bind(&file.content)
+        .fetch_one(&self.db)
+        .await?;
+        Ok(file)
+    }
+
+    /// Retrieves a file of a user, without its content.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The unique identifier of the user.
+    /// * `file_id` - The unique identifier of the file.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<Option<UserFile>>`: A result containing the file, or `None` if the user has no file with this id.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database query fails to execute.
+    #[instrument(level = "trace", skip(self))]
+    pub async fn get_user_file(&self, user_id: i64, file_id: &str) -> Result<Option<UserFile>> {
+        let file = sqlx::query_as::<_, UserFile>(
+            "SELECT id, user_id, purpose, filename, bytes, created_at
+            FROM files WHERE id = $1 AND user_id = $2",
+        )
+        .bind(file_id)
+        .bind(user_id)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(file)
+    }
+
+    /// Retrieves the content of a file of a user.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The unique identifier of the user.
+    /// * `file_id` - The unique identifier of the file.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<Option<Vec<u8>>>`: A result containing the content of the file, or `None` if the user has no file with this id.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database query fails to execute.
+    #[instrument(level = "trace", skip(self))]
+    pub async fn get_user_file_content(
+        &self,
+        user_id: i64,
+        file_id: &str,
+    ) -> Result<Option<Vec<u8>>> {
+        let content = sqlx::query_scalar::<_, Vec<u8>>(
+            "SELECT content FROM files WHERE id = $1 AND user_id = $2",
+        )
+        .bind(file_id)
+        .bind(user_id)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(content)
+    }
+
+    /// Stores a new batch, with its requests, in a single transaction.
+    ///
+    /// # Arguments
+    ///
+    /// * `batch` - The batch to store.
+    /// * `requests` - The requests of the batch, one per line of its input file.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<Batch>`: A result containing the stored batch.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database query fails to execute.
+    #[instrument(level = "trace", skip_all, fields(batch_id = %batch.id))]
+    pub async fn insert_batch(
+        &self,
+        batch: &NewBatch,
+        requests: &[NewBatchRequest],
+    ) -> Result<Batch> {
+        // NOTE: Postgres allows at most 65535 bind parameters per query, and each request takes 4 of them
+        const REQUESTS_PER_QUERY: usize = 10_000;
+
+        let mut transaction = self.db.begin().await?;
+        let stored_batch = sqlx::query_as::<_, Batch>(
+            "INSERT INTO batches (
+                id, user_id, api_token_id, endpoint, input_file_id, completion_window,
+                total_requests, metadata, expires_at
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            RETURNING *",
+        )
+        .bind(&batch.id)
+        .bind(batch.user_id)
+        .bind(batch.api_token_id)
+        .bind(&batch.endpoint)
+        .bind(&batch.input_file_id)
+        .bind(&batch.completion_window)
+        .bind(requests.len() as i64)
+        .bind(&batch.metadata)
+        .bind(batch.expires_at)
+        .fetch_one(&mut *transaction)
+        .await?;
+        for chunk in requests.chunks(REQUESTS_PER_QUERY) {
+            sqlx::QueryBuilder::new(
+                "INSERT INTO batch_requests (batch_id, line_index, custom_id, body) ",
+            )
+            .push_values(chunk, |mut query, request| {
+                query
+                    .push_bind(&batch.id)
+                    .push_bind(request.line_index)
+                    .push_bind(&request.custom_id)
+                    .push_bind(&request.body);
+            })
+            .build()
+            .execute(&mut *transaction)
+            .await?;
+        }
+        transaction.commit().await?;
+        Ok(stored_batch)
+    }
+
+    /// Retrieves a batch of a user.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The unique identifier of the user.
+    /// * `batch_id` - The unique identifier of the batch.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<Option<Batch>>`: A result containing the batch, or `None` if the user has no batch with this id.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database query fails to execute.
+    #[instrument(level = "trace", skip(self))]
+    pub async fn get_batch(&self, user_id: i64, batch_id: &str) -> Result<Option<Batch>> {
+        let batch =
+            sqlx::query_as::<_, Batch>("SELECT * FROM batches WHERE id = $1 AND user_id = $2")
+                .bind(batch_id)
+                .bind(user_id)
+                .fetch_optional(&self.db)
+                .await?;
+        Ok(batch)
+    }
+
+    /// Retrieves the batches of a user, most recent first.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The unique identifier of the user.
+    /// * `after` - Only return the batches created before this batch, to paginate the results.
+    /// * `limit` - The maximum number of batches to return.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<Vec<Batch>>`: A result containing the batches of the user.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database query fails to execute.
+    #[instrument(level = "trace", skip(self))]
+    pub async fn list_batches(
+        &self,
+        user_id: i64,
+        after: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<Batch>> {
+        let batches = sqlx::query_as::<_, Batch>(
+            "SELECT * FROM batches
+            WHERE user_id = $1
+            AND ($2::TEXT IS NULL OR (created_at, id) < (
+                SELECT created_at, id FROM batches WHERE id = $2 AND user_id = $1
+            ))
+            ORDER BY created_at DESC, id DESC
+            LIMIT $3",
+        )
+        .bind(user_id)
+        .bind(after)
+        .bind(limit)
+        .fetch_all(&self.db)
         .await?;
-        Ok(())
+        Ok(batches)
     }
 
-    /// Deduct from the usdc balance for the user.
+    /// Cancels a batch of a user.
     ///
-    /// This method withdraws the balance from the user in the `users` table.
+    /// Batches that are not finalized yet are marked as cancelling, and finalized by the batch
+    /// worker once the requests being executed complete. Finalized batches are left unchanged.
     ///
     /// # Arguments
     ///
     /// * `user_id` - The unique identifier of the user.
-    /// * `balance` - The balance to withdraw from the user.
+    /// * `batch_id` - The unique identifier of the batch.
     ///
     /// # Returns
     ///
-    /// - `Result<()>`: A result indicating success (Ok(())) or failure (Err(AtomaStateManagerError)).
+    /// - `Result<Option<Batch>>`: A result containing the batch, or `None` if the user has no batch with this id.
     ///
     /// # Errors
     ///
     /// This function will return an error if:
-    ///
-    /// - The database query fails to execute (that could mean the balance is not available)
+    /// - The database query fails to execute.
     #[instrument(level = "trace", skip(self))]
-    pub async fn deduct_from_usdc(&self, user_id: i64, balance: i64) -> Result<()> {
-        let result = sqlx::query("UPDATE balance SET usdc_balance = usdc_balance - $2 WHERE user_id = $1 AND usdc_balance >= $2")
-            .bind(user_id)
-            .bind(balance)
-            .execute(&self.db)
-            .await?;
-        if result.rows_affected() != 1 {
-            return Err(AtomaStateManagerError::InsufficientBalance);
+    pub async fn cancel_batch(&self, user_id: i64, batch_id: &str) -> Result<Option<Batch>> {
+        let batch = sqlx::query_as::<_, Batch>(
+            "UPDATE batches SET status = 'cancelling', cancelling_at = NOW()
+            WHERE id = $1 AND user_id = $2 AND status IN ('validating', 'in_progress')
+            RETURNING *",
+        )
+        .bind(batch_id)
+        .bind(user_id)
+        .fetch_optional(&self.db)
+        .await?;
+        match batch {
+            Some(batch) => Ok(Some(batch)),
+            None => self.get_batch(user_id, batch_id).await,
         }
-        Ok(())
     }
 
-    /// Refunds a USDC payment.
+    /// Retrieves the batches that are not finalized yet, of all users, oldest first.
     ///
-    /// This method refunds a USDC payment to the user in the `balance` table.
+    /// # Returns
+    ///
+    /// - `Result<Vec<Batch>>`: A result containing the validating, in progress and cancelling batches.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database query fails to execute.
+    #[instrument(level = "trace", skip(self))]
+    pub async fn get_active_batches(&self) -> Result<Vec<Batch>> {
+        let batches = sqlx::query_as::<_, Batch>(
+            "SELECT * FROM batches
+            WHERE status IN ('validating', 'in_progress', 'cancelling')
+            ORDER BY created_at",
+        )
+        .fetch_all(&self.db)
+        .await?;
+        Ok(batches)
+    }
+
+    /// Claims pending requests of a batch, in the order of its input file, to execute them.
+    ///
+    /// The claimed requests are marked as running, so that they are not claimed again,
+    /// and the batch is marked as in progress.
     ///
     /// # Arguments
     ///
-    /// * `user_id` - The unique identifier of the user.
-    /// * `amount` - The amount to refund.
+    /// * `batch_id` - The unique identifier of the batch.
+    /// * `limit` - The maximum number of requests to claim.
     ///
     /// # Returns
     ///
-    /// - `Result<()>`: A result indicating success (Ok(())) or failure (Err(AtomaStateManagerError)).
+    /// - `Result<Vec<BatchRequest>>`: A result containing the claimed requests.
     ///
     /// # Errors
     ///
     /// This function will return an error if:
-    ///
     /// - The database query fails to execute.
     #[instrument(level = "trace", skip(self))]
-    pub async fn refund_usdc(&self, user_id: i64, amount: i64) -> Result<()> {
-        sqlx::query("UPDATE balance SET usdc_balance = usdc_balance + $2 WHERE user_id = $1")
-            .bind(user_id)
-            .bind(amount)
-            .execute(&self.db)
+    pub async fn claim_batch_requests(
+        &self,
+        batch_id: &str,
+        limit: i64,
+    ) -> Result<Vec<BatchRequest>> {
+        let mut transaction = self.db.begin().await?;
+        sqlx::query(
+            "UPDATE batches SET status = 'in_progress', in_progress_at = NOW()
+            WHERE id = $1 AND status = 'validating'",
+        )
+        .bind(batch_id)
+        .execute(&mut *transaction)
+        .await?;
+        let requests = sqlx::query_as::<_, BatchRequest>(
+            "UPDATE batch_requests SET status = 'running', updated_at = NOW()
+            WHERE batch_id = $1 AND line_index IN (
+                SELECT line_index FROM batch_requests
+                WHERE batch_id = $1 AND status = 'pending'
+                ORDER BY line_index
+                LIMIT $2
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING *",
+        )
+        .bind(batch_id)
+        .bind(limit)
+        .fetch_all(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(requests)
+    }
+
+    /// Records the outcome of a request of a batch, and updates the request counts of the batch.
+    ///
+    /// # Arguments
+    ///
+    /// * `batch_id` - The unique identifier of the batch.
+    /// * `line_index` - The index of the request in the input file.
+    /// * `outcome` - The outcome of the request.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<()>`: A result indicating success (Ok(())) or failure (Err(AtomaStateManagerError)).
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database query fails to execute.
+    #[instrument(level = "trace", skip(self, outcome))]
+    pub async fn complete_batch_request(
+        &self,
+        batch_id: &str,
+        line_index: i64,
+        outcome: &BatchRequestOutcome,
+    ) -> Result<()> {
+        let mut transaction = self.db.begin().await?;
+        let result = sqlx::query(
+            "UPDATE batch_requests
+            SET status = $3, response_status = $4, response_body = $5, error = $6, updated_at = NOW()
+            WHERE batch_id = $1 AND line_index = $2 AND status = 'running'",
+        )
+        .bind(batch_id)
+        .bind(line_index)
+        .bind(if outcome.succeeded {
+            "completed"
+        } else {
+            "failed"
+        })
+        .bind(outcome.response_status)
+        .bind(&outcome.response_body)
+        .bind(&outcome.error)
+        .execute(&mut *transaction)
+        .await?;
+        // NOTE: Only count requests once, in case the outcome of a request is recorded twice
+        if result.rows_affected() > 0 {
+            sqlx::query(
+                "UPDATE batches
+                SET completed_requests = completed_requests + $2::INT,
+                    failed_requests = failed_requests + 1 - $2::INT
+                WHERE id = $1",
+            )
+            .bind(batch_id)
+            .bind(i32::from(outcome.succeeded))
+            .execute(&mut *transaction)
             .await?;
+        }
+        transaction.commit().await?;
         Ok(())
     }
 
-    /// Insert a new usdc payment digest.
-    ///
-    /// This method inserts a new usdc payment digest into the `usdc_payment_digests` table. It fails if the digest already exists.
+    /// Releases a claimed request of a batch, so that it is claimed and executed again later.
     ///
     /// # Arguments
     ///
-    /// * `digest` - The digest to insert.
+    /// * `batch_id` - The unique identifier of the batch.
+    /// * `line_index` - The index of the request in the input file.
     ///
     /// # Returns
     ///
@@ -4422,56 +5755,166 @@ impl AtomaState {
     /// # Errors
     ///
     /// This function will return an error if:
-    ///
-    /// - The database query fails to execute. Including if the digest already exists.
+    /// - The database query fails to execute.
     #[instrument(level = "trace", skip(self))]
-    pub async fn insert_new_usdc_payment_digest(&self, digest: String) -> Result<()> {
-        sqlx::query("INSERT INTO usdc_payment_digests (digest) VALUES ($1)")
-            .bind(digest)
-            .execute(&self.db)
-            .await?;
+    pub async fn release_batch_request(&self, batch_id: &str, line_index: i64) -> Result<()> {
+        sqlx::query(
+            "UPDATE batch_requests SET status = 'pending', updated_at = NOW()
+            WHERE batch_id = $1 AND line_index = $2 AND status = 'running'",
+        )
+        .bind(batch_id)
+        .bind(line_index)
+        .execute(&self.db)
+        .await?;
         Ok(())
     }
 
-    /// Gets the zk_salt for the user.
+    /// Releases all the claimed requests of all batches.
     ///
-    /// This method fetches the zk_salt for the user from the `users` table.
+    /// This is called when the batch worker starts, so that the requests that were being executed
+    /// when the proxy stopped are executed again.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<u64>`: A result containing the number of released requests.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database query fails to execute.
+    #[instrument(level = "trace", skip(self))]
+    pub async fn release_running_batch_requests(&self) -> Result<u64> {
+        let result = sqlx::query(
+            "UPDATE batch_requests SET status = 'pending', updated_at = NOW()
+            WHERE status = 'running'",
+        )
+        .execute(&self.db)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Retrieves all the requests of a batch, in the order of its input file.
     ///
     /// # Arguments
     ///
-    /// * `user_id` - The unique identifier of the user.
+    /// * `batch_id` - The unique identifier of the batch.
     ///
     /// # Returns
     ///
-    /// - `Result<Option<String>>`: A result containing either:
-    ///   - `Ok(Some(String))`: The zk_salt for the user.
-    ///   - `Ok(None)`: If the user is not found.
-    ///   - `Err(AtomaStateManagerError)`: An error if the database query fails.
+    /// - `Result<Vec<BatchRequest>>`: A result containing the requests of the batch.
     ///
     /// # Errors
     ///
     /// This function will return an error if:
-    ///
     /// - The database query fails to execute.
     #[instrument(level = "trace", skip(self))]
-    pub async fn get_zk_salt(&self, user_id: i64) -> Result<Option<String>> {
-        let zk_salt =
-            sqlx::query_scalar::<_, Option<String>>("SELECT zk_salt FROM users WHERE id = $1")
-                .bind(user_id)
-                .fetch_optional(&self.db)
-                .await?;
+    pub async fn get_batch_requests(&self, batch_id: &str) -> Result<Vec<BatchRequest>> {
+        let requests = sqlx::query_as::<_, BatchRequest>(
+            "SELECT * FROM batch_requests WHERE batch_id = $1 ORDER BY line_index",
+        )
+        .bind(batch_id)
+        .fetch_all(&self.db)
+        .await?;
+        Ok(requests)
+    }
 
-        Ok(zk_salt.flatten())
+    /// Finalizes a batch, storing its output and error files, in a single transaction.
+    ///
+    /// # Arguments
+    ///
+    /// * `batch_id` - The unique identifier of the batch.
+    /// * `status` - The final status of the batch, one of `Completed`, `Expired` or `Cancelled`.
+    /// * `output_file` - The file with the responses of the successful requests, if any.
+    /// * `error_file` - The file with the responses of the failed requests, if any.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<Batch>`: A result containing the finalized batch.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database query fails to execute.
+    /// - The batch does not exist.
+    #[instrument(level = "trace", skip(self, output_file, error_file))]
+    pub async fn finalize_batch(
+        &self,
+        batch_id: &str,
+        status: BatchStatus,
+        output_file: Option<&NewUserFile>,
+        error_file: Option<&NewUserFile>,
+    ) -> Result<Batch> {
+        let mut transaction = self.db.begin().await?;
+        for file in output_file.iter().chain(error_file.iter()) {
+            sqlx::query(
+                "INSERT INTO files (id, user_id, purpose, filename, bytes, content)
+                VALUES ($1, $2, $3, $4, $5, $6)",
+            )
+            .bind(&file.id)
+            .bind(file.user_id)
+            .bind(&file.purpose)
+            .bind(&file.filename)
+            .bind(file.content.len() as i64)
+            .bind(&file.content)
+            .execute(&mut *transaction)
+            .await?;
+        }
+        let batch = sqlx::query_as::<_, Batch>(
+            "UPDATE batches
+            SET status = $2,
+                output_file_id = $3,
+                error_file_id = $4,
+                completed_at = CASE WHEN $2 = 'completed' THEN NOW() END,
+                expired_at = CASE WHEN $2 = 'expired' THEN NOW() END,
+                cancelled_at = CASE WHEN $2 = 'cancelled' THEN NOW() END
+            WHERE id = $1
+            RETURNING *",
+        )
+        .bind(batch_id)
+        .bind(status.as_str())
+        .bind(output_file.map(|file| &file.id))
+        .bind(error_file.map(|file| &file.id))
+        .fetch_one(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(batch)
     }
 
-    /// Sets the zk_salt for the user.
+    /// Retrieves a cached response, if it did not expire yet.
     ///
-    /// This method updates the `zk_salt` field for the user in the `users` table.
+    /// # Arguments
+    ///
+    /// * `key` - The cache key of the request.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<Option<String>>`: A result containing the JSON encoded response, or `None` if no response is cached for the key.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database query fails to execute.
+    #[instrument(level = "trace", skip(self))]
+    pub async fn get_cached_response(&self, key: &str) -> Result<Option<String>> {
+        let response = sqlx::query_scalar::<_, String>(
+            "SELECT response FROM response_cache WHERE key = $1 AND expires_at > NOW()",
+        )
+        .bind(key)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(response)
+    }
+
+    /// Caches a response, replacing any response cached for the same key,
+    /// and removes the cached responses that expired.
     ///
     /// # Arguments
     ///
-    /// * `user_id` - The unique identifier of the user.
-    /// * `zk_salt` - The new zk_salt to store for the user.
+    /// * `key` - The cache key of the request.
+    /// * `model` - The model of the request.
+    /// * `endpoint` - The endpoint of the request.
+    /// * `response` - The JSON encoded response.
+    /// * `expires_at` - When the cached response expires.
     ///
     /// # Returns
     ///
@@ -4480,14 +5923,100 @@ impl AtomaState {
     /// # Errors
     ///
     /// This function will return an error if:
-    ///
     /// - The database query fails to execute.
-    pub async fn set_zk_salt(&self, user_id: i64, zk_salt: &str) -> Result<()> {
-        sqlx::query("UPDATE users SET zk_salt = $1 WHERE id = $2")
-            .bind(zk_salt)
-            .bind(user_id)
+    #[instrument(level = "trace", skip(self, response))]
+    pub async fn insert_cached_response(
+        &self,
+        key: &str,
+        model: &str,
+        endpoint: &str,
+        response: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<()> {
+        sqlx::query("DELETE FROM response_cache WHERE expires_at <= NOW()")
             .execute(&self.db)
             .await?;
+        sqlx::query(
+            "INSERT INTO response_cache (key, model, endpoint, response, expires_at)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (key) DO UPDATE
+            SET response = EXCLUDED.response, created_at = NOW(), expires_at = EXCLUDED.expires_at",
+        )
+        .bind(key)
+        .bind(model)
+        .bind(endpoint)
+        .bind(response)
+        .bind(expires_at)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    /// Charges a request served from the response cache, and records it in the usage ledger.
+    ///
+    /// Cached responses are not processed by any node, so they are not billed on a stack: their cost
+    /// is deducted from the USDC balance of the user, and added to the spend of the API token, in the
+    /// same transaction as the usage record.
+    ///
+    /// # Arguments
+    ///
+    /// * `usage_record` - The billing details of the request.
+    /// * `cost` - The cost of the request, in USDC.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<()>`: A result indicating success (Ok(())) or failure (Err(AtomaStateManagerError)).
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The balance of the user does not cover the cost of the request.
+    /// - The database query fails to execute.
+    #[instrument(level = "trace", skip(self))]
+    pub async fn charge_cached_response(
+        &self,
+        usage_record: &NewUsageRecord,
+        cost: i64,
+    ) -> Result<()> {
+        let mut tx = self.db.begin().await?;
+        if cost > 0 {
+            let result = sqlx::query(
+                "UPDATE balance SET usdc_balance = usdc_balance - $2 WHERE user_id = $1 AND usdc_balance >= $2",
+            )
+            .bind(usage_record.user_id)
+            .bind(cost)
+            .execute(&mut *tx)
+            .await?;
+            if result.rows_affected() != 1 {
+                return Err(AtomaStateManagerError::InsufficientBalance);
+            }
+            sqlx::query("UPDATE api_tokens SET amount_spent = amount_spent + $2 WHERE id = $1")
+                .bind(usage_record.api_token_id)
+                .bind(cost)
+                .execute(&mut *tx)
+                .await?;
+        }
+        sqlx::query(
+            "INSERT INTO usage_records (
+                user_id, api_token_id, model, endpoint, stack_small_id, node_small_id,
+                prompt_tokens, completion_tokens, cost, latency_ms, status
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
+        )
+        .bind(usage_record.user_id)
+        .bind(usage_record.api_token_id)
+        .bind(&usage_record.model)
+        .bind(&usage_record.endpoint)
+        .bind(usage_record.stack_small_id)
+        .bind(usage_record.node_small_id)
+        .bind(usage_record.prompt_tokens)
+        .bind(usage_record.completion_tokens)
+        .bind(cost)
+        .bind(usage_record.latency_ms)
+        .bind(usage_record.status)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
         Ok(())
     }
 }
@@ -19,6 +19,10 @@ pub enum Modalities {
     Embeddings,
     #[serde(rename = "Images Generations")]
     ImagesGenerations,
+    #[serde(rename = "Audio Transcriptions")]
+    AudioTranscriptions,
+    #[serde(rename = "Audio Speech")]
+    AudioSpeech,
 }
 
 /// Request payload for revoking an API token
@@ -61,21 +65,74 @@ pub struct AuthResponse {
 
 /// Request payload for creating a new API token
 ///
-/// Contains the name of the token
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+/// Contains the name of the token, and optionally the scopes, expiration and spending limit of the token
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
 pub struct CreateTokenRequest {
     /// The name of the token
     pub name: String,
+    /// The scopes of the token, the token is not restricted if not set
+    #[serde(default)]
+    pub scopes: ApiTokenScopes,
+    /// The expiration timestamp of the token, the token never expires if not set
+    #[serde(default)]
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// The maximum amount of USDC the token can spend, the token can spend the whole balance if not set
+    #[serde(default)]
+    pub spending_limit: Option<i64>,
+}
+
+/// Scopes of an API token
+///
+/// Restricts the endpoints and models an API token can be used for. Empty lists mean no restriction.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct ApiTokenScopes {
+    /// The endpoints the token can be used on (e.g. `/v1/embeddings`)
+    #[serde(default)]
+    #[sqlx(rename = "allowed_endpoints")]
+    pub endpoints: Vec<String>,
+    /// The models the token can be used with
+    #[serde(default)]
+    #[sqlx(rename = "allowed_models")]
+    pub models: Vec<String>,
+    /// Whether the token can only be used for confidential compute
+    #[serde(default)]
+    pub confidential_only: bool,
+}
+
+/// A valid API token, as returned when authenticating a request
+///
+/// Contains the owner of the token and the restrictions that apply to its use
+#[derive(Clone, Debug, PartialEq, Eq, FromRow)]
+pub struct ApiToken {
+    /// The id of the token
+    pub id: i64,
+    /// The id of the user owning the token
+    pub user_id: i64,
+    /// The scopes of the token
+    #[sqlx(flatten)]
+    pub scopes: ApiTokenScopes,
+    /// The expiration timestamp of the token
+    pub expires_at: Option<DateTime<Utc>>,
+    /// The maximum amount of USDC the token can spend
+    pub spending_limit: Option<i64>,
+    /// The amount of USDC spent with the token so far
+    pub amount_spent: i64,
 }
 
 /// After requesting api tokens vec of these will be returned
 ///
-/// Contains the id of the token, the last 4 digits of the token, the name of the token and the creation date of the token
+/// Contains the id of the token, the display prefix of the token, the name of the token and the creation date of the token.
+/// The token itself is only stored as a keyed hash and is never returned.
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromRow)]
 pub struct TokenResponse {
     /// The id of the token
     pub id: i64,
-    /// The last 4 chars of the token
+    /// The non-secret display prefix of the token
+    pub token_prefix: String,
+    /// The last 4 chars of the token.
+    ///
+    /// Deprecated in favour of `token_prefix`, and removed in the next release.
     pub token_last_4: String,
     /// The last used timestamp of the token
     pub last_used_timestamp: Option<DateTime<Utc>>,
@@ -83,6 +140,343 @@ pub struct TokenResponse {
     pub created_at: DateTime<Utc>,
     /// The name of the token
     pub name: String,
+    /// The scopes of the token
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub scopes: ApiTokenScopes,
+    /// The expiration timestamp of the token
+    pub expires_at: Option<DateTime<Utc>>,
+    /// The maximum amount of USDC the token can spend
+    pub spending_limit: Option<i64>,
+    /// The amount of USDC spent with the token so far
+    pub amount_spent: i64,
+}
+
+/// A request to be recorded in the usage ledger, once it has been billed
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NewUsageRecord {
+    /// The id of the user who made the request
+    pub user_id: i64,
+    /// The id of the API token used to authenticate the request
+    pub api_token_id: i64,
+    /// The model requested
+    pub model: String,
+    /// The endpoint requested
+    pub endpoint: String,
+    /// The small id of the stack the request was billed on, 0 for requests served from the response cache
+    pub stack_small_id: i64,
+    /// The small id of the node that processed the request, 0 for requests served from the response cache
+    pub node_small_id: i64,
+    /// The number of prompt (input) tokens
+    pub prompt_tokens: i64,
+    /// The number of completion (output) tokens
+    pub completion_tokens: i64,
+    /// Time taken to process the request, in milliseconds
+    pub latency_ms: i64,
+    /// HTTP status code of the response
+    pub status: i32,
+}
+
+/// An entry of the usage ledger
+///
+/// Contains the billing details of a single request, so that users can reconcile their invoices.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct UsageRecord {
+    /// The id of the usage record
+    pub id: i64,
+    /// The id of the user who made the request
+    pub user_id: i64,
+    /// The id of the API token used to authenticate the request
+    pub api_token_id: i64,
+    /// The model requested
+    pub model: String,
+    /// The endpoint requested
+    pub endpoint: String,
+    /// The small id of the stack the request was billed on, 0 for requests served from the response cache
+    pub stack_small_id: i64,
+    /// The small id of the node that processed the request, 0 for requests served from the response cache
+    pub node_small_id: i64,
+    /// The number of prompt (input) tokens
+    pub prompt_tokens: i64,
+    /// The number of completion (output) tokens
+    pub completion_tokens: i64,
+    /// The cost of the request in USDC
+    pub cost: i64,
+    /// Time taken to process the request, in milliseconds
+    pub latency_ms: i64,
+    /// HTTP status code of the response
+    pub status: i32,
+    /// The timestamp of the request
+    pub created_at: DateTime<Utc>,
+}
+
+/// Request payload for registering a webhook
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct CreateWebhookRequest {
+    /// The URL the events are delivered to
+    pub url: String,
+    /// Notify when the USDC balance drops below this threshold
+    pub low_balance_threshold: Option<i64>,
+    /// Notify when the USDC spent in the current month reaches this limit
+    pub monthly_spend_limit: Option<i64>,
+}
+
+/// Request payload for deleting a webhook
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct DeleteWebhookRequest {
+    /// The id of the webhook to delete
+    pub webhook_id: i64,
+}
+
+/// A webhook registered by a user
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct Webhook {
+    /// The id of the webhook
+    pub id: i64,
+    /// The id of the user who registered the webhook
+    pub user_id: i64,
+    /// The URL the events are delivered to
+    pub url: String,
+    /// Hex encoded key used to sign the delivered payloads, only returned when the webhook is created
+    #[serde(skip_serializing, default)]
+    pub secret: String,
+    /// Notify when the USDC balance drops below this threshold
+    pub low_balance_threshold: Option<i64>,
+    /// Notify when the USDC spent in the current month reaches this limit
+    pub monthly_spend_limit: Option<i64>,
+    /// The timestamp of the webhook registration
+    pub created_at: DateTime<Utc>,
+}
+
+/// Response of the webhook registration
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CreateWebhookResponse {
+    /// The registered webhook
+    pub webhook: Webhook,
+    /// Hex encoded key used to sign the delivered payloads
+    pub secret: String,
+}
+
+/// An event delivered to the webhooks of a user
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum WebhookEvent {
+    /// The USDC balance dropped below the threshold of the webhook
+    BalanceBelowThreshold {
+        /// The current USDC balance
+        balance: i64,
+        /// The threshold of the webhook
+        threshold: i64,
+    },
+    /// The USDC spent in the current month reached the limit of the webhook
+    MonthlySpendLimitReached {
+        /// The USDC spent in the current month
+        spend: i64,
+        /// The limit of the webhook
+        limit: i64,
+    },
+    /// A USDC payment was credited to the balance
+    BalanceToppedUp {
+        /// The amount credited
+        amount: i64,
+        /// The USDC balance after the top-up
+        balance: i64,
+    },
+    /// An API token was revoked
+    ApiTokenRevoked {
+        /// The id of the revoked API token
+        api_token_id: i64,
+    },
+}
+
+/// A file stored by the proxy, either uploaded by a user (e.g. the input of a batch)
+/// or generated by the proxy (e.g. the output of a batch)
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct UserFile {
+    /// The id of the file
+    pub id: String,
+    /// The id of the user owning the file
+    pub user_id: i64,
+    /// What the file is used for, e.g. `batch` or `batch_output`
+    pub purpose: String,
+    /// The name of the file
+    pub filename: String,
+    /// The size of the file, in bytes
+    pub bytes: i64,
+    /// The timestamp of the file creation
+    pub created_at: DateTime<Utc>,
+}
+
+/// A new file to store
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NewUserFile {
+    /// The id of the file
+    pub id: String,
+    /// The id of the user owning the file
+    pub user_id: i64,
+    /// What the file is used for, e.g. `batch` or `batch_output`
+    pub purpose: String,
+    /// The name of the file
+    pub filename: String,
+    /// The content of the file
+    pub content: Vec<u8>,
+}
+
+/// Status of a batch
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchStatus {
+    /// The batch was created, and none of its requests were executed yet
+    Validating,
+    /// The requests of the batch are being executed
+    InProgress,
+    /// The batch was cancelled by the user, and is being finalized
+    Cancelling,
+    /// All the requests of the batch were executed
+    Completed,
+    /// The batch was not completed within its completion window
+    Expired,
+    /// The batch was cancelled by the user
+    Cancelled,
+}
+
+impl BatchStatus {
+    /// The status, as stored in the `batches` table
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Validating => "validating",
+            Self::InProgress => "in_progress",
+            Self::Cancelling => "cancelling",
+            Self::Completed => "completed",
+            Self::Expired => "expired",
+            Self::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// A batch of requests, executed in the background on behalf of an API token of a user
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct Batch {
+    /// The id of the batch
+    pub id: String,
+    /// The id of the user owning the batch
+    pub user_id: i64,
+    /// The id of the API token the requests of the batch are executed with
+    pub api_token_id: i64,
+    /// The endpoint the requests of the batch are sent to
+    pub endpoint: String,
+    /// The id of the input file of the batch
+    pub input_file_id: String,
+    /// The time frame within which the batch should be processed, e.g. `24h`
+    pub completion_window: String,
+    /// The status of the batch, see [`BatchStatus`]
+    pub status: String,
+    /// The id of the file with the responses of the successful requests
+    pub output_file_id: Option<String>,
+    /// The id of the file with the responses of the failed requests
+    pub error_file_id: Option<String>,
+    /// The number of requests of the batch
+    pub total_requests: i64,
+    /// The number of requests that completed successfully
+    pub completed_requests: i64,
+    /// The number of requests that failed
+    pub failed_requests: i64,
+    /// JSON encoded metadata set by the user
+    pub metadata: Option<String>,
+    /// The timestamp of the batch creation
+    pub created_at: DateTime<Utc>,
+    /// The timestamp at which the batch started being processed
+    pub in_progress_at: Option<DateTime<Utc>>,
+    /// The timestamp at which the batch was cancelled by the user
+    pub cancelling_at: Option<DateTime<Utc>>,
+    /// The timestamp at which the batch was completed
+    pub completed_at: Option<DateTime<Utc>>,
+    /// The timestamp at which the batch expired
+    pub expired_at: Option<DateTime<Utc>>,
+    /// The timestamp at which the cancellation of the batch was finalized
+    pub cancelled_at: Option<DateTime<Utc>>,
+    /// The timestamp at which the batch expires, if it was not completed
+    pub expires_at: DateTime<Utc>,
+}
+
+/// A new batch to store
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NewBatch {
+    /// The id of the batch
+    pub id: String,
+    /// The id of the user owning the batch
+    pub user_id: i64,
+    /// The id of the API token the requests of the batch are executed with
+    pub api_token_id: i64,
+    /// The endpoint the requests of the batch are sent to
+    pub endpoint: String,
+    /// The id of the input file of the batch
+    pub input_file_id: String,
+    /// The time frame within which the batch should be processed, e.g. `24h`
+    pub completion_window: String,
+    /// JSON encoded metadata set by the user
+    pub metadata: Option<String>,
+    /// The timestamp at which the batch expires, if it was not completed
+    pub expires_at: DateTime<Utc>,
+}
+
+/// A request of a batch, from one line of its input file
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct BatchRequest {
+    /// The id of the batch
+    pub batch_id: String,
+    /// The index of the line of the request in the input file
+    pub line_index: i64,
+    /// The id set by the user to match the request with its response
+    pub custom_id: String,
+    /// JSON encoded body of the request
+    pub body: String,
+    /// One of `pending`, `running`, `completed` or `failed`
+    pub status: String,
+    /// The HTTP status code of the response
+    pub response_status: Option<i32>,
+    /// JSON encoded body of the response
+    pub response_body: Option<String>,
+    /// JSON encoded error, when the request could not be executed
+    pub error: Option<String>,
+}
+
+/// A new request of a batch, from one line of its input file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NewBatchRequest {
+    /// The index of the line of the request in the input file
+    pub line_index: i64,
+    /// The id set by the user to match the request with its response
+    pub custom_id: String,
+    /// JSON encoded body of the request
+    pub body: String,
+}
+
+/// The outcome of a request of a batch
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BatchRequestOutcome {
+    /// Whether the request completed successfully
+    pub succeeded: bool,
+    /// The HTTP status code of the response, if a response was received
+    pub response_status: Option<i32>,
+    /// JSON encoded body of the response, if a response was received
+    pub response_body: Option<String>,
+    /// JSON encoded error, when the request could not be executed
+    pub error: Option<String>,
+}
+
+/// A page of the usage ledger of a user, most recent requests first
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UsagePage {
+    /// The usage records of the page
+    pub records: Vec<UsageRecord>,
+    /// The index of the page, starting at 0
+    pub page: i64,
+    /// The maximum number of usage records per page
+    pub page_size: i64,
+    /// The total number of usage records matching the query
+    pub total: i64,
 }
 
 /// Request payload for updating the sui address for the user.
@@ -391,6 +785,31 @@ pub struct CheapestNode {
     pub node_small_id: i64,
 }
 
+/// Represents the live node subscriptions of a model, aggregated over all its nodes
+#[derive(Clone, Debug, FromRow, Serialize, Deserialize)]
+pub struct ModelCatalogEntry {
+    /// The name of the model
+    pub model: String,
+
+    /// When the first task of the model was registered
+    pub created_at: DateTime<Utc>,
+
+    /// Number of nodes with a valid subscription to the model
+    pub num_nodes: i64,
+
+    /// Cheapest price per one million compute units offered for the model
+    pub min_price_per_one_million_compute_units: i64,
+
+    /// Median price per one million compute units offered for the model
+    pub median_price_per_one_million_compute_units: i64,
+
+    /// Largest number of compute units accepted for the model by a node
+    pub max_num_compute_units: i64,
+
+    /// Whether some node serves the model with confidential compute
+    pub is_confidential_available: bool,
+}
+
 /// Response for getting the node distribution.
 ///
 /// This struct represents the response for the get_node_distribution endpoint.
@@ -595,6 +1014,8 @@ pub enum AtomaAtomaStateManagerEvent {
         estimated_total_tokens: i64,
         /// Total number of tokens in the stack
         total_tokens: i64,
+        /// Total number of tokens billed to the user, charged to metered balances
+        billed_total_tokens: i64,
     },
     /// Represents an update to the total hash of a stack
     UpdateStackTotalHash {
@@ -624,6 +1045,8 @@ pub enum AtomaAtomaStateManagerEvent {
         user_id: i64,
         /// Indicates whether the stacks are associated with confidential compute or not
         is_confidential: bool,
+        /// Nodes whose stacks must not be selected
+        excluded_node_small_ids: Vec<i64>,
         /// Channel to send back the list of matching stacks
         /// Returns Ok(Vec<Stack>) with matching stacks or an error if the query fails
         result_sender: oneshot::Sender<Result<Option<Stack>>>,
@@ -664,10 +1087,31 @@ pub enum AtomaAtomaStateManagerEvent {
         model: String,
         /// Indicates whether the stacks are associated with confidential compute or not
         is_confidential: bool,
+        /// Nodes that must not be returned
+        excluded_node_small_ids: Vec<i64>,
         /// Channel to send back the cheapest node
         /// Returns Ok(Option<CheapestNode>) with the cheapest node or an error if the query fails
         result_sender: oneshot::Sender<Result<Option<CheapestNode>>>,
     },
+    /// Retrieves the catalog of the models with live node subscriptions
+    GetModelCatalog {
+        /// Channel to send back the models, sorted by name
+        result_sender: oneshot::Sender<Result<Vec<ModelCatalogEntry>>>,
+    },
+    /// Retrieves the best ranked node for a specific model, among a list of candidate nodes
+    GetBestRankedNodeForModel {
+        /// The name/identifier of the model to query the best ranked node for
+        model: String,
+        /// The candidate node small ids, sorted by preference (best node first)
+        ranked_node_small_ids: Vec<i64>,
+        /// Optional price ceiling, per one million compute units, for the selected node
+        max_price_per_one_million_compute_units: Option<i64>,
+        /// Indicates whether the stacks are associated with confidential compute or not
+        is_confidential: bool,
+        /// Channel to send back the best ranked node
+        /// Returns Ok(Option<CheapestNode>) with the best ranked node or an error if the query fails
+        result_sender: oneshot::Sender<Result<Option<CheapestNode>>>,
+    },
     GetNodePublicUrlAndSmallId {
         /// Unique small integer identifier for the stack
         stack_small_id: i64,
@@ -729,6 +1173,8 @@ pub enum AtomaAtomaStateManagerEvent {
         transaction_timestamp: DateTime<Utc>,
         /// User id of the stack owner (referencing local user table)
         user_id: i64,
+        /// Whether the user is charged by actual usage instead of for the whole stack
+        is_metered: bool,
         /// Channel to send back the result
         /// Returns Ok(()) if the stack is valid or an error if it is not
         result_sender: oneshot::Sender<Result<()>>,
@@ -810,11 +1256,20 @@ pub enum AtomaAtomaStateManagerEvent {
     },
     /// Checks if an API token is valid for a user
     IsApiTokenValid {
-        /// The API token
-        api_token: String,
+        /// The keyed hash of the API token
+        api_token_hash: String,
         /// Channel to send back the result
-        /// Returns Ok(bool) with true if the API token is valid or false if it is not
-        result_sender: oneshot::Sender<Result<i64>>,
+        /// Returns Ok(ApiToken) with the token owner and scopes if the API token exists or an error if it does not
+        result_sender: oneshot::Sender<Result<ApiToken>>,
+    },
+    /// Retrieves an API token by its id, used to execute the requests forwarded by the proxy to itself on behalf of
+    /// the API token of the client
+    GetApiTokenById {
+        /// The API token id
+        api_token_id: i64,
+        /// Channel to send back the result
+        /// Returns Ok(ApiToken) if the API token exists (it was not revoked) or an error if it does not
+        result_sender: oneshot::Sender<Result<ApiToken>>,
     },
     /// Revokes an API token for a user
     RevokeApiToken {
@@ -827,10 +1282,28 @@ pub enum AtomaAtomaStateManagerEvent {
     StoreNewApiToken {
         /// The user ID
         user_id: i64,
-        /// The API token
-        api_token: String,
-        /// Name of the token
-        name: String,
+        /// The keyed hash of the API token
+        api_token_hash: String,
+        /// The non-secret display prefix of the API token
+        api_token_prefix: String,
+        /// The last 4 chars of the API token, still returned for one release
+        api_token_last_4: String,
+        /// The token creation request, with the name and restrictions of the token
+        request: CreateTokenRequest,
+    },
+    /// Adds the cost of processed compute units to the amount spent by an API token
+    UpdateApiTokenSpend {
+        /// The API token id
+        api_token_id: i64,
+        /// The stack small id, whose price is used to compute the cost
+        stack_small_id: i64,
+        /// The number of compute units processed
+        num_compute_units: i64,
+    },
+    /// Records a billed request in the usage ledger
+    RecordUsage {
+        /// The usage record
+        usage_record: NewUsageRecord,
     },
     /// Retrieves all API tokens for a user
     GetApiTokensForUser {
@@ -911,4 +1384,153 @@ pub enum AtomaAtomaStateManagerEvent {
         /// The result sender to send back the result
         result_sender: oneshot::Sender<Result<()>>,
     },
+    /// Stores a new file
+    InsertUserFile {
+        /// The file to store
+        file: NewUserFile,
+        /// The result sender to send back the stored file
+        result_sender: oneshot::Sender<Result<UserFile>>,
+    },
+    /// Retrieves a file of a user
+    GetUserFile {
+        /// The user ID
+        user_id: i64,
+        /// The file ID
+        file_id: String,
+        /// The result sender to send back the file, if the user owns it
+        result_sender: oneshot::Sender<Result<Option<UserFile>>>,
+    },
+    /// Retrieves the content of a file of a user
+    GetUserFileContent {
+        /// The user ID
+        user_id: i64,
+        /// The file ID
+        file_id: String,
+        /// The result sender to send back the content of the file, if the user owns it
+        result_sender: oneshot::Sender<Result<Option<Vec<u8>>>>,
+    },
+    /// Stores a new batch, with its requests
+    CreateBatch {
+        /// The batch to store
+        batch: NewBatch,
+        /// The requests of the batch
+        requests: Vec<NewBatchRequest>,
+        /// The result sender to send back the stored batch
+        result_sender: oneshot::Sender<Result<Batch>>,
+    },
+    /// Retrieves a batch of a user
+    GetBatch {
+        /// The user ID
+        user_id: i64,
+        /// The batch ID
+        batch_id: String,
+        /// The result sender to send back the batch, if the user owns it
+        result_sender: oneshot::Sender<Result<Option<Batch>>>,
+    },
+    /// Retrieves the batches of a user, most recent first
+    ListBatches {
+        /// The user ID
+        user_id: i64,
+        /// Only return the batches created before this batch, if set
+        after: Option<String>,
+        /// The maximum number of batches to return
+        limit: i64,
+        /// The result sender to send back the batches
+        result_sender: oneshot::Sender<Result<Vec<Batch>>>,
+    },
+    /// Cancels a batch of a user, its remaining requests are not executed
+    CancelBatch {
+        /// The user ID
+        user_id: i64,
+        /// The batch ID
+        batch_id: String,
+        /// The result sender to send back the batch, if the user owns it
+        result_sender: oneshot::Sender<Result<Option<Batch>>>,
+    },
+    /// Retrieves the batches that are not finalized yet, of all users
+    GetActiveBatches {
+        /// The result sender to send back the batches
+        result_sender: oneshot::Sender<Result<Vec<Batch>>>,
+    },
+    /// Claims pending requests of a batch to execute them, marking the batch as in progress
+    ClaimBatchRequests {
+        /// The batch ID
+        batch_id: String,
+        /// The maximum number of requests to claim
+        limit: i64,
+        /// The result sender to send back the claimed requests
+        result_sender: oneshot::Sender<Result<Vec<BatchRequest>>>,
+    },
+    /// Records the outcome of a request of a batch
+    CompleteBatchRequest {
+        /// The batch ID
+        batch_id: String,
+        /// The index of the request in the input file
+        line_index: i64,
+        /// The outcome of the request
+        outcome: BatchRequestOutcome,
+    },
+    /// Releases a claimed request of a batch, so that it is executed again later
+    ReleaseBatchRequest {
+        /// The batch ID
+        batch_id: String,
+        /// The index of the request in the input file
+        line_index: i64,
+    },
+    /// Releases all the claimed requests of all batches, e.g. after a restart of the proxy
+    ReleaseRunningBatchRequests {
+        /// The result sender to send back the number of released requests
+        result_sender: oneshot::Sender<Result<u64>>,
+    },
+    /// Retrieves all the requests of a batch, in the order of its input file
+    GetBatchRequests {
+        /// The batch ID
+        batch_id: String,
+        /// The result sender to send back the requests
+        result_sender: oneshot::Sender<Result<Vec<BatchRequest>>>,
+    },
+    /// Finalizes a batch, storing its output and error files
+    FinalizeBatch {
+        /// The batch ID
+        batch_id: String,
+        /// The final status of the batch
+        status: BatchStatus,
+        /// The file with the responses of the successful requests, if any
+        output_file: Option<NewUserFile>,
+        /// The file with the responses of the failed requests, if any
+        error_file: Option<NewUserFile>,
+        /// The result sender to send back the finalized batch
+        result_sender: oneshot::Sender<Result<Batch>>,
+    },
+    /// Retrieves a cached response, if it did not expire yet
+    GetCachedResponse {
+        /// The cache key of the request
+        key: String,
+        /// The result sender to send back the JSON encoded response, if any
+        result_sender: oneshot::Sender<Result<Option<String>>>,
+    },
+    /// Caches a response
+    InsertCachedResponse {
+        /// The cache key of the request
+        key: String,
+        /// The model of the request
+        model: String,
+        /// The endpoint of the request
+        endpoint: String,
+        /// The JSON encoded response
+        response: String,
+        /// When the cached response expires
+        expires_at: DateTime<Utc>,
+    },
+    /// Charges a request served from the response cache to the balance of its user,
+    /// and records it in the usage ledger
+    ChargeCachedResponse {
+        /// The usage record of the request
+        usage_record: NewUsageRecord,
+        /// The cost of the request, in USDC
+        cost: i64,
+        /// Channel to send back the result
+        /// Returns Ok(()) if the request was charged, or an error if the balance of the user does not cover its cost
+        result_sender: oneshot::Sender<Result<()>>,
+    },
 }
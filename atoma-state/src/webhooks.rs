@@ -0,0 +1,306 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    time::Duration,
+};
+
+use chrono::Utc;
+use serde::Serialize;
+use tracing::{error, instrument, warn};
+use url::{Host, Url};
+
+use crate::{
+    state_manager::AtomaState,
+    types::{Webhook, WebhookEvent},
+    AtomaStateManagerError,
+};
+
+type Result<T> = std::result::Result<T, AtomaStateManagerError>;
+
+/// Header containing the unix timestamp, in seconds, at which the payload was signed
+pub const WEBHOOK_TIMESTAMP_HEADER: &str = "X-Atoma-Webhook-Timestamp";
+
+/// Header containing the hex encoded signature of the payload
+pub const WEBHOOK_SIGNATURE_HEADER: &str = "X-Atoma-Webhook-Signature";
+
+/// Timeout of a single webhook delivery attempt
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Maximum number of delivery attempts of an event to a webhook
+const MAX_DELIVERY_ATTEMPTS: u32 = 3;
+
+/// Delay before the first retry of a failed delivery, doubled after each attempt
+const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Resolves the host of a webhook URL, and checks that it only resolves to public IP addresses.
+///
+/// Webhook URLs are set by users, so they must not reach the loopback, private, link-local
+/// (e.g. cloud metadata services) or otherwise reserved addresses of the network the proxy runs in.
+///
+/// # Returns
+///
+/// The host of the URL and the addresses it resolves to, the delivery is pinned to.
+///
+/// # Errors
+///
+/// Returns an error if the URL is not a valid http(s) URL, its host cannot be resolved, or any of
+/// the addresses it resolves to is not public.
+pub async fn resolve_webhook_url(url: &str) -> Result<(String, Vec<SocketAddr>)> {
+    let invalid_url = |reason: &str| AtomaStateManagerError::InvalidUrl(format!("{url}: {reason}"));
+    let url = Url::parse(url).map_err(|e| invalid_url(&e.to_string()))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(invalid_url("the scheme must be http or https"));
+    }
+    let port = url
+        .port_or_known_default()
+        .ok_or_else(|| invalid_url("missing port"))?;
+    let (host, addresses) = match url.host() {
+        Some(Host::Domain(domain)) => (
+            domain.to_string(),
+            tokio::net::lookup_host((domain, port))
+                .await
+                .map_err(|e| invalid_url(&format!("failed to resolve host: {e}")))?
+                .collect::<Vec<_>>(),
+        ),
+        Some(Host::Ipv4(ip)) => (ip.to_string(), vec![SocketAddr::new(ip.into(), port)]),
+        Some(Host::Ipv6(ip)) => (ip.to_string(), vec![SocketAddr::new(ip.into(), port)]),
+        None => return Err(invalid_url("missing host")),
+    };
+    if addresses.is_empty() {
+        return Err(invalid_url("the host does not resolve to any address"));
+    }
+    if let Some(address) = addresses.iter().find(|address| !is_public_ip(address.ip())) {
+        return Err(invalid_url(&format!(
+            "the host resolves to the non public address {}",
+            address.ip()
+        )));
+    }
+    Ok((host, addresses))
+}
+
+/// Whether an IP address is publicly routable, i.e. not a loopback, private, link-local, shared,
+/// multicast, documentation or otherwise reserved address.
+fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_ipv4(ip),
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            if let Some(ipv4) = ip.to_ipv4_mapped() {
+                return is_public_ipv4(ipv4);
+            }
+            // NOTE: NAT64 addresses (64:ff9b::/96) embed an IPv4 address
+            if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+                let octets = ip.octets();
+                return is_public_ipv4(Ipv4Addr::new(
+                    octets[12], octets[13], octets[14], octets[15],
+                ));
+            }
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // Unique local addresses (fc00::/7)
+                || segments[0] & 0xfe00 == 0xfc00
+                // Link-local addresses (fe80::/10)
+                || segments[0] & 0xffc0 == 0xfe80
+                // Documentation addresses (2001:db8::/32)
+                || segments[..2] == [0x2001, 0xdb8])
+        }
+    }
+}
+
+fn is_public_ipv4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        // "This network" addresses (0.0.0.0/8)
+        || a == 0
+        // Shared addresses (100.64.0.0/10)
+        || (a == 100 && b & 0xc0 == 64)
+        // Benchmarking addresses (198.18.0.0/15)
+        || (a == 198 && b & 0xfe == 18)
+        // Reserved addresses (240.0.0.0/4)
+        || a >= 240)
+}
+
+/// Builds the HTTP client for a webhook delivery, pinned to the checked addresses of the host
+/// of the webhook, so that the host cannot be resolved again to another address. Redirects are
+/// not followed, as they could point to any address.
+fn webhook_client(host: &str, addresses: &[SocketAddr]) -> Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .timeout(WEBHOOK_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none())
+        .resolve_to_addrs(host, addresses)
+        .build()
+        .map_err(AtomaStateManagerError::WebhookDeliveryError)
+}
+
+/// The JSON payload delivered to a webhook
+#[derive(Debug, Serialize)]
+struct WebhookPayload<'a> {
+    /// The id of the webhook the payload is delivered to
+    webhook_id: i64,
+    /// The id of the user the event relates to
+    user_id: i64,
+    /// The unix timestamp, in seconds, of the event
+    created_at: i64,
+    /// The event
+    #[serde(flatten)]
+    event: &'a WebhookEvent,
+}
+
+/// Signs a webhook payload.
+///
+/// The signature is the hex encoded BLAKE3 keyed hash of `"{timestamp}.{body}"`, keyed with the
+/// (hex decoded) secret of the webhook. Receivers should recompute it, compare it to the
+/// `X-Atoma-Webhook-Signature` header, and reject payloads with a stale `X-Atoma-Webhook-Timestamp`.
+///
+/// # Errors
+///
+/// Returns an error if the secret is not a hex encoded 32 bytes key.
+pub fn sign_webhook_payload(secret: &str, timestamp: i64, body: &[u8]) -> Result<String> {
+    let key: [u8; 32] = hex::decode(secret)
+        .ok()
+        .and_then(|key| key.try_into().ok())
+        .ok_or(AtomaStateManagerError::InvalidWebhookSecret)?;
+    let mut hasher = blake3::Hasher::new_keyed(&key);
+    hasher.update(timestamp.to_string().as_bytes());
+    hasher.update(b".");
+    hasher.update(body);
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// Delivers an event to webhooks, in the background.
+///
+/// Each delivery is retried with an exponential backoff until it succeeds or
+/// `MAX_DELIVERY_ATTEMPTS` is reached. Failures are only logged.
+pub fn dispatch_webhook_event(webhooks: Vec<Webhook>, event: WebhookEvent) {
+    for webhook in webhooks {
+        let event = event.clone();
+        tokio::spawn(async move {
+            if let Err(e) = deliver_webhook_event(&webhook, &event).await {
+                error!(
+                    target = "atoma-state-webhooks",
+                    webhook_id = webhook.id,
+                    user_id = webhook.user_id,
+                    "Failed to deliver webhook event: {e}"
+                );
+            }
+        });
+    }
+}
+
+#[instrument(level = "trace", skip_all, fields(webhook_id = webhook.id, user_id = webhook.user_id))]
+async fn deliver_webhook_event(webhook: &Webhook, event: &WebhookEvent) -> Result<()> {
+    let timestamp = Utc::now().timestamp();
+    let body = serde_json::to_vec(&WebhookPayload {
+        webhook_id: webhook.id,
+        user_id: webhook.user_id,
+        created_at: timestamp,
+        event,
+    })?;
+    let signature = sign_webhook_payload(&webhook.secret, timestamp, &body)?;
+    // NOTE: The host is checked again right before the delivery, as it may resolve to other
+    // addresses than when the webhook was registered
+    let (host, addresses) = resolve_webhook_url(&webhook.url).await?;
+    let client = webhook_client(&host, &addresses)?;
+    let mut retry_delay = INITIAL_RETRY_DELAY;
+    let mut attempt = 1;
+    loop {
+        let response = client
+            .post(&webhook.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(WEBHOOK_TIMESTAMP_HEADER, timestamp)
+            .header(WEBHOOK_SIGNATURE_HEADER, &signature)
+            .body(body.clone())
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status);
+        match response {
+            Ok(_) => return Ok(()),
+            Err(e) if attempt >= MAX_DELIVERY_ATTEMPTS => {
+                return Err(AtomaStateManagerError::WebhookDeliveryError(e));
+            }
+            Err(e) => {
+                warn!(
+                    target = "atoma-state-webhooks",
+                    attempt, "Webhook delivery failed, retrying: {e}"
+                );
+                tokio::time::sleep(retry_delay).await;
+                retry_delay *= 2;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// Notifies the webhooks of a user whose low balance threshold was just crossed.
+///
+/// # Errors
+///
+/// Returns an error if the webhooks or the balance of the user can't be retrieved.
+pub async fn notify_low_balance(state: &AtomaState, user_id: i64) -> Result<()> {
+    let webhooks = state.claim_low_balance_webhooks(user_id).await?;
+    if webhooks.is_empty() {
+        return Ok(());
+    }
+    let balance = state.get_balance_for_user(user_id).await?;
+    for webhook in webhooks {
+        let threshold = webhook.low_balance_threshold.unwrap_or_default();
+        dispatch_webhook_event(
+            vec![webhook],
+            WebhookEvent::BalanceBelowThreshold { balance, threshold },
+        );
+    }
+    Ok(())
+}
+
+/// Notifies the webhooks of a user whose monthly spend limit was just reached.
+///
+/// # Errors
+///
+/// Returns an error if the webhooks of the user can't be retrieved.
+pub async fn notify_monthly_spend_limit(state: &AtomaState, user_id: i64) -> Result<()> {
+    for (webhook, spend) in state.claim_monthly_spend_limit_webhooks(user_id).await? {
+        let limit = webhook.monthly_spend_limit.unwrap_or_default();
+        dispatch_webhook_event(
+            vec![webhook],
+            WebhookEvent::MonthlySpendLimitReached { spend, limit },
+        );
+    }
+    Ok(())
+}
+
+/// Notifies the webhooks of a user that a USDC payment was credited to their balance.
+///
+/// # Errors
+///
+/// Returns an error if the webhooks or the balance of the user can't be retrieved.
+pub async fn notify_balance_topped_up(state: &AtomaState, user_id: i64, amount: i64) -> Result<()> {
+    state.reset_low_balance_webhooks(user_id).await?;
+    let webhooks = state.get_webhooks_for_user(user_id).await?;
+    if webhooks.is_empty() {
+        return Ok(());
+    }
+    let balance = state.get_balance_for_user(user_id).await?;
+    dispatch_webhook_event(webhooks, WebhookEvent::BalanceToppedUp { amount, balance });
+    Ok(())
+}
+
+/// Notifies the webhooks of a user that one of their API tokens was revoked.
+///
+/// # Errors
+///
+/// Returns an error if the webhooks of the user can't be retrieved.
+pub async fn notify_api_token_revoked(
+    state: &AtomaState,
+    user_id: i64,
+    api_token_id: i64,
+) -> Result<()> {
+    let webhooks = state.get_webhooks_for_user(user_id).await?;
+    dispatch_webhook_event(webhooks, WebhookEvent::ApiTokenRevoked { api_token_id });
+    Ok(())
+}